@@ -2,15 +2,53 @@
 //! about CPU topography and NUMA memory regions
 
 use core::mem::size_of;
+use alloc::vec::Vec;
 
 use crate::mm::physmem::{PhysAddr, PhysSlice};
 use crate::efi;
 
+use rangeset::{Range, RangeSet};
 use serial::{BaudRate, Interface};
-use generic_access_structure::Gas;
+use generic_access_structure::{Gas, IoAddr, AccessSize};
 
-/// Maximum number of cores on the system
-const MAX_CORES: usize = 2;
+/// Maximum number of cores which can be handed over to the kernel
+const MAX_CORES: usize = boot_info::MAX_CORES;
+
+/// Maximum number of SRAT memory affinity ranges
+const MAX_MEMORY_AFFINITIES: usize = boot_info::MAX_MEMORY_AFFINITIES;
+
+/// Maximum number of I/O APICs
+const MAX_IO_APICS: usize = 8;
+
+/// Maximum number of MADT interrupt source overrides, there is at most one
+/// for each of the 16 ISA IRQs
+const MAX_INTERRUPT_OVERRIDES: usize = 16;
+
+/// Maximum number of IOMMU remapping hardware units
+const MAX_IOMMU_UNITS: usize = 8;
+
+/// Maximum number of IOMMU reserved memory regions
+const MAX_IOMMU_RESERVED: usize = 16;
+
+/// OEM IDs of firmware which is known to leave the access size of 16550 I/O
+/// ports in the SPCR undefined
+const UNDEFINED_UART_ACCESS_OEMS: &[&[u8; 6]] = &[
+    b"BOCHS ",
+    b"OVMF  ",
+];
+
+/// Maximum number of PMTT memory devices
+const MAX_MEMORY_DEVICES: usize = 64;
+
+/// Maximum nesting depth of PMTT memory devices
+const MAX_MEMORY_DEVICE_DEPTH: usize = 8;
+
+/// Maximum number of entries in the XSDT before the XSDT is considered to be
+/// corrupt
+const MAX_XSDT_ENTRIES: usize = 256;
+
+/// Maximum number of per-table errors recorded during [`init`]
+const MAX_TABLE_ERRORS: usize = 8;
 
 /// A `Result` type which wraps an ACPI error
 pub type Result<T> = core::result::Result<T, Error>;
@@ -36,6 +74,27 @@ pub enum TableType {
     /// Serial Port Console Redirection Table
     Spcr,
 
+    /// Fixed ACPI Description Table
+    Fadt,
+
+    /// DMA Remapping Table (Intel VT-d)
+    Dmar,
+
+    /// I/O Virtualization Reporting Structure (AMD-Vi)
+    Ivrs,
+
+    /// Platform Memory Topology Table
+    Pmtt,
+
+    /// Boot Graphics Resource Table
+    Bgrt,
+
+    /// High Precision Event Timer Table
+    Hpet,
+
+    /// Generic Timer Description Table
+    Gtdt,
+
     /// An unknown table type
     Unknown([u8; 4]),
 }
@@ -47,6 +106,13 @@ impl From<[u8; 4]> for TableType {
             b"APIC" => Self::Madt,
             b"SRAT" => Self::Srat,
             b"SPCR" => Self::Spcr,
+            b"FACP" => Self::Fadt,
+            b"DMAR" => Self::Dmar,
+            b"IVRS" => Self::Ivrs,
+            b"PMTT" => Self::Pmtt,
+            b"BGRT" => Self::Bgrt,
+            b"HPET" => Self::Hpet,
+            b"GTDT" => Self::Gtdt,
                   _ => Self::Unknown(val),
         }
     }
@@ -74,15 +140,43 @@ pub enum Error {
 
     /// The XSDT table size was not evenly divisible by the array element size
     XsdtBadEntries,
+
+    /// The XSDT contained more entries than any sane firmware would report
+    XsdtTooManyEntries,
     
     /// An integer overflow occured
     IntegerOverflow,
 
-    /// More APICs have been detected than we statically allocate room for
-    TooManyApics,
+    /// More I/O APICs have been detected than we statically allocate room
+    /// for
+    TooManyIoApics,
+
+    /// More interrupt source overrides have been detected than we statically
+    /// allocate room for
+    TooManyInterruptOverrides,
+
+    /// More SRAT memory affinities have been detected than we statically
+    /// allocate room for
+    TooManyMemoryAffinities,
+
+    /// More IOMMU remapping units have been detected than we statically
+    /// allocate room for
+    TooManyIommuUnits,
+
+    /// More IOMMU reserved memory regions have been detected than we
+    /// statically allocate room for
+    TooManyIommuReserved,
+
+    /// More PMTT memory devices have been detected than we statically
+    /// allocate room for
+    TooManyMemoryDevices,
+
+    /// PMTT memory devices were nested deeper than we statically allocate
+    /// room for
+    MemoryDevicesTooDeep,
 
-    /// More x2APICs have been detected than we statically allocate room for
-    TooManyX2Apics,
+    /// A table has a revision which we do not know how to parse
+    UnsupportedRevision(TableType),
     
     /// The SPCR did not specify zero parity bits (all other values are reserved)
     InvalidParityBits,
@@ -92,6 +186,59 @@ pub enum Error {
 
     /// The SPCR specified a reserved baud rate
     InvalidBaudRate,
+
+    /// The FADT did not report a PM timer
+    NoPmTimer,
+
+    /// The BGRT logo is not a BMP image
+    UnsupportedImageType(u8),
+
+    /// The HPET registers are not in system memory, but in the address
+    /// space with this ID
+    UnsupportedHpetAddressSpace(u8),
+
+    /// Accessing a register via its [`Gas`] returned an error
+    GasError(generic_access_structure::Error),
+
+    /// The memory backing a table could not be recorded
+    Regions(rangeset::Error),
+}
+
+/// How strictly ACPI tables are validated during [`init`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Any checksum or length mismatch is an error
+    Strict,
+
+    /// Checksum and length mismatches are printed as warnings and the table
+    /// is used anyways. Some firmware ships tables which are otherwise fine
+    /// but have a stale checksum or a wrong length field.
+    WarnAndContinue,
+}
+
+impl ValidationPolicy {
+    /// Apply the policy to the result of a validation check
+    ///
+    /// # Parameters
+    ///
+    /// * `res` - The result of a checksum or length validation
+    ///
+    /// # Returns
+    ///
+    /// `res`, unless the error is downgraded to a warning by the policy in
+    /// which case `()`
+    ///
+    fn apply(self, res: Result<()>) -> Result<()> {
+        match res {
+            Err(err @ Error::ChecksumMismatch(_)) |
+            Err(err @ Error::LengthMismatch(_))
+                    if self == Self::WarnAndContinue => {
+                log_warn!("ACPI: {:?}, downgraded to a warning\n", err);
+                Ok(())
+            }
+            _ => res,
+        }
+    }
 }
 
 /// Compute an ACPI checksum on physical memory
@@ -113,8 +260,8 @@ unsafe fn checksum(addr: PhysAddr, size: usize, typ: TableType) -> Result<()> {
     // Compute checksum
     let chk = (0..size as u64).try_fold(0u8, |acc, offset| {
         Ok(acc.wrapping_add(
-            PhysAddr(addr.0.checked_add(offset)
-                .ok_or(Error::IntegerOverflow)?).read_unaligned::<u8>()))
+            addr.checked_add(offset).map_err(|_| Error::IntegerOverflow)?
+                .read_unaligned::<u8>()))
     })?;
 
     // Validate checksum
@@ -157,17 +304,19 @@ impl Rsdp {
     ///
     /// # Parameters
     ///
-    /// * `addr` - The physical address of the memory to be interpreted as an
-    ///            RSDP table
+    /// * `addr`   - The physical address of the memory to be interpreted as
+    ///              an RSDP table
+    /// * `policy` - The [`ValidationPolicy`] to apply to the checksum
     ///
     /// # Returns
     ///
     /// A well formed [`Rsdp`] if `addr` references a valid RSDP table.
     /// [`Error`] on errors.
     ///
-    unsafe fn from_addr(addr: PhysAddr) -> Result<Self> {
+    unsafe fn from_addr(addr: PhysAddr, policy: ValidationPolicy)
+            -> Result<Self> {
         // Validate the checksum
-        checksum(addr, size_of::<Self>(), TableType::Rsdp)?;
+        policy.apply(checksum(addr, size_of::<Self>(), TableType::Rsdp))?;
 
         // Get the RSDP table
         let rsdp = addr.read_unaligned::<Self>();
@@ -209,18 +358,21 @@ impl RsdpExtended {
     ///
     /// # Parameters
     ///
-    /// * `addr` - The physical address of the memory to be interpreted as an
-    ///            extended RSDP table
+    /// * `addr`   - The physical address of the memory to be interpreted as
+    ///              an extended RSDP table
+    /// * `policy` - The [`ValidationPolicy`] to apply to the checksum and
+    ///              length
     ///
     /// # Returns
     ///
     /// A well formed [`RsdpExtended`] if `addr` references a valid extended 
     /// RSDP table. [`Error`] on errors.
     ///
-    unsafe fn from_addr(addr: PhysAddr) -> Result<Self> {
+    unsafe fn from_addr(addr: PhysAddr, policy: ValidationPolicy)
+            -> Result<Self> {
         // First read the RSDP. This is the ACPI 1.0 structure and thus is
         // a subset and backwards compatible with all future revisions.
-        let rsdp = Rsdp::from_addr(addr)?;
+        let rsdp = Rsdp::from_addr(addr, policy)?;
 
         // The extended RSDP requires ACPI 2.0
         if rsdp.revision < 2 {
@@ -228,14 +380,15 @@ impl RsdpExtended {
         }
 
         // Validate the checksum
-        checksum(addr, size_of::<Self>(), TableType::Rsdp)?;
+        policy.apply(checksum(addr, size_of::<Self>(), TableType::Rsdp))?;
 
         // Get the extended RSDP table
         let rsdp = addr.read_unaligned::<Self>();
 
         // Check the size
         if rsdp.length as usize != size_of::<Self>() {
-            return Err(Error::LengthMismatch(TableType::RsdpExtended));
+            policy.apply(
+                Err(Error::LengthMismatch(TableType::RsdpExtended)))?;
         }
 
         // Rsdp seems all good!
@@ -243,6 +396,9 @@ impl RsdpExtended {
     }
 }
 
+/// Size (in bytes) of the header every table listed in the XSDT starts with
+pub const TABLE_HEADER_SIZE: usize = size_of::<Table>();
+
 /// In-memory representation of an ACPI table header
 #[repr(C, packed)]
 struct Table {
@@ -290,8 +446,9 @@ impl Table {
     ///
     /// # Parameters
     ///
-    /// * `addr` - The physical address of the memory to be interpreted as an
-    ///            ACPI table
+    /// * `addr`   - The physical address of the memory to be interpreted as
+    ///              an ACPI table
+    /// * `policy` - The [`ValidationPolicy`] to apply to the checksum
     ///
     /// # Returns
     ///
@@ -305,7 +462,7 @@ impl Table {
     ///
     /// On error, an [`Error`]
     ///
-    unsafe fn from_addr(addr: PhysAddr)
+    unsafe fn from_addr(addr: PhysAddr, policy: ValidationPolicy)
             -> Result<(Self, TableType, PhysAddr, usize)> {
         // Read the table
         let table = addr.read_unaligned::<Self>();
@@ -314,14 +471,14 @@ impl Table {
         let typ = TableType::from(table.signature);
 
         // Validate the checksum
-        checksum(addr, table.length as usize, typ)?;
+        policy.apply(checksum(addr, table.length as usize, typ))?;
 
         // Computer the address of the table's payload and its size in bytes
         let header_size  = size_of::<Self>();
         let payload_size = (table.length as usize).checked_sub(header_size)
             .ok_or(Error::LengthMismatch(typ))?;
-        let payload_addr = PhysAddr(addr.0.checked_add(header_size as u64)
-            .ok_or(Error::IntegerOverflow)?);
+        let payload_addr = addr.checked_add(header_size as u64)
+            .map_err(|_| Error::IntegerOverflow)?;
 
         // Return the parsed information 
         Ok((table, typ, payload_addr, payload_size))
@@ -331,17 +488,41 @@ impl Table {
 /// The Multiple APIC Description Table
 #[derive(Debug)]
 pub struct Madt {
+    /// Physical address of the local APIC registers of every processor,
+    /// from the Local APIC Address Override entry if there is one
+    local_apic_addr: PhysAddr,
+
     /// Local APICs detected from ACPI
-    apics: [LocalApic; MAX_CORES],
+    apics: Vec<LocalApic>,
+
+    /// x2APICs detected from ACPI
+    x2apics: Vec<LocalX2Apic>,
+
+    /// GIC CPU interfaces detected from ACPI, one for each ARM processor
+    giccs: Vec<Gicc>,
 
-    /// Number of APCIs which have been initialized in `apics`
-    num_apics: usize,
+    /// The GIC distributor, there is only ever one
+    gicd: Option<Gicd>,
 
-    /// Number of x2APICs detected from ACPI
-    x2apics: [LocalX2Apic; MAX_CORES],
+    /// GIC redistributor ranges detected from ACPI
+    gicrs: Vec<Gicr>,
 
-    /// Number of X2APCIs which have been initialized in `x2apics`
-    num_x2apics: usize,
+    /// RISC-V hart local interrupt controllers detected from ACPI, one for
+    /// each hart
+    rintcs: Vec<Rintc>,
+
+    /// I/O APICs detected from ACPI
+    io_apics: [IoApicEntry; MAX_IO_APICS],
+
+    /// Number of I/O APICs which have been initialized in `io_apics`
+    num_io_apics: usize,
+
+    /// Interrupt source overrides detected from ACPI
+    overrides: [InterruptSourceOverride; MAX_INTERRUPT_OVERRIDES],
+
+    /// Number of interrupt source overrides which have been initialized in
+    /// `overrides`
+    num_overrides: usize,
 }
 
 /// Processor Local APIC structure
@@ -386,7 +567,442 @@ struct LocalX2Apic {
     acpi_processor_uid: u32,
 }
 
+/// I/O APIC Structure
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct IoApicEntry {
+    /// The I/O APIC's ID
+    io_apic_id: u8,
+
+    /// Reserved - must be zero
+    reserved: u8,
+
+    /// The 32-bit physical address of the I/O APIC registers
+    io_apic_address: u32,
+
+    /// The global system interrupt number where this I/O APIC's interrupt
+    /// inputs start
+    global_system_interrupt_base: u32,
+}
+
+/// Interrupt Source Override Structure
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct InterruptSourceOverride {
+    /// Always zero, for ISA
+    bus: u8,
+
+    /// The bus-relative interrupt source, the ISA IRQ
+    source: u8,
+
+    /// The global system interrupt the source signals
+    global_system_interrupt: u32,
+
+    /// MPS INTI flags
+    ///
+    /// Bits 0-1: Polarity (0 conforms to the bus, 1 active high, 3 active
+    /// low)
+    /// Bits 2-3: Trigger mode (0 conforms to the bus, 1 edge, 3 level)
+    flags: u16,
+}
+
+/// GIC CPU Interface (GICC) Structure, up to the MPIDR. Later ACPI revisions
+/// append more fields, which we do not care about.
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Gicc {
+    /// Reserved - must be zero
+    reserved: u16,
+
+    /// GIC's CPU Interface Number
+    cpu_interface_number: u32,
+
+    /// The OS associates this GICC Structure with a processor device object
+    /// in the namespace when the _UID child object of the processor device
+    /// evaluates to a numeric value that matches the numeric value in this
+    /// field
+    acpi_processor_uid: u32,
+
+    /// GICC flags
+    ///
+    /// Bit 0: Enabled (set if ready for use)
+    /// Bit 3: Online Capable (RAZ if enabled, indicates if the processor can
+    /// be enabled at runtime)
+    flags: u32,
+
+    /// Version of the ARM processor parking protocol implemented
+    parking_protocol_version: u32,
+
+    /// The GSIV used for performance monitoring interrupts
+    performance_interrupt_gsiv: u32,
+
+    /// The 64-bit physical address of the processor's parking protocol
+    /// mailbox
+    parked_address: u64,
+
+    /// Physical address of the GIC CPU interface registers, for GICv1/v2
+    physical_base_address: u64,
+
+    /// Address of the GIC virtual CPU interface registers
+    gicv: u64,
+
+    /// Address of the GIC virtual interface control block registers
+    gich: u64,
+
+    /// GSIV for the virtual GIC maintenance interrupt
+    vgic_maintenance_interrupt: u32,
+
+    /// Physical address of the associated GICv3/v4 redistributor, zero if
+    /// the redistributors are described by GICR entries instead
+    gicr_base_address: u64,
+
+    /// The MPIDR of the processor with this GICC
+    mpidr: u64,
+}
+
+/// GIC Distributor (GICD) Structure
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Gicd {
+    /// Reserved - must be zero
+    reserved: u16,
+
+    /// This GIC Distributor's hardware ID
+    gic_id: u32,
+
+    /// The 64-bit physical address of the distributor registers
+    physical_base_address: u64,
+
+    /// Reserved - must be zero
+    system_vector_base: u32,
+
+    /// The GIC version, zero if it has to be detected from the hardware
+    gic_version: u8,
+
+    /// Reserved - must be zero
+    reserved2: [u8; 3],
+}
+
+/// GIC Redistributor (GICR) Structure
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Gicr {
+    /// Reserved - must be zero
+    reserved: u16,
+
+    /// The 64-bit physical address of a page range containing all GIC
+    /// redistributors
+    discovery_range_base_address: u64,
+
+    /// Length of the GIC redistributor discovery page range
+    discovery_range_length: u32,
+}
+
+/// RISC-V Hart Local Interrupt Controller (RINTC) Structure, up to the ACPI
+/// processor UID. Later revisions append the external interrupt controller
+/// and IMSIC fields, which we do not care about.
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rintc {
+    /// Version of the structure
+    version: u8,
+
+    /// Reserved - must be zero
+    reserved: u8,
+
+    /// RINTC flags
+    ///
+    /// Bit 0: Enabled (set if ready for use)
+    /// Bit 1: Online Capable (RAZ if enabled, indicates if the hart can be
+    /// enabled at runtime)
+    flags: u32,
+
+    /// The hart ID of the hart, which the SBI identifies it by
+    hart_id: u64,
+
+    /// The OS associates this RINTC Structure with a processor device object
+    /// in the namespace when the _UID child object of the processor device
+    /// evaluates to a numeric value that matches the numeric value in this
+    /// field
+    acpi_processor_uid: u32,
+}
+
+/// The GIC distributor described by the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct GicDistributor {
+    /// Physical address of the distributor registers
+    pub base: PhysAddr,
+
+    /// The GIC version, zero if it has to be detected from the hardware
+    pub version: u8,
+}
+
+/// An ARM processor described by a GICC entry of the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct GicProcessor {
+    /// ACPI processor UID of the processor
+    pub acpi_processor_uid: u32,
+
+    /// The affinity fields of the MPIDR of the processor, which PSCI
+    /// identifies it by
+    pub mpidr: u64,
+
+    /// Set if the processor is ready for use
+    pub enabled: bool,
+
+    /// Set if the processor is disabled but can be enabled at runtime
+    pub online_capable: bool,
+
+    /// Physical address of the GICv2 CPU interface registers of the
+    /// processor
+    pub gicc_base: PhysAddr,
+
+    /// Physical address of the GICv3 redistributor of the processor, zero
+    /// if the redistributors are found through
+    /// [`Madt::gic_redistributor_ranges`]
+    pub gicr_base: PhysAddr,
+}
+
+/// An I/O APIC described by the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    /// The I/O APIC ID
+    pub id: u8,
+
+    /// Physical address of the I/O APIC registers
+    pub base: PhysAddr,
+
+    /// The global system interrupt of the first interrupt input
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ which the MADT reports as signalling a different global
+/// system interrupt, or with a different polarity or trigger mode, than it
+/// would by default
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    /// The ISA IRQ
+    pub irq: u8,
+
+    /// The global system interrupt the IRQ signals
+    pub gsi: u32,
+
+    /// The MPS INTI flags giving the polarity and trigger mode
+    pub flags: u16,
+}
+
+/// A RISC-V hart described by a RINTC entry of the MADT
+#[derive(Debug, Clone, Copy)]
+pub struct Hart {
+    /// ACPI processor UID of the hart
+    pub acpi_processor_uid: u32,
+
+    /// The hart ID, which the SBI identifies it by
+    pub hart_id: u64,
+
+    /// Set if the hart is ready for use
+    pub enabled: bool,
+
+    /// Set if the hart is disabled but can be enabled at runtime
+    pub online_capable: bool,
+}
+
+/// A processor described by the MADT, from either a local APIC or a local
+/// x2APIC entry
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    /// ACPI processor UID of the processor
+    pub acpi_processor_uid: u32,
+
+    /// APIC ID (or x2APIC ID) of the processor
+    pub apic_id: u32,
+
+    /// Set if the processor was described by a local x2APIC entry
+    pub x2apic: bool,
+
+    /// Set if the processor is ready for use
+    pub enabled: bool,
+
+    /// Set if the processor is disabled but can be enabled at runtime
+    pub online_capable: bool,
+}
+
 impl Madt {
+    /// Local APIC flag which is set if the processor is ready for use
+    const APIC_ENABLED: u32 = 1 << 0;
+
+    /// Local APIC flag which is set if a disabled processor can be enabled
+    /// at runtime
+    const APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+    /// Get all processors which are enabled or can be enabled at runtime.
+    /// Local APIC entries are reported first, followed by x2APIC entries
+    /// which were not already described by a local APIC entry.
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the usable [`Processor`]s
+    ///
+    pub fn processors(&self) -> impl Iterator<Item = Processor> + '_ {
+        /// Convert MADT flags into a [`Processor`]
+        fn processor(uid: u32, apic_id: u32, x2apic: bool, flags: u32)
+                -> Processor {
+            Processor {
+                acpi_processor_uid: uid,
+                apic_id:            apic_id,
+                x2apic:             x2apic,
+                enabled:        flags & Madt::APIC_ENABLED        != 0,
+                online_capable: flags & Madt::APIC_ONLINE_CAPABLE != 0,
+            }
+        }
+
+        let apics = &self.apics;
+        let x2apics = &self.x2apics;
+
+        apics.iter().map(|x| {
+            processor(x.acpi_processor_uid as u32, x.apic_id as u32,
+                      false, x.flags)
+        }).chain(x2apics.iter().filter(move |x| {
+            // Some firmware describes the same processor in both tables
+            let id = x.x2apic_id;
+            !apics.iter().any(|apic| apic.apic_id as u32 == id)
+        }).map(|x| {
+            processor(x.acpi_processor_uid, x.x2apic_id, true, x.flags)
+        })).filter(|x| x.enabled || x.online_capable)
+    }
+
+    /// Get all ARM processors which are enabled or can be enabled at runtime
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the usable [`GicProcessor`]s
+    ///
+    pub fn gic_processors(&self) -> impl Iterator<Item = GicProcessor> + '_ {
+        /// GICC flag which is set if the processor is ready for use
+        const GICC_ENABLED: u32 = 1 << 0;
+
+        /// GICC flag which is set if a disabled processor can be enabled at
+        /// runtime
+        const GICC_ONLINE_CAPABLE: u32 = 1 << 3;
+
+        /// The affinity fields of the MPIDR, the rest are flags
+        const MPIDR_AFFINITY: u64 = 0xff_00ff_ffff;
+
+        self.giccs.iter().map(|x| GicProcessor {
+            acpi_processor_uid: x.acpi_processor_uid,
+            mpidr:              x.mpidr & MPIDR_AFFINITY,
+            enabled:        x.flags & GICC_ENABLED        != 0,
+            online_capable: x.flags & GICC_ONLINE_CAPABLE != 0,
+            gicc_base:          PhysAddr(x.physical_base_address),
+            gicr_base:          PhysAddr(x.gicr_base_address),
+        }).filter(|x| x.enabled || x.online_capable)
+    }
+
+    /// Get all RISC-V harts which are enabled or can be enabled at runtime
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the usable [`Hart`]s
+    ///
+    pub fn harts(&self) -> impl Iterator<Item = Hart> + '_ {
+        /// RINTC flag which is set if the hart is ready for use
+        const RINTC_ENABLED: u32 = 1 << 0;
+
+        /// RINTC flag which is set if a disabled hart can be enabled at
+        /// runtime
+        const RINTC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+        self.rintcs.iter().map(|x| Hart {
+            acpi_processor_uid: x.acpi_processor_uid,
+            hart_id:            x.hart_id,
+            enabled:        x.flags & RINTC_ENABLED        != 0,
+            online_capable: x.flags & RINTC_ONLINE_CAPABLE != 0,
+        }).filter(|x| x.enabled || x.online_capable)
+    }
+
+    /// Get the I/O APICs
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the [`IoApic`]s
+    ///
+    pub fn io_apics(&self) -> impl Iterator<Item = IoApic> + '_ {
+        self.io_apics[..self.num_io_apics].iter().map(|x| IoApic {
+            id:       x.io_apic_id,
+            base:     PhysAddr(x.io_apic_address as u64),
+            gsi_base: x.global_system_interrupt_base,
+        })
+    }
+
+    /// Get the interrupt source overrides of the ISA IRQs
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the [`InterruptOverride`]s
+    ///
+    pub fn interrupt_overrides(&self)
+            -> impl Iterator<Item = InterruptOverride> + '_ {
+        self.overrides[..self.num_overrides].iter().map(|x| InterruptOverride {
+            irq:   x.source,
+            gsi:   x.global_system_interrupt,
+            flags: x.flags,
+        })
+    }
+
+    /// Get the GIC distributor
+    ///
+    /// # Returns
+    ///
+    /// The [`GicDistributor`], `None` if the MADT does not describe one
+    ///
+    pub fn gic_distributor(&self) -> Option<GicDistributor> {
+        self.gicd.map(|x| GicDistributor {
+            base:    PhysAddr(x.physical_base_address),
+            version: x.gic_version,
+        })
+    }
+
+    /// Get the ranges the GICv3 redistributors are found in
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the physical address and size (in bytes) of each
+    /// range
+    ///
+    pub fn gic_redistributor_ranges(&self)
+            -> impl Iterator<Item = (PhysAddr, u64)> + '_ {
+        self.gicrs.iter().map(|x| {
+            (PhysAddr(x.discovery_range_base_address),
+             x.discovery_range_length as u64)
+        })
+    }
+
+    /// Look up the APIC ID of the processor with ACPI processor UID `uid`
+    ///
+    /// # Parameters
+    ///
+    /// * `uid` - The ACPI processor UID to look up
+    ///
+    /// # Returns
+    ///
+    /// The APIC ID (or x2APIC ID) of the usable processor with `uid`, `None`
+    /// if no such processor exists
+    ///
+    pub fn apic_id_for_uid(&self, uid: u32) -> Option<u32> {
+        self.processors().find(|x| x.acpi_processor_uid == uid)
+            .map(|x| x.apic_id)
+    }
+
+    /// Get the physical address of the local APIC registers
+    ///
+    /// # Returns
+    ///
+    /// The address every processor sees its own local APIC at
+    ///
+    pub fn local_apic_addr(&self) -> PhysAddr {
+        self.local_apic_addr
+    }
+
     /// Parse the payload of an ACPI MADT table
     ///
     /// # Parameters
@@ -406,17 +1022,24 @@ impl Madt {
         let mut slice = PhysSlice::new(addr, size);
 
         // Read the local APIC physical address
-        let _local_apic_addr = slice.consume::<u32>().map_err(|_| E)?;
+        let local_apic_addr = slice.consume::<u32>().map_err(|_| E)?;
 
         // Get the APIC flags
         let _flags = slice.consume::<u32>().map_err(|_| E)?;
 
         // Create an empty `Madt`
         let mut ret = Self {
-            apics:   [Default::default(); MAX_CORES],
-            num_apics:   0,
-            x2apics: [Default::default(); MAX_CORES],
-            num_x2apics: 0,
+            local_apic_addr: PhysAddr(local_apic_addr as u64),
+            apics:   Vec::new(),
+            x2apics: Vec::new(),
+            giccs:   Vec::new(),
+            gicd:    None,
+            gicrs:   Vec::new(),
+            rintcs:  Vec::new(),
+            io_apics:  [Default::default(); MAX_IO_APICS],
+            num_io_apics:  0,
+            overrides: [Default::default(); MAX_INTERRUPT_OVERRIDES],
+            num_overrides: 0,
         };
 
         // Handle Interrupt Controller Structures
@@ -437,9 +1060,37 @@ impl Madt {
                     let apic = slice.consume::<LocalApic>().map_err(|_| E)?;
 
                     // Update APIC information
-                    *ret.apics.get_mut(ret.num_apics)
-                        .ok_or(Error::TooManyApics)? = apic;
-                    ret.num_apics += 1;
+                    ret.apics.push(apic);
+                }
+                1 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<IoApicEntry>() {
+                        return Err(E);
+                    }
+
+                    // Get the `IoApicEntry` information
+                    let io_apic =
+                        slice.consume::<IoApicEntry>().map_err(|_| E)?;
+
+                    // Update I/O APIC information
+                    *ret.io_apics.get_mut(ret.num_io_apics)
+                        .ok_or(Error::TooManyIoApics)? = io_apic;
+                    ret.num_io_apics += 1;
+                }
+                2 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<InterruptSourceOverride>() {
+                        return Err(E);
+                    }
+
+                    // Get the `InterruptSourceOverride` information
+                    let iso = slice.consume::<InterruptSourceOverride>()
+                        .map_err(|_| E)?;
+
+                    // Update interrupt source override information
+                    *ret.overrides.get_mut(ret.num_overrides)
+                        .ok_or(Error::TooManyInterruptOverrides)? = iso;
+                    ret.num_overrides += 1;
                 }
                 9 => {
                     // Ensure the data is the correct size
@@ -452,9 +1103,64 @@ impl Madt {
                         slice.consume::<LocalX2Apic>().map_err(|_| E)?;
                     
                     // Update x2APIC information
-                    *ret.x2apics.get_mut(ret.num_x2apics)
-                        .ok_or(Error::TooManyX2Apics)? = x2apic;
-                    ret.num_x2apics += 1;
+                    ret.x2apics.push(x2apic);
+                }
+                5 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<u16>() + size_of::<u64>() {
+                        return Err(E);
+                    }
+
+                    // Get the 64-bit address which replaces the 32-bit one
+                    let _reserved = slice.consume::<u16>().map_err(|_| E)?;
+                    ret.local_apic_addr =
+                        PhysAddr(slice.consume::<u64>().map_err(|_| E)?);
+                }
+                0xb => {
+                    // The entry has grown with every ACPI revision, but
+                    // always starts with the same fields
+                    let extra = (len as usize).checked_sub(size_of::<Gicc>())
+                        .ok_or(E)?;
+
+                    // Get the `Gicc` information
+                    let gicc = slice.consume::<Gicc>().map_err(|_| E)?;
+                    slice.discard(extra).map_err(|_| E)?;
+
+                    // Update GICC information
+                    ret.giccs.push(gicc);
+                }
+                0xc => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<Gicd>() {
+                        return Err(E);
+                    }
+
+                    // Get the `Gicd` information
+                    ret.gicd = Some(slice.consume::<Gicd>().map_err(|_| E)?);
+                }
+                0xe => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<Gicr>() {
+                        return Err(E);
+                    }
+
+                    // Get the `Gicr` information
+                    let gicr = slice.consume::<Gicr>().map_err(|_| E)?;
+
+                    // Update GICR information
+                    ret.gicrs.push(gicr);
+                }
+                0x18 => {
+                    // Newer revisions of the entry append fields
+                    let extra = (len as usize).checked_sub(size_of::<Rintc>())
+                        .ok_or(E)?;
+
+                    // Get the `Rintc` information
+                    let rintc = slice.consume::<Rintc>().map_err(|_| E)?;
+                    slice.discard(extra).map_err(|_| E)?;
+
+                    // Update RINTC information
+                    ret.rintcs.push(rintc);
                 }
                 _ => {
                     // Unknown type, just discard the data
@@ -478,69 +1184,1424 @@ pub struct Spcr {
 
     /// Baud rate to use for the serial port
     pub baud_rate: BaudRate,
-}
 
-impl Spcr {
-    /// Parse the payload of an ACPI SPCR table
+    /// Input clock frequency of the UART in Hz, if reported (SPCR revision
+    /// 3 and newer)
+    pub clock: Option<u32>,
+
+    /// The terminal the console is expected to be viewed on
+    pub terminal: TerminalType,
+
+    /// The interrupt controllers the serial port interrupt is reported for,
+    /// zero if the serial port can only be polled
+    pub interrupt_type: u8,
+
+    /// The PC-AT compatible IRQ of the serial port
+    pub irq: u8,
+
+    /// The global system interrupt of the serial port
+    pub gsiv: u32,
+}
+
+/// Types of terminals in the SPCR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalType {
+    /// A VT100
+    Vt100,
+
+    /// A VT100 with extensions
+    Vt100Plus,
+
+    /// A VT100 with UTF-8 support
+    VtUtf8,
+
+    /// An ANSI terminal
+    Ansi,
+
+    /// A reserved terminal type
+    Unknown(u8),
+}
+
+impl TerminalType {
+    /// Check whether the terminal understands ANSI color sequences
+    ///
+    /// # Returns
+    ///
+    /// `true` if colors can be used, which a plain VT100 and unknown
+    /// terminals are not trusted with
+    ///
+    pub fn supports_color(self) -> bool {
+        matches!(self, Self::Vt100Plus | Self::VtUtf8 | Self::Ansi)
+    }
+}
+
+impl From<u8> for TerminalType {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::Vt100,
+            1 => Self::Vt100Plus,
+            2 => Self::VtUtf8,
+            3 => Self::Ansi,
+            _ => Self::Unknown(val),
+        }
+    }
+}
+
+impl Spcr {
+    /// Set in `interrupt_type` if `irq` is a PC-AT 8259 interrupt
+    const INTERRUPT_PIC: u8 = 1 << 0;
+
+    /// Set in `interrupt_type` if `gsiv` is an I/O APIC interrupt
+    const INTERRUPT_IO_APIC: u8 = 1 << 1;
+
+    /// Set in `interrupt_type` if `gsiv` is an ARM GIC interrupt
+    const INTERRUPT_GIC: u8 = 1 << 3;
+
+    /// Get the I/O APIC interrupt of the serial port
+    ///
+    /// # Returns
+    ///
+    /// The global system interrupt, `None` if the serial port does not have
+    /// an I/O APIC interrupt
+    ///
+    pub fn io_apic_interrupt(&self) -> Option<u32> {
+        (self.interrupt_type & Self::INTERRUPT_IO_APIC != 0)
+            .then_some(self.gsiv)
+    }
+
+    /// Get the PC-AT interrupt of the serial port
+    ///
+    /// # Returns
+    ///
+    /// The ISA IRQ, `None` if the serial port does not have a PC-AT
+    /// interrupt
+    ///
+    pub fn isa_interrupt(&self) -> Option<u8> {
+        (self.interrupt_type & Self::INTERRUPT_PIC != 0).then_some(self.irq)
+    }
+
+    /// Get the GIC interrupt of the serial port
+    ///
+    /// # Returns
+    ///
+    /// The interrupt ID, `None` if the serial port does not have a GIC
+    /// interrupt
+    ///
+    pub fn gic_interrupt(&self) -> Option<u32> {
+        (self.interrupt_type & Self::INTERRUPT_GIC != 0).then_some(self.gsiv)
+    }
+
+    /// Parse the payload of an ACPI SPCR table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of an SPCR payload
+    /// * `size` - The size (in bytes) of the SPCR payload
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Spcr`], on error [`Error`]
+    /// 
+    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the SPCR is truncated
+        const E: Error = Error::LengthMismatch(TableType::Spcr);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Get the serial interface type
+        let typ: Interface =
+            slice.consume::<u8>().map_err(|_| E)?.into();
+
+        // Reserved (3 bytes)
+        slice.discard(3).map_err(|_| E)?;
+
+        // The generic address structure
+        let info: Gas = slice.consume::<[u8; 12]>().map_err(|_| E)?.into();
+
+        // Get the interrupt types, the IRQ and the global system interrupt
+        let interrupt_type = slice.consume::<u8>().map_err(|_| E)?;
+        let irq            = slice.consume::<u8>().map_err(|_| E)?;
+        let gsiv           = slice.consume::<u32>().map_err(|_| E)?;
+
+        // Get the baud rate. A reserved value is only an error if the
+        // precise baud rate below does not override it.
+        let mut baud_rate = match slice.consume::<u8>().map_err(|_| E)? {
+            0 => Some(BaudRate::AsIs),
+            3 => Some(BaudRate::Baud9600),
+            4 => Some(BaudRate::Baud19200),
+            6 => Some(BaudRate::Baud57600),
+            7 => Some(BaudRate::Baud115200),
+            _ => None,
+        };
+
+        // Get parity and stop bit information
+        let parity_bits = slice.consume::<u8>().map_err(|_| E)?;
+        let stop_bits   = slice.consume::<u8>().map_err(|_| E)?;
+
+        // Currently SPCR spec only allows for no parity and one stop bit
+        if parity_bits != 0 { return Err(Error::InvalidParityBits) };
+        if stop_bits   != 1 { return Err(Error::InvalidStopBits)   };
+
+        // Flow control, do not care
+        slice.discard(1).map_err(|_| E)?;
+
+        // Get the terminal type
+        let terminal: TerminalType =
+            slice.consume::<u8>().map_err(|_| E)?.into();
+
+        // Language, PCI information, do not care
+        let mut clock = None;
+        if slice.discard(13).is_ok() && slice.len() >= 4 {
+            // Get the UART clock frequency, zero means not specified
+            let freq = slice.consume::<u32>().map_err(|_| E)?;
+            clock = (freq != 0).then_some(freq);
+
+            // Get the precise baud rate, if non-zero this overrides the
+            // enumerated baud rate and allows for rates above 115200
+            if slice.len() >= 4 {
+                let precise = slice.consume::<u32>().map_err(|_| E)?;
+                if precise != 0 {
+                    baud_rate = Some(precise.into());
+                }
+            }
+        }
+
+        // Return out the serial port info
+        Ok(Self {
+            interface_type: typ,
+            address:        info,
+            baud_rate:      baud_rate.ok_or(Error::InvalidBaudRate)?,
+            clock:          clock,
+            terminal:       terminal,
+            interrupt_type: interrupt_type,
+            irq:            irq,
+            gsiv:           gsiv,
+        })
+    }
+}
+
+/// A memory range associated with a proximity domain by the SRAT
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MemoryAffinity {
+    /// Proximity domain the memory range belongs to
+    pub domain: u32,
+
+    /// Physical base address of the memory range
+    pub base: u64,
+
+    /// Length of the memory range in bytes
+    pub length: u64,
+
+    /// Memory affinity flags
+    ///
+    /// Bit 0: Enabled
+    /// Bit 1: Hot pluggable
+    /// Bit 2: Non-volatile
+    pub flags: u32,
+}
+
+/// A processor associated with a proximity domain by the SRAT
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ApicAffinity {
+    /// Proximity domain the processor belongs to
+    pub domain: u32,
+
+    /// The APIC ID (or x2APIC ID) of the processor
+    pub apic_id: u32,
+
+    /// Affinity flags
+    ///
+    /// Bit 0: Enabled
+    pub flags: u32,
+}
+
+/// Memory Affinity structure as found in the SRAT
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct SratMemory {
+    /// Integer that represents the proximity domain to which the memory
+    /// range belongs
+    domain: u32,
+
+    /// Reserved
+    reserved1: u16,
+
+    /// Base address of the memory range
+    base: u64,
+
+    /// Length of the memory range
+    length: u64,
+
+    /// Reserved
+    reserved2: u32,
+
+    /// Memory affinity flags
+    flags: u32,
+
+    /// Reserved
+    reserved3: u64,
+}
+
+/// Processor Local APIC/SAPIC Affinity structure as found in the SRAT
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct SratApic {
+    /// Bits [7:0] of the proximity domain to which the processor belongs
+    domain_low: u8,
+
+    /// The processor local APIC ID
+    apic_id: u8,
+
+    /// Affinity flags
+    flags: u32,
+
+    /// The processor local SAPIC EID
+    sapic_eid: u8,
+
+    /// Bits [31:8] of the proximity domain to which the processor belongs
+    domain_high: [u8; 3],
+
+    /// The clock domain to which the processor belongs
+    clock_domain: u32,
+}
+
+/// Processor Local x2APIC Affinity structure as found in the SRAT
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct SratX2Apic {
+    /// Reserved
+    reserved1: u16,
+
+    /// The proximity domain to which the processor belongs
+    domain: u32,
+
+    /// The processor local x2APIC ID
+    x2apic_id: u32,
+
+    /// Affinity flags
+    flags: u32,
+
+    /// The clock domain to which the processor belongs
+    clock_domain: u32,
+
+    /// Reserved
+    reserved2: u32,
+}
+
+/// The System Resource Affinity Table
+#[derive(Debug)]
+pub struct Srat {
+    /// Memory ranges and their proximity domains
+    memory: [MemoryAffinity; MAX_MEMORY_AFFINITIES],
+
+    /// Number of memory affinities which have been initialized in `memory`
+    num_memory: usize,
+
+    /// Processors and their proximity domains
+    apics: Vec<ApicAffinity>,
+}
+
+impl Srat {
+    /// Processor affinity flag which is set if the entry is in use
+    const AFFINITY_ENABLED: u32 = 1 << 0;
+
+    /// Parse the payload of an ACPI SRAT table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of an SRAT payload
+    /// * `size` - The size (in bytes) of the SRAT payload
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Srat`], on error [`Error`]
+    /// 
+    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the SRAT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Srat);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Reserved (12 bytes)
+        slice.discard(12).map_err(|_| E)?;
+
+        // Create an empty `Srat`
+        let mut ret = Self {
+            memory:     [Default::default(); MAX_MEMORY_AFFINITIES],
+            num_memory: 0,
+            apics:      Vec::new(),
+        };
+
+        // Handle Static Resource Allocation Structures
+        while slice.len() > 0 {
+            // Read the resource allocation structure header
+            let typ = slice.consume::<u8>().map_err(|_| E)?;
+            let len = slice.consume::<u8>().map_err(|_| E)?
+                .checked_sub(2).ok_or(E)?;
+
+            match typ {
+                0 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<SratApic>() {
+                        return Err(E);
+                    }
+
+                    // Get the `SratApic` information, firmware lists
+                    // processors which don't exist as disabled
+                    let apic = slice.consume::<SratApic>().map_err(|_| E)?;
+                    if apic.flags & Self::AFFINITY_ENABLED == 0 { continue; }
+                    let high = apic.domain_high;
+
+                    // Update processor affinity information
+                    ret.apics.push(ApicAffinity {
+                        domain: u32::from_le_bytes(
+                            [apic.domain_low, high[0], high[1], high[2]]),
+                        apic_id: apic.apic_id as u32,
+                        flags:   apic.flags,
+                    });
+                }
+                1 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<SratMemory>() {
+                        return Err(E);
+                    }
+
+                    // Get the `SratMemory` information
+                    let mem = slice.consume::<SratMemory>().map_err(|_| E)?;
+
+                    // Update memory affinity information
+                    *ret.memory.get_mut(ret.num_memory)
+                        .ok_or(Error::TooManyMemoryAffinities)? =
+                            MemoryAffinity {
+                        domain: mem.domain,
+                        base:   mem.base,
+                        length: mem.length,
+                        flags:  mem.flags,
+                    };
+                    ret.num_memory += 1;
+                }
+                2 => {
+                    // Ensure the data is the correct size
+                    if len as usize != size_of::<SratX2Apic>() {
+                        return Err(E);
+                    }
+
+                    // Get the `SratX2Apic` information, skipping disabled
+                    // entries
+                    let x2apic =
+                        slice.consume::<SratX2Apic>().map_err(|_| E)?;
+                    if x2apic.flags & Self::AFFINITY_ENABLED == 0 {
+                        continue;
+                    }
+
+                    // Update processor affinity information
+                    ret.apics.push(ApicAffinity {
+                        domain:  x2apic.domain,
+                        apic_id: x2apic.x2apic_id,
+                        flags:   x2apic.flags,
+                    });
+                }
+                _ => {
+                    // Unknown type, just discard the data
+                    slice.discard(len as usize).map_err(|_| E)?;
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Get the memory affinity ranges
+    ///
+    /// # Returns
+    ///
+    /// A slice to the [`MemoryAffinity`] entries of the SRAT
+    ///
+    pub fn memory(&self) -> &[MemoryAffinity] {
+        &self.memory[..self.num_memory]
+    }
+
+    /// Get the processor affinities
+    ///
+    /// # Returns
+    ///
+    /// A slice to the enabled [`ApicAffinity`] entries of the SRAT
+    ///
+    pub fn apics(&self) -> &[ApicAffinity] {
+        &self.apics
+    }
+}
+
+/// An IOMMU remapping hardware unit, from either a DMAR DRHD or an IVRS IVHD
+#[derive(Default, Debug, Clone, Copy)]
+pub struct IommuUnit {
+    /// The PCI segment the unit is associated with
+    pub segment: u16,
+
+    /// Physical base address of the unit's register set
+    pub register_base: u64,
+
+    /// Flags of the unit as reported by the firmware. For DRHD units bit 0
+    /// indicates that all devices not otherwise listed are in scope.
+    pub flags: u8,
+}
+
+/// IOMMU information from the DMAR or IVRS
+#[derive(Debug)]
+pub struct Iommu {
+    /// DMA physical addressing capability of the platform (DMAR only)
+    pub host_address_width: u8,
+
+    /// Remapping hardware units
+    units: [IommuUnit; MAX_IOMMU_UNITS],
+
+    /// Number of remapping units which have been initialized in `units`
+    num_units: usize,
+
+    /// Memory regions which are in use by devices for DMA and which must
+    /// never be handed out as free memory (RMRR or IVMD)
+    reserved: [Range; MAX_IOMMU_RESERVED],
+
+    /// Number of reserved regions which have been initialized in `reserved`
+    num_reserved: usize,
+}
+
+impl Iommu {
+    /// Create an empty `Iommu`
+    ///
+    /// # Returns
+    ///
+    /// An [`Iommu`] without any units or reserved regions
+    ///
+    fn new() -> Self {
+        Self {
+            host_address_width: 0,
+            units:        [Default::default(); MAX_IOMMU_UNITS],
+            num_units:    0,
+            reserved:     [Range { start: 0, end: 0 }; MAX_IOMMU_RESERVED],
+            num_reserved: 0,
+        }
+    }
+
+    /// Record a remapping unit, ignoring a unit with the same register base
+    /// being reported again
+    ///
+    /// # Parameters
+    ///
+    /// * `unit` - The [`IommuUnit`] to record
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    fn add_unit(&mut self, unit: IommuUnit) -> Result<()> {
+        // IVRS reports the same IOMMU once for every IVHD type it supports
+        if self.units().iter().any(|x| x.register_base == unit.register_base) {
+            return Ok(());
+        }
+
+        *self.units.get_mut(self.num_units)
+            .ok_or(Error::TooManyIommuUnits)? = unit;
+        self.num_units += 1;
+        Ok(())
+    }
+
+    /// Record a reserved memory region
+    ///
+    /// # Parameters
+    ///
+    /// * `base` - The physical base address of the region
+    /// * `end`  - The physical address of the last byte of the region
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    fn add_reserved(&mut self, base: u64, end: u64) -> Result<()> {
+        *self.reserved.get_mut(self.num_reserved)
+            .ok_or(Error::TooManyIommuReserved)? = Range { start: base, end };
+        self.num_reserved += 1;
+        Ok(())
+    }
+
+    /// Parse the payload of an ACPI DMAR table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of a DMAR payload
+    /// * `size` - The size (in bytes) of the DMAR payload
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Iommu`], on error [`Error`]
+    /// 
+    unsafe fn from_dmar(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the DMAR is truncated
+        const E: Error = Error::LengthMismatch(TableType::Dmar);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Create an empty `Iommu`
+        let mut ret = Self::new();
+
+        // Get the host address width, it is reported as N - 1
+        ret.host_address_width =
+            slice.consume::<u8>().map_err(|_| E)?.wrapping_add(1);
+
+        // Flags and reserved (11 bytes), do not care
+        slice.discard(11).map_err(|_| E)?;
+
+        // Handle Remapping Structures
+        while slice.len() > 0 {
+            // Read the remapping structure header
+            let typ = slice.consume::<u16>().map_err(|_| E)?;
+            let len = slice.consume::<u16>().map_err(|_| E)?
+                .checked_sub(4).ok_or(E)? as usize;
+
+            match typ {
+                0 => {
+                    // DMA Remapping Hardware Unit Definition
+                    let flags = slice.consume::<u8>().map_err(|_| E)?;
+                    let _size = slice.consume::<u8>().map_err(|_| E)?;
+                    let segment = slice.consume::<u16>().map_err(|_| E)?;
+                    let register_base = slice.consume::<u64>().map_err(|_| E)?;
+
+                    // Device scopes, do not care
+                    slice.discard(len.checked_sub(12).ok_or(E)?)
+                        .map_err(|_| E)?;
+
+                    ret.add_unit(IommuUnit { segment, register_base, flags })?;
+                }
+                1 => {
+                    // Reserved Memory Region Reporting
+                    let _reserved = slice.consume::<u16>().map_err(|_| E)?;
+                    let _segment  = slice.consume::<u16>().map_err(|_| E)?;
+                    let base      = slice.consume::<u64>().map_err(|_| E)?;
+                    let limit     = slice.consume::<u64>().map_err(|_| E)?;
+
+                    // Device scopes, do not care
+                    slice.discard(len.checked_sub(20).ok_or(E)?)
+                        .map_err(|_| E)?;
+
+                    if limit >= base {
+                        ret.add_reserved(base, limit)?;
+                    }
+                }
+                _ => {
+                    // Unknown type, just discard the data
+                    slice.discard(len).map_err(|_| E)?;
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Parse the payload of an ACPI IVRS table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of an IVRS payload
+    /// * `size` - The size (in bytes) of the IVRS payload
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Iommu`], on error [`Error`]
+    /// 
+    unsafe fn from_ivrs(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the IVRS is truncated
+        const E: Error = Error::LengthMismatch(TableType::Ivrs);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Create an empty `Iommu`
+        let mut ret = Self::new();
+
+        // IVinfo and reserved (12 bytes), do not care
+        slice.discard(12).map_err(|_| E)?;
+
+        // Handle I/O Virtualization Definition Blocks
+        while slice.len() > 0 {
+            // Read the definition block header
+            let typ   = slice.consume::<u8>().map_err(|_| E)?;
+            let flags = slice.consume::<u8>().map_err(|_| E)?;
+            let len   = slice.consume::<u16>().map_err(|_| E)?
+                .checked_sub(4).ok_or(E)? as usize;
+
+            match typ {
+                0x10 | 0x11 | 0x40 => {
+                    // I/O Virtualization Hardware Definition
+                    let _device_id  = slice.consume::<u16>().map_err(|_| E)?;
+                    let _cap_offset = slice.consume::<u16>().map_err(|_| E)?;
+                    let register_base = slice.consume::<u64>().map_err(|_| E)?;
+                    let segment     = slice.consume::<u16>().map_err(|_| E)?;
+
+                    // IOMMU info, feature reporting and device entries, do
+                    // not care
+                    slice.discard(len.checked_sub(14).ok_or(E)?)
+                        .map_err(|_| E)?;
+
+                    ret.add_unit(IommuUnit { segment, register_base, flags })?;
+                }
+                0x20 | 0x21 | 0x22 => {
+                    // I/O Virtualization Memory Definition
+                    let _device_id = slice.consume::<u16>().map_err(|_| E)?;
+                    let _aux_data  = slice.consume::<u16>().map_err(|_| E)?;
+                    let _reserved  = slice.consume::<u64>().map_err(|_| E)?;
+                    let base       = slice.consume::<u64>().map_err(|_| E)?;
+                    let length     = slice.consume::<u64>().map_err(|_| E)?;
+
+                    // Anything else, do not care
+                    slice.discard(len.checked_sub(28).ok_or(E)?)
+                        .map_err(|_| E)?;
+
+                    if length > 0 {
+                        let end = PhysAddr(base).checked_add(length - 1)
+                            .map_err(|_| Error::IntegerOverflow)?;
+                        ret.add_reserved(base, end.0)?;
+                    }
+                }
+                _ => {
+                    // Unknown type, just discard the data
+                    slice.discard(len).map_err(|_| E)?;
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Get the remapping hardware units
+    ///
+    /// # Returns
+    ///
+    /// A slice to the [`IommuUnit`]s of the platform
+    ///
+    pub fn units(&self) -> &[IommuUnit] {
+        &self.units[..self.num_units]
+    }
+
+    /// Get the memory regions reserved for device DMA
+    ///
+    /// # Returns
+    ///
+    /// A slice to the [`Range`]s which must not be used as general purpose
+    /// memory
+    ///
+    pub fn reserved(&self) -> &[Range] {
+        &self.reserved[..self.num_reserved]
+    }
+}
+
+/// Types of memory devices in the PMTT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryDeviceType {
+    /// A processor socket
+    Socket,
+
+    /// A memory controller
+    MemoryController,
+
+    /// A DIMM
+    Dimm,
+
+    /// A vendor specific device
+    VendorSpecific,
+
+    /// A reserved device type
+    Unknown(u8),
+}
+
+impl From<u8> for MemoryDeviceType {
+    fn from(val: u8) -> Self {
+        match val {
+               0 => Self::Socket,
+               1 => Self::MemoryController,
+               2 => Self::Dimm,
+            0xff => Self::VendorSpecific,
+               _ => Self::Unknown(val),
+        }
+    }
+}
+
+/// A memory device from the PMTT
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryDevice {
+    /// The type of the memory device
+    pub typ: MemoryDeviceType,
+
+    /// Nesting depth of the device, top level devices have a depth of zero
+    /// and every device is contained in the closest preceding device with a
+    /// smaller depth
+    pub depth: u8,
+
+    /// Memory device flags
+    ///
+    /// Bit 0:    Top level aggregator device
+    /// Bit 1:    Physical element (as opposed to a logical element)
+    /// Bits 2-3: Volatile, non-volatile or both
+    pub flags: u16,
+
+    /// The socket ID, memory controller ID or DIMM SMBIOS handle of the
+    /// device. Zero for vendor specific devices.
+    pub id: u32,
+}
+
+/// The Platform Memory Topology Table
+#[derive(Debug)]
+pub struct Pmtt {
+    /// Memory devices in the order they are described by the PMTT
+    devices: [MemoryDevice; MAX_MEMORY_DEVICES],
+
+    /// Number of memory devices which have been initialized in `devices`
+    num_devices: usize,
+}
+
+impl Pmtt {
+    /// Parse the payload of an ACPI PMTT table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`     - The physical address of the start of a PMTT payload
+    /// * `size`     - The size (in bytes) of the PMTT payload
+    /// * `revision` - The revision of the PMTT from the table header
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Pmtt`], on error [`Error`]
+    /// 
+    unsafe fn from_addr(addr: PhysAddr, size: usize, revision: u8)
+            -> Result<Self> {
+        /// The error type to throw when the PMTT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Pmtt);
+
+        /// Size of the common memory device header
+        const HEADER_SIZE: usize = 12;
+
+        // The layout was reworked in revision 2 (ACPI 6.4)
+        if revision < 2 {
+            return Err(Error::UnsupportedRevision(TableType::Pmtt));
+        }
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Number of top level memory devices, do not care
+        slice.discard(4).map_err(|_| E)?;
+
+        // Create an empty `Pmtt`
+        let mut ret = Self {
+            devices: [MemoryDevice {
+                typ: MemoryDeviceType::Unknown(0), depth: 0, flags: 0, id: 0,
+            }; MAX_MEMORY_DEVICES],
+            num_devices: 0,
+        };
+
+        // Devices contain their child devices, keep track of the bytes
+        // remaining in each of the devices we are currently inside of
+        let mut remaining = [0usize; MAX_MEMORY_DEVICE_DEPTH];
+        remaining[0] = slice.len();
+        let mut depth = 0;
+
+        loop {
+            // Move back out of every device which has been completely parsed
+            if remaining[depth] == 0 {
+                if depth == 0 { break; }
+                depth -= 1;
+                continue;
+            }
+
+            // Read the memory device header
+            let typ: MemoryDeviceType =
+                slice.consume::<u8>().map_err(|_| E)?.into();
+            let _reserved = slice.consume::<u8>().map_err(|_| E)?;
+            let len       = slice.consume::<u16>().map_err(|_| E)? as usize;
+            let flags     = slice.consume::<u16>().map_err(|_| E)?;
+            let _reserved = slice.consume::<u16>().map_err(|_| E)?;
+            let _children = slice.consume::<u32>().map_err(|_| E)?;
+
+            // Account for the entire device in the parent
+            remaining[depth] = remaining[depth].checked_sub(len).ok_or(E)?;
+
+            // Get the type specific data
+            let (id, data_size) = match typ {
+                MemoryDeviceType::Socket | MemoryDeviceType::MemoryController
+                        => {
+                    let id = slice.consume::<u16>().map_err(|_| E)?;
+                    let _reserved = slice.consume::<u16>().map_err(|_| E)?;
+                    (id as u32, 4)
+                }
+                MemoryDeviceType::Dimm => {
+                    (slice.consume::<u32>().map_err(|_| E)?, 4)
+                }
+                _ => (0, 0),
+            };
+
+            // Record the device
+            *ret.devices.get_mut(ret.num_devices)
+                .ok_or(Error::TooManyMemoryDevices)? = MemoryDevice {
+                typ, depth: depth as u8, flags, id,
+            };
+            ret.num_devices += 1;
+
+            // The rest of the device is made up of child devices, except for
+            // vendor specific and unknown devices, which we cannot parse
+            let rest = len.checked_sub(HEADER_SIZE + data_size).ok_or(E)?;
+            if data_size == 0 {
+                slice.discard(rest).map_err(|_| E)?;
+            } else if rest > 0 {
+                depth += 1;
+                *remaining.get_mut(depth)
+                    .ok_or(Error::MemoryDevicesTooDeep)? = rest;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Get the memory devices
+    ///
+    /// # Returns
+    ///
+    /// A slice to the [`MemoryDevice`]s in the order they are described by
+    /// the PMTT
+    ///
+    pub fn devices(&self) -> &[MemoryDevice] {
+        &self.devices[..self.num_devices]
+    }
+}
+
+/// A copy of the FADT saved during [`init`] so that [`reset`] can be used
+/// from places which do not have access to the parsed [`Acpi`], such as the
+/// panic handler
+static mut FADT: Option<Fadt> = None;
+
+/// The Fixed ACPI Description Table
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// Fixed feature flags
+    pub flags: u32,
+
+    /// The register used to reset the system, `None` if the platform does
+    /// not support the ACPI reset mechanism
+    pub reset_reg: Option<Gas>,
+
+    /// The value to write to `reset_reg` to reset the system
+    pub reset_value: u8,
+
+    /// The power management timer counter register, `None` if the platform
+    /// does not have a PM timer
+    pub pm_timer: Option<Gas>,
+
+    /// ARM boot architecture flags, zero on other architectures and on
+    /// tables older than ACPI 5.1
+    pub arm_boot_flags: u16,
+}
+
+impl Fadt {
+    /// Set in `flags` if the PM timer counter is 32 bits wide instead of 24
+    const TMR_VAL_EXT: u32 = 1 << 8;
+
+    /// Set in `flags` if the `reset_reg` is supported
+    const RESET_REG_SUP: u32 = 1 << 10;
+
+    /// Set in `arm_boot_flags` if PSCI is implemented
+    const PSCI_COMPLIANT: u16 = 1 << 0;
+
+    /// Set in `arm_boot_flags` if PSCI is called with HVC instead of SMC
+    const PSCI_USE_HVC: u16 = 1 << 1;
+
+    /// Check how PSCI is called, if it is implemented
+    ///
+    /// # Returns
+    ///
+    /// `Some(true)` if PSCI is called with HVC, `Some(false)` if it is called
+    /// with SMC, `None` if the firmware does not implement PSCI
+    ///
+    pub fn psci_use_hvc(&self) -> Option<bool> {
+        (self.arm_boot_flags & Self::PSCI_COMPLIANT != 0)
+            .then_some(self.arm_boot_flags & Self::PSCI_USE_HVC != 0)
+    }
+
+    /// Parse the payload of an ACPI FADT table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of an FADT payload
+    /// * `size` - The size (in bytes) of the FADT payload
+    /// 
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Fadt`], on error [`Error`]
+    /// 
+    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the FADT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Fadt);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        // Everything from the FIRMWARE_CTRL up until the PM2 control block,
+        // do not care
+        slice.discard(40).map_err(|_| E)?;
+
+        // Get the legacy I/O port of the PM timer
+        let pm_tmr_blk = slice.consume::<u32>().map_err(|_| E)?;
+
+        // GPE blocks and PM1/PM2 block lengths, do not care
+        slice.discard(11).map_err(|_| E)?;
+
+        // Get the length of the PM timer block
+        let pm_tmr_len = slice.consume::<u8>().map_err(|_| E)?;
+
+        // Everything up until the IA-PC boot architecture flags, do not care
+        slice.discard(20).map_err(|_| E)?;
+
+        // Get the fixed feature flags
+        let flags = slice.consume::<u32>().map_err(|_| E)?;
+
+        // The reset register was added in ACPI 2.0, old FADTs may end here
+        let mut reset_reg   = None;
+        let mut reset_value = 0;
+        if slice.len() >= 13 {
+            let reg: Gas = slice.consume::<[u8; 12]>().map_err(|_| E)?.into();
+            let val      = slice.consume::<u8>().map_err(|_| E)?;
+
+            if flags & Self::RESET_REG_SUP != 0 {
+                reset_reg   = Some(reg);
+                reset_value = val;
+            }
+        }
+
+        // The legacy PM timer is a 4 byte I/O port block
+        let mut pm_timer = (pm_tmr_len == 4 && pm_tmr_blk != 0).then_some(
+            Gas::Io {
+                addr:            IoAddr(pm_tmr_blk as u64),
+                register_width:  32,
+                register_offset: 0,
+                access_size:     AccessSize::Dword,
+            });
+
+        // The ARM boot architecture flags follow, reserved before ACPI 5.1
+        let arm_boot_flags = slice.consume::<u16>().unwrap_or(0);
+
+        // FADT minor version, the 64-bit FIRMWARE_CTRL and DSDT and the
+        // extended PM1/PM2 blocks, do not care
+        if slice.discard(77).is_ok() && slice.len() >= 12 {
+            // The extended PM timer block takes priority if it is present
+            let mut reg: Gas =
+                slice.consume::<[u8; 12]>().map_err(|_| E)?.into();
+
+            match &mut reg {
+                Gas::Io { addr, access_size, .. } if addr.0 != 0 => {
+                    // Legacy tables leave the access size undefined
+                    if let AccessSize::Undefined = access_size {
+                        *access_size = AccessSize::Dword;
+                    }
+                    pm_timer = Some(reg);
+                }
+                Gas::Memory { addr, access_size, .. } if !addr.is_null() => {
+                    // Legacy tables leave the access size undefined
+                    if let AccessSize::Undefined = access_size {
+                        *access_size = AccessSize::Dword;
+                    }
+                    pm_timer = Some(reg);
+                }
+                _ => {}
+            }
+        }
+
+        // Return out the FADT info
+        Ok(Self {
+            flags:          flags,
+            reset_reg:      reset_reg,
+            reset_value:    reset_value,
+            pm_timer:       pm_timer,
+            arm_boot_flags: arm_boot_flags,
+        })
+    }
+}
+
+/// Frequency of the ACPI PM timer in Hz
+const PM_TIMER_FREQ: u64 = 3_579_545;
+
+/// Read the current value of the PM timer
+///
+/// # Returns
+///
+/// A tuple containing the current counter value and a mask of the valid bits
+/// of the counter, on error [`Error`]
+///
+unsafe fn pm_timer_read() -> Result<(u64, u64)> {
+    let fadt  = FADT.ok_or(Error::NoPmTimer)?;
+    let timer = fadt.pm_timer.ok_or(Error::NoPmTimer)?;
+
+    // The timer is either 24 or 32 bits wide
+    let mask = if fadt.flags & Fadt::TMR_VAL_EXT != 0 {
+        0xffff_ffff
+    } else {
+        0x00ff_ffff
+    };
+
+    Ok((timer.read(0).map_err(Error::GasError)? & mask, mask))
+}
+
+/// Busy-wait for `us` microseconds using the ACPI PM timer
+///
+/// # Parameters
+///
+/// * `us` - The number of microseconds to wait
+///
+/// # Returns
+///
+/// `()` after at least `us` microseconds have passed, [`Error`] if there is
+/// no usable PM timer
+///
+pub fn pm_delay_us(us: u64) -> Result<()> {
+    unsafe {
+        // Compute the number of timer ticks to wait for
+        let ticks = us.checked_mul(PM_TIMER_FREQ)
+            .ok_or(Error::IntegerOverflow)? / 1_000_000;
+
+        // Accumulate elapsed ticks, taking into account that the counter
+        // wraps around
+        let (mut prev, mask) = pm_timer_read()?;
+        let mut elapsed = 0u64;
+        while elapsed < ticks {
+            let (now, _) = pm_timer_read()?;
+            elapsed = elapsed.saturating_add(now.wrapping_sub(prev) & mask);
+            prev    = now;
+            core::hint::spin_loop();
+        }
+    }
+
+    Ok(())
+}
+
+/// Reset the system
+///
+/// The reset register reported by the FADT is tried first. On x86 the
+/// keyboard controller and the `0xcf9` reset control register are then used
+/// as fallbacks for firmware which does not report a usable reset register.
+///
+/// # Returns
+///
+/// This function never returns. If none of the reset methods worked the CPU
+/// is parked in a spin loop.
+///
+pub fn reset() -> ! {
+    unsafe {
+        // Try the firmware-blessed reset path
+        if let Some(Fadt { reset_reg: Some(reg), reset_value, .. }) = FADT {
+            if reg.write(0, reset_value as u64).is_ok() {
+                // Give the reset some time to take effect
+                for _ in 0..10_000_000 { core::hint::spin_loop(); }
+            }
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            /// Create a byte-sized [`Gas`] for the I/O port `port`
+            fn io_port(port: u64) -> Gas {
+                Gas::Io {
+                    addr:            IoAddr(port),
+                    register_width:  8,
+                    register_offset: 0,
+                    access_size:     AccessSize::Byte,
+                }
+            }
+
+            // Pulse the reset line via the keyboard controller, waiting for
+            // its input buffer to be empty first
+            let kbc = io_port(0x64);
+            for _ in 0..100_000 {
+                if kbc.read(0).map(|x| x & 2 == 0).unwrap_or(true) {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+            let _ = kbc.write(0, 0xfe);
+            for _ in 0..10_000_000 { core::hint::spin_loop(); }
+
+            // Request a hard reset via the reset control register
+            let cf9 = io_port(0xcf9);
+            let _ = cf9.write(0, 0x02);
+            let _ = cf9.write(0, 0x0e);
+            for _ in 0..10_000_000 { core::hint::spin_loop(); }
+        }
+    }
+
+    // Nothing worked, loop forever
+    loop { core::hint::spin_loop(); }
+}
+
+/// Identification of the ACPI implementation of the platform
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiInfo {
+    /// OEM ID from the RSDP
+    pub oem_id: [u8; 6],
+
+    /// OEM table ID from the XSDT
+    pub oem_table_id: [u8; 8],
+
+    /// Revision of the RSDP
+    pub rsdp_revision: u8,
+
+    /// Revision of the XSDT
+    pub xsdt_revision: u8,
+}
+
+impl AcpiInfo {
+    /// Get the OEM ID as a string
+    ///
+    /// # Returns
+    ///
+    /// The OEM ID with trailing padding removed, or `"?"` if it is not valid
+    /// UTF-8
+    ///
+    pub fn oem_id_str(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).map(|x| x.trim_end())
+            .unwrap_or("?")
+    }
+
+    /// Get the OEM table ID as a string
+    ///
+    /// # Returns
+    ///
+    /// The OEM table ID with trailing padding removed, or `"?"` if it is not
+    /// valid UTF-8
+    ///
+    pub fn oem_table_id_str(&self) -> &str {
+        core::str::from_utf8(&self.oem_table_id).map(|x| x.trim_end())
+            .unwrap_or("?")
+    }
+}
+
+/// An error which occurred while parsing one of the tables in the XSDT
+#[derive(Debug)]
+pub struct TableError {
+    /// Physical address of the table which failed to parse
+    pub addr: PhysAddr,
+
+    /// The error which occurred
+    pub error: Error,
+}
+
+/// The Boot Graphics Resource Table, describing the logo the firmware drew
+#[derive(Debug)]
+pub struct Bgrt {
+    /// Physical address of the BMP image of the logo
+    pub image: PhysAddr,
+
+    /// Column of the top left corner of the logo on the screen
+    pub x: u32,
+
+    /// Row of the top left corner of the logo on the screen
+    pub y: u32,
+
+    /// Set if the logo is currently displayed on the screen
+    pub displayed: bool,
+}
+
+impl Bgrt {
+    /// Parse the payload of an ACPI BGRT table
     ///
     /// # Parameters
     ///
-    /// * `addr` - The physical address of the start of an SPCR payload
-    /// * `size` - The size (in bytes) of the SPCR payload
-    /// 
+    /// * `addr` - The physical address of the start of a BGRT payload
+    /// * `size` - The size (in bytes) of the BGRT payload
+    ///
     /// # Returns
     ///
-    /// A parsed representation of the [`Spcr`], on error [`Error`]
-    /// 
+    /// A parsed representation of the [`Bgrt`], on error [`Error`]
+    ///
     unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
-        /// The error type to throw when the SPCR is truncated
-        const E: Error = Error::LengthMismatch(TableType::Spcr);
+        /// The error type to throw when the BGRT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Bgrt);
 
         // Create a slice to the physical memory
         let mut slice = PhysSlice::new(addr, size);
 
-        // Get the serial interface type
-        let typ: Interface =
-            slice.consume::<u8>().map_err(|_| E)?.into();
+        // Version, do not care
+        slice.discard(2).map_err(|_| E)?;
 
-        // Reserved (3 bytes)
-        slice.discard(3).map_err(|_| E)?;
+        // Get the status and the image type, which must be a BMP
+        let status     = slice.consume::<u8>().map_err(|_| E)?;
+        let image_type = slice.consume::<u8>().map_err(|_| E)?;
+        if image_type != 0 {
+            return Err(Error::UnsupportedImageType(image_type));
+        }
 
-        // The generic address structure
-        let info: Gas = slice.consume::<[u8; 12]>().map_err(|_| E)?.into();
+        Ok(Self {
+            image:     PhysAddr(slice.consume::<u64>().map_err(|_| E)?),
+            x:         slice.consume::<u32>().map_err(|_| E)?,
+            y:         slice.consume::<u32>().map_err(|_| E)?,
+            displayed: status & 1 != 0,
+        })
+    }
 
-        // Interrupt types, do not care
-        slice.discard(1).map_err(|_| E)?;
+    /// Get the raw BMP image of the logo. The image is in boot services
+    /// memory, so it is gone after exiting boot services.
+    ///
+    /// # Returns
+    ///
+    /// The BMP file contents, with the size taken from the BMP file header
+    ///
+    /// # Safety
+    ///
+    /// The BGRT must point to a valid image and boot services must be running
+    ///
+    pub unsafe fn image(&self) -> &'static [u8] {
+        // The file size is in the BMP file header
+        let base = self.image.0 as usize as *const u8;
+        let header = core::slice::from_raw_parts(base, 6);
+        let size = u32::from_le_bytes([header[2], header[3], header[4],
+                                       header[5]]);
+        core::slice::from_raw_parts(base, size.max(6) as usize)
+    }
+}
 
-        // IRQ, do not care
-        slice.discard(1).map_err(|_| E)?;
+/// The High Precision Event Timer Table, locating the HPET registers
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    /// Physical address of the HPET register block
+    pub base: PhysAddr,
+
+    /// Sequence number of the HPET block
+    pub number: u8,
+
+    /// Minimum number of counter ticks a periodic timer can be set to
+    pub min_tick: u16,
+}
+
+impl Hpet {
+    /// Parse the payload of an ACPI HPET table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of an HPET payload
+    /// * `size` - The size (in bytes) of the HPET payload
+    ///
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Hpet`], on error [`Error`]
+    ///
+    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the HPET is truncated
+        const E: Error = Error::LengthMismatch(TableType::Hpet);
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
 
-        // Global system interrupt vector, do not care
+        // Event timer block ID, do not care
         slice.discard(4).map_err(|_| E)?;
 
-        // Get the baud rate
-        let baud_rate = match slice.consume::<u8>().map_err(|_| E)? {
-            0 => BaudRate::AsIs,
-            3 => BaudRate::Baud9600,
-            4 => BaudRate::Baud19200,
-            6 => BaudRate::Baud57600,
-            7 => BaudRate::Baud115200,
-            _ => return Err(Error::InvalidBaudRate),
-        };
+        // The register block must be memory mapped
+        let space = slice.consume::<u8>().map_err(|_| E)?;
+        if space != 0 {
+            return Err(Error::UnsupportedHpetAddressSpace(space));
+        }
 
-        // Get parity and stop bit information
-        let parity_bits = slice.consume::<u8>().map_err(|_| E)?;
-        let stop_bits   = slice.consume::<u8>().map_err(|_| E)?;
+        // Register width, offset and access size, do not care
+        slice.discard(3).map_err(|_| E)?;
 
-        // Currently SPCR spec only allows for no parity and one stop bit
-        if parity_bits != 0 { return Err(Error::InvalidParityBits) };
-        if stop_bits   != 1 { return Err(Error::InvalidStopBits)   };
+        Ok(Self {
+            base:     PhysAddr(slice.consume::<u64>().map_err(|_| E)?),
+            number:   slice.consume::<u8>().map_err(|_| E)?,
+            min_tick: slice.consume::<u16>().map_err(|_| E)?,
+        })
+    }
+}
+
+/// The interrupt of one of the per-processor generic timers
+#[derive(Debug, Clone, Copy)]
+pub struct GtdtTimer {
+    /// The GSIV of the timer interrupt, zero if the timer is not provided
+    pub gsiv: u32,
+
+    /// Interrupt flags
+    ///
+    /// Bit 0: Set if the interrupt is edge triggered, clear if level
+    /// Bit 1: Set if the interrupt is active low, clear if active high
+    /// Bit 2: Set if the timer keeps running in all power states
+    pub flags: u32,
+}
+
+/// The Generic Timer Description Table, describing the ARM generic timers
+#[derive(Debug, Clone, Copy)]
+pub struct Gtdt {
+    /// Physical address of the CNTControlBase frame of the system counter,
+    /// `None` if it is not provided
+    pub cnt_control_base: Option<PhysAddr>,
+
+    /// The secure EL1 timer
+    pub secure_el1: GtdtTimer,
+
+    /// The non-secure EL1 physical timer
+    pub non_secure_el1: GtdtTimer,
+
+    /// The EL1 virtual timer
+    pub virtual_el1: GtdtTimer,
+
+    /// The EL2 physical timer
+    pub el2: GtdtTimer,
+
+    /// Physical address of the CNTReadBase frame of the system counter,
+    /// `None` if it is not provided
+    pub cnt_read_base: Option<PhysAddr>,
+}
+
+impl Gtdt {
+    /// Parse the payload of an ACPI GTDT table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the start of a GTDT payload
+    /// * `size` - The size (in bytes) of the GTDT payload
+    ///
+    /// # Returns
+    ///
+    /// A parsed representation of the [`Gtdt`], on error [`Error`]
+    ///
+    unsafe fn from_addr(addr: PhysAddr, size: usize) -> Result<Self> {
+        /// The error type to throw when the GTDT is truncated
+        const E: Error = Error::LengthMismatch(TableType::Gtdt);
+
+        /// Frames which are not provided have all bits set
+        fn frame(addr: u64) -> Option<PhysAddr> {
+            (addr != !0 && addr != 0).then_some(PhysAddr(addr))
+        }
+
+        // Create a slice to the physical memory
+        let mut slice = PhysSlice::new(addr, size);
+
+        let cnt_control_base = frame(slice.consume::<u64>().map_err(|_| E)?);
+
+        // Reserved
+        slice.discard(4).map_err(|_| E)?;
+
+        let mut timer = || -> Result<GtdtTimer> {
+            Ok(GtdtTimer {
+                gsiv:  slice.consume::<u32>().map_err(|_| E)?,
+                flags: slice.consume::<u32>().map_err(|_| E)?,
+            })
+        };
+        let secure_el1     = timer()?;
+        let non_secure_el1 = timer()?;
+        let virtual_el1    = timer()?;
+        let el2            = timer()?;
 
-        // Return out the serial port info
         Ok(Self {
-            interface_type: typ,
-            address:        info,
-            baud_rate:      baud_rate,
+            cnt_control_base,
+            secure_el1,
+            non_secure_el1,
+            virtual_el1,
+            el2,
+            cnt_read_base: frame(slice.consume::<u64>().map_err(|_| E)?),
         })
     }
 }
@@ -548,28 +2609,325 @@ impl Spcr {
 /// Information parsed out of ACPI
 #[derive(Debug)]
 pub struct Acpi {
+    /// Physical address of the RSDP
+    pub rsdp: PhysAddr,
+
+    /// Identification of the ACPI implementation
+    pub info: AcpiInfo,
+
     /// Contains information about the APICs from the MADT
     pub madt: Option<Madt>,
 
     /// Contains information from ACPI data structures about the serial device
     pub spcr: Option<Spcr>,
+
+    /// Contains the fixed hardware information from the FADT
+    pub fadt: Option<Fadt>,
+
+    /// Contains the NUMA information from the SRAT
+    pub srat: Option<Srat>,
+
+    /// Contains the IOMMU information from the DMAR or IVRS
+    pub iommu: Option<Iommu>,
+
+    /// Contains the memory topology from the PMTT
+    pub pmtt: Option<Pmtt>,
+
+    /// Contains the firmware boot logo from the BGRT
+    pub bgrt: Option<Bgrt>,
+
+    /// Contains the location of the event timer block from the HPET
+    pub hpet: Option<Hpet>,
+
+    /// Contains the ARM generic timer information from the GTDT
+    pub gtdt: Option<Gtdt>,
+
+    /// Memory backing the RSDP, the XSDT and the tables listed in it
+    regions: RangeSet,
+
+    /// Errors of tables which were skipped because they were malformed
+    errors: [Option<TableError>; MAX_TABLE_ERRORS],
+
+    /// Number of errors which did not fit in `errors`
+    dropped_errors: usize,
+}
+
+impl Acpi {
+    /// Apply workarounds for known firmware bugs to the parsed information
+    fn apply_quirks(&mut self) {
+        // Some firmware leaves the access size of 16550 I/O ports undefined,
+        // we know that for these the access size should always be byte
+        if UNDEFINED_UART_ACCESS_OEMS.contains(&&self.info.oem_id) {
+            if let Some(Spcr {
+                interface_type: Interface::Serial16550,
+                address: Gas::Io {
+                    access_size: access_size @ AccessSize::Undefined, ..
+                }, ..
+            }) = &mut self.spcr {
+                *access_size = AccessSize::Byte;
+            }
+        }
+    }
+
+    /// Record an error for a table which has been skipped
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the table which failed to parse
+    /// * `error` - The error which occurred when parsing the table
+    ///
+    fn record_error(&mut self, addr: PhysAddr, error: Error) {
+        if let Some(ent) = self.errors.iter_mut().find(|x| x.is_none()) {
+            *ent = Some(TableError { addr, error });
+        } else {
+            self.dropped_errors += 1;
+        }
+    }
+
+    /// Record memory which backs a table
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the table
+    /// * `size` - The size (in bytes) of the table
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    fn record_region(&mut self, addr: PhysAddr, size: usize) -> Result<()> {
+        if size == 0 { return Ok(()); }
+
+        let end = addr.checked_add(size as u64 - 1)
+            .map_err(|_| Error::IntegerOverflow)?;
+        self.regions.insert(Range { start: addr.0, end: end.0 })
+            .map_err(Error::Regions)
+    }
+
+    /// Get the memory backing the ACPI tables, which must stay intact for
+    /// the kernel to parse them again
+    ///
+    /// # Returns
+    ///
+    /// The [`RangeSet`] covering the RSDP, the XSDT and every table listed
+    /// in it
+    ///
+    pub fn regions(&self) -> &RangeSet {
+        &self.regions
+    }
+
+    /// Get the errors of the tables which were skipped during [`init`]
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the recorded [`TableError`]s
+    ///
+    pub fn errors(&self) -> impl Iterator<Item = &TableError> {
+        self.errors.iter().flatten()
+    }
+
+    /// Get the number of table errors which did not fit in the error list
+    ///
+    /// # Returns
+    ///
+    /// The number of table errors which occurred but were not recorded
+    ///
+    pub fn dropped_errors(&self) -> usize {
+        self.dropped_errors
+    }
+
+    /// Convert the parsed ACPI information into the stable representation
+    /// which is handed over to the kernel
+    ///
+    /// # Returns
+    ///
+    /// The [`boot_info::Acpi`] representation of `self`
+    ///
+    pub fn boot_info(&self) -> boot_info::Acpi {
+        /// Convert MADT APIC information into the boot info representation
+        fn apic(uid: u32, apic_id: u32, flags: u32) -> boot_info::Apic {
+            boot_info::Apic { acpi_processor_uid: uid, apic_id, flags }
+        }
+
+        /// Get the number of processor entries which fit the boot info, the
+        /// first ones are kept and the rest are dropped with a warning
+        fn fitting(what: &str, count: usize) -> u32 {
+            if count > MAX_CORES {
+                log_warn!("ACPI: only {} of {} {} entries fit the boot info\n",
+                    MAX_CORES, count, what);
+            }
+            count.min(MAX_CORES) as u32
+        }
+
+        // Get the MADT information
+        let mut madt = boot_info::Madt {
+            present:     0,
+            num_apics:   0,
+            apics:       [Default::default(); MAX_CORES],
+            num_x2apics: 0,
+            x2apics:     [Default::default(); MAX_CORES],
+        };
+        if let Some(parsed) = &self.madt {
+            madt.present     = 1;
+            madt.num_apics   = fitting("MADT APIC", parsed.apics.len());
+            madt.num_x2apics = fitting("MADT x2APIC", parsed.x2apics.len());
+            for (ent, x) in madt.apics.iter_mut().zip(parsed.apics.iter()) {
+                *ent = apic(x.acpi_processor_uid as u32, x.apic_id as u32,
+                            x.flags);
+            }
+            for (ent, x) in madt.x2apics.iter_mut()
+                    .zip(parsed.x2apics.iter()) {
+                *ent = apic(x.acpi_processor_uid, x.x2apic_id, x.flags);
+            }
+        }
+
+        // Get the SPCR information
+        let mut spcr = boot_info::Spcr {
+            present:        0,
+            interface_type: 0,
+            address:        [0; 12],
+            baud_rate:      0,
+        };
+        if let Some(parsed) = &self.spcr {
+            spcr.present        = 1;
+            spcr.interface_type = u8::from(parsed.interface_type) as u32;
+            spcr.address        = parsed.address.into();
+            spcr.baud_rate      = parsed.baud_rate.bps().unwrap_or(0);
+        }
+
+        // Get the SRAT information
+        let mut srat = boot_info::Srat {
+            present:    0,
+            num_memory: 0,
+            memory:     [Default::default(); MAX_MEMORY_AFFINITIES],
+            num_apics:  0,
+            apics:      [Default::default(); MAX_CORES],
+        };
+        if let Some(parsed) = &self.srat {
+            srat.present    = 1;
+            srat.num_memory = parsed.num_memory as u32;
+            srat.num_apics  = fitting("SRAT processor", parsed.apics.len());
+            for (ent, x) in srat.memory.iter_mut().zip(parsed.memory()) {
+                *ent = boot_info::MemoryAffinity {
+                    domain: x.domain,
+                    flags:  x.flags,
+                    base:   x.base,
+                    length: x.length,
+                };
+            }
+            for (ent, x) in srat.apics.iter_mut().zip(parsed.apics()) {
+                *ent = boot_info::ApicAffinity {
+                    domain:  x.domain,
+                    apic_id: x.apic_id,
+                    flags:   x.flags,
+                };
+            }
+        }
+
+        boot_info::Acpi {
+            rsdp: self.rsdp.0,
+            madt,
+            spcr,
+            srat,
+        }
+    }
+}
+
+/// Parse a table referenced by the XSDT into `acpi`
+///
+/// # Parameters
+///
+/// * `acpi`   - The [`Acpi`] to update with the parsed table information
+/// * `addr`   - The physical address of the table
+/// * `policy` - The [`ValidationPolicy`] to validate the table with
+///
+/// # Returns
+///
+/// `()` if the table was parsed or has an unknown type, on error [`Error`]
+///
+unsafe fn parse_table(acpi: &mut Acpi, addr: PhysAddr,
+                      policy: ValidationPolicy) -> Result<()> {
+    // Parse and validate the table header
+    let (table, typ, data, len) = Table::from_addr(addr, policy)?;
+
+    match typ {
+        TableType::Madt => {
+            acpi.madt = Some(Madt::from_addr(data, len)?);
+        }
+
+        TableType::Spcr => {
+            acpi.spcr = Some(Spcr::from_addr(data, len)?);
+        }
+
+        TableType::Fadt => {
+            let fadt  = Fadt::from_addr(data, len)?;
+            FADT      = Some(fadt);
+            acpi.fadt = Some(fadt);
+        }
+
+        TableType::Srat => {
+            acpi.srat = Some(Srat::from_addr(data, len)?);
+        }
+
+        TableType::Dmar => {
+            acpi.iommu = Some(Iommu::from_dmar(data, len)?);
+        }
+
+        TableType::Ivrs => {
+            acpi.iommu = Some(Iommu::from_ivrs(data, len)?);
+        }
+
+        TableType::Pmtt => {
+            acpi.pmtt = Some(Pmtt::from_addr(data, len, table.revision)?);
+        }
+
+        TableType::Bgrt => {
+            acpi.bgrt = Some(Bgrt::from_addr(data, len)?);
+        }
+
+        TableType::Hpet => {
+            acpi.hpet = Some(Hpet::from_addr(data, len)?);
+        }
+
+        TableType::Gtdt => {
+            acpi.gtdt = Some(Gtdt::from_addr(data, len)?);
+        }
+
+        // Unknown 
+        _ => {}
+    }
+
+    Ok(())
 }
 
 /// Initialize the ACPI subsystem
 ///
+/// # Parameters
+///
+/// * `root_policy`  - The [`ValidationPolicy`] to validate the RSDP and the
+///                    XSDT with
+/// * `table_policy` - The [`ValidationPolicy`] to validate the tables listed
+///                    in the XSDT with
+///
 /// # Returns
 ///
 /// Parsed [`Acpi`] information on success, on error [`Error`]
 ///
-pub unsafe fn init() -> Result<Acpi> {
+pub unsafe fn init(root_policy: ValidationPolicy,
+                   table_policy: ValidationPolicy) -> Result<Acpi> {
+    /// An empty error slot, used as `TableError` is not `Copy`
+    const NO_ERROR: Option<TableError> = None;
+
     // Get the ACPI table base from the EFI
     let rsdp_addr = efi::get_acpi_table().map_err(Error::EfiError)?;
     
     // Validate and get the RSDP
-    let rsdp = RsdpExtended::from_addr(PhysAddr(rsdp_addr as u64))?;
+    let rsdp = RsdpExtended::from_addr(PhysAddr(rsdp_addr as u64),
+                                       root_policy)?;
     
     // Get the XSDT
-    let (_, typ, xsdt, len) = Table::from_addr(PhysAddr(rsdp.xsdt_addr))?;
+    let (xsdt_table, typ, xsdt, len) =
+        Table::from_addr(PhysAddr(rsdp.xsdt_addr), root_policy)?;
     if typ != TableType::Xsdt {
         return Err(Error::SignatureMismatch(TableType::Xsdt));
     }
@@ -581,41 +2939,75 @@ pub unsafe fn init() -> Result<Acpi> {
 
     // Get the number of entries in the XSDT
     let entries = len / size_of::<u64>();
+    if entries > MAX_XSDT_ENTRIES {
+        return Err(Error::XsdtTooManyEntries);
+    }
+
+    // Addresses of the tables which have been seen so far
+    let mut seen = [0u64; MAX_XSDT_ENTRIES];
 
     // Parsed ACPI information
     let mut ret = Acpi {
+        rsdp: PhysAddr(rsdp_addr as u64),
+        info: AcpiInfo {
+            oem_id:        rsdp.base.oem_id,
+            oem_table_id:  xsdt_table.oem_table_id.to_le_bytes(),
+            rsdp_revision: rsdp.base.revision,
+            xsdt_revision: xsdt_table.revision,
+        },
         madt: None,
         spcr: None,
+        fadt: None,
+        srat: None,
+        iommu: None,
+        pmtt: None,
+        bgrt: None,
+        hpet: None,
+        gtdt: None,
+        regions: RangeSet::new(),
+        errors: [NO_ERROR; MAX_TABLE_ERRORS],
+        dropped_errors: 0,
     };
 
+    // Remember the memory of the RSDP and the XSDT
+    ret.record_region(ret.rsdp,
+        core::cmp::max({ rsdp.length } as usize, size_of::<RsdpExtended>()))?;
+    ret.record_region(PhysAddr(rsdp.xsdt_addr), xsdt_table.length as usize)?;
+
     // Go through each table in the XSDT
     for idx in 0..entries {
         // Get the physical address of the XSDT entry
-        let entry_addr = idx.checked_mul(size_of::<u64>()).and_then(|x| {
-            x.checked_add(xsdt.0 as usize)
-        }).ok_or(Error::IntegerOverflow)?;
+        let entry_addr = idx.checked_mul(size_of::<u64>())
+            .ok_or(Error::IntegerOverflow)
+            .and_then(|x| {
+                xsdt.checked_add(x as u64).map_err(|_| Error::IntegerOverflow)
+            })?;
 
         // Get the table address by reading the XSDT entry. It has been
         // observed in some versions of OVMF that these addresses can
         // sometimes be unaligned.
-        let table_addr = PhysAddr(entry_addr as u64).read_unaligned::<u64>();
-
-        // Parse and validate the table header
-        let (_, typ, data, len) = Table::from_addr(PhysAddr(table_addr))?;
+        let table_addr = entry_addr.read_unaligned::<u64>();
 
-        match typ {
-            TableType::Madt => {
-                ret.madt = Some(Madt::from_addr(data, len)?);
-            }
+        // Skip null entries and tables which were already listed
+        if table_addr == 0 || seen[..idx].contains(&table_addr) {
+            continue;
+        }
+        seen[idx] = table_addr;
 
-            TableType::Spcr => {
-                ret.spcr = Some(Spcr::from_addr(data, len)?);
-            }
+        // Remember the memory of the table, even if it fails to parse
+        let header = PhysAddr(table_addr).read_unaligned::<Table>();
+        ret.record_region(PhysAddr(table_addr), header.length as usize)?;
 
-            // Unknown 
-            _ => {}
+        // Parse the table. A malformed table is recorded and skipped rather
+        // than failing the entire initialization.
+        if let Err(error) =
+                parse_table(&mut ret, PhysAddr(table_addr), table_policy) {
+            ret.record_error(PhysAddr(table_addr), error);
         }
     }
     
+    // Fix up anything the firmware is known to get wrong
+    ret.apply_quirks();
+
     Ok(ret)
 }
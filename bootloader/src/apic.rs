@@ -0,0 +1,300 @@
+//! The local APIC of x86_64 processors, used to find out which processor we
+//! are running on and to send interprocessor interrupts
+//!
+//! Both the memory mapped xAPIC interface and the MSR based x2APIC interface
+//! are supported, as firmware on machines with many processors may already
+//! have switched to x2APIC mode, in which the memory mapped registers are
+//! gone.
+
+use crate::acpi::Madt;
+use crate::mm::virtmem::phys_to_virt;
+
+/// A `Result` type which wraps a local APIC error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the local APIC
+#[derive(Debug)]
+pub enum Error {
+    /// The processor has no local APIC
+    NotPresent,
+
+    /// A vector below 16 was given for a fixed interrupt, these are reserved
+    /// and rejected by the local APIC
+    InvalidVector(u8),
+
+    /// The local APIC did not finish sending a previous interrupt
+    DeliveryTimeout,
+}
+
+/// The `IA32_APIC_BASE` MSR
+const IA32_APIC_BASE: u32 = 0x1b;
+
+/// `IA32_APIC_BASE` bit which enables the local APIC
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// `IA32_APIC_BASE` bit which is set in x2APIC mode
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+/// Size (in bytes) of the memory mapped registers
+pub const MMIO_SIZE: u64 = 4096;
+
+/// Register offset of the local APIC ID
+const REG_ID: usize = 0x20;
+
+/// Register offset of the spurious interrupt vector register
+const REG_SVR: usize = 0xf0;
+
+/// Register offset of the low half of the interrupt command register
+const REG_ICR_LOW: usize = 0x300;
+
+/// Register offset of the high half of the interrupt command register, only
+/// used in xAPIC mode
+const REG_ICR_HIGH: usize = 0x310;
+
+/// Spurious interrupt vector register bit which software enables the APIC
+const SVR_ENABLE: u32 = 1 << 8;
+
+/// The vector spurious interrupts are delivered to
+const SPURIOUS_VECTOR: u32 = 0xff;
+
+/// Interrupt command register bit which is set while an interrupt is still
+/// being sent, only used in xAPIC mode
+const ICR_PENDING: u32 = 1 << 12;
+
+/// Interrupt command register bit for an asserting level
+const ICR_ASSERT: u32 = 1 << 14;
+
+/// Number of times to poll for a previous interrupt to be sent
+const DELIVERY_POLLS: usize = 1_000_000;
+
+/// An interprocessor interrupt
+#[derive(Clone, Copy, Debug)]
+pub enum Ipi {
+    /// Put the processor back into its wait-for-SIPI state
+    Init,
+
+    /// Start a processor waiting for a SIPI in real mode, at the 4 KiB page
+    /// with this number
+    Startup(u8),
+
+    /// A fixed interrupt with this vector
+    Fixed(u8),
+}
+
+/// Where to send an interprocessor interrupt
+#[derive(Clone, Copy, Debug)]
+pub enum Destination {
+    /// The processor with this APIC ID (or x2APIC ID)
+    Apic(u32),
+
+    /// Every processor but ourselves
+    AllExcludingSelf,
+}
+
+/// The local APIC of the processor we are running on
+pub struct LocalApic {
+    /// The memory mapped registers, or `None` in x2APIC mode
+    mmio: Option<*mut u8>,
+}
+
+impl LocalApic {
+    /// Enable the local APIC of this processor
+    ///
+    /// # Parameters
+    ///
+    /// * `madt` - The MADT, which gives the address of the registers
+    ///
+    /// # Returns
+    ///
+    /// The enabled [`LocalApic`], on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be programming the local APIC of this processor.
+    ///
+    pub unsafe fn init(madt: &Madt) -> Result<Self> {
+        use core::arch::x86_64::__cpuid;
+
+        // The local APIC is reported in CPUID.01H:EDX[9]
+        if __cpuid(1).edx & (1 << 9) == 0 { return Err(Error::NotPresent); }
+
+        // Hardware enable the local APIC, the firmware normally has already
+        let mut base = rdmsr(IA32_APIC_BASE);
+        if base & APIC_BASE_ENABLE == 0 {
+            base |= APIC_BASE_ENABLE;
+            wrmsr(IA32_APIC_BASE, base);
+        }
+
+        let mmio = if base & APIC_BASE_X2APIC != 0 {
+            None
+        } else {
+            Some(phys_to_virt(madt.local_apic_addr()).as_mut_ptr::<u8>())
+        };
+
+        // Software enable it, with spurious interrupts going to the last
+        // vector
+        let apic = LocalApic { mmio };
+        let svr  = apic.read(REG_SVR);
+        apic.write(REG_SVR, (svr & !0xff) | SVR_ENABLE | SPURIOUS_VECTOR);
+
+        Ok(apic)
+    }
+
+    /// Check if the local APIC is in x2APIC mode
+    pub fn is_x2apic(&self) -> bool {
+        self.mmio.is_none()
+    }
+
+    /// Get the APIC ID of this processor
+    ///
+    /// # Returns
+    ///
+    /// The x2APIC ID in x2APIC mode, or the 8-bit APIC ID otherwise
+    ///
+    pub fn id(&self) -> u32 {
+        let id = unsafe { self.read(REG_ID) };
+        if self.is_x2apic() { id } else { id >> 24 }
+    }
+
+    /// Send an interprocessor interrupt
+    ///
+    /// # Parameters
+    ///
+    /// * `dest` - The processors to send the interrupt to
+    /// * `ipi`  - The interrupt to send
+    ///
+    /// # Returns
+    ///
+    /// `()` once the interrupt has been sent, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// The processors must be ready for the interrupt, an INIT or SIPI
+    /// restarts them.
+    ///
+    pub unsafe fn send_ipi(&self, dest: Destination, ipi: Ipi) -> Result<()> {
+        // The delivery mode is in bits 8 to 10, the vector in bits 0 to 7
+        let mut icr = match ipi {
+            Ipi::Init          => (0b101 << 8) | ICR_ASSERT,
+            Ipi::Startup(page) => (0b110 << 8) | ICR_ASSERT | page as u32,
+            Ipi::Fixed(vec)    => {
+                if vec < 16 { return Err(Error::InvalidVector(vec)); }
+                ICR_ASSERT | vec as u32
+            }
+        };
+        let target = match dest {
+            Destination::Apic(id) => id,
+            Destination::AllExcludingSelf => {
+                // The destination shorthand is in bits 18 and 19
+                icr |= 0b11 << 18;
+                0
+            }
+        };
+
+        match self.mmio {
+            // A single write sends the interrupt in x2APIC mode
+            None => {
+                wrmsr(msr(REG_ICR_LOW), (target as u64) << 32 | icr as u64);
+            }
+            Some(_) => {
+                self.wait_for_delivery()?;
+                self.write(REG_ICR_HIGH, target << 24);
+                self.write(REG_ICR_LOW, icr);
+                self.wait_for_delivery()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the interrupt being sent in xAPIC mode to be sent
+    ///
+    /// # Returns
+    ///
+    /// `()` once no interrupt is being sent, on error [`Error`]
+    ///
+    unsafe fn wait_for_delivery(&self) -> Result<()> {
+        for _ in 0..DELIVERY_POLLS {
+            if self.read(REG_ICR_LOW) & ICR_PENDING == 0 { return Ok(()); }
+            core::hint::spin_loop();
+        }
+
+        Err(Error::DeliveryTimeout)
+    }
+
+    /// Read a local APIC register
+    ///
+    /// # Parameters
+    ///
+    /// * `reg` - The offset of the register in the memory mapped registers
+    ///
+    /// # Returns
+    ///
+    /// The value of the register
+    ///
+    unsafe fn read(&self, reg: usize) -> u32 {
+        match self.mmio {
+            Some(mmio) => core::ptr::read_volatile(mmio.add(reg) as *const u32),
+            None       => rdmsr(msr(reg)) as u32,
+        }
+    }
+
+    /// Write a local APIC register
+    ///
+    /// # Parameters
+    ///
+    /// * `reg` - The offset of the register in the memory mapped registers
+    /// * `val` - The value to write
+    ///
+    unsafe fn write(&self, reg: usize, val: u32) {
+        match self.mmio {
+            Some(mmio) => {
+                core::ptr::write_volatile(mmio.add(reg) as *mut u32, val);
+            }
+            None => wrmsr(msr(reg), val as u64),
+        }
+    }
+}
+
+/// Get the x2APIC MSR of a local APIC register
+///
+/// # Parameters
+///
+/// * `reg` - The offset of the register in the memory mapped registers
+///
+/// # Returns
+///
+/// The MSR number of the register
+///
+fn msr(reg: usize) -> u32 {
+    0x800 + (reg >> 4) as u32
+}
+
+/// Read an MSR
+///
+/// # Parameters
+///
+/// * `msr` - The MSR to read
+///
+/// # Returns
+///
+/// The value of the MSR
+///
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi,
+        options(nomem, nostack, preserves_flags));
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Write an MSR
+///
+/// # Parameters
+///
+/// * `msr` - The MSR to write
+/// * `val` - The value to write
+///
+unsafe fn wrmsr(msr: u32, val: u64) {
+    asm!("wrmsr", in("ecx") msr, in("eax") val as u32,
+        in("edx") (val >> 32) as u32, options(nostack, preserves_flags));
+}
@@ -0,0 +1,70 @@
+//! Stack backtraces, by walking the chain of frame pointers. Every target is
+//! built with frame pointers forced on, so every function links its frame
+//! into the chain.
+
+/// Maximum number of frames which are walked
+const MAX_FRAMES: usize = 32;
+
+/// Largest distance (in bytes) between two frames which is still believed,
+/// a frame pointer which jumps further is most likely garbage
+const MAX_FRAME_SIZE: u64 = 1024 * 1024;
+
+/// An iterator over the return addresses of the frames on the stack, from the
+/// innermost frame outwards
+pub struct Backtrace {
+    /// The frame pointer of the next frame, zero once the walk is over
+    fp: u64,
+
+    /// Number of frames walked so far
+    frames: usize,
+}
+
+impl Backtrace {
+    /// Start a backtrace at a frame
+    ///
+    /// # Parameters
+    ///
+    /// * `fp` - The frame pointer of the innermost frame
+    ///
+    /// # Returns
+    ///
+    /// The [`Backtrace`]
+    ///
+    /// # Safety
+    ///
+    /// `fp` must be a frame pointer of the current stack, and the stack must
+    /// stay untouched while the backtrace is walked.
+    ///
+    pub unsafe fn new(fp: u64) -> Self {
+        Backtrace { fp, frames: 0 }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        // Stop on a frame pointer which can't be one
+        let fp = self.fp;
+        if fp < 16 || fp % 8 != 0 || self.frames >= MAX_FRAMES { return None; }
+
+        // x86_64 and aarch64 point at the saved frame pointer, followed by
+        // the return address. riscv64 points just above both of them, the
+        // other way around.
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        let (next, ret) = unsafe {
+            (*(fp as *const u64), *((fp + 8) as *const u64))
+        };
+        #[cfg(target_arch = "riscv64")]
+        let (next, ret) = unsafe {
+            (*((fp - 16) as *const u64), *((fp - 8) as *const u64))
+        };
+
+        // The stack grows down, so the frames of the callers are above
+        let sane = next > fp && next - fp <= MAX_FRAME_SIZE;
+        self.fp  = if sane { next } else { 0 };
+        self.frames += 1;
+
+        (ret != 0).then_some(ret)
+    }
+}
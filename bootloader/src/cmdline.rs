@@ -0,0 +1,420 @@
+//! The kernel command line, taken from the EFI load options
+//!
+//! The command line is a whitespace separated list of `key=value` or bare
+//! `key` options. Options we recognize also configure the bootloader itself:
+//!
+//! * `console=efi` - Do not use a serial port, only the EFI console
+//! * `console=uart,io,<port>[,<baud>]` - Use a 16550 at an I/O port
+//! * `console=uart,mmio,<addr>[,<baud>]` - Use a 16550 with byte registers
+//! * `console=uart,mmio32,<addr>[,<baud>]` - Use a 16550 with dword registers
+//! * `console=pl011,<addr>[,<baud>]` - Use an ARM PL011
+//!
+//! Any of these take precedence over the serial port reported by the SPCR.
+//!
+//! * `mpprobe` - Run a probe on every application processor before boot
+//! * `nosmp` - Leave the application processors (or secondary cores) alone,
+//!   rather than starting them and parking them for the kernel
+//! * `kernel=<source>` - Where to load the kernel from
+//! * `initrd=<source>` - Where to load an initial RAM disk from
+//! * `memtest[=quick|full]` - Test free memory and never use faulty pages,
+//!   the full test also catches faulty address lines
+//! * `kaslr=off` - Load the kernel at the lowest addresses it fits at, rather
+//!   than random ones
+//! * `loglevel=<error|warn|info|debug>` - Only print messages at least as
+//!   severe as this, `debug` also dumps the ACPI tables
+//! * `panic=hang|reset[,<seconds>]|exit[,<seconds>]` - What to do after a
+//!   panic: spin forever, reset the system or exit to the firmware. The
+//!   default is to reset after 5 seconds.
+//! * `logformat=pretty|kv` - Print log messages for people, or as one
+//!   `ts=.. level=.. target=.. msg=".."` record each for a test harness
+//! * `color=on|off` - Whether warnings and errors are printed in color, by
+//!   default they are unless the SPCR reports a terminal without colors
+//! * `mmstats` - Print the page allocator counters before entering the kernel
+//! * `memselftest` - Check `memcpy`, `memmove`, `memset` and `memcmp` against
+//!   reference loops early on, and panic if they disagree
+//! * `splash[=<path>]` - Show a splash screen with the firmware logo, or a
+//!   BMP on the file system we were loaded from
+//! * `textmode=<columns>x<rows>` - Switch the EFI console to this text mode
+//!   rather than the largest one
+//!
+//! A source is either a path on the file system we were loaded from, or a
+//! `tftp://[<server>]/<path>` URL. Without a server the boot server reported
+//! by DHCP is used.
+
+use core::fmt;
+use generic_access_structure::{Gas, IoAddr, AccessSize};
+use serial::{BaudRate, Interface};
+
+/// Maximum length of the command line in bytes
+pub const MAX_CMDLINE: usize = boot_info::MAX_CMDLINE;
+
+/// A command line stored in a fixed size buffer
+#[derive(Clone, Copy)]
+pub struct CommandLine {
+    /// UTF-8 contents of the command line
+    buf: [u8; MAX_CMDLINE],
+
+    /// Number of in use bytes in `buf`
+    len: usize,
+}
+
+/// The console requested with the `console=` option
+#[derive(Clone, Copy, Debug)]
+pub enum Console {
+    /// Only use the EFI console
+    Firmware,
+
+    /// Use the specified serial port
+    Serial {
+        /// Type of the serial port register interface
+        interface: Interface,
+
+        /// Address to access the serial port
+        address: Gas,
+
+        /// Baud rate to use for the serial port
+        baud_rate: BaudRate,
+    },
+}
+
+/// What to do after a panic, as requested with the `panic=` option
+#[derive(Clone, Copy, Debug)]
+pub enum PanicAction {
+    /// Spin forever, leaving the panic on the screen
+    Hang,
+
+    /// Reset the system after this many seconds
+    Reset(u64),
+
+    /// Exit back to the firmware after this many seconds
+    Exit(u64),
+}
+
+/// Where to load a file from
+#[derive(Clone, Copy, Debug)]
+pub enum Source<'a> {
+    /// A path on the file system we were loaded from
+    File(&'a str),
+
+    /// A file on a TFTP server
+    Tftp {
+        /// IPv4 address of the server, `None` for the boot server reported by
+        /// DHCP
+        server: Option<[u8; 4]>,
+
+        /// Path of the file on the server
+        path: &'a str,
+    },
+}
+
+impl<'a> Source<'a> {
+    /// Parse a source from a path or a `tftp://[<server>]/<path>` URL
+    fn parse(val: &'a str) -> Option<Self> {
+        let url = match val.strip_prefix("tftp://") {
+            Some(url) => url,
+            None      => return (!val.is_empty()).then_some(Source::File(val)),
+        };
+
+        // Split the server from the path
+        let idx = url.find('/')?;
+        let (host, path) = (&url[..idx], &url[idx + 1..]);
+        if path.is_empty() { return None; }
+
+        // Parse the dotted IPv4 address
+        let server = if host.is_empty() {
+            None
+        } else {
+            let mut ip = [0u8; 4];
+            let mut octets = host.split('.');
+            for octet in ip.iter_mut() {
+                *octet = octets.next()?.parse().ok()?;
+            }
+            if octets.next().is_some() { return None; }
+            Some(ip)
+        };
+
+        Some(Source::Tftp { server, path })
+    }
+}
+
+impl fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path),
+            Source::Tftp { server: Some(ip), path } => {
+                write!(f, "tftp://{}.{}.{}.{}/{}", ip[0], ip[1], ip[2], ip[3],
+                       path)
+            }
+            Source::Tftp { server: None, path } => {
+                write!(f, "tftp:///{}", path)
+            }
+        }
+    }
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal integer
+fn parse_int(val: &str) -> Option<u64> {
+    if let Some(hex) = val.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        val.parse().ok()
+    }
+}
+
+impl CommandLine {
+    /// Create a new empty command line
+    pub const fn new() -> Self {
+        Self { buf: [0; MAX_CMDLINE], len: 0 }
+    }
+
+    /// Create a command line from EFI load options
+    ///
+    /// # Parameters
+    ///
+    /// * `options` - The raw load options, which are expected to be a UCS-2
+    ///               string. When we are started from the EFI shell the first
+    ///               word is the path of our image, which is dropped.
+    ///
+    /// # Returns
+    ///
+    /// The command line, which is empty if the load options were not a
+    /// string. Characters which do not fit are dropped.
+    ///
+    pub fn from_load_options(options: &[u8]) -> Self {
+        let mut ret = Self::new();
+
+        // Decode the UCS-2 string up to the null terminator
+        let chars = options.chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .take_while(|&x| x != 0);
+        for chr in core::char::decode_utf16(chars) {
+            match chr {
+                Ok(chr) => { ret.push(chr); }
+
+                // Binary load options from a boot entry, not a command line
+                Err(_) => return Self::new(),
+            }
+        }
+
+        // Drop the image path the shell passes as the first argument
+        let cmdline = ret.as_str().trim();
+        let first = cmdline.split_whitespace().next().unwrap_or("");
+        // Compared as bytes, the last 4 bytes need not be whole characters
+        if first.len() >= 4 && first.as_bytes()[first.len() - 4..]
+                .eq_ignore_ascii_case(b".efi") {
+            let mut trimmed = Self::new();
+            for chr in cmdline[first.len()..].trim_start().chars() {
+                trimmed.push(chr);
+            }
+            return trimmed;
+        }
+
+        ret
+    }
+
+    /// Get the command line as a string
+    pub fn as_str(&self) -> &str {
+        // We only ever append whole characters
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    /// Append a character to the command line
+    ///
+    /// # Parameters
+    ///
+    /// * `chr` - The character to append
+    ///
+    /// # Returns
+    ///
+    /// `true` if the character was appended, `false` if there was no room
+    /// for it
+    ///
+    pub fn push(&mut self, chr: char) -> bool {
+        let len = chr.len_utf8();
+        if self.len + len > self.buf.len() { return false; }
+
+        chr.encode_utf8(&mut self.buf[self.len..]);
+        self.len += len;
+        true
+    }
+
+    /// Remove the last character of the command line
+    ///
+    /// # Returns
+    ///
+    /// The removed character, or `None` if the command line was empty
+    ///
+    pub fn pop(&mut self) -> Option<char> {
+        let chr = self.as_str().chars().next_back()?;
+        self.len -= chr.len_utf8();
+        Some(chr)
+    }
+
+    /// Iterate over the options of the command line
+    ///
+    /// # Returns
+    ///
+    /// An iterator of `(key, value)` pairs, `value` is `None` for bare keys
+    ///
+    pub fn options(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.as_str().split_whitespace().map(|opt| {
+            match opt.find('=') {
+                Some(idx) => (&opt[..idx], Some(&opt[idx + 1..])),
+                None      => (opt, None),
+            }
+        })
+    }
+
+    /// Get the value of an option, if an option is given multiple times the
+    /// last one wins
+    ///
+    /// # Parameters
+    ///
+    /// * `key` - The key of the option
+    ///
+    /// # Returns
+    ///
+    /// The value of the option, `Some("")` for a bare key, or `None` if the
+    /// option is not present
+    ///
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options().filter(|&(k, _)| k == key)
+            .map(|(_, v)| v.unwrap_or("")).last()
+    }
+
+    /// Get the console requested with the `console=` option
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no `console=` option, `Some(Err(value))` if the
+    /// option could not be parsed
+    ///
+    pub fn console(&self) -> Option<core::result::Result<Console, &str>> {
+        let val = self.get("console")?;
+        if val == "efi" { return Some(Ok(Console::Firmware)); }
+
+        // Split up the fields
+        let mut fields = val.split(',');
+        let kind = fields.next();
+        let mut parse = || -> Option<Console> {
+            let (interface, space) = match kind? {
+                "uart"  => (Interface::Serial16550, fields.next()?),
+                "pl011" => (Interface::ArmPL011, "mmio32"),
+                _       => return None,
+            };
+            let addr = parse_int(fields.next()?)?;
+            let baud_rate = match fields.next() {
+                Some(baud) => BaudRate::from(baud.parse::<u32>().ok()?),
+                None       => BaudRate::AsIs,
+            };
+
+            let address = match space {
+                "io" => Gas::Io {
+                    addr:            IoAddr(addr),
+                    register_width:  8,
+                    register_offset: 0,
+                    access_size:     AccessSize::Byte,
+                },
+                "mmio" => Gas::Memory {
+                    addr:            addr as *mut u8,
+                    register_width:  8,
+                    register_offset: 0,
+                    access_size:     AccessSize::Byte,
+                },
+                "mmio32" => Gas::Memory {
+                    addr:            addr as *mut u8,
+                    register_width:  32,
+                    register_offset: 0,
+                    access_size:     AccessSize::Dword,
+                },
+                _ => return None,
+            };
+
+            Some(Console::Serial { interface, address, baud_rate })
+        };
+
+        Some(parse().ok_or(val))
+    }
+
+    /// Get what to do after a panic, as given with the `panic=` option
+    ///
+    /// # Parameters
+    ///
+    /// * `delay` - Seconds to wait before a reset or exit which does not
+    ///             give its own delay
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no `panic=` option, `Some(Err(value))` if the
+    /// option could not be parsed
+    ///
+    pub fn panic_action(&self, delay: u64)
+            -> Option<core::result::Result<PanicAction, &str>> {
+        let val = self.get("panic")?;
+
+        let mut fields = val.splitn(2, ',');
+        let kind = fields.next();
+        let delay = match fields.next() {
+            Some(secs) => secs.parse().ok(),
+            None       => Some(delay),
+        };
+        let action = match (kind, delay) {
+            (Some("hang"), _) if val == "hang" => Some(PanicAction::Hang),
+            (Some("reset"), Some(delay)) => Some(PanicAction::Reset(delay)),
+            (Some("exit"),  Some(delay)) => Some(PanicAction::Exit(delay)),
+            _ => None,
+        };
+
+        Some(action.ok_or(val))
+    }
+
+    /// Get the EFI console text mode requested with the `textmode=` option
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no `textmode=` option, `Some(Err(value))` if the
+    /// option could not be parsed, otherwise the columns and rows
+    ///
+    pub fn text_mode(&self)
+            -> Option<core::result::Result<(usize, usize), &str>> {
+        let val = self.get("textmode")?;
+
+        let mut fields = val.splitn(2, 'x');
+        let columns = fields.next().and_then(|x| x.parse().ok());
+        let rows    = fields.next().and_then(|x| x.parse().ok());
+        Some(columns.zip(rows).ok_or(val))
+    }
+
+    /// Get where to load the kernel from, as given with the `kernel=` option
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no `kernel=` option, `Some(Err(value))` if the
+    /// option could not be parsed
+    ///
+    pub fn kernel(&self) -> Option<core::result::Result<Source, &str>> {
+        let val = self.get("kernel")?;
+        Some(Source::parse(val).ok_or(val))
+    }
+
+    /// Get where to load the initial RAM disk from, as given with the
+    /// `initrd=` option
+    ///
+    /// # Returns
+    ///
+    /// `None` if there is no `initrd=` option, `Some(Err(value))` if the
+    /// option could not be parsed
+    ///
+    pub fn initrd(&self) -> Option<core::result::Result<Source, &str>> {
+        let val = self.get("initrd")?;
+        Some(Source::parse(val).ok_or(val))
+    }
+
+    /// Convert the command line into the boot info representation
+    pub fn boot_info(&self) -> boot_info::CommandLine {
+        boot_info::CommandLine {
+            len:   self.len as u32,
+            bytes: self.buf,
+        }
+    }
+}
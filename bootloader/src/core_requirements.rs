@@ -1,9 +1,114 @@
 //! This holds the [`core`] basic requirements for things like libc routines
+//!
+//! Compiler intrinsics, such as the `__udivti3` family needed for `u128`
+//! arithmetic, come from `compiler_builtins` (see `.cargo/config.toml`).
 
 use core::mem::size_of;
 
+#[cfg(target_arch = "x86_64")]
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Copies of at least this many bytes are done with non-temporal stores on
+/// x86_64 processors without a fast `rep movsb`, so staging the kernel and
+/// initrd doesn't keep evicting the whole cache
+#[cfg(target_arch = "x86_64")]
+const NON_TEMPORAL_THRESHOLD: usize = 1024 * 1024;
+
+/// Whether `rep movsb` is fast for large copies, `0` until [`fast_movsb`]
+/// has checked, then `1` if it is and `2` if it isn't
+#[cfg(target_arch = "x86_64")]
+static FAST_MOVSB: AtomicU8 = AtomicU8::new(0);
+
+/// Check if the processor has a fast `rep movsb`, from the ERMS or FSRM
+/// CPUID bits
+///
+/// # Returns
+///
+/// `true` if `rep movsb` is at least as fast as any other way of copying
+///
+#[cfg(target_arch = "x86_64")]
+fn fast_movsb() -> bool {
+    match FAST_MOVSB.load(Ordering::Relaxed) {
+        0 => {
+            // ERMS is reported in CPUID.(EAX=07H,ECX=0):EBX[9] and FSRM in
+            // CPUID.(EAX=07H,ECX=0):EDX[4]
+            let fast = unsafe {
+                use core::arch::x86_64::{__cpuid, __cpuid_count};
+                __cpuid(0).eax >= 7 && {
+                    let leaf = __cpuid_count(7, 0);
+                    leaf.ebx & (1 << 9) != 0 || leaf.edx & (1 << 4) != 0
+                }
+            };
+
+            FAST_MOVSB.store(if fast { 1 } else { 2 }, Ordering::Relaxed);
+            fast
+        }
+        state => state == 1,
+    }
+}
+
+/// Copy memory with non-temporal stores, which go around the cache. General
+/// purpose registers are used with `movnti` as SSE registers are not
+/// available to the bootloader.
+///
+/// # Parameters
+///
+/// * `dest` - Pointer to memory to copy to
+/// * `src`  - Pointer to memory to copy from
+/// * `n`    - Number of bytes to copy
+///
+#[cfg(target_arch = "x86_64")]
+unsafe fn memcpy_non_temporal(dest: *mut u8, src: *const u8, n: usize) {
+    asm!(
+        // `u64`-align the dest with one byte copies
+        "2:",
+        "test  rdi, 7",
+        "jz    3f",
+        "test  rcx, rcx",
+        "jz    5f",
+        "mov   {a:l}, byte ptr [rsi]",
+        "mov   byte ptr [rdi], {a:l}",
+        "inc   rsi",
+        "inc   rdi",
+        "dec   rcx",
+        "jmp   2b",
+
+        // Copy 32 bytes at a time
+        "3:",
+        "cmp   rcx, 32",
+        "jb    4f",
+        "mov   {a}, qword ptr [rsi]",
+        "mov   {b}, qword ptr [rsi + 8]",
+        "movnti qword ptr [rdi], {a}",
+        "movnti qword ptr [rdi + 8], {b}",
+        "mov   {a}, qword ptr [rsi + 16]",
+        "mov   {b}, qword ptr [rsi + 24]",
+        "movnti qword ptr [rdi + 16], {a}",
+        "movnti qword ptr [rdi + 24], {b}",
+        "add   rsi, 32",
+        "add   rdi, 32",
+        "sub   rcx, 32",
+        "jmp   3b",
+
+        // Non-temporal stores are weakly ordered, so fence them before
+        // copying the remainder normally
+        "4:",
+        "sfence",
+        "rep movsb",
+        "5:",
+        inout("rdi") dest => _,
+        inout("rsi") src  => _,
+        inout("rcx") n    => _,
+        a = out(reg) _,
+        b = out(reg) _,
+        options(nostack));
+}
+
 /// libc `memcpy` implementation in Rust
 ///
+/// Large copies use non-temporal stores unless the processor has a fast
+/// `rep movsb`.
+///
 /// # Parameters
 ///
 /// * `dest` - Pointer to memory to copy to
@@ -17,6 +122,11 @@ use core::mem::size_of;
 #[no_mangle]
 #[cfg(target_arch = "x86_64")]
 unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    if n >= NON_TEMPORAL_THRESHOLD && !fast_movsb() {
+        memcpy_non_temporal(dest, src, n);
+        return dest;
+    }
+
     asm!("rep movsb",
         inout("rcx") n    => _,
         inout("rdi") dest => _,
@@ -28,6 +138,9 @@ unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
 
 /// libc `memcpy` implementation in Rust
 ///
+/// The dest is 16-byte aligned with one byte copies, so the bulk of the copy
+/// is done with `ldp`/`stp` pairs which only the loads may leave unaligned.
+///
 /// # Parameters
 ///
 /// * `dest` - Pointer to memory to copy to
@@ -39,7 +152,119 @@ unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
 /// Pointer to `dest`
 ///
 #[no_mangle]
-#[cfg(not(target_arch = "x86_64"))]
+#[cfg(target_arch = "aarch64")]
+unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    asm!(
+        // Align the dest with one byte copies
+        "2:",
+        "tst   {dest}, #15",
+        "b.eq  3f",
+        "cbz   {n}, 5f",
+        "ldrb  {a:w}, [{src}], #1",
+        "strb  {a:w}, [{dest}], #1",
+        "sub   {n}, {n}, #1",
+        "b     2b",
+
+        // Copy 16 bytes at a time
+        "3:",
+        "cmp   {n}, #16",
+        "b.lo  4f",
+        "ldp   {a}, {b}, [{src}], #16",
+        "stp   {a}, {b}, [{dest}], #16",
+        "sub   {n}, {n}, #16",
+        "b     3b",
+
+        // Copy the remainder
+        "4:",
+        "cbz   {n}, 5f",
+        "ldrb  {a:w}, [{src}], #1",
+        "strb  {a:w}, [{dest}], #1",
+        "sub   {n}, {n}, #1",
+        "b     4b",
+        "5:",
+        dest = inout(reg) dest => _,
+        src  = inout(reg) src  => _,
+        n    = inout(reg) n    => _,
+        a    = out(reg) _,
+        b    = out(reg) _,
+        options(nostack));
+
+    dest
+}
+
+/// libc `memcpy` implementation in Rust
+///
+/// Unaligned accesses may trap to the firmware to be emulated, so the copy is
+/// only done a `u64` at a time when `dest` and `src` can be aligned together.
+///
+/// # Parameters
+///
+/// * `dest` - Pointer to memory to copy to
+/// * `src`  - Pointer to memory to copy from
+/// * `n`    - Number of bytes to copy
+///
+/// # Returns
+///
+/// Pointer to `dest`
+///
+#[no_mangle]
+#[cfg(target_arch = "riscv64")]
+unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    let mut ii = 0;
+
+    if (dest as usize ^ src as usize) & (size_of::<u64>() - 1) == 0 {
+        // `u64`-align the dest with one byte copies
+        while ii < n && (dest as usize)
+                .wrapping_add(ii) & (size_of::<u64>() - 1) != 0 {
+            core::ptr::write(dest.add(ii), core::ptr::read(src.add(ii)));
+            ii += 1;
+        }
+
+        // Copy 4 `u64`s at a time, then a `u64` at a time
+        while n - ii >= 4 * size_of::<u64>() {
+            let dest = dest.add(ii) as *mut u64;
+            let src  = src.add(ii) as *const u64;
+            let a = core::ptr::read(src);
+            let b = core::ptr::read(src.add(1));
+            let c = core::ptr::read(src.add(2));
+            let d = core::ptr::read(src.add(3));
+            core::ptr::write(dest, a);
+            core::ptr::write(dest.add(1), b);
+            core::ptr::write(dest.add(2), c);
+            core::ptr::write(dest.add(3), d);
+            ii += 4 * size_of::<u64>();
+        }
+        while n - ii >= size_of::<u64>() {
+            core::ptr::write(dest.add(ii) as *mut u64,
+                core::ptr::read(src.add(ii) as *const u64));
+            ii += size_of::<u64>();
+        }
+    }
+
+    // Copy the remainder
+    while ii < n {
+        core::ptr::write(dest.add(ii), core::ptr::read(src.add(ii)));
+        ii += 1;
+    }
+
+    dest
+}
+
+/// libc `memcpy` implementation in Rust
+///
+/// # Parameters
+///
+/// * `dest` - Pointer to memory to copy to
+/// * `src`  - Pointer to memory to copy from
+/// * `n`    - Number of bytes to copy
+///
+/// # Returns
+///
+/// Pointer to `dest`
+///
+#[no_mangle]
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64",
+            target_arch = "riscv64")))]
 unsafe extern fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
     let mut ii = 0;
 
@@ -172,6 +397,9 @@ unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
 
 /// libc `memset` implementation in Rust
 ///
+/// Like `memcpy`, `s` is 16-byte aligned with one byte stores and the bulk
+/// is set with `stp` pairs of the byte spread over a whole register.
+///
 /// # Parameters
 ///
 /// * `s` - Pointer to memory to set
@@ -183,7 +411,113 @@ unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
 /// Original pointer to `s`
 ///
 #[no_mangle]
-#[cfg(not(target_arch = "x86_64"))]
+#[cfg(target_arch = "aarch64")]
+unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
+    asm!(
+        // Spread the byte over the whole register
+        "and   {c}, {c}, #0xff",
+        "orr   {c}, {c}, {c}, lsl #8",
+        "orr   {c}, {c}, {c}, lsl #16",
+        "orr   {c}, {c}, {c}, lsl #32",
+
+        // Align `s` with one byte stores
+        "2:",
+        "tst   {s}, #15",
+        "b.eq  3f",
+        "cbz   {n}, 5f",
+        "strb  {c:w}, [{s}], #1",
+        "sub   {n}, {n}, #1",
+        "b     2b",
+
+        // Set 16 bytes at a time
+        "3:",
+        "cmp   {n}, #16",
+        "b.lo  4f",
+        "stp   {c}, {c}, [{s}], #16",
+        "sub   {n}, {n}, #16",
+        "b     3b",
+
+        // Set the remainder
+        "4:",
+        "cbz   {n}, 5f",
+        "strb  {c:w}, [{s}], #1",
+        "sub   {n}, {n}, #1",
+        "b     4b",
+        "5:",
+        s = inout(reg) s        => _,
+        c = inout(reg) c as u64 => _,
+        n = inout(reg) n        => _,
+        options(nostack));
+
+    s
+}
+
+/// libc `memset` implementation in Rust
+///
+/// Like `memcpy`, `s` is `u64`-aligned with one byte stores so the bulk is
+/// set with aligned stores of the byte spread over a whole `u64`.
+///
+/// # Parameters
+///
+/// * `s` - Pointer to memory to set
+/// * `c` - Character to set bytes to
+/// * `n` - Number of bytes to set
+///
+/// # Returns
+///
+/// Original pointer to `s`
+///
+#[no_mangle]
+#[cfg(target_arch = "riscv64")]
+unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
+    let mut ii = 0;
+    let val = (c as u8 as u64).wrapping_mul(0x0101_0101_0101_0101);
+
+    // `u64`-align `s` with one byte stores
+    while ii < n && (s as usize)
+            .wrapping_add(ii) & (size_of::<u64>() - 1) != 0 {
+        core::ptr::write(s.add(ii), c as u8);
+        ii += 1;
+    }
+
+    // Set 4 `u64`s at a time, then a `u64` at a time
+    while n - ii >= 4 * size_of::<u64>() {
+        let s = s.add(ii) as *mut u64;
+        core::ptr::write(s, val);
+        core::ptr::write(s.add(1), val);
+        core::ptr::write(s.add(2), val);
+        core::ptr::write(s.add(3), val);
+        ii += 4 * size_of::<u64>();
+    }
+    while n - ii >= size_of::<u64>() {
+        core::ptr::write(s.add(ii) as *mut u64, val);
+        ii += size_of::<u64>();
+    }
+
+    // Set the remainder
+    while ii < n {
+        core::ptr::write(s.add(ii), c as u8);
+        ii += 1;
+    }
+
+    s
+}
+
+/// libc `memset` implementation in Rust
+///
+/// # Parameters
+///
+/// * `s` - Pointer to memory to set
+/// * `c` - Character to set bytes to
+/// * `n` - Number of bytes to set
+///
+/// # Returns
+///
+/// Original pointer to `s`
+///
+#[no_mangle]
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64",
+            target_arch = "riscv64")))]
 unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
     let mut ii = 0;
 
@@ -196,6 +530,57 @@ unsafe extern fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
     s
 }
 
+/// Fill memory with a 64-bit pattern
+///
+/// # Parameters
+///
+/// * `ptr`   - Pointer to the memory to fill
+/// * `val`   - Value to fill the memory with
+/// * `count` - Number of `u64`s to fill
+///
+/// # Returns
+///
+/// Original pointer to `ptr`
+///
+#[no_mangle]
+#[cfg(target_arch = "x86_64")]
+pub unsafe extern fn fill64(ptr: *mut u64, val: u64, count: usize)
+        -> *mut u64 {
+    asm!("rep stosq",
+         inout("rcx") count => _,
+         inout("rdi") ptr   => _,
+         in("rax")    val,
+         options(nostack));
+
+    ptr
+}
+
+/// Fill memory with a 64-bit pattern
+///
+/// # Parameters
+///
+/// * `ptr`   - Pointer to the memory to fill
+/// * `val`   - Value to fill the memory with
+/// * `count` - Number of `u64`s to fill
+///
+/// # Returns
+///
+/// Original pointer to `ptr`
+///
+#[no_mangle]
+#[cfg(not(target_arch = "x86_64"))]
+pub unsafe extern fn fill64(ptr: *mut u64, val: u64, count: usize)
+        -> *mut u64 {
+    let mut ii = 0;
+
+    while ii < count {
+        core::ptr::write(ptr.add(ii), val);
+        ii += 1;
+    }
+
+    ptr
+}
+
 /// libc `memcmp` implementation in Rust
 /// 
 /// # Parameters
@@ -213,6 +598,33 @@ unsafe extern fn memcmp(s1: *const u8, s2: *const u8, n: usize)
         -> i32 {
     let mut ii = 0;
 
+    // If both regions have the same alignment, compare them a `usize` at a
+    // time once aligned
+    if (s1 as usize ^ s2 as usize) & (size_of::<usize>() - 1) == 0 {
+        // `usize`-align the regions with one byte compares
+        while ii < n && (s1 as usize)
+                .wrapping_add(ii) & (size_of::<usize>() - 1) != 0 {
+            let a = core::ptr::read(s1.add(ii));
+            let b = core::ptr::read(s2.add(ii));
+            if a != b {
+                return (a as i32).wrapping_sub(b as i32);
+            }
+            ii = ii.wrapping_add(1);
+        }
+
+        // Stop at the first `usize` which differs, so the byte compares
+        // below find the unmatching byte within it
+        while n - ii >= size_of::<usize>() {
+            let a = core::ptr::read(s1.add(ii) as *const usize);
+            let b = core::ptr::read(s2.add(ii) as *const usize);
+            if a != b {
+                break;
+            }
+            ii = ii.wrapping_add(size_of::<usize>());
+        }
+    }
+
+    // Compare the remainder one byte at a time
     while ii < n {
         let a = core::ptr::read(s1.add(ii));
         let b = core::ptr::read(s2.add(ii));
@@ -224,3 +636,262 @@ unsafe extern fn memcmp(s1: *const u8, s2: *const u8, n: usize)
 
     0
 }
+
+/// libc `strlen` implementation in Rust
+///
+/// # Parameters
+///
+/// * `s` - Pointer to a null terminated string
+///
+/// # Returns
+///
+/// The number of bytes in `s` before the null terminator
+///
+#[no_mangle]
+pub unsafe extern fn strlen(s: *const u8) -> usize {
+    let mut ii = 0;
+
+    while core::ptr::read(s.add(ii)) != 0 {
+        ii = ii.wrapping_add(1);
+    }
+
+    ii
+}
+
+/// libc `strnlen` implementation in Rust
+///
+/// # Parameters
+///
+/// * `s`      - Pointer to a string which is null terminated or at least
+///              `maxlen` bytes long
+/// * `maxlen` - Maximum number of bytes to look at
+///
+/// # Returns
+///
+/// The number of bytes in `s` before the null terminator, or `maxlen` if
+/// there is none in the first `maxlen` bytes
+///
+#[no_mangle]
+pub unsafe extern fn strnlen(s: *const u8, maxlen: usize) -> usize {
+    let mut ii = 0;
+
+    while ii < maxlen && core::ptr::read(s.add(ii)) != 0 {
+        ii = ii.wrapping_add(1);
+    }
+
+    ii
+}
+
+/// `wcslen` for the UCS-2 strings EFI uses, where a character is a `u16`
+/// rather than libc's `wchar_t`
+///
+/// # Parameters
+///
+/// * `s` - Pointer to a null terminated UCS-2 string
+///
+/// # Returns
+///
+/// The number of characters in `s` before the null terminator
+///
+pub unsafe fn wcslen(s: *const u16) -> usize {
+    wcsnlen(s, usize::MAX)
+}
+
+/// `wcsnlen` for the UCS-2 strings EFI uses, where a character is a `u16`
+/// rather than libc's `wchar_t`
+///
+/// # Parameters
+///
+/// * `s`      - Pointer to a UCS-2 string which is null terminated or at
+///              least `maxlen` characters long
+/// * `maxlen` - Maximum number of characters to look at
+///
+/// # Returns
+///
+/// The number of characters in `s` before the null terminator, or `maxlen`
+/// if there is none in the first `maxlen` characters
+///
+pub unsafe fn wcsnlen(s: *const u16, maxlen: usize) -> usize {
+    let mut ii = 0;
+
+    while ii < maxlen && core::ptr::read_unaligned(s.add(ii)) != 0 {
+        ii = ii.wrapping_add(1);
+    }
+
+    ii
+}
+
+/// A case which failed in [`self_test`]
+#[derive(Debug)]
+pub struct Failure {
+    /// The routine which failed
+    pub routine: &'static str,
+
+    /// The offset of the destination, or of the first region compared
+    pub dest: usize,
+
+    /// The offset of the source, or of the second region compared
+    pub src: usize,
+
+    /// The number of bytes
+    pub len: usize,
+}
+
+/// Offsets and lengths tested by [`self_test`], chosen around the `u64` and
+/// 16-byte chunk sizes and the 64-byte `memmove` overhang cutoff
+const SELF_TEST_SIZES: &[usize] = &[
+    0, 1, 3, 7, 8, 9, 15, 16, 17, 31, 63, 64, 65, 72, 100, 130,
+];
+
+/// Size of the buffers used by [`self_test`]
+const SELF_TEST_BUF: usize = 256;
+
+/// Fill a buffer with a pattern with no repeats within it, using volatile
+/// writes so this can't turn into a call to the routines being tested
+///
+/// # Parameters
+///
+/// * `buf`  - The buffer to fill
+/// * `seed` - Makes the pattern differ between buffers
+///
+fn self_test_pattern(buf: &mut [u8; SELF_TEST_BUF], seed: u8) {
+    for (ii, byte) in buf.iter_mut().enumerate() {
+        unsafe {
+            core::ptr::write_volatile(byte,
+                (ii as u8).wrapping_mul(7).wrapping_add(seed) ^ 0x80);
+        }
+    }
+}
+
+/// Compare two buffers with volatile reads, so this can't turn into a call
+/// to the routines being tested
+///
+/// # Parameters
+///
+/// * `a`, `b` - The buffers to compare
+///
+/// # Returns
+///
+/// `true` if the buffers are identical
+///
+fn self_test_equal(a: &[u8; SELF_TEST_BUF], b: &[u8; SELF_TEST_BUF]) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| unsafe {
+        core::ptr::read_volatile(a) == core::ptr::read_volatile(b)
+    })
+}
+
+/// Check `memcpy`, `memmove`, `memset` and `memcmp` against simple reference
+/// loops, over unaligned and overlapping cases which the chunked paths have
+/// to get right
+///
+/// # Returns
+///
+/// `()` if every case passed, on error the first [`Failure`]
+///
+pub fn self_test() -> Result<(), Failure> {
+    let mut buf      = [0u8; SELF_TEST_BUF];
+    let mut src      = [0u8; SELF_TEST_BUF];
+    let mut expected = [0u8; SELF_TEST_BUF];
+
+    for &dest_off in SELF_TEST_SIZES {
+        for &src_off in SELF_TEST_SIZES {
+            for &len in SELF_TEST_SIZES {
+                if dest_off.max(src_off) + len > SELF_TEST_BUF { continue; }
+                let failure = |routine| Failure {
+                    routine, dest: dest_off, src: src_off, len,
+                };
+
+                // Copy between separate buffers
+                self_test_pattern(&mut src, 0x5a);
+                self_test_pattern(&mut buf, 0xa5);
+                self_test_pattern(&mut expected, 0xa5);
+                for ii in 0..len {
+                    unsafe {
+                        core::ptr::write_volatile(&mut expected[dest_off + ii],
+                            core::ptr::read_volatile(&src[src_off + ii]));
+                    }
+                }
+                unsafe {
+                    memcpy(buf.as_mut_ptr().add(dest_off),
+                           src.as_ptr().add(src_off), len);
+                }
+                if !self_test_equal(&buf, &expected) {
+                    return Err(failure("memcpy"));
+                }
+
+                // Move within one buffer, `src` holds a copy to move from
+                self_test_pattern(&mut buf, 0x5a);
+                self_test_pattern(&mut expected, 0x5a);
+                for ii in 0..len {
+                    unsafe {
+                        core::ptr::write_volatile(&mut expected[dest_off + ii],
+                            core::ptr::read_volatile(&src[src_off + ii]));
+                    }
+                }
+                unsafe {
+                    let ptr = buf.as_mut_ptr();
+                    memmove(ptr.add(dest_off), ptr.add(src_off), len);
+                }
+                if !self_test_equal(&buf, &expected) {
+                    return Err(failure("memmove"));
+                }
+            }
+        }
+
+        // Set with a value which only fits after truncating it to a byte
+        for &len in SELF_TEST_SIZES {
+            if dest_off + len > SELF_TEST_BUF { continue; }
+
+            self_test_pattern(&mut buf, 0xa5);
+            self_test_pattern(&mut expected, 0xa5);
+            for ii in 0..len {
+                unsafe {
+                    core::ptr::write_volatile(&mut expected[dest_off + ii],
+                                              0xc3);
+                }
+            }
+            unsafe { memset(buf.as_mut_ptr().add(dest_off), 0x1c3, len); }
+            if !self_test_equal(&buf, &expected) {
+                return Err(Failure {
+                    routine: "memset", dest: dest_off, src: 0, len,
+                });
+            }
+        }
+    }
+
+    // Compare regions which differ in their last byte, both ways round and
+    // with the high bit set in one of them so a signed compare would be
+    // caught out. `src` is all but identical to `buf`, at an offset.
+    for &off in SELF_TEST_SIZES {
+        for &len in SELF_TEST_SIZES {
+            if len == 0 || off + len > SELF_TEST_BUF { continue; }
+            let failure = Failure { routine: "memcmp", dest: 0, src: off, len };
+
+            self_test_pattern(&mut buf, 0x5a);
+            for ii in 0..len {
+                unsafe {
+                    core::ptr::write_volatile(&mut src[off + ii],
+                        core::ptr::read_volatile(&buf[ii]));
+                }
+            }
+
+            let (a, b) = (buf.as_ptr(), unsafe { src.as_ptr().add(off) });
+            if unsafe { memcmp(a, b, len) } != 0 { return Err(failure); }
+
+            for &(x, y) in &[(0x01u8, 0x02u8), (0x02, 0x01), (0x7f, 0x80),
+                             (0x80, 0x7f), (0x00, 0xff), (0xff, 0x00)] {
+                unsafe {
+                    core::ptr::write_volatile(&mut buf[len - 1], x);
+                    core::ptr::write_volatile(&mut src[off + len - 1], y);
+                }
+                let (a, b) = (buf.as_ptr(), unsafe { src.as_ptr().add(off) });
+                if unsafe { memcmp(a, b, len) } !=
+                        (x as i32).wrapping_sub(y as i32) {
+                    return Err(failure);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -8,9 +8,15 @@
 //! complex type as `usize`, if we don't actually have a use for this
 //! structure.
 
+pub mod device_path;
+
 use core::mem::size_of;
-use core::sync::atomic::{AtomicPtr, Ordering};
-use rangeset::{Range, RangeSet};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicPtr, AtomicBool, AtomicUsize, Ordering};
+use rangeset::{Range, TaggedRangeSet};
+use fbcon::{Framebuffer, PixelFormat};
+use device_path::{DevicePath, EfiDevicePathProtocol};
+use crate::core_requirements::wcslen;
 
 /// A `Result` type which wraps an EFI error
 type Result<T> = core::result::Result<T, Error>;
@@ -34,162 +40,2356 @@ pub enum Error {
     /// We failed to exit EFI boot services
     ExitBootServices(EfiStatus),
 
+    /// The memory map kept changing while trying to exit boot services
+    ExitBootServicesRetries,
+
     /// An integer overflow occurred when processing EFI memory map data
     MemoryMapIntegerOverflow,
 
     /// An error occured when trying to construct the memory map `RangeSet`
     MemoryRangeSet(rangeset::Error),
+
+    /// EFI did not report a graphics output protocol
+    GraphicsOutputNotFound(EfiStatus),
+
+    /// The graphics mode does not have a linear framebuffer with a pixel
+    /// format we understand
+    UnsupportedPixelFormat(u32),
+
+    /// We failed to get the loaded image protocol for our image
+    LoadedImage(EfiStatus),
+
+    /// We failed to open the file system we were loaded from
+    FileSystem(EfiStatus),
+
+    /// The file path does not fit in our UCS-2 path buffer
+    PathTooLong,
+
+    /// We failed to open the requested file
+    FileOpen(EfiStatus),
+
+    /// We failed to get the size of the requested file
+    FileInfo(EfiStatus),
+
+    /// We failed to read the requested file
+    FileRead(EfiStatus),
+
+    /// We failed to allocate pages from EFI
+    AllocatePages(EfiStatus),
+
+    /// The variable name does not fit in our UCS-2 name buffer
+    VariableNameTooLong,
+
+    /// We failed to read an EFI variable
+    GetVariable(EfiStatus),
+
+    /// We failed to write an EFI variable
+    SetVariable(EfiStatus),
+
+    /// We failed to query the variable storage
+    QueryVariableInfo(EfiStatus),
+
+    /// A persistent variable write of this many bytes was refused as it
+    /// would leave too little variable storage free
+    VariableStorageLow(usize),
+
+    /// We are the last entry in the boot order, or are not in it at all
+    NoNextBootOption,
+
+    /// The firmware refused to let us exit
+    Exit(EfiStatus),
+
+    /// We failed to set the watchdog timer
+    SetWatchdogTimer(EfiStatus),
+
+    /// We failed to stall the processor
+    Stall(EfiStatus),
+
+    /// We failed to read a key from the console
+    ReadKey(EfiStatus),
+
+    /// The console does not support the requested text mode
+    TextModeNotFound,
+
+    /// We failed to set the console text mode
+    SetTextMode(EfiStatus),
+
+    /// We failed to enumerate block devices
+    BlockDevices(EfiStatus),
+
+    /// A block device read failed
+    ReadBlocks(EfiStatus),
+
+    /// Neither the firmware nor the CPU could provide random numbers
+    NoEntropy,
+
+    /// The firmware does not provide the TCG2 protocol
+    NoTpm(EfiStatus),
+
+    /// We failed to measure data into the TPM
+    TpmMeasure(EfiStatus),
+
+    /// We failed to get the TPM event log
+    TpmEventLog(EfiStatus),
+
+    /// The TPM event log is malformed
+    BadTpmEventLog,
+
+    /// A block read buffer is not a multiple of the block size or does not
+    /// meet the alignment the device requires
+    BadBlockBuffer,
+
+    /// The block device reports a block size of zero
+    BadBlockSize,
+
+    /// We failed to get the current time
+    GetTime(EfiStatus),
+
+    /// The operation requires boot services to be exited first
+    BootServicesNotExited,
+
+    /// The memory map is missing descriptors which did not fit
+    MemoryMapTruncated,
+
+    /// We failed to switch the runtime services to virtual addressing
+    SetVirtualAddressMap(EfiStatus),
+
+    /// The firmware does not provide the MP services protocol
+    NoMpServices(EfiStatus),
+
+    /// We failed to get information about the processors
+    ProcessorInfo(EfiStatus),
+
+    /// We failed to run a procedure on the application processors
+    StartupAllAps(EfiStatus),
+
+    /// The device we were loaded from does not provide the PXE base code
+    /// protocol
+    NoNetwork(EfiStatus),
+
+    /// We failed to configure the network with DHCP
+    Dhcp(EfiStatus),
+
+    /// No TFTP server was given and DHCP did not report a boot server
+    NoTftpServer,
+
+    /// A TFTP transfer failed
+    Tftp(EfiStatus),
+
+    /// The firmware does not provide a serial I/O protocol
+    NoSerialIo(EfiStatus),
+
+    /// A firmware serial port read or write failed
+    SerialIo(EfiStatus),
+
+    /// The firmware failed to write to the console
+    OutputString(EfiStatus),
+
+    /// The firmware does not provide a usable timestamp protocol
+    NoTimestamp(EfiStatus),
+
+    /// The firmware failed to set the console colors
+    SetColor(EfiStatus),
+
+    /// The color can only be used as a foreground color
+    InvalidBackground(Color),
+
+    /// The firmware failed to clear the console
+    ClearScreen(EfiStatus),
+}
+
+/// A strongly typed EFI system table pointer which will disallow the copying
+/// of the raw pointer
+#[repr(transparent)]
+pub struct EfiSystemTablePtr(*mut EfiSystemTable);
+
+impl EfiSystemTablePtr {
+    /// Register this system table into a global so it can be used for prints
+    /// which do not take a `self` or a pointer as an argument and thus this
+    /// must be able to be found in a global
+    pub unsafe fn register(self) {
+        let _ = EFI_SYSTEM_TABLE.compare_exchange(
+            core::ptr::null_mut(), self.0, Ordering::SeqCst, Ordering::SeqCst);
+
+        // Runtime services outlive the system table, keep them separately
+        let _ = EFI_RUNTIME_SERVICES.compare_exchange(
+            core::ptr::null_mut(), (*self.0).runtime_services as *mut _,
+            Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+
+impl EfiHandle {
+    /// Register this handle as the handle of our own loaded image, which is
+    /// needed to find the device we were loaded from
+    pub unsafe fn register_image(self) {
+        let _ = EFI_IMAGE_HANDLE.compare_exchange(
+            0, self.0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+/// The handle of our loaded image which is saved upon the entry of the
+/// kernel
+static EFI_IMAGE_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+/// A pointer to the EFI system table which is saved upon the entry of the
+/// kernel.
+///
+/// We'll need access to this table to do input and output to the console
+///
+static EFI_SYSTEM_TABLE: AtomicPtr<EfiSystemTable> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// A pointer to the EFI runtime services table which is saved upon the entry
+/// of the kernel. Unlike the system table this stays valid after exiting boot
+/// services, as long as we keep the firmware's identity map.
+static EFI_RUNTIME_SERVICES: AtomicPtr<EfiRuntimeServices> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// The firmware serial port which is used as a console when ACPI does not
+/// report one
+static EFI_SERIAL_IO: AtomicPtr<EfiSerialIoProtocol> =
+    AtomicPtr::new(core::ptr::null_mut());
+
+/// A serial port driven by the firmware, which can only be used until boot
+/// services are exited
+#[derive(Clone, Copy)]
+pub struct SerialIo(*const EfiSerialIoProtocol);
+
+impl SerialIo {
+    /// Write a slice of bytes to the serial port
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` - The slice of bytes to write to the serial port
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    pub fn write(&self, bytes: &[u8]) -> Result<()> {
+        // Write in chunks, with a CR injected prior to all LFs. We always
+        // leave room for a CR and LF in the buffer.
+        let mut tmp = [0u8; 64];
+        let mut in_use = 0;
+        for (ii, &byte) in bytes.iter().enumerate() {
+            if byte == b'\n' {
+                tmp[in_use] = b'\r';
+                in_use += 1;
+            }
+            tmp[in_use] = byte;
+            in_use += 1;
+
+            // Flush when the buffer could be full or we are out of bytes
+            if in_use >= tmp.len() - 1 || ii == bytes.len() - 1 {
+                let mut offset = 0;
+                while offset < in_use {
+                    let mut size = in_use - offset;
+                    let ret: EfiStatus = unsafe {
+                        ((*self.0).write)(self.0, &mut size,
+                                          tmp[offset..].as_ptr()).into()
+                    };
+                    if ret != EfiStatus::Success || size == 0 {
+                        return Err(Error::SerialIo(ret));
+                    }
+                    offset += size;
+                }
+                in_use = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Find a serial port provided by the firmware and use it as a console until
+/// boot services are exited
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn init_serial_io() -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    let serial = unsafe {
+        (*(*st).boot_services).locate::<EfiSerialIoProtocol>()
+            .map_err(Error::NoSerialIo)?
+    };
+    EFI_SERIAL_IO.store(serial as *mut _, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Check whether the boot services can be used
+///
+/// # Returns
+///
+/// `true` if a system table is registered and boot services are not being
+/// exited
+///
+pub fn boot_services_active() -> bool {
+    !EFI_SYSTEM_TABLE.load(Ordering::SeqCst).is_null() &&
+        !EXITING_BOOT_SERVICES.load(Ordering::SeqCst)
+}
+
+/// Get the firmware serial port console
+///
+/// # Returns
+///
+/// The serial port registered with [`init_serial_io`], or `None` if there is
+/// none or boot services are being exited
+///
+pub fn serial_io() -> Option<SerialIo> {
+    // The port goes away with the boot services
+    if !boot_services_active() { return None; }
+
+    let serial = EFI_SERIAL_IO.load(Ordering::SeqCst);
+    (!serial.is_null()).then_some(SerialIo(serial))
+}
+
+/// Write a `string` to the UEFI console output
+///
+/// # Parameters
+///
+/// * `string` - The string to write to the UEFI console output using the UEFI
+///              API
+///
+/// # Returns
+///
+/// `()`, on error [`Error`]. Characters which could not be displayed are not
+/// an error.
+///
+pub fn output_string(string: &str) -> Result<()> {
+    write_text(string, |st| st.console_out)
+}
+
+/// Write a `string` to the UEFI standard error console
+///
+/// # Parameters
+///
+/// * `string` - The string to write to the UEFI standard error console using
+///              the UEFI API
+///
+/// # Returns
+///
+/// `()`, on error [`Error`]. Characters which could not be displayed are not
+/// an error.
+///
+pub fn error_string(string: &str) -> Result<()> {
+    write_text(string, |st| st.console_err)
+}
+
+/// Write a `string` to one of the text outputs of the system table
+///
+/// # Parameters
+///
+/// * `string` - The string to write
+/// * `output` - Picks the text output protocol from the system table
+///
+/// # Returns
+///
+/// `()`, on error [`Error`]. Characters which could not be displayed are not
+/// an error.
+///
+fn write_text(string: &str,
+        output: fn(&EfiSystemTable) -> *const EfiSimpleTextOutputProtocol)
+        -> Result<()> {
+    // Get the system_table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Drop the output while we're exiting boot services
+    if EXITING_BOOT_SERVICES.load(Ordering::SeqCst) { return Ok(()); }
+
+    // Get the text output pointer
+    let out = output(unsafe { &*st });
+
+    // Create a temporary buffer capable of holding 31 characters a a time
+    // plus a null terminator
+    //
+    // We are using UCS-2 and not UTF-16, as that's what UEFI uses. Thus
+    // we don't have to worry about 32-bit code points.
+    let mut tmp = [0u16; 32];
+    let mut in_use = 0;
+
+    // Go through all the characters
+    for chr in string.encode_utf16() {
+        // Inject carriage return if needed. We always make sure there's
+        // room for one based on the way we check the buffer length (-2
+        // instead of -1)
+        if chr == b'\n' as u16 {
+            tmp[in_use] = b'\r' as u16;
+            in_use += 1;
+        }
+
+        // Write a character into the buffer
+        tmp[in_use] = chr;
+        in_use += 1;
+
+        // If the temporary buffer could potentially be full on the next
+        // iteration, we flush it. We do -2 here because we need room for the
+        // worst case which is a carriage return, newline, and null terminator
+        // in the next iteration. We also need to do >= because we can
+        // potentially skip from 29 in use to 31 in use if the 30th character
+        // is a newline.
+        if in_use >= (tmp.len() - 2) {
+            // Null terminate the buffer
+            tmp[in_use] = 0;
+
+            // Write out the buffer
+            let ret: EfiStatus =
+                unsafe { ((*out).output_string)(out, tmp.as_ptr()).into() };
+            if let EfiStatus::Error(_) = ret {
+                return Err(Error::OutputString(ret));
+            }
+
+            // Clear the buffer
+            in_use = 0;
+        }
+    }
+
+    // Write out any remaining characters
+    if in_use > 0 {
+        // Null terminate the buffer
+        tmp[in_use] = 0;
+
+        let ret: EfiStatus =
+            unsafe { ((*out).output_string)(out, tmp.as_ptr()).into() };
+        if let EfiStatus::Error(_) = ret {
+            return Err(Error::OutputString(ret));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the base of the ACPI table RSDP
+///
+/// # Returns
+///
+/// The base address of the ACPI RSDP table as specified by EFI, on error
+/// [`Error`]
+///
+pub fn get_acpi_table() -> Result<usize> {
+    /// ACPI 2.0 or newer tables should use EFI_ACPI_TABLE_GUID
+    const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid(
+        0x8868e871, 0xe4f1, 0x11d3,
+        [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);  
+
+    /// ACPI 1.0 tables use this GUID
+    const ACPI_TABLE_GUID: EfiGuid = EfiGuid(
+        0xeb9d2d30, 0x2d88, 0x11d3,
+        [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Get a Rust slice to the tables
+    let tables = unsafe {
+        core::slice::from_raw_parts(
+            (*st).tables,
+            (*st).number_of_tables)
+    };
+
+    // First look for the ACPI 2.0 table, if we can't find it, then look
+    // for the ACPI 1.0 table
+    tables.iter().find_map(|EfiConfigurationTable { guid, table }| {
+        (guid == &EFI_ACPI_TABLE_GUID).then_some(*table)
+    }).or_else(|| {
+        tables.iter().find_map(|EfiConfigurationTable { guid, table }| {
+            (guid == &ACPI_TABLE_GUID).then_some(*table)
+        })
+    }).ok_or(Error::AcpiTableNotFound)
 }
 
-/// A strongly typed EFI system table pointer which will disallow the copying
-/// of the raw pointer
-#[repr(transparent)]
-pub struct EfiSystemTablePtr(*mut EfiSystemTable);
+/// Find a text mode of the console output
+///
+/// # Parameters
+///
+/// * `pred` - Called with the mode number, columns and rows of every mode,
+///            the mode for which this returns the largest value is picked.
+///            Modes for which this returns `None` are never picked.
+///
+/// # Returns
+///
+/// The picked mode number, columns and rows, on error [`Error`]
+///
+fn find_text_mode(pred: impl Fn(usize, usize) -> Option<usize>)
+        -> Result<(usize, usize, usize)> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let out = (*st).console_out;
+        let max_mode = (*(*out).mode).max_mode.max(0) as usize;
+
+        // Go through all modes, some may fail as not every mode number has
+        // to be supported
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        for mode in 0..max_mode {
+            let mut columns = 0;
+            let mut rows    = 0;
+            let ret: EfiStatus =
+                ((*out).query_mode)(out, mode, &mut columns, &mut rows).into();
+            if ret != EfiStatus::Success { continue; }
+
+            if let Some(score) = pred(columns, rows) {
+                if best.map_or(true, |(best, ..)| score > best) {
+                    best = Some((score, mode, columns, rows));
+                }
+            }
+        }
+
+        best.map(|(_, mode, columns, rows)| (mode, columns, rows))
+            .ok_or(Error::TextModeNotFound)
+    }
+}
+
+/// Switch the console output to a text mode
+///
+/// # Parameters
+///
+/// * `mode` - The mode number to switch to
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+fn switch_text_mode(mode: usize) -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    let ret: EfiStatus = unsafe {
+        let out = (*st).console_out;
+        ((*out).set_mode)(out, mode).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::SetTextMode(ret));
+    }
+
+    Ok(())
+}
+
+/// Set the console output to a text mode of `columns` by `rows` characters
+///
+/// # Parameters
+///
+/// * `columns` - The number of columns of the mode
+/// * `rows`    - The number of rows of the mode
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn set_text_mode(columns: usize, rows: usize) -> Result<()> {
+    let (mode, ..) = find_text_mode(|c, r| {
+        (c == columns && r == rows).then_some(0)
+    })?;
+    switch_text_mode(mode)
+}
+
+/// Set the console output to the text mode with the most characters
+///
+/// # Returns
+///
+/// The columns and rows of the new mode, on error [`Error`]
+///
+pub fn set_largest_text_mode() -> Result<(usize, usize)> {
+    let (mode, columns, rows) =
+        find_text_mode(|c, r| c.checked_mul(r))?;
+    switch_text_mode(mode)?;
+    Ok((columns, rows))
+}
+
+/// A color of the EFI text console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Color {
+    /// `EFI_BLACK`
+    Black        = 0x0,
+
+    /// `EFI_BLUE`
+    Blue         = 0x1,
+
+    /// `EFI_GREEN`
+    Green        = 0x2,
+
+    /// `EFI_CYAN`
+    Cyan         = 0x3,
+
+    /// `EFI_RED`
+    Red          = 0x4,
+
+    /// `EFI_MAGENTA`
+    Magenta      = 0x5,
+
+    /// `EFI_BROWN`
+    Brown        = 0x6,
+
+    /// `EFI_LIGHTGRAY`
+    LightGray    = 0x7,
+
+    /// `EFI_DARKGRAY`
+    DarkGray     = 0x8,
+
+    /// `EFI_LIGHTBLUE`
+    LightBlue    = 0x9,
+
+    /// `EFI_LIGHTGREEN`
+    LightGreen   = 0xa,
+
+    /// `EFI_LIGHTCYAN`
+    LightCyan    = 0xb,
+
+    /// `EFI_LIGHTRED`
+    LightRed     = 0xc,
+
+    /// `EFI_LIGHTMAGENTA`
+    LightMagenta = 0xd,
+
+    /// `EFI_YELLOW`
+    Yellow       = 0xe,
+
+    /// `EFI_WHITE`
+    White        = 0xf,
+}
+
+/// Set the colors used for further console output and clearing
+///
+/// # Parameters
+///
+/// * `fg` - The foreground color
+/// * `bg` - The background color, only the colors up to
+///          [`Color::LightGray`] are valid backgrounds
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn set_color(fg: Color, bg: Color) -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // The background only gets 3 bits of the attribute
+    if bg as usize > Color::LightGray as usize {
+        return Err(Error::InvalidBackground(bg));
+    }
+
+    let ret: EfiStatus = unsafe {
+        let out = (*st).console_out;
+        ((*out).set_attribute)(out, fg as usize | (bg as usize) << 4).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::SetColor(ret));
+    }
+
+    Ok(())
+}
+
+/// Clear the console to the current background color and move the cursor to
+/// the top left
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn clear() -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    let ret: EfiStatus = unsafe {
+        let out = (*st).console_out;
+        ((*out).clear_screen)(out).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::ClearScreen(ret));
+    }
+
+    Ok(())
+}
+
+/// A key pressed on the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A key with a character representation, this includes enter (`'\r'`)
+    /// and backspace (`'\x08'`)
+    Char(char),
+
+    /// Cursor up
+    Up,
+
+    /// Cursor down
+    Down,
+
+    /// Cursor right
+    Right,
+
+    /// Cursor left
+    Left,
+
+    /// Escape
+    Escape,
+
+    /// Any other key, represented by its EFI scan code
+    Other(u16),
+}
+
+impl From<EfiInputKey> for Key {
+    fn from(val: EfiInputKey) -> Self {
+        match (val.scan_code, val.unicode_char) {
+            (0x00, chr) => core::char::from_u32(chr as u32)
+                .map(Key::Char).unwrap_or(Key::Other(0)),
+            (0x01, _) => Key::Up,
+            (0x02, _) => Key::Down,
+            (0x03, _) => Key::Right,
+            (0x04, _) => Key::Left,
+            (0x17, _) => Key::Escape,
+            (code, _) => Key::Other(code),
+        }
+    }
+}
+
+/// Read a key from the console without waiting
+///
+/// # Returns
+///
+/// The key which was pressed or `None` if no key is pending, on error
+/// [`Error`]
+///
+pub fn read_key() -> Result<Option<Key>> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Read a keystroke
+    let mut key = EfiInputKey::default();
+    let ret: EfiStatus = unsafe {
+        let inp = (*st).console_in;
+        ((*inp).read_keystroke)(inp, &mut key).into()
+    };
+    match ret {
+        EfiStatus::Success => Ok(Some(key.into())),
+        EfiStatus::Error(EfiError::NotReady) => Ok(None),
+        _ => Err(Error::ReadKey(ret)),
+    }
+}
+
+/// Wait for a key to be pressed on the console
+///
+/// # Returns
+///
+/// The key which was pressed, on error [`Error`]
+///
+pub fn wait_for_key() -> Result<Key> {
+    loop {
+        // Get the system table
+        let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+        // We can't do anything if it's null
+        if st.is_null() { return Err(Error::NotRegistered); }
+
+        // Sleep until a key is available
+        let mut index = 0;
+        let ret: EfiStatus = unsafe {
+            ((*(*st).boot_services).wait_for_event)(1,
+                &(*(*st).console_in).wait_for_key, &mut index).into()
+        };
+        if ret != EfiStatus::Success {
+            return Err(Error::ReadKey(ret));
+        }
+
+        // The event may be signaled without a key for us, in which case we
+        // go back to sleep
+        if let Some(key) = read_key()? { return Ok(key); }
+    }
+}
+
+/// Get the linear framebuffer of the current graphics mode
+///
+/// # Returns
+///
+/// The [`Framebuffer`] set up by the graphics output protocol, on error
+/// [`Error`]
+///
+pub fn get_framebuffer() -> Result<Framebuffer> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Find the graphics output protocol and get the current mode
+    let (mode, info) = unsafe {
+        let gop = (*(*st).boot_services)
+            .locate::<EfiGraphicsOutputProtocol>()
+            .map_err(Error::GraphicsOutputNotFound)?;
+        let mode = &*(*gop).mode;
+        (mode, &*mode.info)
+    };
+
+    // Only 32-bit RGB and BGR pixels can be drawn directly
+    let format = match info.pixel_format {
+        0 => PixelFormat::Rgb,
+        1 => PixelFormat::Bgr,
+        x => return Err(Error::UnsupportedPixelFormat(x)),
+    };
+
+    Ok(Framebuffer {
+        base:   mode.frame_buffer_base as usize,
+        size:   mode.frame_buffer_size,
+        width:  info.horizontal_resolution,
+        height: info.vertical_resolution,
+        stride: info.pixels_per_scan_line,
+        format,
+    })
+}
+
+/// Information about our own image
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedImage {
+    /// The address our image was loaded at
+    pub base: usize,
+
+    /// The size of our image in memory (in bytes)
+    pub size: u64,
+
+    /// The raw load options we were started with, usually a UCS-2 command
+    /// line
+    pub load_options: &'static [u8],
+
+    /// The device path of the device we were loaded from, if it has one
+    pub device_path: Option<DevicePath>,
+
+    /// The path of our image on `device_path`
+    pub file_path: Option<DevicePath>,
+}
+
+/// Get information about our own image
+///
+/// # Returns
+///
+/// The [`LoadedImage`] information of our image, on error [`Error`]
+///
+pub fn loaded_image() -> Result<LoadedImage> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Get our image handle
+    let image = EFI_IMAGE_HANDLE.load(Ordering::SeqCst);
+    if image == 0 { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let li = &*(*(*st).boot_services)
+            .open::<EfiLoadedImageProtocol>(EfiHandle(image))
+            .map_err(Error::LoadedImage)?;
+
+        // There may be no load options at all
+        let load_options = if li.load_options.is_null() {
+            &[][..]
+        } else {
+            core::slice::from_raw_parts(li.load_options,
+                                        li.load_options_size as usize)
+        };
+
+        // Get the device path of the device we were loaded from
+        let device_path = (*(*st).boot_services)
+            .open::<EfiDevicePathProtocol>(li.device_handle).ok()
+            .and_then(|path| DevicePath::new(path));
+
+        Ok(LoadedImage {
+            base: li.image_base,
+            size: li.image_size,
+            load_options,
+            device_path,
+            file_path: DevicePath::new(li.file_path),
+        })
+    }
+}
+
+/// Read a whole file from the file system we were loaded from
+///
+/// # Parameters
+///
+/// * `path` - The absolute path of the file, `/` and `\\` are both accepted
+///            as separators
+///
+/// # Returns
+///
+/// The contents of the file in freshly allocated `EfiLoaderData` pages, which
+/// stay reserved after exiting boot services. On error [`Error`]
+///
+pub fn read_file(path: &str) -> Result<&'static mut [u8]> {
+    /// EFI_FILE_INFO_ID
+    const EFI_FILE_INFO_ID: EfiGuid = EfiGuid(
+        0x09576e92, 0x6d3f, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+
+    /// Open the file for reading
+    const EFI_FILE_MODE_READ: u64 = 1;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Get our image handle
+    let image = EFI_IMAGE_HANDLE.load(Ordering::SeqCst);
+    if image == 0 { return Err(Error::NotRegistered); }
+
+    // Convert the path into a null terminated UCS-2 path with EFI separators
+    let mut wpath = [0u16; 256];
+    let mut in_use = 0;
+    for chr in path.encode_utf16() {
+        // Leave room for the null terminator
+        if in_use >= wpath.len() - 1 { return Err(Error::PathTooLong); }
+
+        wpath[in_use] = if chr == b'/' as u16 { b'\\' as u16 } else { chr };
+        in_use += 1;
+    }
+
+    unsafe {
+        let bs = &*(*st).boot_services;
+
+        // Find the device we were loaded from
+        let device = (*bs.open::<EfiLoadedImageProtocol>(EfiHandle(image))
+            .map_err(Error::LoadedImage)?).device_handle;
+
+        // Open the root directory of the file system on that device
+        let fs = bs.open::<EfiSimpleFileSystemProtocol>(device)
+            .map_err(Error::FileSystem)?;
+        let mut root = core::ptr::null();
+        let ret: EfiStatus = ((*fs).open_volume)(fs, &mut root).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::FileSystem(ret));
+        }
+
+        // Open the file
+        let mut file = core::ptr::null();
+        let ret: EfiStatus = ((*root).open)(root, &mut file, wpath.as_ptr(),
+            EFI_FILE_MODE_READ, 0).into();
+        ((*root).close)(root);
+        if ret != EfiStatus::Success {
+            return Err(Error::FileOpen(ret));
+        }
+
+        // Read the file, making sure it is closed no matter what happens
+        let ret = (|| {
+            // Get the size of the file. `EFI_FILE_INFO` is followed by the
+            // file name, we do not care about long names so a fixed buffer is
+            // fine.
+            let mut info = [0u64; 64];
+            let mut size = size_of::<[u64; 64]>();
+            let ret: EfiStatus = ((*file).get_info)(file, &EFI_FILE_INFO_ID,
+                &mut size, info.as_mut_ptr() as *mut u8).into();
+            if ret != EfiStatus::Success {
+                return Err(Error::FileInfo(ret));
+            }
+            let file_size = (*(info.as_ptr() as *const EfiFileInfo))
+                .file_size as usize;
+
+            // Nothing to allocate for an empty file
+            if file_size == 0 { return Ok(&mut [][..]); }
+
+            // Allocate pages to hold the file
+            let buf = core::slice::from_raw_parts_mut(
+                bs.allocate_loader_data(file_size)?, file_size);
+
+            // Read the file, which may take multiple reads. The pages are
+            // given back if the file can't be read in full.
+            let mut offset = 0;
+            while offset < file_size {
+                let mut size = file_size - offset;
+                let ret: EfiStatus = ((*file).read)(file, &mut size,
+                    buf[offset..].as_mut_ptr()).into();
+
+                // A size of zero means the file got shorter than it claimed
+                // to be
+                if ret != EfiStatus::Success || size == 0 {
+                    bs.free_loader_data(buf.as_mut_ptr(), file_size);
+                    return Err(Error::FileRead(ret));
+                }
+                offset += size;
+            }
+
+            Ok(buf)
+        })();
+        ((*file).close)(file);
+
+        ret
+    }
+}
+
+/// Read a whole file from a TFTP server, using the network interface we were
+/// loaded from
+///
+/// # Parameters
+///
+/// * `server` - The IPv4 address of the TFTP server, `None` to use the boot
+///              server reported by DHCP
+/// * `path`   - The path of the file on the server
+///
+/// # Returns
+///
+/// The contents of the file in freshly allocated `EfiLoaderData` pages, which
+/// stay reserved after exiting boot services. On error [`Error`]
+///
+pub fn tftp_read(server: Option<[u8; 4]>, path: &str)
+        -> Result<&'static mut [u8]> {
+    /// Opcode to get the size of a file
+    const TFTP_GET_FILE_SIZE: u32 = 1;
+
+    /// Opcode to read a file
+    const TFTP_READ_FILE: u32 = 2;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Get our image handle
+    let image = EFI_IMAGE_HANDLE.load(Ordering::SeqCst);
+    if image == 0 { return Err(Error::NotRegistered); }
+
+    // Convert the path into a null terminated ASCII path
+    let mut apath = [0u8; 256];
+    if path.len() >= apath.len() - 1 || !path.is_ascii() {
+        return Err(Error::PathTooLong);
+    }
+    apath[..path.len()].copy_from_slice(path.as_bytes());
+
+    unsafe {
+        let bs = &*(*st).boot_services;
+
+        // Find the network interface we were loaded from
+        let device = (*bs.open::<EfiLoadedImageProtocol>(EfiHandle(image))
+            .map_err(Error::LoadedImage)?).device_handle;
+        let pxe = bs.open::<EfiPxeBaseCodeProtocol>(device)
+            .map_err(Error::NoNetwork)?;
+
+        // Bring up the network if we were not network booted
+        if !(*(*pxe).mode).started {
+            let ret: EfiStatus = ((*pxe).start)(pxe, false).into();
+            if ret != EfiStatus::Success {
+                return Err(Error::NoNetwork(ret));
+            }
+            let ret: EfiStatus = ((*pxe).dhcp)(pxe, false).into();
+            if ret != EfiStatus::Success {
+                return Err(Error::Dhcp(ret));
+            }
+        }
+
+        // Find the server, the boot server address is the `siaddr` of the
+        // DHCP packets
+        let mode = &*(*pxe).mode;
+        let mut ip = [0u32; 4];
+        let server = server.or_else(|| {
+            let packet = if mode.proxy_offer_received {
+                &mode.proxy_offer
+            } else if mode.dhcp_ack_received {
+                &mode.dhcp_ack
+            } else {
+                return None;
+            };
+            let mut siaddr = [0u8; 4];
+            siaddr.copy_from_slice(&packet[20..24]);
+            Some(siaddr).filter(|&x| x != [0; 4])
+        }).ok_or(Error::NoTftpServer)?;
+        ip[0] = u32::from_ne_bytes(server);
+
+        // Get the size of the file
+        let mut size = 0u64;
+        let ret: EfiStatus = ((*pxe).mtftp)(pxe, TFTP_GET_FILE_SIZE,
+            core::ptr::null_mut(), false, &mut size, core::ptr::null(), &ip,
+            apath.as_ptr(), core::ptr::null(), false).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::Tftp(ret));
+        }
+
+        // Nothing to allocate for an empty file
+        let file_size = size as usize;
+        if file_size == 0 { return Ok(&mut [][..]); }
+
+        // Allocate pages to hold the file and read it
+        let buf = core::slice::from_raw_parts_mut(
+            bs.allocate_loader_data(file_size)?, file_size);
+        let ret: EfiStatus = ((*pxe).mtftp)(pxe, TFTP_READ_FILE,
+            buf.as_mut_ptr(), false, &mut size, core::ptr::null(), &ip,
+            apath.as_ptr(), core::ptr::null(), false).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::Tftp(ret));
+        }
+
+        Ok(&mut buf[..(size as usize).min(file_size)])
+    }
+}
+
+/// Set the boot services watchdog timer
+///
+/// The firmware arms a 5 minute watchdog before starting us, when it expires
+/// the platform is reset. The watchdog is disabled by the firmware once boot
+/// services are exited.
+///
+/// # Parameters
+///
+/// * `timeout` - The number of seconds until the watchdog fires, `0` to
+///               disable the watchdog
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn set_watchdog_timer(timeout: usize) -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Set the watchdog, the watchdog code is ours to pick as long as it is
+    // above the reserved range
+    let ret: EfiStatus = unsafe {
+        ((*(*st).boot_services).set_watchdog_timer)(
+            timeout, 0x10000, 0, core::ptr::null()).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::SetWatchdogTimer(ret));
+    }
+
+    Ok(())
+}
+
+/// Stall the processor for at least `us` microseconds
+///
+/// # Parameters
+///
+/// * `us` - The number of microseconds to stall for
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn stall(us: usize) -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Stall
+    let ret: EfiStatus = unsafe {
+        ((*(*st).boot_services).stall)(us).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::Stall(ret));
+    }
+
+    Ok(())
+}
+
+/// Allocate memory which stays ours after boot services are exited
+///
+/// # Parameters
+///
+/// * `size` - The number of bytes to allocate, rounded up to whole pages
+///
+/// # Returns
+///
+/// The page aligned memory, on error [`Error`]
+///
+pub fn allocate_pages(size: usize) -> Result<&'static mut [u8]> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let addr = (*(*st).boot_services).allocate_loader_data(size)?;
+        Ok(core::slice::from_raw_parts_mut(addr, size))
+    }
+}
+
+/// Allocate memory which lies entirely at or below an address and stays
+/// ours after boot services are exited
+///
+/// # Parameters
+///
+/// * `last` - The last physical address the memory may use
+/// * `size` - The number of bytes to allocate, rounded up to whole pages
+///
+/// # Returns
+///
+/// The page aligned memory, on error [`Error`]
+///
+pub fn allocate_below(last: u64, size: usize) -> Result<&'static mut [u8]> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let bs = &*(*st).boot_services;
+        let mut addr = bs.allocate_loader_data_below(last, size)?;
+
+        // Page zero can't be handed out as a slice, so if the firmware gives
+        // it to us keep it reserved and ask again
+        if addr.is_null() {
+            addr = bs.allocate_loader_data_below(last, size)?;
+        }
+        Ok(core::slice::from_raw_parts_mut(addr, size))
+    }
+}
+
+/// Read the firmware's timestamp counter
+///
+/// # Returns
+///
+/// The current counter value and the counter frequency in Hz, on error
+/// [`Error`]
+///
+pub fn timestamp() -> Result<(u64, u64)> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let timestamp = (*(*st).boot_services)
+            .locate::<EfiTimestampProtocol>()
+            .map_err(Error::NoTimestamp)?;
+
+        // Get the frequency, a counter which does not tick is of no use
+        let mut properties = EfiTimestampProperties::default();
+        let ret: EfiStatus =
+            ((*timestamp).get_properties)(&mut properties).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::NoTimestamp(ret));
+        }
+        if properties.frequency == 0 {
+            return Err(Error::NoTimestamp(
+                EfiStatus::Error(EfiError::Unsupported)));
+        }
+
+        Ok((((*timestamp).get_timestamp)(), properties.frequency))
+    }
+}
+
+/// Get the current wall-clock time
+///
+/// # Returns
+///
+/// The current [`EfiTime`] as kept by the real time clock, on error
+/// [`Error`]
+///
+pub fn get_time() -> Result<EfiTime> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Get the time, we do not care about the capabilities of the clock
+    let mut time = EfiTime::default();
+    let ret: EfiStatus = unsafe {
+        ((*(*st).runtime_services).get_time)(&mut time, 0).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::GetTime(ret));
+    }
+
+    Ok(time)
+}
+
+/// How the firmware accepts capsule updates
+#[derive(Clone, Copy, Debug)]
+pub struct CapsuleCapabilities {
+    /// Maximum size of a capsule in bytes
+    pub max_size: u64,
+
+    /// The `EFI_RESET_TYPE` needed to process a capsule
+    pub reset_type: u32,
+}
+
+/// Information about the firmware
+#[derive(Clone, Copy, Debug)]
+pub struct FirmwareInfo {
+    /// UTF-8 name of the firmware vendor
+    vendor: [u8; boot_info::MAX_FIRMWARE_VENDOR],
+
+    /// Number of in use bytes in `vendor`
+    vendor_len: usize,
+
+    /// Vendor specific revision of the firmware
+    pub revision: u32,
+
+    /// The UEFI specification revision the firmware implements
+    pub uefi_revision: u32,
+
+    /// Capsule update support for firmware management capsules, `None` if
+    /// they are not supported
+    pub capsule: Option<CapsuleCapabilities>,
+}
+
+impl FirmwareInfo {
+    /// Get the name of the firmware vendor
+    pub fn vendor(&self) -> &str {
+        // We only ever store whole characters
+        core::str::from_utf8(&self.vendor[..self.vendor_len]).unwrap_or("")
+    }
+
+    /// Convert the information into the boot info representation
+    pub fn boot_info(&self) -> boot_info::Firmware {
+        let capsule = self.capsule.unwrap_or(CapsuleCapabilities {
+            max_size:   0,
+            reset_type: 0,
+        });
+
+        boot_info::Firmware {
+            present:            1,
+            vendor_len:         self.vendor_len as u32,
+            vendor:             self.vendor,
+            revision:           self.revision,
+            uefi_revision:      self.uefi_revision,
+            capsule_present:    self.capsule.is_some() as u32,
+            capsule_reset_type: capsule.reset_type,
+            max_capsule_size:   capsule.max_size,
+        }
+    }
+}
+
+impl core::fmt::Display for FirmwareInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // UEFI revisions are major.minor with the minor also holding a patch
+        // digit, e.g. 2.31 is 2.3.1
+        let major = self.uefi_revision >> 16;
+        let minor = (self.uefi_revision & 0xffff) / 10;
+        let patch = (self.uefi_revision & 0xffff) % 10;
+        write!(f, "{} rev {:#x}, UEFI {}.{}", self.vendor(), self.revision,
+               major, minor)?;
+        if patch != 0 { write!(f, ".{}", patch)?; }
+
+        match self.capsule {
+            Some(capsule) => write!(f, ", capsules up to {} bytes",
+                                    capsule.max_size),
+            None => write!(f, ", no capsule updates"),
+        }
+    }
+}
+
+/// Get information about the firmware
+///
+/// # Returns
+///
+/// The [`FirmwareInfo`] of the firmware, on error [`Error`]
+///
+pub fn firmware_info() -> Result<FirmwareInfo> {
+    /// `EFI_FIRMWARE_MANAGEMENT_CAPSULE_ID_GUID`
+    const EFI_FIRMWARE_MANAGEMENT_CAPSULE_ID_GUID: EfiGuid = EfiGuid(
+        0x6dcbd5ed, 0xe82d, 0x4c44,
+        [0xbd, 0xa1, 0x71, 0x94, 0x19, 0x9a, 0xd9, 0x2a]);
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let mut info = FirmwareInfo {
+            vendor:        [0; boot_info::MAX_FIRMWARE_VENDOR],
+            vendor_len:    0,
+            revision:      (*st).firmware_revision,
+            uefi_revision: (*st).header.revision,
+            capsule:       None,
+        };
+
+        // Convert the vendor string, dropping what does not fit
+        if !(*st).firmware_vendor.is_null() {
+            let vendor = core::slice::from_raw_parts((*st).firmware_vendor,
+                wcslen((*st).firmware_vendor));
+            for chr in core::char::decode_utf16(vendor.iter().copied()) {
+                let chr = chr.unwrap_or(core::char::REPLACEMENT_CHARACTER);
+                let len = chr.len_utf8();
+                if info.vendor_len + len > info.vendor.len() { break; }
+
+                chr.encode_utf8(&mut info.vendor[info.vendor_len..]);
+                info.vendor_len += len;
+            }
+        }
+
+        // Capsules were only introduced in UEFI 2.0
+        let rt = &*(*st).runtime_services;
+        if rt.header.revision >= 2 << 16 {
+            let header = EfiCapsuleHeader {
+                capsule_guid:       EFI_FIRMWARE_MANAGEMENT_CAPSULE_ID_GUID,
+                header_size:        size_of::<EfiCapsuleHeader>() as u32,
+                flags:              0,
+                capsule_image_size: size_of::<EfiCapsuleHeader>() as u32,
+            };
+            let headers = [&header as *const EfiCapsuleHeader];
+            let mut max_size = 0;
+            let mut reset_type = 0;
+            let ret: EfiStatus = (rt.query_capsule_capabilities)(
+                headers.as_ptr(), headers.len(), &mut max_size,
+                &mut reset_type).into();
+            if ret == EfiStatus::Success {
+                info.capsule = Some(CapsuleCapabilities {
+                    max_size,
+                    reset_type,
+                });
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// A raw block device, such as a disk or a partition on it
+#[derive(Clone, Copy)]
+pub struct BlockDevice(*const EfiBlockIoProtocol);
+
+impl BlockDevice {
+    /// Get the media information of this device
+    fn media(&self) -> &EfiBlockIoMedia {
+        unsafe { &*(*self.0).media }
+    }
+
+    /// Get the size of a block (in bytes)
+    pub fn block_size(&self) -> u32 {
+        self.media().block_size
+    }
+
+    /// Get the number of blocks on the device
+    pub fn blocks(&self) -> u64 {
+        self.media().last_block.wrapping_add(1)
+    }
+
+    /// Returns `true` if this device is a partition rather than a whole disk
+    pub fn is_partition(&self) -> bool {
+        self.media().logical_partition != 0
+    }
+
+    /// Returns `true` if there is media in the device
+    pub fn is_present(&self) -> bool {
+        self.media().media_present != 0
+    }
+
+    /// Read blocks from the device
+    ///
+    /// # Parameters
+    ///
+    /// * `lba` - The first block to read
+    /// * `buf` - The buffer to read into, this must be a multiple of the
+    ///           block size and meet the alignment needs of the device
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    pub fn read(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        // Block I/O is only usable while boot services are up
+        if EFI_SYSTEM_TABLE.load(Ordering::SeqCst).is_null() {
+            return Err(Error::NotRegistered);
+        }
+
+        // Validate the buffer
+        let media = self.media();
+        if media.block_size == 0 {
+            return Err(Error::BadBlockSize);
+        }
+        let align = media.io_align.max(1) as usize;
+        if buf.len() % media.block_size as usize != 0 ||
+                buf.as_ptr() as usize % align != 0 {
+            return Err(Error::BadBlockBuffer);
+        }
+
+        let ret: EfiStatus = unsafe {
+            ((*self.0).read_blocks)(self.0, media.media_id, lba, buf.len(),
+                                    buf.as_mut_ptr()).into()
+        };
+        if ret != EfiStatus::Success {
+            return Err(Error::ReadBlocks(ret));
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the block devices known to the firmware
+///
+/// # Parameters
+///
+/// * `devices` - Buffer to store the devices into, any devices which do not
+///               fit are dropped
+///
+/// # Returns
+///
+/// The number of devices stored in `devices`, on error [`Error`]
+///
+pub fn block_devices(devices: &mut [Option<BlockDevice>]) -> Result<usize> {
+    /// Maximum number of block devices we look at
+    const MAX_BLOCK_DEVICES: usize = 32;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let bs = &*(*st).boot_services;
+
+        // Get the handles of all block devices
+        let mut handles = [EfiHandle(0); MAX_BLOCK_DEVICES];
+        let count = bs.locate_handles::<EfiBlockIoProtocol>(&mut handles)
+            .map_err(Error::BlockDevices)?;
+
+        // Get the protocol on each of them
+        let mut in_use = 0;
+        for &handle in &handles[..count.min(devices.len())] {
+            devices[in_use] = Some(BlockDevice(
+                bs.open::<EfiBlockIoProtocol>(handle)
+                    .map_err(Error::BlockDevices)?));
+            in_use += 1;
+        }
+
+        Ok(in_use)
+    }
+}
+
+/// Get a random 64-bit number from the CPU's hardware random number
+/// generator
+///
+/// # Returns
+///
+/// A random number, or `None` if the CPU does not have a random number
+/// generator or it failed to produce a number
+///
+fn cpu_random() -> Option<u64> {
+    /// Number of times to retry a read when the generator is exhausted
+    const RETRIES: usize = 10;
+
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        // Check for RDRAND support, CPUID.01H:ECX[30]
+        if core::arch::x86_64::__cpuid(1).ecx & (1 << 30) == 0 {
+            return None;
+        }
+
+        for _ in 0..RETRIES {
+            let val: u64;
+            let ok: u8;
+            asm!("rdrand {}", "setc {}", out(reg) val, out(reg_byte) ok,
+                options(nomem, nostack));
+            if ok != 0 { return Some(val); }
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // Check for RNDR support, ID_AA64ISAR0_EL1.RNDR
+        let isar0: u64;
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0,
+            options(nomem, nostack, preserves_flags));
+        if (isar0 >> 60) & 0xf == 0 {
+            return None;
+        }
+
+        for _ in 0..RETRIES {
+            let val: u64;
+            let ok: u64;
+            asm!("mrs {}, s3_3_c2_c4_0", "cset {}, ne", out(reg) val,
+                out(reg) ok, options(nomem, nostack));
+            if ok != 0 { return Some(val); }
+        }
+    }
+
+    None
+}
+
+/// Fill `buf` with random bytes
+///
+/// The firmware's RNG protocol is used while boot services are up, the CPU's
+/// random number generator (RDRAND or RNDR) is used otherwise or when the
+/// firmware does not provide one.
+///
+/// # Parameters
+///
+/// * `buf` - The buffer to fill
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn get_random(buf: &mut [u8]) -> Result<()> {
+    // Try the firmware first
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+    if !st.is_null() {
+        unsafe {
+            if let Ok(rng) =
+                    (*(*st).boot_services).locate::<EfiRngProtocol>() {
+                // Use the default algorithm of the firmware
+                let ret: EfiStatus = ((*rng).get_rng)(rng, core::ptr::null(),
+                    buf.len(), buf.as_mut_ptr()).into();
+                if ret == EfiStatus::Success { return Ok(()); }
+            }
+        }
+    }
+
+    // Fall back to the CPU
+    for chunk in buf.chunks_mut(size_of::<u64>()) {
+        let val = cpu_random().ok_or(Error::NoEntropy)?;
+        chunk.copy_from_slice(&val.to_le_bytes()[..chunk.len()]);
+    }
+
+    Ok(())
+}
+
+/// Measure `data` into a TPM PCR and record it in the event log
+///
+/// # Parameters
+///
+/// * `pcr`         - The PCR to extend
+/// * `data`        - The data to hash and extend the PCR with
+/// * `description` - Event data describing what was measured, this is
+///                   truncated if it is very long
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn tpm_measure(pcr: u32, data: &[u8], description: &str) -> Result<()> {
+    /// `EV_IPL` event type, used for anything measured by a bootloader
+    const EV_IPL: u32 = 0xd;
+
+    /// Size of the `EFI_TCG2_EVENT_HEADER`
+    const HEADER_SIZE: usize = 14;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    // Build the `EFI_TCG2_EVENT`, which is the total size followed by the
+    // header and the event data
+    let mut event = [0u8; 256];
+    let desc = description.as_bytes();
+    let desc = &desc[..desc.len().min(event.len() - 4 - HEADER_SIZE)];
+    let size = 4 + HEADER_SIZE + desc.len();
+    event[ 0.. 4].copy_from_slice(&(size as u32).to_le_bytes());
+    event[ 4.. 8].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+    event[ 8..10].copy_from_slice(&1u16.to_le_bytes());
+    event[10..14].copy_from_slice(&pcr.to_le_bytes());
+    event[14..18].copy_from_slice(&EV_IPL.to_le_bytes());
+    event[18..size].copy_from_slice(desc);
+
+    unsafe {
+        let tcg2 = (*(*st).boot_services).locate::<EfiTcg2Protocol>()
+            .map_err(Error::NoTpm)?;
+        let ret: EfiStatus = ((*tcg2).hash_log_extend_event)(tcg2, 0,
+            data.as_ptr() as u64, data.len() as u64, event.as_ptr()).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::TpmMeasure(ret));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a copy of the TPM event log which stays valid after exiting boot
+/// services
+///
+/// Measurements done after this is called are not part of the copy.
+///
+/// # Returns
+///
+/// The location of the copied TCG2 crypto agile event log, on error
+/// [`Error`]
+///
+pub fn tpm_event_log() -> Result<boot_info::TpmEventLog> {
+    /// `EFI_TCG2_EVENT_LOG_FORMAT_TCG_2`, the crypto agile log format
+    const EVENT_LOG_FORMAT_TCG_2: u32 = 2;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let bs = &*(*st).boot_services;
+        let tcg2 = bs.locate::<EfiTcg2Protocol>().map_err(Error::NoTpm)?;
+
+        // Get the event log
+        let mut location   = 0u64;
+        let mut last_entry = 0u64;
+        let mut truncated  = false;
+        let ret: EfiStatus = ((*tcg2).get_event_log)(tcg2,
+            EVENT_LOG_FORMAT_TCG_2, &mut location, &mut last_entry,
+            &mut truncated).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::TpmEventLog(ret));
+        }
+
+        // An empty log
+        if location == 0 {
+            return Ok(boot_info::TpmEventLog::default());
+        }
+
+        // The log is only given as the start of its first and last entry, so
+        // find the size of the last entry
+        let size = last_entry.checked_sub(location)
+            .and_then(|x| x.checked_add(tcg2_event_size(
+                last_entry, last_entry == location)?))
+            .ok_or(Error::BadTpmEventLog)? as usize;
+
+        // Copy the log somewhere which is not handed out after exiting boot
+        // services
+        let copy = bs.allocate_loader_data(size)?;
+        core::ptr::copy_nonoverlapping(location as *const u8, copy, size);
+
+        Ok(boot_info::TpmEventLog {
+            present:   1,
+            truncated: truncated as u32,
+            addr:      copy as u64,
+            size:      size as u64,
+        })
+    }
+}
+
+/// Compute the size of a TCG2 event log entry
+///
+/// # Parameters
+///
+/// * `addr`   - The address of the entry
+/// * `legacy` - `true` for the first entry of the log, which is always in the
+///              SHA1 only `TCG_PCR_EVENT` format
+///
+/// # Returns
+///
+/// The size of the entry (in bytes), or `None` if it uses a digest algorithm
+/// we don't know the size of
+///
+unsafe fn tcg2_event_size(addr: u64, legacy: bool) -> Option<u64> {
+    /// Read a little-endian `u32` at `offset` into the entry
+    unsafe fn read_u32(addr: u64, offset: u64) -> u32 {
+        core::ptr::read_unaligned((addr + offset) as *const u32)
+    }
+
+    // `TCG_PCR_EVENT` is the PCR index, event type and a SHA1 digest
+    // followed by the event size and data
+    if legacy {
+        return Some(32 + read_u32(addr, 28) as u64);
+    }
+
+    // `TCG_PCR_EVENT2` is the PCR index and event type followed by a list
+    // of digests, the event size and data
+    let count = read_u32(addr, 8);
+    let mut offset = 12u64;
+    for _ in 0..count {
+        let alg = core::ptr::read_unaligned((addr + offset) as *const u16);
+        let digest_size = match alg {
+            0x0004 => 20, // SHA1
+            0x000b => 32, // SHA256
+            0x000c => 48, // SHA384
+            0x000d => 64, // SHA512
+            0x0012 => 32, // SM3_256
+            _      => return None,
+        };
+        offset += 2 + digest_size;
+    }
+
+    Some(offset + 4 + read_u32(addr, offset) as u64)
+}
+
+/// A processor as reported by the MP services protocol
+#[derive(Clone, Copy, Debug)]
+pub struct Processor {
+    /// The APIC ID of the processor on x86_64, the MPIDR on aarch64
+    pub id: u64,
+
+    /// Set if this is the bootstrap processor
+    pub bsp: bool,
+
+    /// Set if the processor is enabled
+    pub enabled: bool,
+
+    /// Set if the processor passed its built-in self test
+    pub healthy: bool,
+
+    /// The physical package of the processor
+    pub package: u32,
+
+    /// The core of the processor within its package
+    pub core: u32,
+
+    /// The hardware thread of the processor within its core
+    pub thread: u32,
+}
+
+/// Get the processors known to the firmware
+///
+/// # Returns
+///
+/// Every processor the firmware reports, on error [`Error`]
+///
+pub fn processors() -> Result<Vec<Processor>> {
+    /// `StatusFlag` bit set for the bootstrap processor
+    const PROCESSOR_AS_BSP_BIT: u32 = 1 << 0;
+
+    /// `StatusFlag` bit set for enabled processors
+    const PROCESSOR_ENABLED_BIT: u32 = 1 << 1;
+
+    /// `StatusFlag` bit set for processors which passed their self test
+    const PROCESSOR_HEALTH_STATUS_BIT: u32 = 1 << 2;
+
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    unsafe {
+        let mp = (*(*st).boot_services).locate::<EfiMpServicesProtocol>()
+            .map_err(Error::NoMpServices)?;
+
+        // Get the number of processors
+        let mut count = 0;
+        let mut enabled = 0;
+        let ret: EfiStatus = ((*mp).get_number_of_processors)(mp,
+            &mut count, &mut enabled).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::ProcessorInfo(ret));
+        }
+
+        // Get the information about each of them
+        let mut processors = Vec::with_capacity(count);
+        for ii in 0..count {
+            let mut info = EfiProcessorInformation::default();
+            let ret: EfiStatus =
+                ((*mp).get_processor_info)(mp, ii, &mut info).into();
+            if ret != EfiStatus::Success {
+                return Err(Error::ProcessorInfo(ret));
+            }
+
+            processors.push(Processor {
+                id:      info.processor_id,
+                bsp:     info.status_flag & PROCESSOR_AS_BSP_BIT != 0,
+                enabled: info.status_flag & PROCESSOR_ENABLED_BIT != 0,
+                healthy: info.status_flag & PROCESSOR_HEALTH_STATUS_BIT != 0,
+                package: info.package,
+                core:    info.core,
+                thread:  info.thread,
+            });
+        }
+
+        Ok(processors)
+    }
+}
+
+/// Run a procedure on every enabled application processor and wait for all
+/// of them to finish
+///
+/// # Parameters
+///
+/// * `procedure`  - The procedure to run, it is passed `arg`
+/// * `arg`        - The argument to pass to `procedure`
+/// * `timeout_us` - Number of microseconds to wait for the processors to
+///                  finish, `0` to wait forever
+///
+/// # Returns
+///
+/// `()` if the procedure finished on every application processor, also if
+/// there are none. On error [`Error`]
+///
+/// # Safety
+///
+/// `procedure` runs concurrently on all application processors, it must be
+/// safe to do so with `arg`. It must not use any boot services.
+///
+pub unsafe fn startup_all_aps(procedure: unsafe extern fn(arg: *mut u8),
+                              arg: *mut u8, timeout_us: usize) -> Result<()> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
+
+    let mp = (*(*st).boot_services).locate::<EfiMpServicesProtocol>()
+        .map_err(Error::NoMpServices)?;
+
+    // Run the procedure in blocking mode
+    let ret: EfiStatus = ((*mp).startup_all_aps)(mp, procedure, false, 0,
+        timeout_us, arg, core::ptr::null_mut()).into();
+    match ret {
+        EfiStatus::Success |
+        EfiStatus::Error(EfiError::NotStarted) => Ok(()),
+        _ => Err(Error::StartupAllAps(ret)),
+    }
+}
+
+/// Kinds of resets supported by `ResetSystem()`
+#[derive(Clone, Copy, Debug)]
+#[repr(u32)]
+pub enum ResetType {
+    /// A system-wide reset which sets all circuitry within the system to its
+    /// initial state
+    Cold = 0,
+
+    /// A system-wide initialization where the processors are set to their
+    /// initial state, and pending cycles are not corrupted
+    Warm = 1,
+
+    /// Power the system off
+    Shutdown = 2,
+}
+
+/// Reset the system using the runtime services
+///
+/// # Parameters
+///
+/// * `reset_type` - The kind of reset to perform
+///
+/// # Returns
+///
+/// This function does not return if the runtime services are available,
+/// otherwise [`Error::NotRegistered`]
+///
+pub fn reset(reset_type: ResetType) -> Error {
+    // Get the runtime services
+    let rt = EFI_RUNTIME_SERVICES.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if rt.is_null() { return Error::NotRegistered; }
+
+    unsafe {
+        ((*rt).reset_system)(reset_type as u32, EfiStatusCode(0), 0,
+                             core::ptr::null())
+    }
+}
+
+/// Exit back to the firmware, which usually continues with its boot manager
+///
+/// # Parameters
+///
+/// * `status` - The status to report as our exit status
+///
+/// # Returns
+///
+/// This function does not return if the exit succeeded, otherwise the
+/// [`Error`] which prevented it
+///
+pub fn exit(status: EfiStatusCode) -> Error {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Error::NotRegistered; }
+
+    // Get our image handle
+    let image = EFI_IMAGE_HANDLE.load(Ordering::SeqCst);
+    if image == 0 { return Error::NotRegistered; }
+
+    let ret: EfiStatus = unsafe {
+        ((*(*st).boot_services).exit)(EfiHandle(image), status, 0,
+            core::ptr::null()).into()
+    };
+    Error::Exit(ret)
+}
+
+/// The `EFI_GLOBAL_VARIABLE` vendor GUID of architecturally defined variables
+const EFI_GLOBAL_VARIABLE: EfiGuid = EfiGuid(
+    0x8be4df61, 0x93ca, 0x11d2,
+    [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]);
+
+/// Read an EFI variable
+///
+/// # Parameters
+///
+/// * `name`   - The name of the variable
+/// * `vendor` - The vendor GUID of the variable
+/// * `data`   - The buffer to read the variable contents into
+///
+/// # Returns
+///
+/// The size of the variable (in bytes), on error [`Error`]
+///
+fn get_variable(name: &str, vendor: &EfiGuid, data: &mut [u8])
+        -> Result<usize> {
+    // Get the system table
+    let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
+
+    // We can't do anything if it's null
+    if st.is_null() { return Err(Error::NotRegistered); }
 
-impl EfiSystemTablePtr {
-    /// Register this system table into a global so it can be used for prints
-    /// which do not take a `self` or a pointer as an argument and thus this
-    /// must be able to be found in a global
-    pub unsafe fn register(self) {
-        let _ = EFI_SYSTEM_TABLE.compare_exchange(
-            core::ptr::null_mut(), self.0, Ordering::SeqCst, Ordering::SeqCst);
+    // Read the variable
+    let wname = variable_name(name)?;
+    let mut size = data.len();
+    let ret: EfiStatus = unsafe {
+        ((*(*st).runtime_services).get_variable)(wname.as_ptr(), vendor,
+            core::ptr::null_mut(), &mut size, data.as_mut_ptr()).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::GetVariable(ret));
     }
+
+    Ok(size)
 }
 
+/// Variable attribute of variables which persist across resets
+const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
 
-/// A pointer to the EFI system table which is saved upon the entry of the
-/// kernel.
+/// Variable attribute of variables which are accessible during boot services
+const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+
+/// Variable attribute of variables which are accessible during runtime
+const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+/// Convert a variable name into a null terminated UCS-2 string
 ///
-/// We'll need access to this table to do input and output to the console
+/// # Parameters
 ///
-static EFI_SYSTEM_TABLE: AtomicPtr<EfiSystemTable> =
-    AtomicPtr::new(core::ptr::null_mut());
+/// * `name` - The name of the variable
+///
+/// # Returns
+///
+/// The UCS-2 name, on error [`Error::VariableNameTooLong`]
+///
+fn variable_name(name: &str) -> Result<[u16; 64]> {
+    let mut wname = [0u16; 64];
+    let mut in_use = 0;
+    for chr in name.encode_utf16() {
+        // Leave room for the null terminator
+        if in_use >= wname.len() - 1 {
+            return Err(Error::VariableNameTooLong);
+        }
 
-/// Write a `string` to the UEFI console output
+        wname[in_use] = chr;
+        in_use += 1;
+    }
+
+    Ok(wname)
+}
+
+/// Write an EFI variable
 ///
 /// # Parameters
 ///
-/// * `string` - The string to write to the UEFI console output using the UEFI
-///              API
+/// * `name`       - The name of the variable
+/// * `vendor`     - The vendor GUID of the variable
+/// * `attributes` - The `EFI_VARIABLE_*` attributes of the variable
+/// * `data`       - The new contents of the variable
 ///
 /// # Returns
 ///
-/// `()`, on error [`Error`]
+/// `()` on success, on error [`Error`]
 ///
-pub fn output_string(string: &str) -> Result<()> {
-    // Get the system_table
+fn set_variable(name: &str, vendor: &EfiGuid, attributes: u32, data: &[u8])
+        -> Result<()> {
+    // Get the system table
     let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
     // We can't do anything if it's null
     if st.is_null() { return Err(Error::NotRegistered); }
 
-    // Get the console out pointer
-    let out = unsafe { (*st).console_out };
+    // Some firmware bricks itself when its variable storage fills up, so
+    // anything but tiny persistent writes must leave a reserve free
+    if attributes & EFI_VARIABLE_NON_VOLATILE != 0 &&
+            data.len() > SMALL_VARIABLE_SIZE {
+        let storage = variable_storage()
+            .map_err(|_| Error::VariableStorageLow(data.len()))?;
+        if storage.remaining < data.len() as u64 + VARIABLE_STORAGE_RESERVE {
+            return Err(Error::VariableStorageLow(data.len()));
+        }
+    }
 
-    // Create a temporary buffer capable of holding 31 characters a a time
-    // plus a null terminator
-    //
-    // We are using UCS-2 and not UTF-16, as that's what UEFI uses. Thus
-    // we don't have to worry about 32-bit code points.
-    let mut tmp = [0u16; 32];
-    let mut in_use = 0;
+    // Write the variable
+    let wname = variable_name(name)?;
+    let ret: EfiStatus = unsafe {
+        ((*(*st).runtime_services).set_variable)(wname.as_ptr(), vendor,
+            attributes, data.len(), data.as_ptr()).into()
+    };
+    if ret != EfiStatus::Success {
+        return Err(Error::SetVariable(ret));
+    }
 
-    // Go through all the characters
-    for chr in string.encode_utf16() {
-        // Inject carriage return if needed. We always make sure there's
-        // room for one based on the way we check the buffer length (-2
-        // instead of -1)
-        if chr == b'\n' as u16 {
-            tmp[in_use] = b'\r' as u16;
-            in_use += 1;
-        }
+    Ok(())
+}
 
-        // Write a character into the buffer
-        tmp[in_use] = chr;
-        in_use += 1;
+/// Size (in bytes) up to which persistent variables are written without
+/// checking the remaining variable storage
+const SMALL_VARIABLE_SIZE: usize = 64;
 
-        // If the temporary buffer could potentially be full on the next
-        // iteration, we flush it. We do -2 here because we need room for the
-        // worst case which is a carriage return, newline, and null terminator
-        // in the next iteration. We also need to do >= because we can
-        // potentially skip from 29 in use to 31 in use if the 30th character
-        // is a newline.
-        if in_use >= (tmp.len() - 2) {
-            // Null terminate the buffer
-            tmp[in_use] = 0;
+/// Number of bytes of persistent variable storage we always leave free for
+/// the firmware
+const VARIABLE_STORAGE_RESERVE: u64 = 16 * 1024;
 
-            // Write out the buffer
-            unsafe { ((*out).output_string)(out, tmp.as_ptr()); }
+/// The persistent variable storage of the firmware
+#[derive(Clone, Copy, Debug)]
+pub struct VariableStorage {
+    /// Size (in bytes) of the storage for persistent variables
+    pub max_storage: u64,
 
-            // Clear the buffer
-            in_use = 0;
-        }
-    }
+    /// Size (in bytes) of the storage still free for persistent variables
+    pub remaining: u64,
 
-    // Write out any remaining characters
-    if in_use > 0 {
-        // Null terminate the buffer
-        tmp[in_use] = 0;
+    /// Maximum size (in bytes) of a single persistent variable
+    pub max_variable_size: u64,
+}
 
-        unsafe { ((*out).output_string)(out, tmp.as_ptr()); }
+impl core::fmt::Display for VariableStorage {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} of {} bytes free, variables up to {} bytes",
+               self.remaining, self.max_storage, self.max_variable_size)
     }
-
-    Ok(())
 }
 
-/// Get the base of the ACPI table RSDP
+/// Query the persistent variable storage of the firmware
 ///
 /// # Returns
 ///
-/// The base address of the ACPI RSDP table as specified by EFI, on error
-/// [`Error`]
+/// The [`VariableStorage`] of the firmware, on error [`Error`]
 ///
-pub fn get_acpi_table() -> Result<usize> {
-    /// ACPI 2.0 or newer tables should use EFI_ACPI_TABLE_GUID
-    const EFI_ACPI_TABLE_GUID: EfiGuid = EfiGuid(
-        0x8868e871, 0xe4f1, 0x11d3,
-        [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]);  
-
-    /// ACPI 1.0 tables use this GUID
-    const ACPI_TABLE_GUID: EfiGuid = EfiGuid(
-        0xeb9d2d30, 0x2d88, 0x11d3,
-        [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
-
+pub fn variable_storage() -> Result<VariableStorage> {
     // Get the system table
     let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
     // We can't do anything if it's null
     if st.is_null() { return Err(Error::NotRegistered); }
 
-    // Get a Rust slice to the tables
-    let tables = unsafe {
-        core::slice::from_raw_parts(
-            (*st).tables,
-            (*st).number_of_tables)
+    unsafe {
+        // `QueryVariableInfo()` was only introduced in UEFI 2.0
+        let rt = &*(*st).runtime_services;
+        if rt.header.revision < 2 << 16 {
+            return Err(Error::QueryVariableInfo(
+                EfiStatus::Error(EfiError::Unsupported)));
+        }
+
+        let mut storage = VariableStorage {
+            max_storage:       0,
+            remaining:         0,
+            max_variable_size: 0,
+        };
+        let ret: EfiStatus = (rt.query_variable_info)(
+            EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS |
+            EFI_VARIABLE_RUNTIME_ACCESS, &mut storage.max_storage,
+            &mut storage.remaining, &mut storage.max_variable_size).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::QueryVariableInfo(ret));
+        }
+
+        Ok(storage)
+    }
+}
+
+/// Make the firmware boot the entry following ours in the boot order on the
+/// next boot, by setting the `BootNext` variable
+///
+/// # Returns
+///
+/// The number of the `Boot####` option which will be booted next, on error
+/// [`Error`]
+///
+pub fn set_boot_next() -> Result<u16> {
+    // Find out which boot option we were started from
+    let mut current = [0u8; 2];
+    get_variable("BootCurrent", &EFI_GLOBAL_VARIABLE, &mut current)?;
+    let current = u16::from_le_bytes(current);
+
+    // Get the entry after it in the boot order
+    let mut order = [0u8; 256];
+    let size = get_variable("BootOrder", &EFI_GLOBAL_VARIABLE, &mut order)?;
+    let next = order[..size].chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .skip_while(|&x| x != current)
+        .nth(1)
+        .ok_or(Error::NoNextBootOption)?;
+
+    set_variable("BootNext", &EFI_GLOBAL_VARIABLE,
+        EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS |
+        EFI_VARIABLE_RUNTIME_ACCESS, &next.to_le_bytes())?;
+    Ok(next)
+}
+
+/// The state of UEFI secure boot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecureBootState {
+    /// The firmware does not implement secure boot
+    Unsupported,
+
+    /// No platform key is enrolled, so nothing is verified and the secure
+    /// boot keys may be changed by anyone
+    SetupMode,
+
+    /// Secure boot is implemented but turned off
+    Disabled,
+
+    /// Secure boot is enforced, every image we are given was verified by the
+    /// firmware
+    Enabled,
+}
+
+/// Get the secure boot state from the `SecureBoot` and `SetupMode` variables
+///
+/// # Returns
+///
+/// The [`SecureBootState`] of the platform, on error [`Error`]
+///
+pub fn secure_boot_state() -> Result<SecureBootState> {
+    /// Read a one byte boolean global variable, missing variables are `None`
+    fn read_bool(name: &str) -> Result<Option<bool>> {
+        let mut val = [0u8; 1];
+        match get_variable(name, &EFI_GLOBAL_VARIABLE, &mut val) {
+            Ok(_) => Ok(Some(val[0] != 0)),
+            Err(Error::GetVariable(
+                EfiStatus::Error(EfiError::NotFound))) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    Ok(match (read_bool("SecureBoot")?, read_bool("SetupMode")?) {
+        (None, _)              => SecureBootState::Unsupported,
+        (_, Some(true))        => SecureBootState::SetupMode,
+        (Some(true), _)        => SecureBootState::Enabled,
+        (Some(false), _)       => SecureBootState::Disabled,
+    })
+}
+
+/// Set while we are in between getting the memory map and exiting boot
+/// services. Output to the EFI console is suppressed during this window as it
+/// may allocate memory, which invalidates the memory map key.
+static EXITING_BOOT_SERVICES: AtomicBool = AtomicBool::new(false);
+
+/// Convert a raw EFI memory map into a [`TaggedRangeSet`] of the kinds of
+/// memory
+///
+/// # Parameters
+///
+/// * `memory_map` - The raw memory map as returned by `GetMemoryMap()`
+/// * `mdesc_size` - The size (in bytes) of each memory descriptor
+///
+/// # Returns
+///
+/// The [`TaggedRangeSet`] containing every described range of physical
+/// addresses with the [`MemoryKind`] it is after exiting boot services, and
+/// a copy of every descriptor for the kernel. On error [`Error`] .
+///
+fn parse_memory_map(memory_map: &[u8], mdesc_size: usize)
+        -> Result<(TaggedRangeSet<MemoryKind>, boot_info::MemoryMap)> {
+    // Make sure the descriptors are at least as large as we expect them to be
+    if mdesc_size < size_of::<EfiMemoryDescriptor>() {
+        return Err(Error::MemoryMapOutOfBounds);
+    }
+
+    // The Rust memory map
+    let mut memory = TaggedRangeSet::new();
+
+    // The copy of the descriptors for the kernel
+    let mut raw = boot_info::MemoryMap {
+        num_descriptors: 0,
+        dropped:         0,
+        descriptors:     [Default::default();
+                          boot_info::MAX_MEMORY_DESCRIPTORS],
     };
 
-    // First look for the ACPI 2.0 table, if we can't find it, then look
-    // for the ACPI 1.0 table
-    tables.iter().find_map(|EfiConfigurationTable { guid, table }| {
-        (guid == &EFI_ACPI_TABLE_GUID).then_some(*table)
-    }).or_else(|| {
-        tables.iter().find_map(|EfiConfigurationTable { guid, table }| {
-            (guid == &ACPI_TABLE_GUID).then_some(*table)
-        })
-    }).ok_or(Error::AcpiTableNotFound)
+    // Go through each memory map entry
+    for off in (0..memory_map.len()).step_by(mdesc_size) {
+        // Read the memory as a descriptor
+        let entry = unsafe {
+            core::ptr::read_unaligned(
+                memory_map.get(off..)
+                    .ok_or(Error::MemoryMapOutOfBounds)?
+                    .get(..size_of::<EfiMemoryDescriptor>())
+                    .ok_or(Error::MemoryMapOutOfBounds)?
+                    .as_ptr() as *const EfiMemoryDescriptor)
+        };
+
+        // Keep a copy of the descriptor
+        match raw.descriptors.get_mut(raw.num_descriptors as usize) {
+            Some(desc) => {
+                *desc = boot_info::MemoryDescriptor {
+                    typ:             entry.typ,
+                    physical_start:  entry.physical_start,
+                    virtual_start:   entry.virtual_start,
+                    number_of_pages: entry.number_of_pages,
+                    attribute:       entry.attribute,
+                };
+                raw.num_descriptors += 1;
+            }
+            None => raw.dropped += 1,
+        }
+
+        // Convert the type into our Rust enum
+        let kind: MemoryKind = EfiMemoryType::from(entry.typ).into();
+
+        // Record what this memory is after we exit boot services
+        if entry.number_of_pages > 0 {
+            // Get the number of bytes for this memory region
+            let bytes = entry.number_of_pages.checked_mul(4096)
+                .ok_or(Error::MemoryMapIntegerOverflow)?;
+
+            // Compute the end physical address of this region
+            let end = entry.physical_start.checked_add(bytes - 1)
+                .ok_or(Error::MemoryMapIntegerOverflow)?;
+
+            // Set the memory information
+            memory.insert(Range {
+                start: entry.physical_start,
+                end:   end
+            }, kind).map_err(Error::MemoryRangeSet)?;
+        }
+    }
+
+    Ok((memory, raw))
 }
 
 /// Get the memory map for the system from the UEFI, and exit boot services
 ///
+/// `ExitBootServices()` fails with `EFI_INVALID_PARAMETER` if the memory map
+/// changed after it was obtained, in which case the memory map is obtained
+/// again with a fresh key and the exit is retried.
+///
 /// # Parameters
 ///
 /// * `image_handle` - The handle to the EFI image as passed into `efi_main`
 ///
 /// # Returns
 ///
-/// The [`RangeSet`] containing the ranges of physical addresses which are
-/// available for general purpose use from this point onwards. On error
-/// [`Error`] .
+/// The [`TaggedRangeSet`] containing the ranges of physical addresses with
+/// the [`MemoryKind`] they are from this point onwards, and the full memory
+/// map the exit was done with. On error [`Error`] .
 /// 
 /// # Safety
 ///
@@ -199,85 +2399,173 @@ pub fn get_acpi_table() -> Result<usize> {
 /// the [`EFI_SYSTEM_TABLE`] when we delete it.
 ///
 pub unsafe fn get_memory_map_and_exit_boot_services(image_handle: EfiHandle)
-        -> Result<RangeSet> {
+        -> Result<(TaggedRangeSet<MemoryKind>, boot_info::MemoryMap)> {
+    /// Number of times to retry exiting boot services
+    const MAX_ATTEMPTS: usize = 8;
+
     // Get the system table
     let st = EFI_SYSTEM_TABLE.load(Ordering::SeqCst);
 
     // We can't do anything if it's null
     if st.is_null() { return Err(Error::NotRegistered); }
 
-    // Create an empty memory map
-    let mut memory_map = [0u8; 16 * 1024];
+    // Create an empty memory map, it lives on the heap so growing it does
+    // not change the EFI memory map
+    let mut memory_map = alloc::vec![0u8; 16 * 1024];
+
+    // Do not print to the EFI console until we're done
+    EXITING_BOOT_SERVICES.store(true, Ordering::SeqCst);
+
+    let mut ret = Err(Error::ExitBootServicesRetries);
+    for _ in 0..MAX_ATTEMPTS {
+        // Set up the initial arguments to the `get_memory_map` EFI call
+        let mut size = memory_map.len();
+        let mut key = 0;
+        let mut mdesc_size = 0;
+        let mut mdesc_version = 0;
+
+        // Get the memory map
+        let status: EfiStatus = ((*(*st).boot_services).get_memory_map)(
+            &mut size,
+            memory_map.as_mut_ptr(),
+            &mut key,
+            &mut mdesc_size,
+            &mut mdesc_version).into();
+
+        // Check that the memory map was obtained, `size` holds the required
+        // size if the buffer was too small. Leave some room for the
+        // descriptors which growing the buffer might add.
+        match status {
+            EfiStatus::Error(EfiError::BufferTooSmall) => {
+                memory_map.resize(size + 8 * mdesc_size, 0);
+                continue;
+            }
+            EfiStatus::Error(_) => {
+                ret = Err(Error::MemoryMap(status));
+                break;
+            }
+            _ => {}
+        }
 
-    // The Rust memory map
-    let mut usable_memory = RangeSet::new();
+        // Parse the memory map
+        let parsed = match memory_map.get(..size)
+                .ok_or(Error::MemoryMapOutOfBounds)
+                .and_then(|map| parse_memory_map(map, mdesc_size)) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                ret = Err(err);
+                break;
+            }
+        };
+
+        // Exit boot services
+        let status: EfiStatus = ((*(*st).boot_services).exit_boot_services)(
+            image_handle, key).into();
+        match status {
+            EfiStatus::Success => {
+                ret = Ok(parsed);
+                break;
+            }
+
+            // The memory map key is stale, try again
+            EfiStatus::Error(EfiError::InvalidParameter) => continue,
+
+            _ => {
+                ret = Err(Error::ExitBootServices(status));
+                break;
+            }
+        }
+    }
 
-    // Set up the initial arguments to the `get_memory_map` EFI call
-    let mut size = core::mem::size_of_val(&memory_map);
-    let mut key = 0;
-    let mut mdesc_size = 0;
-    let mut mdesc_version = 0;
+    if ret.is_ok() {
+        // Destroy the system table
+        EFI_SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::SeqCst);
+    }
 
-    // Get the memory map
-    let ret: EfiStatus = ((*(*st).boot_services).get_memory_map)(
-        &mut size,
-        memory_map.as_mut_ptr(),
-        &mut key,
-        &mut mdesc_size,
-        &mut mdesc_version).into();
+    // Printing is fine again, either boot services are still up or the
+    // system table is gone
+    EXITING_BOOT_SERVICES.store(false, Ordering::SeqCst);
+    
+    ret
+}
 
-    // Check that the memory map was obtained
-    if let EfiStatus::Error(_) = ret {
-        return Err(Error::MemoryMap(ret));
-    }
+/// Switch the runtime services to virtual addressing
+///
+/// Every region with the `EFI_MEMORY_RUNTIME` attribute is assigned the
+/// virtual address `physical_start + offset`, the kernel must map the regions
+/// there before calling into the runtime services. This can only be done
+/// once, after boot services have been exited.
+///
+/// # Parameters
+///
+/// * `memory_map` - The memory map returned by
+///                  [`get_memory_map_and_exit_boot_services`], the virtual
+///                  addresses of the runtime regions are filled in
+/// * `offset`     - The offset of the virtual addresses from the physical
+///                  addresses
+///
+/// # Returns
+///
+/// The virtual address of the runtime services table, on error [`Error`]
+///
+/// # Safety
+///
+/// Unless `offset` is zero we can not use the runtime services ourselves
+/// anymore, so they are unregistered. This must be called in a single
+/// threaded context, as other threads could be using them.
+///
+pub unsafe fn set_virtual_address_map(memory_map: &mut boot_info::MemoryMap,
+                                      offset: u64) -> Result<u64> {
+    /// Attribute of regions which must be mapped for the runtime services
+    const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
 
-    // Go through each memory map entry
-    for off in (0..size).step_by(mdesc_size) {
-        // Read the memory as a descriptor
-        let entry = core::ptr::read_unaligned(
-            memory_map.get(off..)
-                .ok_or(Error::MemoryMapOutOfBounds)?
-                .get(..off + size_of::<EfiMemoryDescriptor>())
-                .ok_or(Error::MemoryMapOutOfBounds)?
-                .as_ptr() as *const EfiMemoryDescriptor);
+    /// The `EFI_MEMORY_DESCRIPTOR_VERSION` our descriptors are laid out as
+    const EFI_MEMORY_DESCRIPTOR_VERSION: u32 = 1;
 
-        // Convert the type into our Rust enum
-        let typ: EfiMemoryType = entry.typ.into();
+    // Get the runtime services
+    let rt = EFI_RUNTIME_SERVICES.load(Ordering::SeqCst);
 
-        // Check if this memory is usable after we exit boot services
-        if typ.avail_post_exit_boot_services() &&
-                entry.number_of_pages > 0 {
-            // Get the number of bytes for this memory region
-            let bytes = entry.number_of_pages.checked_mul(4096)
-                .ok_or(Error::MemoryMapIntegerOverflow)?;
+    // We can't do anything if it's null
+    if rt.is_null() { return Err(Error::NotRegistered); }
 
-            // Compute the end physical address of this region
-            let end = entry.physical_start.checked_add(bytes - 1)
-                .ok_or(Error::MemoryMapIntegerOverflow)?;
+    // The firmware only allows this after exiting boot services
+    if !EFI_SYSTEM_TABLE.load(Ordering::SeqCst).is_null() {
+        return Err(Error::BootServicesNotExited);
+    }
 
-            // Set the usable memory information
-            usable_memory.insert(Range {
-                start: entry.physical_start,
-                end:   end
-            }).map_err(Error::MemoryRangeSet)?;
+    // Every runtime region must be described, or the firmware will not
+    // convert the pointers into it
+    if memory_map.dropped > 0 { return Err(Error::MemoryMapTruncated); }
+
+    // Assign the virtual addresses
+    let descriptors =
+        &mut memory_map.descriptors[..memory_map.num_descriptors as usize];
+    for desc in descriptors.iter_mut() {
+        if desc.attribute & EFI_MEMORY_RUNTIME != 0 {
+            desc.virtual_start = desc.physical_start.checked_add(offset)
+                .ok_or(Error::MemoryMapIntegerOverflow)?;
         }
     }
 
-    // Exit boot services
-    let ret: EfiStatus = ((*(*st).boot_services).exit_boot_services)(
-        image_handle, key).into();
+    // Switch to virtual addressing
+    let mdesc_size = size_of::<boot_info::MemoryDescriptor>();
+    let ret: EfiStatus = ((*rt).set_virtual_address_map)(
+        descriptors.len() * mdesc_size, mdesc_size,
+        EFI_MEMORY_DESCRIPTOR_VERSION, descriptors.as_mut_ptr()).into();
     if ret != EfiStatus::Success {
-        return Err(Error::ExitBootServices(ret));
+        return Err(Error::SetVirtualAddressMap(ret));
     }
 
-    // Destroy the system table
-    EFI_SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::SeqCst);
-    
-    Ok(usable_memory)
+    // The pointers in the runtime services table are virtual now
+    if offset != 0 {
+        EFI_RUNTIME_SERVICES.store(core::ptr::null_mut(), Ordering::SeqCst);
+    }
+
+    (rt as u64).checked_add(offset).ok_or(Error::MemoryMapIntegerOverflow)
 }
 
 /// A collection of related interfaces. Type `VOID *`.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 #[repr(transparent)]
 pub struct EfiHandle(usize);
 
@@ -286,6 +2574,14 @@ pub struct EfiHandle(usize);
 #[repr(transparent)]
 pub struct EfiStatusCode(isize);
 
+impl EfiStatusCode {
+    /// The `EFI_SUCCESS` status code
+    pub const SUCCESS: Self = Self(0);
+
+    /// The `EFI_ABORTED` status code
+    pub const ABORTED: Self = Self(isize::MIN | 21);
+}
+
 /// EFI status codes
 #[derive(Debug, PartialEq, Eq)]
 pub enum EfiStatus {
@@ -361,6 +2657,16 @@ impl From<EfiStatusCode> for EfiStatus {
     }
 }
 
+impl core::fmt::Display for EfiStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            EfiStatus::Success      => write!(f, "success"),
+            EfiStatus::Warning(val) => write!(f, "warning: {}", val),
+            EfiStatus::Error(val)   => write!(f, "{}", val),
+        }
+    }
+}
+
 /// EFI warning codes
 #[derive(Debug, PartialEq, Eq)]
 pub enum EfiWarning {
@@ -393,6 +2699,24 @@ pub enum EfiWarning {
     Unknown(u64),
 }
 
+impl core::fmt::Display for EfiWarning {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let desc = match self {
+            EfiWarning::UnknownGlyph   => "unknown glyph",
+            EfiWarning::DeleteFailure  => "delete failure",
+            EfiWarning::WriteFailure   => "write failure",
+            EfiWarning::BufferTooSmall => "buffer too small",
+            EfiWarning::StaleData      => "stale data",
+            EfiWarning::FileSystem     => "file system",
+            EfiWarning::ResetRequired  => "reset required",
+            EfiWarning::Unknown(code) => {
+                return write!(f, "unknown warning {:#x}", code);
+            }
+        };
+        write!(f, "{}", desc)
+    }
+}
+
 /// EFI error codes
 #[derive(Debug, PartialEq, Eq)]
 pub enum EfiError {
@@ -504,7 +2828,99 @@ pub enum EfiError {
     Unknown(u64),
 }
 
+impl core::fmt::Display for EfiError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let desc = match self {
+            EfiError::LoadError           => "load error",
+            EfiError::InvalidParameter    => "invalid parameter",
+            EfiError::Unsupported         => "unsupported",
+            EfiError::BadBufferSize       => "bad buffer size",
+            EfiError::BufferTooSmall      => "buffer too small",
+            EfiError::NotReady            => "not ready",
+            EfiError::DeviceError         => "device error",
+            EfiError::WriteProtected      => "write protected",
+            EfiError::OutOfResources      => "out of resources",
+            EfiError::VolumeCorrupted     => "volume corrupted",
+            EfiError::VolumeFull          => "volume full",
+            EfiError::NoMedia             => "no media",
+            EfiError::MediaChanged        => "media changed",
+            EfiError::NotFound            => "not found",
+            EfiError::AccessDenied        => "access denied",
+            EfiError::NoResponse          => "no response",
+            EfiError::NoMapping           => "no mapping",
+            EfiError::Timeout             => "timeout",
+            EfiError::NotStarted          => "not started",
+            EfiError::AlreadyStarted      => "already started",
+            EfiError::Aborted             => "aborted",
+            EfiError::IcmpError           => "ICMP error",
+            EfiError::TftpError           => "TFTP error",
+            EfiError::ProtocolError       => "protocol error",
+            EfiError::IncompatibleVersion => "incompatible version",
+            EfiError::SecurityViolation   => "security violation",
+            EfiError::CrcError            => "CRC error",
+            EfiError::EndOfMedia          => "end of media",
+            EfiError::EndOfFile           => "end of file",
+            EfiError::InvalidLanguage     => "invalid language",
+            EfiError::CompromisedData     => "compromised data",
+            EfiError::IpAddressConflict   => "IP address conflict",
+            EfiError::HttpError           => "HTTP error",
+            EfiError::Unknown(code) => {
+                return write!(f, "unknown error {:#x}", code);
+            }
+        };
+        write!(f, "{}", desc)
+    }
+}
+
+/// A point in time as reported by the real time clock
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct EfiTime {
+    /// 1900 - 9999
+    pub year: u16,
+
+    /// 1 - 12
+    pub month: u8,
+
+    /// 1 - 31
+    pub day: u8,
+
+    /// 0 - 23
+    pub hour: u8,
+
+    /// 0 - 59
+    pub minute: u8,
+
+    /// 0 - 59
+    pub second: u8,
+
+    /// Padding
+    _pad1: u8,
+
+    /// 0 - 999,999,999
+    pub nanosecond: u32,
+
+    /// The offset from UTC in minutes, -1440 to 1440 or 2047 if the time is
+    /// local time
+    pub time_zone: i16,
+
+    /// Daylight saving time information
+    pub daylight: u8,
+
+    /// Padding
+    _pad2: u8,
+}
+
+impl core::fmt::Display for EfiTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day,
+            self.hour, self.minute, self.second)
+    }
+}
+
 /// A scan code and unicode value for an input keypress
+#[derive(Default)]
 #[repr(C)]
 struct EfiInputKey {
     /// The scan code for the key pres
@@ -578,25 +2994,94 @@ enum EfiMemoryType {
     /// byte-addressable non-volatility.
     PersistentMemory,
 
-    /// An unknown UEFI memory type
-    Other(u32),
+    /// An unknown UEFI memory type
+    Other(u32),
+}
+
+/// What a range of physical memory is once boot services have been exited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Memory available for general use
+    Usable,
+
+    /// Memory in use by the bootloader, which the kernel may use as it sees
+    /// fit once it is done with the boot information
+    Loader,
+
+    /// Memory of the boot services, which still holds the page tables,
+    /// descriptor tables and stack we run on until the kernel's page table
+    /// is loaded
+    BootServices,
+
+    /// Memory of the runtime services, which must be preserved
+    Runtime,
+
+    /// Memory holding the ACPI tables, which is available for general use
+    /// once they have been parsed
+    AcpiReclaim,
+
+    /// Memory used by the firmware for ACPI, which must be preserved in the
+    /// working and sleep states
+    AcpiNvs,
+
+    /// Memory mapped device registers
+    Mmio,
+
+    /// Byte-addressable non-volatile memory
+    Persistent,
+
+    /// Faulty, reserved or unknown memory which must not be used
+    Reserved,
 }
 
-impl EfiMemoryType {
-    /// Returns whether or not this memory type is available for general
-    /// purpose use after boot services have been exited
+impl MemoryKind {
+    /// Returns whether or not this memory is available for general purpose
+    /// use by the bootloader. Persistent memory is left out, as what it
+    /// holds outlives a reboot and is the kernel's to manage.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the memory may be allocated
+    ///
+    pub fn is_usable(self) -> bool {
+        matches!(self, MemoryKind::Usable)
+    }
+
+    /// Returns whether or not this memory is RAM, which may be mapped cached,
+    /// regardless of who is using it
     ///
-    /// # Returns 
+    /// # Returns
     ///
-    /// `true` if the memory type is usable after exiting boot services
+    /// `true` if the memory is RAM rather than device memory or a hole
     ///
-    fn avail_post_exit_boot_services(&self) -> bool {
-        matches!(self,
-            EfiMemoryType::BootServicesCode   |
-            EfiMemoryType::BootServicesData   |
-            EfiMemoryType::ConventionalMemory |
-            EfiMemoryType::PersistentMemory
-        )
+    pub fn is_ram(self) -> bool {
+        !matches!(self, MemoryKind::Mmio | MemoryKind::Reserved)
+    }
+}
+
+impl Default for MemoryKind {
+    fn default() -> Self {
+        MemoryKind::Reserved
+    }
+}
+
+impl From<EfiMemoryType> for MemoryKind {
+    fn from(typ: EfiMemoryType) -> Self {
+        match typ {
+            EfiMemoryType::BootServicesCode    |
+            EfiMemoryType::BootServicesData    => MemoryKind::BootServices,
+            EfiMemoryType::ConventionalMemory  => MemoryKind::Usable,
+            EfiMemoryType::LoaderCode          |
+            EfiMemoryType::LoaderData          => MemoryKind::Loader,
+            EfiMemoryType::RuntimeServicesCode |
+            EfiMemoryType::RuntimeServicesData => MemoryKind::Runtime,
+            EfiMemoryType::ACPIReclaimMemory   => MemoryKind::AcpiReclaim,
+            EfiMemoryType::ACPIMemoryNVS       => MemoryKind::AcpiNvs,
+            EfiMemoryType::MemoryMappedIO      |
+            EfiMemoryType::MemoryMappedIOPortSpace => MemoryKind::Mmio,
+            EfiMemoryType::PersistentMemory    => MemoryKind::Persistent,
+            _                                  => MemoryKind::Reserved,
+        }
     }
 }
 
@@ -713,10 +3198,14 @@ struct EfiBootServices {
     _restore_tpl: usize,
 
     /// Allocates pages of a particular type
-    _allocate_pages: usize,
+    allocate_pages: unsafe extern fn(typ:         u32,
+                                     memory_type: u32,
+                                     pages:       usize,
+                                     memory:      &mut u64) -> EfiStatusCode,
 
     /// Frees allocated pages
-    _free_pages: usize,
+    free_pages: unsafe extern fn(memory: u64,
+                                 pages:  usize) -> EfiStatusCode,
 
     /// Returns the current boot service memory map and memory map key
     get_memory_map: unsafe extern fn(memory_map_size:    &mut usize,
@@ -730,7 +3219,7 @@ struct EfiBootServices {
     _allocate_pool: usize,
 
     /// Frees allocated pool
-    _free_pool: usize,
+    free_pool: unsafe extern fn(buffer: *mut u8) -> EfiStatusCode,
 
     /// Creates a general-purpose event structure
     _create_event: usize,
@@ -739,7 +3228,10 @@ struct EfiBootServices {
     _set_timer: usize,
 
     /// Stops execution until an event is signaled
-    _wait_for_event: usize,
+    wait_for_event: unsafe extern fn(number_of_events: usize,
+                                     event:            *const usize,
+                                     index:            &mut usize)
+                                         -> EfiStatusCode,
 
     /// Signals an event
     _signal_event: usize,
@@ -760,7 +3252,9 @@ struct EfiBootServices {
     _uninstall_protocol_interface: usize,
 
     /// Queries a handle to determine if it supports a specified protocol
-    _handle_protocol: usize,
+    handle_protocol: unsafe extern fn(handle:    EfiHandle,
+                                      protocol:  *const EfiGuid,
+                                      interface: *mut usize) -> EfiStatusCode,
 
     /// Reserved
     _reserved: usize,
@@ -788,7 +3282,10 @@ struct EfiBootServices {
     _start_image: usize,
 
     /// Exits the image's entry point
-    _exit: usize,
+    exit: unsafe extern fn(image_handle:   EfiHandle,
+                           exit_status:    EfiStatusCode,
+                           exit_data_size: usize,
+                           exit_data:      *const u16) -> EfiStatusCode,
 
     /// Unloads an image
     _unload_image: usize,
@@ -796,6 +3293,886 @@ struct EfiBootServices {
     /// Terminates boot services
     exit_boot_services: unsafe extern fn(image_handle: EfiHandle,
                                          map_key: usize) -> EfiStatusCode,
+
+    /// Returns a monotonically increasing count for the platform
+    _get_next_monotonic_count: usize,
+
+    /// Stalls the processor
+    stall: unsafe extern fn(microseconds: usize) -> EfiStatusCode,
+
+    /// Resets and sets a watchdog timer used during boot services time
+    set_watchdog_timer: unsafe extern fn(timeout:       usize,
+                                         watchdog_code: u64,
+                                         data_size:     usize,
+                                         watchdog_data: *const u16)
+                                             -> EfiStatusCode,
+
+    /// Uses a set of precedence rules to find the best set of drivers to
+    /// manage a controller
+    _connect_controller: usize,
+
+    /// Informs a set of drivers to stop managing a controller
+    _disconnect_controller: usize,
+
+    /// Adds elements to the list of agents consuming a protocol interface
+    open_protocol: unsafe extern fn(handle:            EfiHandle,
+                                    protocol:          *const EfiGuid,
+                                    interface:         *mut usize,
+                                    agent_handle:      EfiHandle,
+                                    controller_handle: EfiHandle,
+                                    attributes:        u32) -> EfiStatusCode,
+
+    /// Removes elements from the list of agents consuming a protocol
+    /// interface
+    _close_protocol: usize,
+
+    /// Retrieve the list of agents that are currently consuming a protocol
+    /// interface
+    _open_protocol_information: usize,
+
+    /// Retrieves the list of protocols installed on a handle. The return
+    /// buffer is automatically allocated.
+    _protocols_per_handle: usize,
+
+    /// Retrieves the list of handles from the handle database that meet the
+    /// search criteria. The return buffer is automatically allocated.
+    locate_handle_buffer: unsafe extern fn(search_type: u32,
+                                           protocol:    *const EfiGuid,
+                                           search_key:  usize,
+                                           no_handles:  &mut usize,
+                                           buffer:      &mut *mut EfiHandle)
+                                               -> EfiStatusCode,
+
+    /// Finds the first handle in the handle database that supports the
+    /// requested protocol
+    locate_protocol: unsafe extern fn(protocol:     *const EfiGuid,
+                                      registration: usize,
+                                      interface:    *mut usize)
+                                          -> EfiStatusCode,
+}
+
+/// Contains pointers to the runtime services, which are available both
+/// before and after exiting boot services
+#[repr(C)]
+struct EfiRuntimeServices {
+    /// The table header for the EFI Runtime Services Table
+    header: EfiTableHeader,
+
+    /// Returns the current time and date information, and the time-keeping
+    /// capabilities of the hardware platform
+    get_time: unsafe extern fn(time:         *mut EfiTime,
+                               capabilities: usize) -> EfiStatusCode,
+
+    /// Sets the current local time and date information
+    _set_time: usize,
+
+    /// Returns the current wakeup alarm clock setting
+    _get_wakeup_time: usize,
+
+    /// Sets the system wakeup alarm clock time
+    _set_wakeup_time: usize,
+
+    /// Used by an OS loader to convert from physical addressing to virtual
+    /// addressing
+    set_virtual_address_map:
+        unsafe extern fn(memory_map_size:    usize,
+                         descriptor_size:    usize,
+                         descriptor_version: u32,
+                         virtual_map:        *mut boot_info::MemoryDescriptor)
+            -> EfiStatusCode,
+
+    /// Used by EFI components to convert internal pointers when switching to
+    /// virtual addressing
+    _convert_pointer: usize,
+
+    /// Returns the value of a variable
+    get_variable: unsafe extern fn(variable_name: *const u16,
+                                   vendor_guid:   *const EfiGuid,
+                                   attributes:    *mut u32,
+                                   data_size:     &mut usize,
+                                   data:          *mut u8) -> EfiStatusCode,
+
+    /// Enumerates the current variable names
+    _get_next_variable_name: usize,
+
+    /// Sets the value of a variable
+    set_variable: unsafe extern fn(variable_name: *const u16,
+                                   vendor_guid:   *const EfiGuid,
+                                   attributes:    u32,
+                                   data_size:     usize,
+                                   data:          *const u8) -> EfiStatusCode,
+
+    /// Returns the next high 32 bits of the platform's monotonic counter
+    _get_next_high_monotonic_count: usize,
+
+    /// Resets the entire platform
+    reset_system: unsafe extern fn(reset_type:   u32,
+                                   reset_status: EfiStatusCode,
+                                   data_size:    usize,
+                                   reset_data:   *const u8) -> !,
+
+    /// Passes capsules to the firmware
+    _update_capsule: usize,
+
+    /// Returns if the capsules can be passed to the firmware
+    query_capsule_capabilities:
+        unsafe extern fn(capsule_header_array: *const *const EfiCapsuleHeader,
+                         capsule_count:        usize,
+                         maximum_capsule_size: &mut u64,
+                         reset_type:           &mut u32) -> EfiStatusCode,
+
+    /// Returns information about the EFI variable store
+    query_variable_info:
+        unsafe extern fn(attributes:                      u32,
+                         maximum_variable_storage_size:   &mut u64,
+                         remaining_variable_storage_size: &mut u64,
+                         maximum_variable_size:           &mut u64)
+            -> EfiStatusCode,
+}
+
+/// The header of a capsule passed to the firmware
+#[repr(C)]
+struct EfiCapsuleHeader {
+    /// The GUID identifying the contents of the capsule
+    capsule_guid: EfiGuid,
+
+    /// The size of the capsule header
+    header_size: u32,
+
+    /// Capsule flags
+    flags: u32,
+
+    /// Size of the entire capsule, including the header
+    capsule_image_size: u32,
+}
+
+/// Information about a loaded image
+#[repr(C)]
+struct EfiLoadedImageProtocol {
+    /// Defines the revision of the `EFI_LOADED_IMAGE_PROTOCOL` structure
+    revision: u32,
+
+    /// Parent image's image handle
+    parent_handle: EfiHandle,
+
+    /// The image's EFI system table pointer
+    _system_table: usize,
+
+    /// The device handle that the EFI image was loaded from
+    device_handle: EfiHandle,
+
+    /// A pointer to the file path portion specific to `device_handle` that
+    /// the EFI image was loaded from
+    file_path: *const EfiDevicePathProtocol,
+
+    /// Reserved
+    _reserved: usize,
+
+    /// The size in bytes of `load_options`
+    load_options_size: u32,
+
+    /// A pointer to the image's binary load options
+    load_options: *const u8,
+
+    /// The base address at which the image was loaded
+    image_base: usize,
+
+    /// The size in bytes of the loaded image
+    image_size: u64,
+}
+
+impl Protocol for EfiLoadedImageProtocol {
+    const GUID: EfiGuid = EfiGuid(0x5b1b31a1, 0x9562, 0x11d2,
+        [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+/// Provides services to manage the processors of the system while boot
+/// services are running
+#[repr(C)]
+struct EfiMpServicesProtocol {
+    /// Gets the number of logical processors and the number of enabled
+    /// logical processors
+    get_number_of_processors:
+        unsafe extern fn(this:               *const EfiMpServicesProtocol,
+                         processors:         &mut usize,
+                         enabled_processors: &mut usize) -> EfiStatusCode,
+
+    /// Gets detailed information on the requested processor
+    get_processor_info:
+        unsafe extern fn(this:      *const EfiMpServicesProtocol,
+                         processor: usize,
+                         info:      &mut EfiProcessorInformation)
+            -> EfiStatusCode,
+
+    /// Starts up all the enabled application processors to run a function
+    startup_all_aps:
+        unsafe extern fn(this:            *const EfiMpServicesProtocol,
+                         procedure:       unsafe extern fn(arg: *mut u8),
+                         single_thread:   bool,
+                         wait_event:      usize,
+                         timeout_us:      usize,
+                         arg:             *mut u8,
+                         failed_cpu_list: *mut *mut usize) -> EfiStatusCode,
+
+    /// Starts up a single application processor to run a function
+    _startup_this_ap: usize,
+
+    /// Switches the bootstrap processor
+    _switch_bsp: usize,
+
+    /// Enables or disables an application processor
+    _enable_disable_ap: usize,
+
+    /// Gets the number of the processor calling this function
+    _who_am_i: usize,
+}
+
+impl Protocol for EfiMpServicesProtocol {
+    const GUID: EfiGuid = EfiGuid(0x3fdda605, 0xa76e, 0x4f46,
+        [0xad, 0x29, 0x12, 0xf4, 0x53, 0x1b, 0x3d, 0x08]);
+}
+
+/// Information about a processor, without the extended topology information
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct EfiProcessorInformation {
+    /// The APIC ID or MPIDR of the processor
+    processor_id: u64,
+
+    /// Flags indicating if the processor is the BSP, enabled and healthy
+    status_flag: u32,
+
+    /// Zero-based physical package number
+    package: u32,
+
+    /// Zero-based physical core number within the package
+    core: u32,
+
+    /// Zero-based logical thread number within the core
+    thread: u32,
+}
+
+/// Provides access to the network for PXE booting
+#[repr(C)]
+struct EfiPxeBaseCodeProtocol {
+    /// The revision of the `EFI_PXE_BASE_CODE_PROTOCOL`
+    revision: u64,
+
+    /// Enables the use of the PXE base code protocol functions
+    start: unsafe extern fn(this:     *const EfiPxeBaseCodeProtocol,
+                            use_ipv6: bool) -> EfiStatusCode,
+
+    /// Disables the use of the PXE base code protocol functions
+    _stop: usize,
+
+    /// Attempts to complete a DHCPv4 or DHCPv6 sequence
+    dhcp: unsafe extern fn(this:        *const EfiPxeBaseCodeProtocol,
+                           sort_offers: bool) -> EfiStatusCode,
+
+    /// Attempts to complete the PXE boot server and boot image discovery
+    _discover: usize,
+
+    /// Performs TFTP and MTFTP services
+    mtftp: unsafe extern fn(this:            *const EfiPxeBaseCodeProtocol,
+                            operation:       u32,
+                            buffer:          *mut u8,
+                            overwrite:       bool,
+                            buffer_size:     &mut u64,
+                            block_size:      *const usize,
+                            server_ip:       &[u32; 4],
+                            filename:        *const u8,
+                            info:            *const u8,
+                            dont_use_buffer: bool) -> EfiStatusCode,
+
+    /// Writes a UDP packet to the network interface
+    _udp_write: usize,
+
+    /// Reads a UDP packet from the network interface
+    _udp_read: usize,
+
+    /// Updates the IP receive filters of the network device
+    _set_ip_filter: usize,
+
+    /// Uses the ARP protocol to resolve a MAC address
+    _arp: usize,
+
+    /// Updates the parameters that affect the operation of the protocol
+    _set_parameters: usize,
+
+    /// Updates the station IP address and subnet mask values
+    _set_station_ip: usize,
+
+    /// Updates the contents of the cached DHCP and discover packets
+    _set_packets: usize,
+
+    /// Pointer to the current state of the protocol
+    mode: *const EfiPxeBaseCodeMode,
+}
+
+impl Protocol for EfiPxeBaseCodeProtocol {
+    const GUID: EfiGuid = EfiGuid(0x03c4e603, 0xac28, 0x11d3,
+        [0x9a, 0x2d, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]);
+}
+
+/// The state of the PXE base code protocol, up to the packets we use
+#[repr(C)]
+struct EfiPxeBaseCodeMode {
+    /// Set if the protocol has been started
+    started: bool,
+
+    /// Set if the interface supports IPv6
+    _ipv6_available: bool,
+
+    /// Set if the protocol supports IPv6
+    _ipv6_supported: bool,
+
+    /// Set if IPv6 is in use
+    _using_ipv6: bool,
+
+    /// Set if the boot integrity services are supported
+    _bis_supported: bool,
+
+    /// Set if the boot integrity services are detected
+    _bis_detected: bool,
+
+    /// Set if ARP is done automatically
+    _auto_arp: bool,
+
+    /// Set if the system GUID is sent in DHCP packets
+    _send_guid: bool,
+
+    /// Set if `dhcp_discover` is valid
+    _dhcp_discover_valid: bool,
+
+    /// Set if `dhcp_ack` is valid
+    dhcp_ack_received: bool,
+
+    /// Set if `proxy_offer` is valid
+    proxy_offer_received: bool,
+
+    /// Set if the PXE discover packet is valid
+    _pxe_discover_valid: bool,
+
+    /// Set if the PXE reply packet is valid
+    _pxe_reply_received: bool,
+
+    /// Set if the PXE BIS reply packet is valid
+    _pxe_bis_reply_received: bool,
+
+    /// Set if an ICMP error was received
+    _icmp_error_received: bool,
+
+    /// Set if a TFTP error was received
+    _tftp_error_received: bool,
+
+    /// Set if callbacks are made
+    _make_callbacks: bool,
+
+    /// Time to live of outgoing packets
+    _ttl: u8,
+
+    /// Type of service of outgoing packets
+    _tos: u8,
+
+    /// The IP address of the station
+    _station_ip: [u32; 4],
+
+    /// The subnet mask of the station
+    _subnet_mask: [u32; 4],
+
+    /// The DHCP discover packet which was sent
+    _dhcp_discover: [u8; 1472],
+
+    /// The DHCP acknowledge packet which was received
+    dhcp_ack: [u8; 1472],
+
+    /// The proxy DHCP offer packet which was received
+    proxy_offer: [u8; 1472],
+}
+
+/// Provides access to a serial port through the firmware
+#[repr(C)]
+struct EfiSerialIoProtocol {
+    /// The revision of the `EFI_SERIAL_IO_PROTOCOL`
+    revision: u32,
+
+    /// Resets the hardware device
+    _reset: usize,
+
+    /// Sets communication parameters for a serial device
+    _set_attributes: usize,
+
+    /// Sets the control bits on a serial device
+    _set_control: usize,
+
+    /// Reads the status of the control bits on a serial device
+    _get_control: usize,
+
+    /// Sends a buffer of characters to a serial device
+    write: unsafe extern fn(this:        *const EfiSerialIoProtocol,
+                            buffer_size: &mut usize,
+                            buffer:      *const u8) -> EfiStatusCode,
+
+    /// Receives a buffer of characters from a serial device
+    _read: usize,
+
+    /// Pointer to the current state of the serial device
+    _mode: usize,
+}
+
+impl Protocol for EfiSerialIoProtocol {
+    const GUID: EfiGuid = EfiGuid(0xbb25cf6f, 0xf1d4, 0x11d2,
+        [0x9a, 0x0c, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0xfd]);
+}
+
+/// Provides a minimal interface for file-type access to a device
+#[repr(C)]
+struct EfiSimpleFileSystemProtocol {
+    /// The version of the `EFI_SIMPLE_FILE_SYSTEM_PROTOCOL`
+    revision: u64,
+
+    /// Opens the volume for file I/O access
+    open_volume: unsafe extern fn(this: *const EfiSimpleFileSystemProtocol,
+                                  root: &mut *const EfiFileProtocol)
+                                      -> EfiStatusCode,
+}
+
+impl Protocol for EfiSimpleFileSystemProtocol {
+    const GUID: EfiGuid = EfiGuid(0x964e5b22, 0x6459, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+/// Provides random numbers for use in applications, or entropy for seeding
+/// other random number generators
+#[repr(C)]
+struct EfiRngProtocol {
+    /// Returns information about the random number generation
+    /// implementation
+    _get_info: usize,
+
+    /// Returns the next set of random numbers
+    get_rng: unsafe extern fn(this:             *const EfiRngProtocol,
+                              rng_algorithm:    *const EfiGuid,
+                              rng_value_length: usize,
+                              rng_value:        *mut u8) -> EfiStatusCode,
+}
+
+impl Protocol for EfiRngProtocol {
+    const GUID: EfiGuid = EfiGuid(0x3152bca5, 0xeade, 0x433d,
+        [0x86, 0x2e, 0xc0, 0x1c, 0xdc, 0x29, 0x1f, 0x44]);
+}
+
+/// Provides access to the TPM 2.0 for measurements and the event log
+#[repr(C)]
+struct EfiTcg2Protocol {
+    /// Provides information about the capabilities of the TPM
+    _get_capability: usize,
+
+    /// Get the address of the event log and its last entry
+    get_event_log: unsafe extern fn(this:       *const EfiTcg2Protocol,
+                                    format:     u32,
+                                    location:   &mut u64,
+                                    last_entry: &mut u64,
+                                    truncated:  &mut bool) -> EfiStatusCode,
+
+    /// Measures data into a PCR and records it in the event log
+    hash_log_extend_event: unsafe extern fn(this:   *const EfiTcg2Protocol,
+                                            flags:  u64,
+                                            data:   u64,
+                                            len:    u64,
+                                            event:  *const u8)
+                                                -> EfiStatusCode,
+
+    /// Sends a command directly to the TPM
+    _submit_command: usize,
+
+    /// Get the currently active PCR banks
+    _get_active_pcr_banks: usize,
+
+    /// Set the active PCR banks
+    _set_active_pcr_banks: usize,
+
+    /// Get the result of a previous `SetActivePcrBanks()`
+    _get_result_of_set_active_pcr_banks: usize,
+}
+
+impl Protocol for EfiTcg2Protocol {
+    const GUID: EfiGuid = EfiGuid(0x607f766c, 0x7455, 0x42be,
+        [0x93, 0x0b, 0xe4, 0xd7, 0x6d, 0xb2, 0x72, 0x0f]);
+}
+
+/// Provides a platform independent timestamp counter
+#[repr(C)]
+struct EfiTimestampProtocol {
+    /// Retrieves the current value of the timestamp counter
+    get_timestamp: unsafe extern fn() -> u64,
+
+    /// Obtains the properties of the timestamp counter
+    get_properties:
+        unsafe extern fn(properties: &mut EfiTimestampProperties)
+            -> EfiStatusCode,
+}
+
+impl Protocol for EfiTimestampProtocol {
+    const GUID: EfiGuid = EfiGuid(0xafbfde41, 0x2e6e, 0x4262,
+        [0xba, 0x65, 0x62, 0xb9, 0x23, 0x6e, 0x54, 0x95]);
+}
+
+/// The properties of the timestamp counter
+#[derive(Default)]
+#[repr(C)]
+struct EfiTimestampProperties {
+    /// The frequency of the counter in Hz
+    frequency: u64,
+
+    /// The value the counter has before it rolls over to zero
+    end_value: u64,
+}
+
+/// Abstracts access to a block device, such as a disk or a partition
+#[repr(C)]
+struct EfiBlockIoProtocol {
+    /// The revision to which the block IO interface adheres
+    revision: u64,
+
+    /// Pointer to the media information of the device
+    media: *const EfiBlockIoMedia,
+
+    /// Resets the block device hardware
+    _reset: usize,
+
+    /// Reads the requested number of blocks from the device
+    read_blocks: unsafe extern fn(this:        *const EfiBlockIoProtocol,
+                                  media_id:    u32,
+                                  lba:         u64,
+                                  buffer_size: usize,
+                                  buffer:      *mut u8) -> EfiStatusCode,
+
+    /// Writes the requested number of blocks to the device
+    _write_blocks: usize,
+
+    /// Flushes all modified data to the physical block device
+    _flush_blocks: usize,
+}
+
+impl Protocol for EfiBlockIoProtocol {
+    const GUID: EfiGuid = EfiGuid(0x964e5b21, 0x6459, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+/// Information about the media of a block device
+#[repr(C)]
+struct EfiBlockIoMedia {
+    /// The current media ID, which changes when the media changes
+    media_id: u32,
+
+    /// Non-zero if the media is removable
+    removable_media: u8,
+
+    /// Non-zero if there is media currently present in the device
+    media_present: u8,
+
+    /// Non-zero if the device is a partition of a larger device
+    logical_partition: u8,
+
+    /// Non-zero if the media is marked read-only
+    read_only: u8,
+
+    /// Non-zero if the device caches writes
+    write_caching: u8,
+
+    /// The intrinsic block size of the device
+    block_size: u32,
+
+    /// Supplies the alignment requirement for any buffer used in a data
+    /// transfer
+    io_align: u32,
+
+    /// The last logical block address on the device
+    last_block: u64,
+}
+
+/// Provides file based access to supported file systems
+#[repr(C)]
+struct EfiFileProtocol {
+    /// The version of the `EFI_FILE_PROTOCOL` interface
+    revision: u64,
+
+    /// Opens a new file relative to the source directory's location
+    open: unsafe extern fn(this:        *const EfiFileProtocol,
+                           new_handle:  &mut *const EfiFileProtocol,
+                           file_name:   *const u16,
+                           open_mode:   u64,
+                           attributes:  u64) -> EfiStatusCode,
+
+    /// Closes a specified file handle
+    close: unsafe extern fn(this: *const EfiFileProtocol) -> EfiStatusCode,
+
+    /// Closes and deletes a file
+    _delete: usize,
+
+    /// Reads data from a file
+    read: unsafe extern fn(this:        *const EfiFileProtocol,
+                           buffer_size: &mut usize,
+                           buffer:      *mut u8) -> EfiStatusCode,
+
+    /// Writes data to a file
+    _write: usize,
+
+    /// Returns a file's current position
+    _get_position: usize,
+
+    /// Sets a file's current position
+    _set_position: usize,
+
+    /// Returns information about a file
+    get_info: unsafe extern fn(this:             *const EfiFileProtocol,
+                               information_type: *const EfiGuid,
+                               buffer_size:      &mut usize,
+                               buffer:           *mut u8) -> EfiStatusCode,
+}
+
+/// Generic information about a file, followed by the null terminated name
+/// of the file
+#[repr(C)]
+struct EfiFileInfo {
+    /// Size of the `EFI_FILE_INFO` structure, including the file name
+    size: u64,
+
+    /// The size of the file in bytes
+    file_size: u64,
+
+    /// The amount of physical space the file consumes on the file system
+    /// volume
+    physical_size: u64,
+}
+
+/// An EFI protocol interface which can be looked up by its GUID
+trait Protocol {
+    /// The GUID identifying this protocol
+    const GUID: EfiGuid;
+}
+
+impl EfiBootServices {
+    /// Find the first instance of the protocol `T` in the handle database
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the protocol interface, on error the [`EfiStatus`]
+    /// reported by `LocateProtocol()`
+    ///
+    unsafe fn locate<T: Protocol>(&self)
+            -> core::result::Result<*const T, EfiStatus> {
+        let mut interface = 0usize;
+        let ret: EfiStatus =
+            (self.locate_protocol)(&T::GUID, 0, &mut interface).into();
+        match ret {
+            EfiStatus::Success if interface != 0 => Ok(interface as *const T),
+            EfiStatus::Success => Err(EfiStatus::Error(EfiError::NotFound)),
+            _ => Err(ret),
+        }
+    }
+
+    /// Get the protocol `T` on `handle`, on behalf of our image
+    ///
+    /// # Parameters
+    ///
+    /// * `handle` - The handle which is expected to support `T`
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the protocol interface, on error the [`EfiStatus`]
+    /// reported by `OpenProtocol()`
+    ///
+    unsafe fn open<T: Protocol>(&self, handle: EfiHandle)
+            -> core::result::Result<*const T, EfiStatus> {
+        /// Get the interface without taking ownership of the protocol
+        const EFI_OPEN_PROTOCOL_GET_PROTOCOL: u32 = 0x2;
+
+        let mut interface = 0usize;
+        let ret: EfiStatus = (self.open_protocol)(handle, &T::GUID,
+            &mut interface, EfiHandle(EFI_IMAGE_HANDLE.load(Ordering::SeqCst)),
+            EfiHandle(0), EFI_OPEN_PROTOCOL_GET_PROTOCOL).into();
+        match ret {
+            EfiStatus::Success if interface != 0 => Ok(interface as *const T),
+            EfiStatus::Success => Err(EfiStatus::Error(EfiError::NotFound)),
+            _ => Err(ret),
+        }
+    }
+
+    /// Get all handles which support the protocol `T`
+    ///
+    /// # Parameters
+    ///
+    /// * `handles` - Buffer to store the handles into, any handles which do
+    ///               not fit are dropped
+    ///
+    /// # Returns
+    ///
+    /// The number of handles stored in `handles`, on error the [`EfiStatus`]
+    /// reported by `LocateHandleBuffer()`
+    ///
+    unsafe fn locate_handles<T: Protocol>(&self, handles: &mut [EfiHandle])
+            -> core::result::Result<usize, EfiStatus> {
+        /// Search for handles supporting a protocol
+        const BY_PROTOCOL: u32 = 2;
+
+        let mut count  = 0;
+        let mut buffer = core::ptr::null_mut();
+        let ret: EfiStatus = (self.locate_handle_buffer)(BY_PROTOCOL,
+            &T::GUID, 0, &mut count, &mut buffer).into();
+        if ret != EfiStatus::Success {
+            return Err(ret);
+        }
+
+        // Copy out the handles and give the buffer back to the firmware
+        let count = count.min(handles.len());
+        handles[..count].copy_from_slice(
+            core::slice::from_raw_parts(buffer, count));
+        (self.free_pool)(buffer as *mut u8);
+
+        Ok(count)
+    }
+
+    /// Allocate `size` bytes of `EfiLoaderData` pages, which will stay
+    /// reserved after boot services are exited
+    ///
+    /// # Parameters
+    ///
+    /// * `size` - The number of bytes to allocate, rounded up to whole pages
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the page aligned allocation, on error [`Error`]
+    ///
+    unsafe fn allocate_loader_data(&self, size: usize) -> Result<*mut u8> {
+        /// Allocate any available range of pages
+        const ALLOCATE_ANY_PAGES: u32 = 0;
+
+        /// `EfiLoaderData` memory type for the allocated pages
+        const EFI_LOADER_DATA: u32 = 2;
+
+        let mut addr = 0u64;
+        let ret: EfiStatus = (self.allocate_pages)(ALLOCATE_ANY_PAGES,
+            EFI_LOADER_DATA, (size + 0xfff) / 0x1000, &mut addr).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::AllocatePages(ret));
+        }
+
+        Ok(addr as *mut u8)
+    }
+
+    /// Give pages from [`EfiBootServices::allocate_loader_data`] back to the
+    /// firmware
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The address of the allocation
+    /// * `size` - The size (in bytes) the allocation was made with
+    ///
+    unsafe fn free_loader_data(&self, addr: *mut u8, size: usize) {
+        (self.free_pages)(addr as u64, (size + 0xfff) / 0x1000);
+    }
+
+    /// Allocate `size` bytes of `EfiLoaderData` pages at or below `last`,
+    /// which will stay reserved after boot services are exited
+    ///
+    /// # Parameters
+    ///
+    /// * `last` - The last physical address the allocation may use
+    /// * `size` - The number of bytes to allocate, rounded up to whole pages
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the page aligned allocation, on error [`Error`]
+    ///
+    unsafe fn allocate_loader_data_below(&self, last: u64, size: usize)
+            -> Result<*mut u8> {
+        /// Allocate any range of pages whose last byte is at or below the
+        /// given address
+        const ALLOCATE_MAX_ADDRESS: u32 = 1;
+
+        /// `EfiLoaderData` memory type for the allocated pages
+        const EFI_LOADER_DATA: u32 = 2;
+
+        let mut addr = last;
+        let ret: EfiStatus = (self.allocate_pages)(ALLOCATE_MAX_ADDRESS,
+            EFI_LOADER_DATA, (size + 0xfff) / 0x1000, &mut addr).into();
+        if ret != EfiStatus::Success {
+            return Err(Error::AllocatePages(ret));
+        }
+
+        Ok(addr as *mut u8)
+    }
+}
+
+/// Provides a basic abstraction to set video modes and copy pixels to and
+/// from the graphics controller's frame buffer
+#[repr(C)]
+struct EfiGraphicsOutputProtocol {
+    /// Returns information for an available graphics mode that the graphics
+    /// device and the set of active video output devices supports
+    _query_mode: usize,
+
+    /// Set the video device into the specified mode and clears the visible
+    /// portions of the output display to black
+    _set_mode: usize,
+
+    /// Software abstraction to draw on the video device's frame buffer
+    _blt: usize,
+
+    /// Pointer to `EFI_GRAPHICS_OUTPUT_PROTOCOL_MODE` data
+    mode: *const EfiGraphicsOutputProtocolMode,
+}
+
+impl Protocol for EfiGraphicsOutputProtocol {
+    const GUID: EfiGuid = EfiGuid(0x9042a9de, 0x23dc, 0x4a38,
+        [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a]);
+}
+
+/// The current mode of a graphics output device
+#[repr(C)]
+struct EfiGraphicsOutputProtocolMode {
+    /// The number of modes supported by `QueryMode()` and `SetMode()`
+    max_mode: u32,
+
+    /// Current mode of the graphics device
+    mode: u32,
+
+    /// Pointer to read-only `EFI_GRAPHICS_OUTPUT_MODE_INFORMATION` data
+    info: *const EfiGraphicsOutputModeInformation,
+
+    /// Size of `info` structure in bytes
+    size_of_info: usize,
+
+    /// Base address of graphics linear frame buffer
+    frame_buffer_base: u64,
+
+    /// Amount of frame buffer needed to support the active mode
+    frame_buffer_size: usize,
+}
+
+/// Information about a graphics mode
+#[repr(C)]
+struct EfiGraphicsOutputModeInformation {
+    /// The version of this data structure
+    version: u32,
+
+    /// The size of video screen in pixels in the X dimension
+    horizontal_resolution: u32,
+
+    /// The size of video screen in pixels in the Y dimension
+    vertical_resolution: u32,
+
+    /// The physical format of the pixel
+    pixel_format: u32,
+
+    /// Bit masks for the red, green, blue and reserved components, only
+    /// valid when `pixel_format` is `PixelBitMask`
+    _pixel_information: [u32; 4],
+
+    /// Number of pixel elements per video memory line
+    pixels_per_scan_line: u32,
 }
 
 /// This protocol is used to obtain input from the ConsoleIn device. The
@@ -814,8 +4191,7 @@ struct EfiSimpleTextInputProtocol {
 
     /// Event to use with `EFI_BOOT_SERVICES.WaitForEvent()` to wait for a
     /// key to be available.
-    /// We don't use the event API, don't expose this function pointer
-    _wait_for_key: usize,
+    wait_for_key: usize,
 }
 
 /// This protocol is used to control text-based output devices.
@@ -836,18 +4212,25 @@ struct EfiSimpleTextOutputProtocol {
 
     /// Returns information for an available text mode that the output
     /// device(s) support
-    _query_mode: usize,
+    query_mode: unsafe extern fn(this:    *const EfiSimpleTextOutputProtocol,
+                                 mode:    usize,
+                                 columns: &mut usize,
+                                 rows:    &mut usize) -> EfiStatusCode,
 
     /// Sets the output device(s) to a specified mode
-    _set_modfe: usize,
+    set_mode: unsafe extern fn(this: *const EfiSimpleTextOutputProtocol,
+                               mode: usize) -> EfiStatusCode,
 
     /// Sets the background and foreground colors for the `OutputString()`
     /// and `ClearScreen()` functions
-    _set_attribute: usize,
+    set_attribute: unsafe extern fn(this: *const EfiSimpleTextOutputProtocol,
+                                    attribute: usize) -> EfiStatusCode,
 
     /// Clears the output device(s) display to the currently selected 
     /// background color
-    _clear_screen: usize,
+    clear_screen:
+        unsafe extern fn(this: *const EfiSimpleTextOutputProtocol)
+            -> EfiStatusCode,
 
     /// Sets the current coordinates of the cursor position
     _set_cursor_position: usize,
@@ -856,7 +4239,29 @@ struct EfiSimpleTextOutputProtocol {
     _enable_cursor: usize,
 
     /// Pointer to `SIMPLE_TEXT_OUTPUT_MODE` data
-    _mode: usize,
+    mode: *const EfiSimpleTextOutputMode,
+}
+
+/// The current state of a text output device
+#[repr(C)]
+struct EfiSimpleTextOutputMode {
+    /// The number of modes supported by `QueryMode()` and `SetMode()`
+    max_mode: i32,
+
+    /// The text mode of the output device(s)
+    mode: i32,
+
+    /// The current character output attribute
+    attribute: i32,
+
+    /// The cursor's column
+    cursor_column: i32,
+
+    /// The cursor's row
+    cursor_row: i32,
+
+    /// The cursor is currently visible or not
+    cursor_visible: bool,
 }
 
 /// Contains pointers to the runtime and boot services tables
@@ -902,7 +4307,7 @@ struct EfiSystemTable {
     console_err: *const EfiSimpleTextOutputProtocol,
 
     /// A pointer to the EFI Runtime Services Table
-    _runtime_services: usize,
+    runtime_services: *const EfiRuntimeServices,
 
     /// A pointer to the EFI Boot Services Table
     boot_services: *const EfiBootServices,
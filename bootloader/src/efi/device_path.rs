@@ -0,0 +1,301 @@
+//! EFI device paths, which describe the location of a device or a file as a
+//! chain of nodes starting from the root of the system
+
+use core::fmt;
+use super::{EfiGuid, Protocol};
+
+/// Node type of hardware device paths
+const HARDWARE_DEVICE_PATH: u8 = 0x01;
+
+/// Node type of ACPI device paths
+const ACPI_DEVICE_PATH: u8 = 0x02;
+
+/// Node type of media device paths
+const MEDIA_DEVICE_PATH: u8 = 0x04;
+
+/// Node type which ends a device path or a device path instance
+const END_DEVICE_PATH: u8 = 0x7f;
+
+/// Hardware sub-type of a PCI device
+const HW_PCI_DP: u8 = 0x01;
+
+/// ACPI sub-type of a device identified by its `_HID` and `_UID`
+const ACPI_DP: u8 = 0x01;
+
+/// Media sub-type of a hard drive partition
+const MEDIA_HARDDRIVE_DP: u8 = 0x01;
+
+/// Media sub-type of a file path
+const MEDIA_FILEPATH_DP: u8 = 0x04;
+
+/// End sub-type which ends one instance of a multi-instance device path
+const END_INSTANCE_DEVICE_PATH: u8 = 0x01;
+
+/// End sub-type which ends the entire device path
+const END_ENTIRE_DEVICE_PATH: u8 = 0xff;
+
+/// EISA ID vendor of `PNP` devices, in its compressed form
+const EISA_PNP_ID: u32 = 0x41d0;
+
+/// The header of every device path node
+#[repr(C)]
+pub(super) struct EfiDevicePathProtocol {
+    /// The type of the node
+    typ: u8,
+
+    /// The sub-type of the node, its meaning depends on `typ`
+    sub_type: u8,
+
+    /// Length of the node (in bytes), including this header
+    length: [u8; 2],
+}
+
+impl Protocol for EfiDevicePathProtocol {
+    const GUID: EfiGuid = EfiGuid(0x09576e91, 0x6d3f, 0x11d2,
+        [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]);
+}
+
+/// A device path provided by the firmware. It lives in boot services memory
+/// and must not be used after exiting boot services.
+#[derive(Clone, Copy, Debug)]
+pub struct DevicePath(*const EfiDevicePathProtocol);
+
+/// The signature identifying the disk a partition is on
+#[derive(Clone, Copy, Debug)]
+pub enum Signature {
+    /// The disk has no signature
+    None,
+
+    /// The 32-bit signature of an MBR disk
+    Mbr(u32),
+
+    /// The unique partition GUID of a GPT partition, in its on-disk byte
+    /// order
+    Gpt([u8; 16]),
+}
+
+/// A single node of a device path
+#[derive(Clone, Copy, Debug)]
+pub enum Node {
+    /// A PCI device
+    Pci {
+        /// PCI function number
+        function: u8,
+
+        /// PCI device number
+        device: u8,
+    },
+
+    /// A device described in the ACPI namespace
+    Acpi {
+        /// The `_HID` of the device, as a compressed EISA ID
+        hid: u32,
+
+        /// The `_UID` of the device
+        uid: u32,
+    },
+
+    /// A partition of a hard drive
+    HardDrive {
+        /// The partition number, starting at 1
+        partition: u32,
+
+        /// The first LBA of the partition
+        start: u64,
+
+        /// The size of the partition (in blocks)
+        size: u64,
+
+        /// The signature of the disk or partition
+        signature: Signature,
+    },
+
+    /// A file path on a file system, as a UCS-2 string
+    File(&'static [u8]),
+
+    /// The end of one instance of a multi-instance device path
+    EndInstance,
+
+    /// A node we do not know how to decode
+    Other {
+        /// The type of the node
+        typ: u8,
+
+        /// The sub-type of the node
+        sub_type: u8,
+    },
+}
+
+/// Read a little-endian `u32` from `bytes` at `offset`
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a little-endian `u64` from `bytes` at `offset`
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let lo = read_u32(bytes, offset)? as u64;
+    let hi = read_u32(bytes, offset + 4)? as u64;
+    Some(hi << 32 | lo)
+}
+
+impl Node {
+    /// Decode a node
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` - The raw node, including its header
+    ///
+    /// # Returns
+    ///
+    /// The decoded node, nodes which are truncated or unknown are returned as
+    /// [`Node::Other`]
+    ///
+    fn parse(bytes: &'static [u8]) -> Self {
+        let (typ, sub_type) = (bytes[0], bytes[1]);
+
+        let node = match (typ, sub_type) {
+            (HARDWARE_DEVICE_PATH, HW_PCI_DP) if bytes.len() >= 6 => {
+                Some(Node::Pci { function: bytes[4], device: bytes[5] })
+            }
+            (ACPI_DEVICE_PATH, ACPI_DP) => (|| Some(Node::Acpi {
+                hid: read_u32(bytes, 4)?,
+                uid: read_u32(bytes, 8)?,
+            }))(),
+            (MEDIA_DEVICE_PATH, MEDIA_HARDDRIVE_DP) if bytes.len() >= 42 => {
+                let mut guid = [0u8; 16];
+                guid.copy_from_slice(&bytes[24..40]);
+
+                (|| Some(Node::HardDrive {
+                    partition: read_u32(bytes, 4)?,
+                    start:     read_u64(bytes, 8)?,
+                    size:      read_u64(bytes, 16)?,
+                    signature: match bytes[41] {
+                        1 => Signature::Mbr(read_u32(bytes, 24)?),
+                        2 => Signature::Gpt(guid),
+                        _ => Signature::None,
+                    },
+                }))()
+            }
+            (MEDIA_DEVICE_PATH, MEDIA_FILEPATH_DP) => {
+                Some(Node::File(&bytes[4..]))
+            }
+            (END_DEVICE_PATH, END_INSTANCE_DEVICE_PATH) => {
+                Some(Node::EndInstance)
+            }
+            _ => None,
+        };
+
+        node.unwrap_or(Node::Other { typ, sub_type })
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Pci { function, device } => {
+                write!(f, "Pci({:#x},{:#x})", device, function)
+            }
+            Node::Acpi { hid, uid } if hid & 0xffff == EISA_PNP_ID => {
+                match hid >> 16 {
+                    0x0a03  => write!(f, "PciRoot({:#x})", uid),
+                    0x0a08  => write!(f, "PcieRoot({:#x})", uid),
+                    product => write!(f, "Acpi(PNP{:04X},{:#x})", product, uid),
+                }
+            }
+            Node::Acpi { hid, uid } => write!(f, "Acpi({:#x},{:#x})", hid, uid),
+            Node::HardDrive { partition, start, size, signature } => {
+                write!(f, "HD({},", partition)?;
+                match signature {
+                    Signature::None     => write!(f, "0,0")?,
+                    Signature::Mbr(sig) => write!(f, "MBR,{:#010x}", sig)?,
+                    Signature::Gpt(g)   => write!(f,
+                        "GPT,{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-\
+                         {:02X}{:02X}-{:02X}{:02X}-\
+                         {:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+                        g[3], g[2], g[1], g[0], g[5], g[4], g[7], g[6],
+                        g[8], g[9], g[10], g[11], g[12], g[13], g[14], g[15])?,
+                }
+                write!(f, ",{:#x},{:#x})", start, size)
+            }
+            Node::File(path) => {
+                let chars = path.chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .take_while(|&x| x != 0);
+                for chr in core::char::decode_utf16(chars) {
+                    write!(f, "{}", chr.unwrap_or(
+                        core::char::REPLACEMENT_CHARACTER))?;
+                }
+                Ok(())
+            }
+            Node::EndInstance => write!(f, ","),
+            Node::Other { typ, sub_type } => {
+                write!(f, "Path({},{})", typ, sub_type)
+            }
+        }
+    }
+}
+
+impl DevicePath {
+    /// Wrap a device path provided by the firmware
+    ///
+    /// # Parameters
+    ///
+    /// * `ptr` - Pointer to the first node of the device path
+    ///
+    /// # Returns
+    ///
+    /// The device path, or `None` if `ptr` is null
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a device path which is terminated by an end node
+    /// and stays valid for as long as boot services are running
+    ///
+    pub(super) unsafe fn new(ptr: *const EfiDevicePathProtocol)
+            -> Option<Self> {
+        (!ptr.is_null()).then_some(Self(ptr))
+    }
+
+    /// Iterate over the nodes of the device path
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the nodes up to, but not including, the end node.
+    /// Iteration also stops at a malformed node length.
+    ///
+    pub fn nodes(&self) -> impl Iterator<Item = Node> {
+        let mut ptr = self.0 as *const u8;
+        core::iter::from_fn(move || unsafe {
+            // Get the raw bytes of the node
+            let header = &*(ptr as *const EfiDevicePathProtocol);
+            let length = u16::from_le_bytes(header.length) as usize;
+            if header.typ == END_DEVICE_PATH &&
+                    header.sub_type == END_ENTIRE_DEVICE_PATH {
+                return None;
+            }
+            if length < core::mem::size_of::<EfiDevicePathProtocol>() {
+                return None;
+            }
+            let bytes = core::slice::from_raw_parts(ptr, length);
+
+            // Advance to the next node
+            ptr = ptr.add(length);
+            Some(Node::parse(bytes))
+        })
+    }
+}
+
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Nodes are separated by `/`, except around the instance separator
+        let mut separate = false;
+        for node in self.nodes() {
+            let end = matches!(node, Node::EndInstance);
+            if separate && !end { write!(f, "/")?; }
+            write!(f, "{}", node)?;
+            separate = !end;
+        }
+        Ok(())
+    }
+}
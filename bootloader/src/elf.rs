@@ -0,0 +1,647 @@
+//! A minimal ELF64 loader for the kernel image. Segments are loaded at their
+//! physical addresses, as we run with an identity map set up by the firmware.
+//!
+//! With KASLR the physical base is picked at random out of free memory, and
+//! a position independent image also gets a virtual base of its own in the
+//! kernel window, so neither gives away the other. Executables can't be
+//! relocated, they keep the virtual addresses they are linked at.
+//!
+//! Segments are mapped into the kernel page table at their virtual addresses
+//! with the permissions of their flags, and no segment may be both writable
+//! and executable. The kernel is entered through that page table, so the
+//! entry point is a virtual address even where it differs from the physical
+//! address the code was loaded to.
+
+use core::mem::size_of;
+
+use rangeset::{Range, RangeSet};
+
+use crate::mm::{
+    page_alloc::{PageAlloc, PAGE_SIZE},
+    paging::{self, Layout, PageTable, Permissions},
+    physmem::PhysAddr,
+    virtmem::VirtAddr,
+};
+
+/// A `Result` type which wraps an ELF error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from loading an ELF image
+#[derive(Debug)]
+pub enum Error {
+    /// The image was too small to hold a structure it claims to contain
+    Truncated,
+
+    /// The image does not start with the ELF magic
+    BadMagic,
+
+    /// The image is not a 64-bit little-endian ELF
+    UnsupportedClass,
+
+    /// The image is not an executable
+    UnsupportedType(u16),
+
+    /// The image was built for a different architecture than we are running
+    UnsupportedMachine(u16),
+
+    /// The program headers are not the size of an `Elf64_Phdr`
+    BadProgramHeaderSize(u16),
+
+    /// A segment's file size is larger than its memory size
+    BadSegmentSize,
+
+    /// An integer overflow occurred when processing segment bounds
+    IntegerOverflow,
+
+    /// A segment is to be loaded into memory which is not free
+    SegmentNotFree(Range),
+
+    /// An error occurred when reserving a segment in the memory map
+    MemoryRangeSet(rangeset::Error),
+
+    /// There is no free memory a position independent image fits in
+    NoRoomForImage,
+
+    /// The dynamic section or the relocations it points to are malformed
+    BadDynamic,
+
+    /// A relocation is of a type other than a relative relocation
+    UnsupportedRelocation(u32),
+
+    /// The segment linked at this virtual address is both writable and
+    /// executable
+    WritableAndExecutable(u64),
+
+    /// Mapping a segment into the kernel page table failed
+    Paging(paging::Error),
+
+    /// The entry point is not in an executable segment
+    EntryNotInImage(u64),
+}
+
+/// A kernel image which has been loaded into memory
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedImage {
+    /// The virtual address of the entry point, in the kernel page table the
+    /// image is mapped into by [`map`]
+    pub entry: u64,
+
+    /// The offset the image runs at from its linked virtual addresses, zero
+    /// unless it is position independent
+    pub slide: u64,
+
+    /// The offset the image was loaded at from its linked physical
+    /// addresses, the virtual ones for a position independent image
+    pub phys_slide: u64,
+}
+
+/// A stream of random numbers expanded from a random seed with SplitMix64,
+/// so every pick made with it is independent of the others
+struct Random(u64);
+
+impl Random {
+    /// Get the next random number
+    ///
+    /// # Returns
+    ///
+    /// A random `u64`
+    ///
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Pick a random number below `bound`, every one equally likely
+    ///
+    /// # Parameters
+    ///
+    /// * `bound` - The number of values to pick from, non-zero
+    ///
+    /// # Returns
+    ///
+    /// A random number in `0..bound`
+    ///
+    fn below(&mut self, bound: u64) -> u64 {
+        // Numbers past the last whole multiple of `bound` would make the
+        // low picks more likely, so draw again on those
+        let excess = (u64::MAX % bound + 1) % bound;
+        loop {
+            let val = self.next();
+            if val <= u64::MAX - excess { return val % bound; }
+        }
+    }
+}
+
+/// `e_ident[EI_CLASS]` of a 64-bit ELF
+const ELFCLASS64: u8 = 2;
+
+/// `e_ident[EI_DATA]` of a little-endian ELF
+const ELFDATA2LSB: u8 = 1;
+
+/// `e_type` of an executable
+const ET_EXEC: u16 = 2;
+
+/// `e_type` of a position independent executable
+const ET_DYN: u16 = 3;
+
+/// `p_type` of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// `p_type` of the dynamic section
+const PT_DYNAMIC: u32 = 2;
+
+/// `p_flags` bit of an executable segment
+const PF_X: u32 = 1 << 0;
+
+/// `p_flags` bit of a writable segment
+const PF_W: u32 = 1 << 1;
+
+/// Minimum alignment of the base of a position independent image, so it can
+/// be mapped with 2 MiB pages
+const IMAGE_ALIGN: u64 = 2 * 1024 * 1024;
+
+/// `e_machine` of the architecture we are running on
+#[cfg(target_arch = "x86_64")]  const MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")] const MACHINE: u16 = 183;
+#[cfg(target_arch = "riscv64")] const MACHINE: u16 = 243;
+
+/// Relocation type which adds the load offset to an address
+#[cfg(target_arch = "x86_64")]  const R_RELATIVE: u32 = 8;
+#[cfg(target_arch = "aarch64")] const R_RELATIVE: u32 = 1027;
+#[cfg(target_arch = "riscv64")] const R_RELATIVE: u32 = 3;
+
+/// The ELF64 file header
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Elf64Header {
+    /// Magic number and other information about how to interpret the file
+    ident: [u8; 16],
+
+    /// The object file type
+    typ: u16,
+
+    /// The required architecture
+    machine: u16,
+
+    /// The object file version
+    version: u32,
+
+    /// The virtual address to which the system first transfers control
+    entry: u64,
+
+    /// The program header table's file offset in bytes
+    phoff: u64,
+
+    /// The section header table's file offset in bytes
+    shoff: u64,
+
+    /// Processor-specific flags
+    flags: u32,
+
+    /// The ELF header's size in bytes
+    ehsize: u16,
+
+    /// The size in bytes of one entry in the program header table
+    phentsize: u16,
+
+    /// The number of entries in the program header table
+    phnum: u16,
+
+    /// The size in bytes of one entry in the section header table
+    shentsize: u16,
+
+    /// The number of entries in the section header table
+    shnum: u16,
+
+    /// The section header table index of the section name string table
+    shstrndx: u16,
+}
+
+/// An ELF64 program header
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Elf64Phdr {
+    /// The kind of segment this header describes
+    typ: u32,
+
+    /// Segment permission flags
+    flags: u32,
+
+    /// The offset of the segment in the file
+    offset: u64,
+
+    /// The virtual address of the segment in memory
+    vaddr: u64,
+
+    /// The physical address of the segment in memory
+    paddr: u64,
+
+    /// The number of bytes of the segment in the file
+    filesz: u64,
+
+    /// The number of bytes of the segment in memory, the bytes past `filesz`
+    /// are zeroed
+    memsz: u64,
+
+    /// The required alignment of the segment
+    align: u64,
+}
+
+/// Read a `T` from `bytes` at `offset`
+///
+/// # Parameters
+///
+/// * `bytes`  - The bytes to read from
+/// * `offset` - The offset (in bytes) to read at
+///
+/// # Returns
+///
+/// The value read, or [`Error::Truncated`] if it does not fit in `bytes`
+///
+fn read<T: Copy>(bytes: &[u8], offset: usize) -> Result<T> {
+    let end = offset.checked_add(size_of::<T>()).ok_or(Error::Truncated)?;
+    let bytes = bytes.get(offset..end).ok_or(Error::Truncated)?;
+    Ok(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Get the program headers of one type from an ELF image
+///
+/// # Parameters
+///
+/// * `image` - The raw ELF image
+/// * `typ`   - The `p_type` of the program headers to get
+///
+/// # Returns
+///
+/// The file header and an iterator over the program headers of type `typ`,
+/// on error [`Error`]
+///
+fn segments(image: &[u8], typ: u32) -> Result<(Elf64Header,
+        impl Iterator<Item = Result<Elf64Phdr>> + '_)> {
+    // Validate the header
+    let header: Elf64Header = read(image, 0)?;
+    if &header.ident[..4] != b"\x7fELF" {
+        return Err(Error::BadMagic);
+    }
+    if header.ident[4] != ELFCLASS64 || header.ident[5] != ELFDATA2LSB {
+        return Err(Error::UnsupportedClass);
+    }
+    if header.typ != ET_EXEC && header.typ != ET_DYN {
+        return Err(Error::UnsupportedType(header.typ));
+    }
+    if header.machine != MACHINE {
+        return Err(Error::UnsupportedMachine(header.machine));
+    }
+    if header.phentsize as usize != size_of::<Elf64Phdr>() {
+        return Err(Error::BadProgramHeaderSize(header.phentsize));
+    }
+
+    // Go through the program headers and only yield the requested ones
+    let phoff = header.phoff as usize;
+    let iter = (0..header.phnum as usize).filter_map(move |ii| {
+        let phdr = ii.checked_mul(size_of::<Elf64Phdr>())
+            .and_then(|x| x.checked_add(phoff))
+            .ok_or(Error::Truncated)
+            .and_then(|offset| read::<Elf64Phdr>(image, offset));
+        match phdr {
+            Ok(phdr) if phdr.typ != typ => None,
+            phdr => Some(phdr),
+        }
+    });
+
+    Ok((header, iter))
+}
+
+/// Pick a random base address for an image out of free address space
+///
+/// Every aligned address the image fits at is equally likely.
+///
+/// # Parameters
+///
+/// * `free`   - The free address ranges
+/// * `size`   - The size (in bytes) of the image
+/// * `align`  - The alignment of the base address, a power of two
+/// * `random` - The random numbers to pick with, `None` for the lowest
+///              address
+///
+/// # Returns
+///
+/// The base address, on error [`Error`]
+///
+fn pick_base(free: &[Range], size: u64, align: u64,
+             random: Option<&mut Random>) -> Result<u64> {
+    // Get the first aligned base in a range and the number of bases in it
+    let bases = |ent: &Range| -> (u64, u64) {
+        let start = match ent.start.checked_add(align - 1) {
+            Some(start) => start & !(align - 1),
+            None        => return (0, 0),
+        };
+        match ent.end.checked_sub(start).and_then(|x| x.checked_add(1)) {
+            Some(len) if len >= size => (start, (len - size) / align + 1),
+            _ => (start, 0),
+        }
+    };
+
+    // Pick one of all of the bases
+    let total = free.iter()
+        .fold(0u64, |acc, ent| acc.saturating_add(bases(ent).1));
+    if total == 0 { return Err(Error::NoRoomForImage); }
+    let mut pick = random.map_or(0, |x| x.below(total));
+    for ent in free {
+        let (start, count) = bases(ent);
+        if pick < count { return Ok(start + pick * align); }
+        pick -= count;
+    }
+
+    Err(Error::NoRoomForImage)
+}
+
+/// Apply the relocations of a loaded position independent image
+///
+/// # Parameters
+///
+/// * `image`      - The raw ELF image
+/// * `span`       - The physical memory the image was loaded to
+/// * `slide`      - The offset the image runs at from its linked addresses
+/// * `phys_slide` - The offset the image was loaded at from its linked
+///                  addresses
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+/// # Safety
+///
+/// The image must have been loaded to `span`, which must be identity mapped.
+///
+unsafe fn relocate(image: &[u8], span: Range, slide: u64, phys_slide: u64)
+        -> Result<()> {
+    /// `d_tag` ending the dynamic section
+    const DT_NULL: i64 = 0;
+
+    /// `d_tag` of the address of the relocations with addends
+    const DT_RELA: i64 = 7;
+
+    /// `d_tag` of the size (in bytes) of the relocations
+    const DT_RELASZ: i64 = 8;
+
+    /// `d_tag` of the size (in bytes) of one relocation
+    const DT_RELAENT: i64 = 9;
+
+    /// Size of an `Elf64_Rela`, `r_offset`, `r_info` and `r_addend`
+    const RELA_SIZE: u64 = 24;
+
+    // Find the relocations in the dynamic section
+    let (mut rela, mut relasz) = (None, 0);
+    let (_, dynamic) = segments(image, PT_DYNAMIC)?;
+    for phdr in dynamic {
+        let phdr = phdr?;
+        for offset in (0..phdr.filesz / 16).map(|x| phdr.offset + x * 16) {
+            let tag: i64 = read(image, offset as usize)?;
+            let val: u64 = read(image, offset as usize + 8)?;
+            match tag {
+                DT_NULL    => break,
+                DT_RELA    => rela = Some(val),
+                DT_RELASZ  => relasz = val,
+                DT_RELAENT if val != RELA_SIZE => return Err(Error::BadDynamic),
+                _          => {}
+            }
+        }
+    }
+    let rela = match rela {
+        Some(rela) => rela.checked_add(phys_slide).ok_or(Error::BadDynamic)?,
+        None       => return Ok(()),
+    };
+
+    // Returns if `size` bytes at `addr` are within the loaded image
+    let loaded = |addr: u64, size: u64| {
+        addr >= span.start &&
+            addr.checked_add(size - 1).map_or(false, |end| end <= span.end)
+    };
+    if relasz > 0 && !loaded(rela, relasz) { return Err(Error::BadDynamic); }
+
+    // Only relative relocations are expected in a static kernel
+    for ent in (0..relasz / RELA_SIZE).map(|x| rela + x * RELA_SIZE) {
+        let ent = ent as usize as *const u64;
+        let (offset, info, addend) = (ent.read_unaligned(),
+            ent.add(1).read_unaligned(), ent.add(2).read_unaligned());
+        if info as u32 != R_RELATIVE {
+            return Err(Error::UnsupportedRelocation(info as u32));
+        }
+
+        let target = offset.wrapping_add(phys_slide);
+        if !loaded(target, 8) { return Err(Error::BadDynamic); }
+        (target as usize as *mut u64)
+            .write_unaligned(addend.wrapping_add(slide));
+    }
+
+    Ok(())
+}
+
+/// Load an ELF64 executable
+///
+/// Executables are loaded to the physical addresses of their segments, and
+/// position independent executables at the lowest base in `mm`. If `random`
+/// is given the physical base is picked at random out of `mm` instead. The
+/// virtual base of a position independent executable is picked in the
+/// kernel window the same way, and the image relocated to it.
+///
+/// # Parameters
+///
+/// * `image`  - The raw ELF image
+/// * `mm`     - The physical memory map, the loaded segments are removed from
+///              it
+/// * `random` - A random seed to pick the bases with, `None` to use the
+///              lowest ones
+/// * `layout` - The layout of the kernel address space, which has the
+///              kernel window
+///
+/// # Returns
+///
+/// The virtual entry point and load offsets of the image, on error [`Error`]
+///
+/// # Safety
+///
+/// The segments are written directly to physical memory, which must be
+/// identity mapped. Every byte of the segments is verified to be free in `mm`
+/// before anything is written.
+///
+pub unsafe fn load(image: &[u8], mm: &mut RangeSet, random: Option<u64>,
+                   layout: &Layout) -> Result<LoadedImage> {
+    let mut random = random.map(Random);
+
+    // Validate every segment before touching any memory
+    let (header, segments_iter) = segments(image, PT_LOAD)?;
+    let mut span: Option<Range> = None;
+    let mut align = IMAGE_ALIGN;
+    let mut entry_found = false;
+    for phdr in segments_iter {
+        let phdr = phdr?;
+        if phdr.memsz == 0 { continue; }
+        if phdr.filesz > phdr.memsz { return Err(Error::BadSegmentSize); }
+
+        // Writable code could be rewritten through any bug in the kernel
+        if phdr.flags & (PF_W | PF_X) == PF_W | PF_X {
+            return Err(Error::WritableAndExecutable(phdr.vaddr));
+        }
+
+        // The file contents must be in the image
+        phdr.offset.checked_add(phdr.filesz)
+            .filter(|&end| end <= image.len() as u64)
+            .ok_or(Error::Truncated)?;
+
+        // The entry point is a virtual address, in the physical addresses of
+        // an executable it could point anywhere
+        if phdr.flags & PF_X != 0 && header.entry >= phdr.vaddr &&
+                header.entry - phdr.vaddr < phdr.memsz {
+            entry_found = true;
+        }
+
+        // Work out where the image is linked to, in virtual addresses as a
+        // position independent image has no meaningful physical ones
+        let addr = if header.typ == ET_DYN { phdr.vaddr } else { phdr.paddr };
+        let end = addr.checked_add(phdr.memsz - 1)
+            .ok_or(Error::IntegerOverflow)?;
+        span = Some(span.map_or(Range { start: addr, end }, |x| Range {
+            start: x.start.min(addr),
+            end:   x.end.max(end),
+        }));
+        if phdr.align.is_power_of_two() { align = align.max(phdr.align); }
+
+        // The memory of an executable which stays in place must be free
+        if header.typ == ET_EXEC && random.is_none() {
+            let range = Range { start: addr, end };
+            if !mm.entries().iter().any(|ent| {
+                ent.start <= range.start && ent.end >= range.end
+            }) {
+                return Err(Error::SegmentNotFree(range));
+            }
+        }
+    }
+    let span = match span {
+        Some(span) if entry_found => span,
+        _ => return Err(Error::EntryNotInImage(header.entry)),
+    };
+
+    // Move the image to wherever it fits in physical memory, unless it is
+    // an executable which is not randomized
+    let size = span.end - span.start + 1;
+    let phys_slide = if header.typ == ET_DYN || random.is_some() {
+        pick_base(mm.entries(), size, align, random.as_mut())?
+            .wrapping_sub(span.start)
+    } else {
+        0
+    };
+
+    // Pick the virtual base of a position independent image separately, so
+    // it gives away nothing about the physical one
+    let slide = if header.typ == ET_DYN {
+        let window = Range {
+            start: layout.kernel_window_start,
+            end:   u64::MAX,
+        };
+        pick_base(&[window], size, align, random.as_mut())?
+            .wrapping_sub(span.start)
+    } else {
+        0
+    };
+
+    // Load the segments
+    let (_, segments_iter) = segments(image, PT_LOAD)?;
+    for phdr in segments_iter {
+        let phdr = phdr?;
+        if phdr.memsz == 0 { continue; }
+
+        // Reserve the memory, the ranges may overlap with previous segments
+        // so we check again
+        let linked = if header.typ == ET_DYN { phdr.vaddr } else { phdr.paddr };
+        let addr = linked.wrapping_add(phys_slide);
+        let range = Range {
+            start: addr,
+            end:   addr + (phdr.memsz - 1),
+        };
+        if !mm.entries().iter()
+                .any(|ent| ent.start <= range.start && ent.end >= range.end) {
+            return Err(Error::SegmentNotFree(range));
+        }
+        mm.remove(range).map_err(Error::MemoryRangeSet)?;
+
+        // Copy the file contents and zero the rest (BSS)
+        let dst = addr as usize as *mut u8;
+        let src = &image[phdr.offset as usize..][..phdr.filesz as usize];
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+        core::ptr::write_bytes(dst.add(src.len()), 0,
+                               (phdr.memsz - phdr.filesz) as usize);
+    }
+
+    // Fix up the absolute addresses in a position independent image
+    if header.typ == ET_DYN {
+        relocate(image, Range {
+            start: span.start.wrapping_add(phys_slide),
+            end:   span.end.wrapping_add(phys_slide),
+        }, slide, phys_slide)?;
+    }
+
+    Ok(LoadedImage {
+        entry: header.entry.wrapping_add(slide),
+        slide,
+        phys_slide,
+    })
+}
+
+/// Map the segments of a loaded image into the kernel page table at their
+/// linked virtual addresses
+///
+/// Segments are readable, and only writable or executable if their flags say
+/// so. Partial pages at the edges of a segment are mapped whole, so segments
+/// with different flags must not share a page.
+///
+/// # Parameters
+///
+/// * `image`  - The raw ELF image, as passed to [`load`]
+/// * `loaded` - Where [`load`] loaded the image
+/// * `table`  - The page table to map the segments into
+/// * `frames` - The allocator to take new page table frames from
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+/// # Safety
+///
+/// Physical memory must be identity mapped.
+///
+pub unsafe fn map(image: &[u8], loaded: &LoadedImage, table: &mut PageTable,
+                  frames: &mut PageAlloc) -> Result<()> {
+    let (header, segments_iter) = segments(image, PT_LOAD)?;
+    for phdr in segments_iter {
+        let phdr = phdr?;
+        if phdr.memsz == 0 { continue; }
+        if phdr.flags & (PF_W | PF_X) == PF_W | PF_X {
+            return Err(Error::WritableAndExecutable(phdr.vaddr));
+        }
+
+        // Get where the segment was loaded to, the same way `load` did
+        let virt = phdr.vaddr.wrapping_add(loaded.slide);
+        let linked = if header.typ == ET_DYN { phdr.vaddr } else { phdr.paddr };
+        let phys = linked.wrapping_add(loaded.phys_slide);
+
+        // Round out to whole pages
+        let offset = virt & (PAGE_SIZE - 1);
+        let size = offset.checked_add(phdr.memsz)
+            .and_then(|x| x.checked_add(PAGE_SIZE - 1))
+            .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+
+        table.map(frames, VirtAddr(virt - offset),
+            PhysAddr(phys.wrapping_sub(offset)), size, Permissions {
+                write:   phdr.flags & PF_W != 0,
+                execute: phdr.flags & PF_X != 0,
+                user:    false,
+            }).map_err(Error::Paging)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,435 @@
+//! The GICv2 and GICv3 interrupt controllers of aarch64 machines, set up to
+//! deliver interrupts to the boot core
+//!
+//! Only what we run on is set up: the distributor, which routes shared
+//! interrupts, and our own CPU interface, plus our redistributor on GICv3.
+//! Interrupts stay masked on the core until something is ready to take them.
+
+use crate::acpi::{GicProcessor, Madt};
+use crate::mm::physmem::PhysAddr;
+use crate::mm::virtmem::phys_to_virt;
+use crate::psci::own_mpidr;
+
+/// A `Result` type which wraps an interrupt controller error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the interrupt controller
+#[derive(Debug)]
+pub enum Error {
+    /// The MADT does not describe a GIC distributor
+    NoDistributor,
+
+    /// The MADT does not describe the core we are running on
+    NoProcessor(u64),
+
+    /// The GIC version is not one we support
+    UnsupportedVersion(u8),
+
+    /// None of the GICv3 redistributors belongs to the core we are running on
+    NoRedistributor,
+
+    /// The interrupt ID is a software generated or special interrupt, which
+    /// can't be configured
+    InvalidInterrupt(u32),
+
+    /// The GIC did not finish a register write or a wakeup in time
+    Timeout,
+}
+
+/// Size (in bytes) of one GICv3 redistributor, its RD and SGI frames
+const GICR_SIZE: u64 = 0x20000;
+
+/// Size (in bytes) of one GICv4 redistributor, which also has VLPI frames
+const GICR_SIZE_VLPI: u64 = 0x40000;
+
+/// Distributor control register
+const GICD_CTLR: usize = 0x0;
+
+/// Interrupt group registers, one bit per interrupt
+const GICD_IGROUPR: usize = 0x80;
+
+/// Interrupt set-enable registers, one bit per interrupt
+const GICD_ISENABLER: usize = 0x100;
+
+/// Interrupt priority registers, one byte per interrupt
+const GICD_IPRIORITYR: usize = 0x400;
+
+/// GICv2 interrupt processor targets registers, one byte per interrupt
+const GICD_ITARGETSR: usize = 0x800;
+
+/// Interrupt configuration registers, two bits per interrupt
+const GICD_ICFGR: usize = 0xc00;
+
+/// GICv3 interrupt routing registers, one 64-bit register per interrupt
+const GICD_IROUTER: usize = 0x6000;
+
+/// `GICD_CTLR` bit which enables group 1 interrupts
+const CTLR_ENABLE_G1: u32 = 1 << 0;
+
+/// `GICD_CTLR` bit which enables non-secure group 1 interrupts, once
+/// affinity routing is enabled
+const CTLR_ENABLE_G1A: u32 = 1 << 1;
+
+/// `GICD_CTLR` bit which enables affinity routing for non-secure interrupts
+const CTLR_ARE_NS: u32 = 1 << 4;
+
+/// `GICD_CTLR` bit which is set while a register write is taking effect
+const CTLR_RWP: u32 = 1 << 31;
+
+/// GICv3 redistributor type register, with the affinity of its core
+const GICR_TYPER: usize = 0x8;
+
+/// GICv3 redistributor power management register
+const GICR_WAKER: usize = 0x14;
+
+/// `GICR_TYPER` bit which is set on redistributors with VLPI frames
+const TYPER_VLPIS: u64 = 1 << 1;
+
+/// `GICR_TYPER` bit which is set on the last redistributor in a range
+const TYPER_LAST: u64 = 1 << 4;
+
+/// `GICR_WAKER` bit which tells the redistributor its core is asleep
+const WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+
+/// `GICR_WAKER` bit which is set while the redistributor is asleep
+const WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+
+/// Offset of the SGI frame of a redistributor, which configures its private
+/// interrupts with the same registers the distributor uses
+const GICR_SGI: usize = 0x10000;
+
+/// GICv2 CPU interface control register
+const GICC_CTLR: usize = 0x0;
+
+/// GICv2 CPU interface priority mask register
+const GICC_PMR: usize = 0x4;
+
+/// Priority given to the interrupts we configure
+const PRIORITY: u8 = 0xa0;
+
+/// Priority mask, interrupts with a lower priority value are signalled
+const PRIORITY_MASK: u32 = 0xf0;
+
+/// Number of times to poll for a register write or a wakeup to finish
+const POLLS: usize = 1_000_000;
+
+/// How an interrupt is signalled
+#[derive(Clone, Copy, Debug)]
+pub enum Trigger {
+    /// The interrupt is pending for as long as the signal is asserted
+    Level,
+
+    /// The interrupt becomes pending on a rising edge of the signal
+    Edge,
+}
+
+/// Our own side of the GIC
+enum Cpu {
+    /// A GICv2 memory mapped CPU interface, which only has to be enabled
+    V2 {
+        /// Our bit in the processor targets registers
+        target: u8,
+    },
+
+    /// A GICv3 system register CPU interface, with our redistributor
+    V3 {
+        /// The redistributor registers
+        gicr: *mut u8,
+
+        /// Our affinity, in the format of the routing registers
+        affinity: u64,
+    },
+}
+
+/// The GIC, as seen from the core we are running on
+pub struct Gic {
+    /// The distributor registers
+    gicd: *mut u8,
+
+    /// Our own CPU interface
+    cpu: Cpu,
+}
+
+/// Find the registers of a GIC component in the address space in use
+///
+/// # Parameters
+///
+/// * `addr` - The physical address of the registers
+///
+/// # Returns
+///
+/// A pointer to the registers
+///
+fn map(addr: PhysAddr) -> *mut u8 {
+    phys_to_virt(addr).as_mut_ptr::<u8>()
+}
+
+/// Read a 32-bit GIC register
+///
+/// # Parameters
+///
+/// * `base` - The registers of the GIC component
+/// * `reg`  - The offset of the register
+///
+/// # Returns
+///
+/// The value of the register
+///
+unsafe fn read(base: *mut u8, reg: usize) -> u32 {
+    core::ptr::read_volatile(base.add(reg) as *const u32)
+}
+
+/// Write a 32-bit GIC register
+///
+/// # Parameters
+///
+/// * `base` - The registers of the GIC component
+/// * `reg`  - The offset of the register
+/// * `val`  - The value to write
+///
+unsafe fn write(base: *mut u8, reg: usize, val: u32) {
+    core::ptr::write_volatile(base.add(reg) as *mut u32, val);
+}
+
+/// Spin until a GIC register bit reads as the value we want
+///
+/// # Parameters
+///
+/// * `base` - The registers of the GIC component
+/// * `reg`  - The offset of the register
+/// * `bit`  - The bit to watch
+/// * `set`  - Whether to wait for `bit` to be set or to be clear
+///
+/// # Returns
+///
+/// `()` once the bit has the value, on error [`Error::Timeout`]
+///
+unsafe fn poll(base: *mut u8, reg: usize, bit: u32, set: bool) -> Result<()> {
+    for _ in 0..POLLS {
+        if (read(base, reg) & bit != 0) == set { return Ok(()); }
+        core::hint::spin_loop();
+    }
+
+    Err(Error::Timeout)
+}
+
+/// Find the GICv3 redistributor of a core
+///
+/// # Parameters
+///
+/// * `madt` - The MADT describing the redistributors
+/// * `ours` - The GICC entry of the core
+///
+/// # Returns
+///
+/// The registers of the redistributor, on error [`Error`]
+///
+unsafe fn find_redistributor(madt: &Madt, ours: &GicProcessor)
+        -> Result<*mut u8> {
+    // Some firmware gives each core its own redistributor in its GICC entry
+    if ours.gicr_base.0 != 0 {
+        return Ok(map(ours.gicr_base));
+    }
+
+    // Otherwise the redistributors are packed into ranges, and each one
+    // reports the affinity of its core in the top half of `GICR_TYPER`
+    let affinity = (ours.mpidr & 0xff_ffff) | ((ours.mpidr >> 32) << 24);
+    for (base, size) in madt.gic_redistributor_ranges() {
+        let range = map(base);
+        let mut offset = 0;
+        while offset + GICR_SIZE <= size {
+            let gicr  = range.add(offset as usize);
+            let typer = core::ptr::read_volatile(
+                gicr.add(GICR_TYPER) as *const u64);
+            if typer >> 32 == affinity { return Ok(gicr); }
+            if typer & TYPER_LAST != 0 { break; }
+
+            offset += match typer & TYPER_VLPIS {
+                0 => GICR_SIZE,
+                _ => GICR_SIZE_VLPI,
+            };
+        }
+    }
+
+    Err(Error::NoRedistributor)
+}
+
+/// Enable the GICv3 system register CPU interface of this core
+unsafe fn enable_system_registers() {
+    /// `ICC_SRE_ELx` bit which enables the system register interface
+    const SRE: u64 = 1 << 0;
+
+    /// `ICC_SRE_EL2` bit which lets EL1 use the system register interface
+    const SRE_ENABLE: u64 = 1 << 3;
+
+    // At EL2 the interface has to be enabled for EL2 before the EL1
+    // registers can be used
+    let el: u64;
+    asm!("mrs {}, CurrentEL", out(reg) el,
+        options(nomem, nostack, preserves_flags));
+    if (el >> 2) & 3 == 2 {
+        let sre: u64;
+        asm!("mrs {}, S3_4_C12_C9_5", out(reg) sre,
+            options(nomem, nostack, preserves_flags));
+        asm!("msr S3_4_C12_C9_5, {}", "isb", in(reg) sre | SRE | SRE_ENABLE,
+            options(nomem, nostack, preserves_flags));
+    }
+
+    // ICC_SRE_EL1
+    let sre: u64;
+    asm!("mrs {}, S3_0_C12_C12_5", out(reg) sre,
+        options(nomem, nostack, preserves_flags));
+    asm!("msr S3_0_C12_C12_5, {}", "isb", in(reg) sre | SRE,
+        options(nomem, nostack, preserves_flags));
+
+    // ICC_PMR_EL1 and ICC_IGRPEN1_EL1
+    asm!("msr S3_0_C4_C6_0, {}", in(reg) PRIORITY_MASK as u64,
+        options(nomem, nostack, preserves_flags));
+    asm!("msr S3_0_C12_C12_7, {}", "isb", in(reg) 1u64,
+        options(nomem, nostack, preserves_flags));
+}
+
+impl Gic {
+    /// Enable the GIC distributor and the CPU interface of this core
+    ///
+    /// # Parameters
+    ///
+    /// * `madt` - The MADT describing the GIC
+    ///
+    /// # Returns
+    ///
+    /// The enabled [`Gic`], on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Boot services must have been exited, as the firmware drives the GIC
+    /// until then.
+    ///
+    pub unsafe fn init(madt: &Madt) -> Result<Self> {
+        let dist = madt.gic_distributor().ok_or(Error::NoDistributor)?;
+        let own  = own_mpidr();
+        let ours = madt.gic_processors().find(|x| x.mpidr == own)
+            .ok_or(Error::NoProcessor(own))?;
+
+        // Firmware which leaves the version to be detected still describes
+        // the redistributors of a GICv3
+        let version = match dist.version {
+            0 if madt.gic_redistributor_ranges().next().is_some() ||
+                 ours.gicr_base.0 != 0 => 3,
+            0 => 2,
+            x => x,
+        };
+
+        match version {
+            2 => {
+                let gicd = map(dist.base);
+                let gicc = map(ours.gicc_base);
+
+                // The first targets registers read back our own bit
+                let target =
+                    core::ptr::read_volatile(gicd.add(GICD_ITARGETSR));
+
+                let ctlr = read(gicd, GICD_CTLR);
+                write(gicd, GICD_CTLR, ctlr | CTLR_ENABLE_G1);
+                write(gicc, GICC_PMR, PRIORITY_MASK);
+                write(gicc, GICC_CTLR, read(gicc, GICC_CTLR) | 1);
+
+                Ok(Gic { gicd, cpu: Cpu::V2 { target } })
+            }
+            3 | 4 => {
+                let gicd = map(dist.base);
+                let gicr = find_redistributor(madt, &ours)?;
+
+                // Affinity routing can only be turned on with the interrupt
+                // groups disabled
+                let ctlr = read(gicd, GICD_CTLR);
+                if ctlr & CTLR_ARE_NS == 0 {
+                    write(gicd, GICD_CTLR, 0);
+                    poll(gicd, GICD_CTLR, CTLR_RWP, false)?;
+                }
+                write(gicd, GICD_CTLR,
+                      ctlr | CTLR_ARE_NS | CTLR_ENABLE_G1A | CTLR_ENABLE_G1);
+                poll(gicd, GICD_CTLR, CTLR_RWP, false)?;
+
+                // Wake up our redistributor
+                let waker = read(gicr, GICR_WAKER);
+                write(gicr, GICR_WAKER, waker & !WAKER_PROCESSOR_SLEEP);
+                poll(gicr, GICR_WAKER, WAKER_CHILDREN_ASLEEP, false)?;
+
+                enable_system_registers();
+
+                Ok(Gic { gicd, cpu: Cpu::V3 { gicr, affinity: own } })
+            }
+            x => Err(Error::UnsupportedVersion(x)),
+        }
+    }
+
+    /// Get the GIC architecture version
+    pub fn version(&self) -> u8 {
+        match self.cpu {
+            Cpu::V2 { .. } => 2,
+            Cpu::V3 { .. } => 3,
+        }
+    }
+
+    /// Configure an interrupt, route it to this core and enable it
+    ///
+    /// # Parameters
+    ///
+    /// * `intid`   - The interrupt ID, a private (16 to 31) or shared (32 to
+    ///               1019) interrupt
+    /// * `trigger` - How the interrupt is signalled
+    ///
+    /// # Returns
+    ///
+    /// `()` once the interrupt is enabled, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Whatever handles the interrupt once it is unmasked on this core must
+    /// be ready for it.
+    ///
+    pub unsafe fn route_to_self(&self, intid: u32, trigger: Trigger)
+            -> Result<()> {
+        if !(16..1020).contains(&intid) {
+            return Err(Error::InvalidInterrupt(intid));
+        }
+
+        // Private interrupts are configured in the redistributor on GICv3,
+        // and in the banked distributor registers on GICv2
+        let id   = intid as usize;
+        let base = match self.cpu {
+            Cpu::V3 { gicr, .. } if id < 32 => gicr.add(GICR_SGI),
+            _                               => self.gicd,
+        };
+
+        let cfg = GICD_ICFGR + id / 16 * 4;
+        let bit = 2 << (id % 16 * 2);
+        let val = read(base, cfg);
+        write(base, cfg, match trigger {
+            Trigger::Level => val & !bit,
+            Trigger::Edge  => val | bit,
+        });
+        core::ptr::write_volatile(base.add(GICD_IPRIORITYR + id), PRIORITY);
+        let group = GICD_IGROUPR + id / 32 * 4;
+        write(base, group, read(base, group) | 1 << (id % 32));
+
+        // Shared interrupts also have to be routed to us
+        if id >= 32 {
+            match self.cpu {
+                Cpu::V2 { target, .. } => {
+                    core::ptr::write_volatile(
+                        self.gicd.add(GICD_ITARGETSR + id), target);
+                }
+                Cpu::V3 { affinity, .. } => {
+                    core::ptr::write_volatile(
+                        self.gicd.add(GICD_IROUTER + id * 8) as *mut u64,
+                        affinity);
+                }
+            }
+        }
+
+        write(base, GICD_ISENABLER + id / 32 * 4, 1 << (id % 32));
+        Ok(())
+    }
+}
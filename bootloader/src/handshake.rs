@@ -0,0 +1,145 @@
+//! The handshake between the boot processor and the secondary processors it
+//! starts, shared by the x86_64 SIPI, aarch64 PSCI and riscv64 HSM paths
+//!
+//! Processors are numbered in the order they are started, the boot
+//! processor is number 0. The boot processor announces the processor it is
+//! about to start, which meets it by checking in, and waits for it with a
+//! timeout. Processors which time out are reported rather than just missing
+//! from the count, and every outcome is recorded for the kernel in the boot
+//! info along with a bitmap of the processors which are online.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spinlock::SpinLock;
+
+use boot_info::{Core, Cores, MAX_CORES};
+use crate::time;
+
+/// Number of the processor being started, zero while no processor is
+const NOBODY: usize = 0;
+
+/// Number of the processor the boot processor is waiting on to check in
+static STARTING: AtomicUsize = AtomicUsize::new(NOBODY);
+
+/// Number of the last processor which checked in through [`check_in`]
+static ARRIVED: AtomicUsize = AtomicUsize::new(NOBODY);
+
+/// Number of processors which checked in through [`check_in`] while none
+/// was being started, after they were given up on
+static LATE: AtomicUsize = AtomicUsize::new(0);
+
+/// What happened to every processor, handed to the kernel
+static CORES: SpinLock<Cores> = SpinLock::new(Cores {
+    num_cores: 0,
+    online:    [0; MAX_CORES.div_ceil(64)],
+    cores:     [Core { processor_id: 0, state: 0 }; MAX_CORES],
+});
+
+/// Record the state of a processor
+///
+/// # Parameters
+///
+/// * `num`          - The number of the processor
+/// * `processor_id` - The firmware ID of the processor
+/// * `state`        - One of the `CORE_STATE_` constants of the boot info
+///
+fn record(num: usize, processor_id: u64, state: u32) {
+    if num >= MAX_CORES {
+        log_warn!("Processor {:#x} does not fit the boot info\n",
+            processor_id);
+        return;
+    }
+
+    let mut cores = CORES.lock();
+    cores.cores[num] = Core { processor_id, state };
+    cores.num_cores = cores.num_cores.max(num as u32 + 1);
+    let bit = 1 << (num % 64);
+    if state == boot_info::CORE_STATE_ONLINE {
+        cores.online[num / 64] |= bit;
+    } else {
+        cores.online[num / 64] &= !bit;
+    }
+}
+
+/// Record the boot processor as processor 0, which is online by definition
+///
+/// # Parameters
+///
+/// * `processor_id` - The firmware ID of the boot processor
+///
+pub fn boot_processor(processor_id: u64) {
+    record(0, processor_id, boot_info::CORE_STATE_ONLINE);
+}
+
+/// Announce the processor about to be started, before starting it
+///
+/// # Parameters
+///
+/// * `num` - The number of the processor, counting from 1
+///
+pub fn announce(num: usize) {
+    STARTING.store(num, Ordering::SeqCst);
+}
+
+/// Check in from a processor which was just started and can use atomics,
+/// which are only safe with the caches on. Processors which check in after
+/// they were given up on are counted as late.
+pub fn check_in() {
+    match STARTING.load(Ordering::SeqCst) {
+        NOBODY => { LATE.fetch_add(1, Ordering::SeqCst); }
+        num    => { ARRIVED.fetch_max(num, Ordering::SeqCst); }
+    }
+}
+
+/// Wait for the processor announced with [`announce`] to check in
+///
+/// # Parameters
+///
+/// * `num`        - The number of the processor
+/// * `timeout_us` - The number of microseconds to wait
+/// * `checked_in` - Check whether the processor checked in some other way
+///                  than [`check_in`], for processors which start with
+///                  their caches off
+///
+/// # Returns
+///
+/// Whether the processor checked in in time, `None` if there is no
+/// calibrated counter to time the wait with
+///
+pub fn wait(num: usize, timeout_us: u64, mut checked_in: impl FnMut() -> bool)
+        -> Option<bool> {
+    time::wait_us(timeout_us, || {
+        ARRIVED.load(Ordering::SeqCst) >= num || checked_in()
+    })
+}
+
+/// Finish starting a processor, recording how it went. A processor which
+/// timed out is reported, it is no longer waited on so if it checks in
+/// later it is counted as late.
+///
+/// # Parameters
+///
+/// * `num`          - The number of the processor
+/// * `processor_id` - The firmware ID of the processor
+/// * `state`        - One of the `CORE_STATE_` constants of the boot info
+///
+pub fn finish(num: usize, processor_id: u64, state: u32) {
+    STARTING.store(NOBODY, Ordering::SeqCst);
+    if state == boot_info::CORE_STATE_TIMED_OUT {
+        log_warn!("Processor {:#x} was started but did not check in, it may \
+                   be hung\n", processor_id);
+    }
+    record(num, processor_id, state);
+}
+
+/// Get the number of processors which checked in after they were given up
+/// on. They are running code nobody is waiting on, and are not in the boot
+/// info as online.
+pub fn late() -> usize {
+    LATE.load(Ordering::SeqCst)
+}
+
+/// Get what happened to every processor, for the boot info
+pub fn boot_info() -> Cores {
+    *CORES.lock()
+}
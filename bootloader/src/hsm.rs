@@ -0,0 +1,201 @@
+//! Starting the secondary harts of riscv64 machines with the SBI hart state
+//! management extension
+//!
+//! A started hart enters the holding pen with translation off, on the stack
+//! it was given. It checks in through its own slot of the pen and waits
+//! there until the kernel releases it, by writing the address to jump to
+//! into the slot.
+
+use core::mem::size_of;
+
+use crate::acpi::Madt;
+use crate::efi;
+use crate::handshake;
+use crate::sbi::{self, HartState};
+
+/// A `Result` type which wraps a secondary hart startup error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from starting the secondary harts
+#[derive(Debug)]
+pub enum Error {
+    /// The SBI does not implement hart state management, so there is no way
+    /// to start harts
+    NotSupported,
+
+    /// The holding pen could not be allocated
+    AllocatePen(efi::Error),
+
+    /// The stacks could not be allocated
+    AllocateStacks(efi::Error),
+
+    /// There is no calibrated counter to time the startup with
+    NoTimer,
+}
+
+/// Size (in bytes) of the stack of each secondary hart
+const HART_STACK_SIZE: usize = 16 * 1024;
+
+/// Number of microseconds to wait for a hart to check in after
+/// `sbi_hart_start`, before giving up on it
+const STARTUP_TIMEOUT_US: u64 = 100_000;
+
+global_asm!(r#"
+    .balign 4
+    .global hsm_pen_entry
+hsm_pen_entry:
+    // We get our hart ID in a0 and the physical address of our `PenSlot` in
+    // a1. Translation is off, so the addresses are used as they are.
+    ld    sp, 32(a1)
+    li    t0, 1
+    fence rw, w
+    sd    t0, 8(a1)
+
+    // Wait for an entry point, then jump to it with our hart ID in a0 and
+    // the argument in a1
+1:
+    ld    t0, 16(a1)
+    beqz  t0, 1b
+    fence r, rw
+    ld    a1, 24(a1)
+    jr    t0
+"#);
+
+extern {
+    /// Where started harts enter the holding pen
+    static hsm_pen_entry: u8;
+}
+
+/// The slot of one secondary hart in the holding pen. The layout is shared
+/// with the pen code and with the kernel, which releases the hart.
+#[derive(Debug)]
+#[repr(C, align(64))]
+pub struct PenSlot {
+    /// The hart ID of the hart
+    pub hart_id: u64,
+
+    /// Set to non-zero by the hart once it is waiting in the pen
+    pub online: u64,
+
+    /// Physical address the hart jumps to once released, zero to keep
+    /// waiting
+    pub entry: u64,
+
+    /// The value the hart is released with in a1
+    pub arg: u64,
+
+    /// Top of the stack of [`HART_STACK_SIZE`] bytes the hart runs on
+    pub stack: u64,
+}
+
+/// The holding pen the secondary harts are started into
+pub struct Pen {
+    /// The hart ID of the hart we run on, `None` if the SBI reports no
+    /// started hart
+    own: Option<u64>,
+
+    /// A [`PenSlot`] for each secondary hart, each pointing at its own
+    /// stack
+    slots: &'static mut [PenSlot],
+}
+
+/// Set up the holding pen and stacks to start the secondary harts with,
+/// while boot services can still allocate memory
+///
+/// # Parameters
+///
+/// * `madt` - The MADT listing the harts
+///
+/// # Returns
+///
+/// The [`Pen`] to pass to [`start_secondaries`], on error [`Error`]
+///
+pub fn prepare(madt: &Madt) -> Result<Pen> {
+    if !sbi::hsm_supported() { return Err(Error::NotSupported); }
+
+    // We don't start any harts before this, so ours is the only one running
+    let own = madt.harts().filter(|x| x.enabled).map(|x| x.hart_id)
+        .find(|&id| matches!(sbi::hart_status(id), Ok(HartState::Started)));
+
+    // Only stopped harts can be started, which leaves out our own
+    let harts = || {
+        madt.harts().filter(|x| x.enabled).map(|x| x.hart_id)
+            .filter(|&id| {
+                matches!(sbi::hart_status(id), Ok(HartState::Stopped))
+            })
+    };
+
+    let (slots, stacks) = match harts().count() {
+        0     => (&mut [][..], &mut [][..]),
+        count => unsafe {
+            let mem = efi::allocate_pages(count * size_of::<PenSlot>())
+                .map_err(Error::AllocatePen)?;
+            let stacks = efi::allocate_pages(count * HART_STACK_SIZE)
+                .map_err(Error::AllocateStacks)?;
+            (core::slice::from_raw_parts_mut(mem.as_mut_ptr() as *mut PenSlot,
+                                             count), stacks)
+        },
+    };
+    for ((slot, hart_id), stack) in slots.iter_mut().zip(harts())
+            .zip(stacks.chunks_exact_mut(HART_STACK_SIZE)) {
+        let stack = stack.as_mut_ptr() as u64 + HART_STACK_SIZE as u64;
+        *slot = PenSlot { hart_id, online: 0, entry: 0, arg: 0, stack };
+    }
+
+    Ok(Pen { own, slots })
+}
+
+/// Start every stopped hart in the MADT into the holding pen, one at a time
+///
+/// # Parameters
+///
+/// * `pen` - The holding pen from [`prepare`]
+///
+/// # Returns
+///
+/// The number of secondary harts which checked in, on error [`Error`].
+/// Harts which don't check in in time are skipped.
+///
+/// # Safety
+///
+/// The secondary harts must not be running anything, and the memory map must
+/// identity map the pen, its stacks and the bootloader.
+///
+pub unsafe fn start_secondaries(pen: &mut Pen) -> Result<usize> {
+    if let Ok((major, minor)) = sbi::spec_version() {
+        log_info!("SBI {}.{} hart state management\n", major, minor);
+    }
+
+    if let Some(own) = pen.own { handshake::boot_processor(own); }
+
+    let entry = &hsm_pen_entry as *const u8 as u64;
+    let mut online = 0;
+    for (num, slot) in pen.slots.iter().enumerate()
+            .map(|(num, slot)| (num + 1, slot)) {
+        handshake::announce(num);
+        let arg = slot as *const PenSlot as u64;
+        if let Err(err) = sbi::hart_start(slot.hart_id, entry, arg) {
+            log_warn!("Secondary hart {} did not start: {:?}\n",
+                slot.hart_id, err);
+            handshake::finish(num, slot.hart_id,
+                              boot_info::CORE_STATE_START_FAILED);
+            continue;
+        }
+
+        // The hart checks in through its slot, from the pen code
+        let started = handshake::wait(num, STARTUP_TIMEOUT_US, || {
+            core::ptr::read_volatile(&slot.online) != 0
+        }).ok_or(Error::NoTimer)?;
+
+        if started {
+            online += 1;
+            handshake::finish(num, slot.hart_id,
+                              boot_info::CORE_STATE_ONLINE);
+        } else {
+            handshake::finish(num, slot.hart_id,
+                              boot_info::CORE_STATE_TIMED_OUT);
+        }
+    }
+
+    Ok(online)
+}
@@ -0,0 +1,369 @@
+//! Reporting processor exceptions on x86_64, rather than letting them end in
+//! a triple fault or in the firmware's own handlers
+//!
+//! The firmware's IDT is copied, so its interrupts keep working while boot
+//! services run, and the invalid opcode, double fault, general protection
+//! and page fault vectors are pointed at us. Double faults get their own
+//! stack, as the one they happened on may be what faulted.
+
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::print;
+use crate::regs::Registers;
+
+/// A `Result` type which wraps an IDT setup error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from setting up the exception handlers
+#[derive(Debug)]
+pub enum Error {
+    /// The firmware's GDT, with this many entries, leaves no room for our
+    /// TSS
+    GdtTooLarge(usize),
+}
+
+/// Maximum number of GDT entries, including the two our TSS takes
+const MAX_GDT_ENTRIES: usize = 32;
+
+/// Number of IDT entries, one for each vector
+const IDT_ENTRIES: usize = 256;
+
+/// Size (in bytes) of the stack double faults are handled on
+const DOUBLE_FAULT_STACK_SIZE: usize = 16 * 1024;
+
+/// The interrupt stack table entry double faults use
+const DOUBLE_FAULT_IST: u8 = 1;
+
+/// Vector of the invalid opcode exception
+const VECTOR_UD: u8 = 6;
+
+/// Vector of the double fault exception
+const VECTOR_DF: u8 = 8;
+
+/// Vector of the general protection exception
+const VECTOR_GP: u8 = 13;
+
+/// Vector of the page fault exception
+const VECTOR_PF: u8 = 14;
+
+global_asm!(r#"
+    // Every entry leaves an error code and the vector on the stack, a zero
+    // error code for exceptions which don't push one
+    .global idt_entry_ud
+idt_entry_ud:
+    push 0
+    push 6
+    jmp idt_entry_common
+
+    .global idt_entry_df
+idt_entry_df:
+    push 8
+    jmp idt_entry_common
+
+    .global idt_entry_gp
+idt_entry_gp:
+    push 13
+    jmp idt_entry_common
+
+    .global idt_entry_pf
+idt_entry_pf:
+    push 14
+    jmp idt_entry_common
+
+idt_entry_common:
+    // Save the registers in the order of `Frame::gpr`, with a placeholder
+    // for rsp which is taken from the interrupt frame instead
+    push r15
+    push r14
+    push r13
+    push r12
+    push r11
+    push r10
+    push r9
+    push r8
+    push 0
+    push rbp
+    push rdi
+    push rsi
+    push rdx
+    push rcx
+    push rbx
+    push rax
+
+    // Link the faulting instruction into the frame pointer chain, so a
+    // backtrace goes through it
+    push qword ptr [rsp + 18 * 8]
+    push rbp
+    mov rbp, rsp
+
+    // Pass the `Frame` in the first argument register of both calling
+    // conventions, with the stack aligned and room to spill arguments
+    lea rdi, [rsp + 16]
+    mov rcx, rdi
+    sub rsp, 40
+    call idt_exception_handler
+    ud2
+"#);
+
+extern {
+    /// Entry point of invalid opcode exceptions
+    fn idt_entry_ud();
+
+    /// Entry point of double fault exceptions
+    fn idt_entry_df();
+
+    /// Entry point of general protection exceptions
+    fn idt_entry_gp();
+
+    /// Entry point of page fault exceptions
+    fn idt_entry_pf();
+}
+
+/// The state saved on the stack when an exception is taken
+#[repr(C)]
+struct Frame {
+    /// The general purpose registers, in the order of the register dump
+    gpr: [u64; 16],
+
+    /// The vector of the exception
+    vector: u64,
+
+    /// The error code of the exception, zero if it has none
+    error_code: u64,
+
+    /// The address of the faulting instruction
+    rip: u64,
+
+    /// The code segment of the faulting instruction
+    cs: u64,
+
+    /// The flags at the time of the exception
+    rflags: u64,
+
+    /// The stack pointer at the time of the exception
+    rsp: u64,
+
+    /// The stack segment at the time of the exception
+    ss: u64,
+}
+
+/// Operand of `lgdt`, `sgdt`, `lidt` and `sidt`
+#[derive(Default)]
+#[repr(C, packed)]
+struct TablePointer {
+    /// Size (in bytes) of the table, minus one
+    limit: u16,
+
+    /// Address of the table
+    base: u64,
+}
+
+/// A 64-bit task state segment, which only holds stacks in long mode
+#[repr(C, packed)]
+struct Tss {
+    /// Reserved
+    reserved0: u32,
+
+    /// Stacks for privilege levels 0 to 2
+    rsp: [u64; 3],
+
+    /// Reserved
+    reserved1: u64,
+
+    /// The interrupt stack table, entries 1 to 7
+    ist: [u64; 7],
+
+    /// Reserved
+    reserved2: u64,
+
+    /// Reserved
+    reserved3: u16,
+
+    /// Offset of the I/O permission bitmap from the start of the TSS
+    iomap_base: u16,
+}
+
+/// A table aligned for the processor to load it quickly
+#[repr(C, align(16))]
+struct Aligned<T>(T);
+
+/// Our IDT, a copy of the firmware's with our exception handlers in it
+static mut IDT: Aligned<[[u64; 2]; IDT_ENTRIES]> =
+    Aligned([[0; 2]; IDT_ENTRIES]);
+
+/// Our GDT, a copy of the firmware's with our TSS appended
+static mut GDT: Aligned<[u64; MAX_GDT_ENTRIES]> =
+    Aligned([0; MAX_GDT_ENTRIES]);
+
+/// Our TSS, which holds the double fault stack
+static mut TSS: Tss = Tss {
+    reserved0:  0,
+    rsp:        [0; 3],
+    reserved1:  0,
+    ist:        [0; 7],
+    reserved2:  0,
+    reserved3:  0,
+    iomap_base: core::mem::size_of::<Tss>() as u16,
+};
+
+/// The stack double faults are handled on
+static mut DOUBLE_FAULT_STACK: Aligned<[u8; DOUBLE_FAULT_STACK_SIZE]> =
+    Aligned([0; DOUBLE_FAULT_STACK_SIZE]);
+
+/// Build a 64-bit interrupt gate
+///
+/// # Parameters
+///
+/// * `handler` - The address of the entry point
+/// * `cs`      - The code segment to run it in
+/// * `ist`     - The interrupt stack table entry to switch to, zero to stay
+///               on the current stack
+///
+/// # Returns
+///
+/// The IDT entry
+///
+fn interrupt_gate(handler: u64, cs: u16, ist: u8) -> [u64; 2] {
+    /// Present, ring 0, 64-bit interrupt gate
+    const TYPE: u64 = 0x8e;
+
+    [(handler & 0xffff) | (cs as u64) << 16 | (ist as u64) << 32 |
+        TYPE << 40 | ((handler >> 16) & 0xffff) << 48,
+     handler >> 32]
+}
+
+/// Build a descriptor for an available 64-bit TSS
+///
+/// # Parameters
+///
+/// * `base`  - The address of the TSS
+/// * `limit` - The size (in bytes) of the TSS, minus one
+///
+/// # Returns
+///
+/// The two GDT entries
+///
+fn tss_descriptor(base: u64, limit: u64) -> [u64; 2] {
+    /// Present, available 64-bit TSS
+    const TYPE: u64 = 0x89;
+
+    [(limit & 0xffff) | (base & 0xff_ffff) << 16 | TYPE << 40 |
+        ((limit >> 16) & 0xf) << 48 | ((base >> 24) & 0xff) << 56,
+     base >> 32]
+}
+
+/// Install our exception handlers, on top of the firmware's IDT
+///
+/// # Returns
+///
+/// `()` once the handlers are in place, on error [`Error`] with the
+/// firmware's tables left alone
+///
+/// # Safety
+///
+/// Interrupts may fire while the tables are switched, and nothing else may
+/// be changing the GDT or the IDT.
+///
+pub unsafe fn init() -> Result<()> {
+    let mut gdtr = TablePointer::default();
+    asm!("sgdt [{}]", in(reg) &mut gdtr, options(nostack, preserves_flags));
+    let entries = (gdtr.limit as usize + 1) / 8;
+    if entries + 2 > MAX_GDT_ENTRIES {
+        return Err(Error::GdtTooLarge(entries));
+    }
+
+    // Keep the firmware's selectors where they are, and put our TSS after
+    // them. The tables are only touched through raw pointers, never through
+    // references to the statics.
+    let gdt = addr_of_mut!(GDT.0) as *mut u64;
+    core::ptr::copy_nonoverlapping(gdtr.base as *const u64, gdt, entries);
+    let tss = addr_of_mut!(TSS);
+    (*tss).ist[DOUBLE_FAULT_IST as usize - 1] =
+        addr_of!(DOUBLE_FAULT_STACK) as u64 + DOUBLE_FAULT_STACK_SIZE as u64;
+    let descriptor = tss_descriptor(tss as u64,
+                                    core::mem::size_of::<Tss>() as u64 - 1);
+    core::ptr::copy_nonoverlapping(descriptor.as_ptr(), gdt.add(entries), 2);
+
+    // Copy the firmware's vectors, and take the exceptions over
+    let mut idtr = TablePointer::default();
+    asm!("sidt [{}]", in(reg) &mut idtr, options(nostack, preserves_flags));
+    let idt = addr_of_mut!(IDT.0) as *mut [u64; 2];
+    let vectors = ((idtr.limit as usize + 1) / 16).min(IDT_ENTRIES);
+    core::ptr::copy_nonoverlapping(idtr.base as *const [u64; 2], idt,
+                                   vectors);
+
+    let cs: u16;
+    asm!("mov {:x}, cs", out(reg) cs, options(nomem, nostack, preserves_flags));
+    for &(vector, handler, ist) in &[
+        (VECTOR_UD, idt_entry_ud as usize, 0),
+        (VECTOR_DF, idt_entry_df as usize, DOUBLE_FAULT_IST),
+        (VECTOR_GP, idt_entry_gp as usize, 0),
+        (VECTOR_PF, idt_entry_pf as usize, 0),
+    ] {
+        *idt.add(vector as usize) = interrupt_gate(handler as u64, cs, ist);
+    }
+
+    // Switch over, the task register can only be loaded from our GDT
+    let gdtr = TablePointer {
+        limit: ((entries + 2) * 8 - 1) as u16,
+        base:  gdt as u64,
+    };
+    let idtr = TablePointer {
+        limit: (IDT_ENTRIES * 16 - 1) as u16,
+        base:  idt as u64,
+    };
+    asm!("lgdt [{}]", in(reg) &gdtr, options(readonly, nostack, preserves_flags));
+    asm!("ltr {:x}", in(reg) (entries * 8) as u16,
+        options(nomem, nostack, preserves_flags));
+    asm!("lidt [{}]", in(reg) &idtr, options(readonly, nostack, preserves_flags));
+
+    Ok(())
+}
+
+/// Report an exception, then panic so the panic action is taken
+///
+/// # Parameters
+///
+/// * `frame` - The state saved when the exception was taken
+///
+#[no_mangle]
+extern fn idt_exception_handler(frame: &Frame) -> ! {
+    // We may have faulted while printing
+    unsafe { print::force_unlock(); }
+
+    let name = match frame.vector as u8 {
+        VECTOR_UD => "invalid opcode",
+        VECTOR_DF => "double fault",
+        VECTOR_GP => "general protection fault",
+        VECTOR_PF => "page fault",
+        _         => "exception",
+    };
+    log_error!("{} at {:#x} (cs {:#x}), error code {:#x}\n", name,
+        frame.rip, frame.cs, frame.error_code);
+
+    // The error code of a page fault tells what kind of access faulted
+    if frame.vector as u8 == VECTOR_PF {
+        let cr2: u64;
+        unsafe {
+            asm!("mov {}, cr2", out(reg) cr2,
+                options(nomem, nostack, preserves_flags));
+        }
+        let access = match frame.error_code {
+            x if x & (1 << 4) != 0 => "instruction fetch",
+            x if x & (1 << 1) != 0 => "write",
+            _                      => "read",
+        };
+        let cause = match frame.error_code & 1 {
+            0 => "not present",
+            _ => "protection violation",
+        };
+        log_error!("Page fault on {} of {:#x}, {}\n", access, cr2, cause);
+    }
+
+    let mut gpr = frame.gpr;
+    gpr[7] = frame.rsp;
+    log_error!("Registers at the fault:\n{}",
+        Registers::from_exception(gpr, frame.rflags));
+
+    panic!("Unhandled {} at {:#x}", name, frame.rip);
+}
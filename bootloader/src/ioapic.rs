@@ -0,0 +1,256 @@
+//! The I/O APICs of x86_64 machines, programmed to deliver the console UART
+//! interrupt to the boot processor
+//!
+//! Global system interrupt `n` is delivered at vector `0x20 + n`, past the
+//! exception vectors, so the kernel can tell which interrupt it got without
+//! being told. The polarity and trigger mode come from the MADT interrupt
+//! source overrides, or the defaults of the bus the interrupt is on. The
+//! entry is left masked as we have no handler for the vector, the kernel
+//! unmasks it once it has one.
+
+use crate::acpi::{IoApic, Madt, Spcr};
+use crate::apic::LocalApic;
+use crate::mm::virtmem::phys_to_virt;
+
+/// A `Result` type which wraps an I/O APIC error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the I/O APICs
+#[derive(Debug)]
+pub enum Error {
+    /// No I/O APIC has an input for this global system interrupt
+    NoIoApic(u32),
+
+    /// The global system interrupt has no vector of its own
+    NoVector(u32),
+
+    /// The APIC ID is too large for the destination field, it needs
+    /// interrupt remapping
+    DestinationTooLarge(u32),
+}
+
+/// Size (in bytes) of the memory mapped registers
+pub const MMIO_SIZE: u64 = 0x20;
+
+/// Offset of the register selecting the register `IOWIN` accesses
+const IOREGSEL: usize = 0x00;
+
+/// Offset of the window to the selected register
+const IOWIN: usize = 0x10;
+
+/// Register holding the version and the number of redirection entries
+const REG_VERSION: u32 = 0x01;
+
+/// Register holding the low half of the first redirection entry, every
+/// entry takes two registers
+const REG_REDIRECTION: u32 = 0x10;
+
+/// Redirection entry bit for an active low interrupt
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+
+/// Redirection entry bit for a level triggered interrupt
+const REDIRECTION_LEVEL: u32 = 1 << 15;
+
+/// Redirection entry bit which masks the interrupt
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+/// The vector global system interrupt zero is delivered at
+const VECTOR_BASE: u32 = 0x20;
+
+/// The last vector an interrupt can be delivered at, the vector after it is
+/// the one spurious interrupts go to
+const VECTOR_LAST: u32 = 0xfe;
+
+/// How the level of an interrupt signal is read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    /// The interrupt is asserted while the signal is high
+    High,
+
+    /// The interrupt is asserted while the signal is low
+    Low,
+}
+
+/// When an interrupt becomes pending
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// The interrupt is pending for as long as the signal is asserted
+    Level,
+
+    /// The interrupt becomes pending when the signal is asserted
+    Edge,
+}
+
+/// A global system interrupt and how its signal is read
+#[derive(Clone, Copy, Debug)]
+pub struct Interrupt {
+    /// The global system interrupt
+    pub gsi: u32,
+
+    /// How the level of the signal is read
+    pub polarity: Polarity,
+
+    /// When the interrupt becomes pending
+    pub trigger: Trigger,
+}
+
+impl Interrupt {
+    /// Get the interrupt of the serial port of the SPCR. An ISA IRQ signals
+    /// the global system interrupt of the same number unless the MADT
+    /// overrides it, as do global system interrupts below 16. The rest are
+    /// PCI interrupts.
+    ///
+    /// # Parameters
+    ///
+    /// * `madt` - The MADT with the interrupt source overrides
+    /// * `spcr` - The SPCR describing the serial port
+    ///
+    /// # Returns
+    ///
+    /// The [`Interrupt`], `None` if the serial port has no APIC or PC-AT
+    /// interrupt
+    ///
+    pub fn for_spcr(madt: &Madt, spcr: &Spcr) -> Option<Self> {
+        let isa = |irq: u8| {
+            Self { gsi: irq as u32, polarity: Polarity::High,
+                   trigger: Trigger::Edge }
+        };
+
+        // An override applies to whichever side the SPCR gives us
+        let (interrupt, over) = match spcr.io_apic_interrupt() {
+            Some(gsi) => {
+                let over = madt.interrupt_overrides().find(|x| x.gsi == gsi);
+                let interrupt = match gsi {
+                    0..=15 => isa(gsi as u8),
+                    _ => Self { gsi, polarity: Polarity::Low,
+                                trigger: Trigger::Level },
+                };
+                (interrupt, over)
+            }
+            None => {
+                let irq  = spcr.isa_interrupt()?;
+                let over = madt.interrupt_overrides().find(|x| x.irq == irq);
+                (isa(irq), over)
+            }
+        };
+
+        // Both fields of the MPS INTI flags leave the bus default alone at
+        // zero
+        Some(over.map_or(interrupt, |over| Self {
+            gsi: over.gsi,
+            polarity: match over.flags & 3 {
+                1 => Polarity::High,
+                3 => Polarity::Low,
+                _ => interrupt.polarity,
+            },
+            trigger: match (over.flags >> 2) & 3 {
+                1 => Trigger::Edge,
+                3 => Trigger::Level,
+                _ => interrupt.trigger,
+            },
+        }))
+    }
+
+    /// Get the vector the interrupt is delivered at
+    ///
+    /// # Returns
+    ///
+    /// The vector, `None` if the global system interrupt is past the last
+    /// vector
+    ///
+    pub fn vector(&self) -> Option<u8> {
+        VECTOR_BASE.checked_add(self.gsi).filter(|&x| x <= VECTOR_LAST)
+            .map(|x| x as u8)
+    }
+}
+
+/// Read an I/O APIC register
+///
+/// # Parameters
+///
+/// * `mmio` - The memory mapped registers
+/// * `reg`  - The register index
+///
+/// # Returns
+///
+/// The value of the register
+///
+unsafe fn read(mmio: *mut u8, reg: u32) -> u32 {
+    core::ptr::write_volatile(mmio.add(IOREGSEL) as *mut u32, reg);
+    core::ptr::read_volatile(mmio.add(IOWIN) as *const u32)
+}
+
+/// Write an I/O APIC register
+///
+/// # Parameters
+///
+/// * `mmio` - The memory mapped registers
+/// * `reg`  - The register index
+/// * `val`  - The value to write
+///
+unsafe fn write(mmio: *mut u8, reg: u32, val: u32) {
+    core::ptr::write_volatile(mmio.add(IOREGSEL) as *mut u32, reg);
+    core::ptr::write_volatile(mmio.add(IOWIN) as *mut u32, val);
+}
+
+/// Find the I/O APIC with an input for a global system interrupt
+///
+/// # Parameters
+///
+/// * `madt` - The MADT listing the I/O APICs
+/// * `gsi`  - The global system interrupt
+///
+/// # Returns
+///
+/// The memory mapped registers of the I/O APIC and the index of the input,
+/// on error [`Error`]
+///
+unsafe fn find(madt: &Madt, gsi: u32) -> Result<(*mut u8, u32)> {
+    for IoApic { base, gsi_base, .. } in
+            madt.io_apics().filter(|x| x.gsi_base <= gsi) {
+        let mmio = phys_to_virt(base).as_mut_ptr::<u8>();
+
+        // The index of the last redirection entry is in bits 16 to 23
+        let last = (read(mmio, REG_VERSION) >> 16) & 0xff;
+        if gsi - gsi_base <= last { return Ok((mmio, gsi - gsi_base)); }
+    }
+
+    Err(Error::NoIoApic(gsi))
+}
+
+/// Program an interrupt to be delivered to our own processor, at its
+/// [`Interrupt::vector`], leaving it masked
+///
+/// # Parameters
+///
+/// * `madt`      - The MADT listing the I/O APICs
+/// * `lapic`     - Our own local APIC, which the interrupt is sent to
+/// * `interrupt` - The interrupt to deliver
+///
+/// # Returns
+///
+/// `()` once the redirection entry is programmed, on error [`Error`]
+///
+/// # Safety
+///
+/// Nothing else may be programming the redirection entry
+///
+pub unsafe fn route_to_self(madt: &Madt, lapic: &LocalApic,
+                            interrupt: Interrupt) -> Result<()> {
+    let vector = interrupt.vector().ok_or(Error::NoVector(interrupt.gsi))?;
+    let id = lapic.id();
+    if id > 0xff { return Err(Error::DestinationTooLarge(id)); }
+    let (mmio, input) = find(madt, interrupt.gsi)?;
+
+    // Fixed delivery to a physical destination, which are zero in bits 8 to
+    // 11. The entry stays masked, also while its halves don't match.
+    let mut low = vector as u32 | REDIRECTION_MASKED;
+    if interrupt.polarity == Polarity::Low { low |= REDIRECTION_ACTIVE_LOW; }
+    if interrupt.trigger  == Trigger::Level { low |= REDIRECTION_LEVEL; }
+    let reg = REG_REDIRECTION + input * 2;
+    write(mmio, reg, REDIRECTION_MASKED);
+    write(mmio, reg + 1, id << 24);
+    write(mmio, reg, low);
+
+    Ok(())
+}
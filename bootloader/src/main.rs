@@ -1,39 +1,215 @@
 //! Main bootlader entry for foobOS
 
-#![feature(asm, panic_info_message, bool_to_option)]
+#![feature(asm, global_asm, panic_info_message, bool_to_option,
+           alloc_error_handler)]
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 #[macro_use] mod print;
 mod core_requirements;
 mod efi;
 mod mm;
 mod acpi;
+mod elf;
+mod cmdline;
+mod menu;
+mod splash;
+mod time;
+mod timing;
+mod handshake;
+mod regs;
+mod backtrace;
+#[cfg(target_arch = "x86_64")] mod idt;
+#[cfg(target_arch = "x86_64")] mod apic;
+#[cfg(target_arch = "x86_64")] mod ioapic;
+#[cfg(target_arch = "x86_64")] mod smp;
+#[cfg(target_arch = "aarch64")] mod psci;
+#[cfg(target_arch = "aarch64")] mod gic;
+#[cfg(target_arch = "aarch64")] mod vectors;
+#[cfg(target_arch = "riscv64")] mod sbi;
+#[cfg(target_arch = "riscv64")] mod hsm;
 
+use core::alloc::Layout;
 use core::panic::PanicInfo;
-use crate::efi::{EfiHandle, EfiSystemTablePtr, EfiStatusCode};
+use core::mem::size_of;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::efi::{EfiHandle, EfiSystemTablePtr, EfiStatusCode, MemoryKind};
+use crate::acpi::ValidationPolicy;
 use serial::Serial;
+use generic_access_structure::Gas;
+use fbcon::{FbCon, PixelFormat};
+use boot_info::BootInfo;
+use rangeset::{Range, RangeSet};
+use crate::cmdline::{CommandLine, Console, PanicAction, Source};
+use crate::splash::Milestone;
+use crate::mm::page_alloc::{PageAlloc, PAGE_SIZE};
+use crate::mm::memtest::PatternMode;
+use crate::mm::paging::{Layout, PageTable};
+use crate::mm::physmem::PhysAddr;
+use crate::mm::virtmem::VirtAddr;
+use crate::backtrace::Backtrace;
+
+/// Path of the kernel image on the boot partition
+const KERNEL_PATH: &str = "/kernel.elf";
+
+/// The TPM PCR the kernel image is measured into
+const KERNEL_PCR: u32 = 9;
+
+/// The TPM PCR the kernel command line is measured into
+const CMDLINE_PCR: u32 = 8;
+
+/// Offset of the virtual addresses of the EFI runtime regions from their
+/// physical addresses. We keep them identity mapped, such that we can still
+/// reset the system on a panic.
+const RUNTIME_SERVICES_OFFSET: u64 = 0;
+
+/// Number of seconds to wait after a panic before resetting or exiting,
+/// unless the `panic=` option gives its own delay
+const PANIC_DELAY_SECS: u64 = 5;
+
+/// Number of microseconds to wait for the application processors to run
+/// [`ap_probe`]
+const AP_PROBE_TIMEOUT_US: usize = 1_000_000;
+
+/// The `EFI_MEMORY_TYPE` of memory nothing has been allocated in
+const EFI_CONVENTIONAL_MEMORY: u32 = 7;
+
+/// Size (in bytes) of the heap backing `alloc`
+const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size (in bytes) of the kernel stack allocated for every processor
+const KERNEL_STACK_SIZE: u64 = 64 * 1024;
+
+/// Size (in bytes) of the registers of a memory mapped serial port, which
+/// covers every interface type the SPCR describes
+const UART_MMIO_SIZE: u64 = 4096;
+
+/// What to do after a panic, set from the `panic=` option
+static mut PANIC_ACTION: PanicAction = PanicAction::Reset(PANIC_DELAY_SECS);
+
+/// Number of application processors which ran [`ap_probe`]
+static AP_PROBES: AtomicUsize = AtomicUsize::new(0);
+
+/// Procedure run on every application processor with the `mpprobe` option,
+/// to check that they are alive
+unsafe extern fn ap_probe(_arg: *mut u8) {
+    AP_PROBES.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Read a file from wherever `source` points to
+///
+/// # Parameters
+///
+/// * `source` - Where to read the file from
+///
+/// # Returns
+///
+/// The contents of the file in pages which stay reserved after exiting boot
+/// services, on error [`efi::Error`]
+///
+fn read_source(source: &Source) -> Result<&'static mut [u8], efi::Error> {
+    match *source {
+        Source::File(path)           => efi::read_file(path),
+        Source::Tftp { server, path } => efi::tftp_read(server, path),
+    }
+}
+
+/// Measure `data` into the TPM, if there is one
+///
+/// # Parameters
+///
+/// * `pcr`         - The PCR to extend
+/// * `data`        - The data to measure
+/// * `description` - Description of the data for the event log
+///
+fn measure(pcr: u32, data: &[u8], description: &str) {
+    match efi::tpm_measure(pcr, data, description) {
+        Ok(()) | Err(efi::Error::NoTpm(_)) => {}
+        Err(err) => {
+            log_warn!("Failed to measure the {}: {:?}\n", description, err);
+        }
+    }
+}
 
 /// Entry point for panics
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    print!("!!! PANIC !!!\n");
+    // Capture the registers before anything else changes them
+    let regs = regs::Registers::capture();
+
+    // We may have panicked while printing, which would leave the print lock
+    // held forever
+    unsafe { print::force_unlock(); }
+
+    // Make the panic stand out on the EFI console. The message goes to every
+    // sink, and to the EFI standard error console while there is one.
+    let _ = efi::set_color(efi::Color::LightRed, efi::Color::Black);
+    eprint!("!!! PANIC !!!\n");
 
     // Print the location if there is one
     if let Some(location) = info.location() {
-        print!("{}:{}:{}\n",
+        eprint!("{}:{}:{}\n",
             location.file(), location.line(), location.column());
     }
 
     // Print the panic message
     if let Some(message) = info.message() {
-        print!("{}\n", message);
+        eprint!("{}\n", message);
+    }
+
+    // Print the state of the processor, as the panic may come from a fault
+    eprint!("{}", regs);
+
+    // Print the callers, relative to `efi_main` as the image may have been
+    // relocated
+    let base = efi_main as usize as u64;
+    eprint!("Backtrace (efi_main at {:#x}):\n", base);
+    let frames = unsafe { Backtrace::new(regs.frame_pointer()) };
+    for (idx, ret) in frames.enumerate() {
+        let (sign, offset) = if ret >= base {
+            ('+', ret - base)
+        } else {
+            ('-', base - ret)
+        };
+        eprint!("  #{:<2} {:#018x} efi_main{}{:#x}\n", idx, ret, sign, offset);
     }
 
-    // Loop forever
+    // Give whoever is watching the screen a moment to read the message,
+    // then get out of the way. Anything which fails leaves us spinning.
+    let delay = |secs: u64| {
+        let us = secs.saturating_mul(1_000_000);
+        if efi::stall(us as usize).is_err() &&
+                time::busy_wait_us(us).is_none() {
+            let _ = acpi::pm_delay_us(us);
+        }
+    };
+    match unsafe { PANIC_ACTION } {
+        PanicAction::Hang => {}
+        PanicAction::Reset(secs) => {
+            // The ACPI reset still works without the runtime services
+            delay(secs);
+            efi::reset(efi::ResetType::Cold);
+            acpi::reset();
+        }
+        PanicAction::Exit(secs) => {
+            delay(secs);
+            let err = efi::exit(EfiStatusCode::ABORTED);
+            eprint!("Failed to exit to the firmware: {:?}\n", err);
+        }
+    }
     loop { core::hint::spin_loop(); }
 }
 
+/// Handler for when the heap cannot satisfy an allocation
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!("Failed to allocate {} bytes aligned to {}", layout.size(),
+        layout.align());
+}
+
 /// EFI entry point
 #[no_mangle]
 extern fn efi_main(image_handle: EfiHandle,
@@ -42,40 +218,858 @@ extern fn efi_main(image_handle: EfiHandle,
         // First, register the system table in a global so we can use it in
         // other places such as a `print!` macro
         system_table.register();
+        image_handle.register_image();
+        time::init();
+        timing::init();
+
+        // Set up the heap, before anything might allocate
+        match efi::allocate_pages(HEAP_SIZE) {
+            Ok(memory) => mm::heap::init(memory),
+            Err(err)   => { log_error!("No heap: {:?}\n", err); }
+        }
+
+        // Report faults rather than letting them reset the machine
+        #[cfg(target_arch = "x86_64")]
+        if let Err(err) = idt::init() {
+            log_warn!("No exception handlers: {:?}\n", err);
+        }
+
+        // Use as much of the screen as we can, a failure just leaves us in
+        // the default mode
+        let _ = efi::set_largest_text_mode();
 
         // Seems there's no Rust std for the UEFI target, so can't use e.g.
         // std::env::consts::ARCH
         #[cfg(target_arch = "aarch64")] let arch = "aarch64";
         #[cfg(target_arch = "x86_64")]  let arch = "x86_64";
         #[cfg(target_arch = "riscv64")] let arch = "riscv64";
-        print!("\nFoobOS/{} boot\n\n", arch);
+        log_info!("\nFoobOS/{} boot\n\n", arch);
 
-        // Initialize ACPI
-        let acpi = acpi::init().expect("Failed to initialize ACPI");
-        print!("{:#x?}\n", acpi);
-        
-        // Initialize serial
-        let spcr = acpi.spcr.as_ref()
-            .expect("ACPI did not report an SPCR, cannot initialize serial");
+        // Don't let the firmware reset us while we wait on slow devices or
+        // the user
+        if let Err(err) = efi::set_watchdog_timer(0) {
+            log_warn!("Failed to disable the watchdog: {:?}\n", err);
+        }
+
+        // Report the firmware, which is what quirks are keyed on
+        let firmware = efi::firmware_info();
+        match &firmware {
+            Ok(firmware) => { log_info!("Firmware: {}\n", firmware); }
+            Err(err)     => { log_warn!("Firmware unknown: {:?}\n", err); }
+        }
+        let firmware = firmware.map(|x| x.boot_info()).unwrap_or_default();
+
+        // Timestamp the boot
+        if let Ok(time) = efi::get_time() {
+            log_info!("Time: {}\n", time);
+        }
+
+        // Report whether the firmware verified us
+        match efi::secure_boot_state() {
+            Ok(state) => { log_info!("Secure boot: {:?}\n", state); }
+            Err(err)  => {
+                log_warn!("Secure boot state unknown: {:?}\n", err);
+            }
+        }
+        match efi::variable_storage() {
+            Ok(storage) => { log_info!("Variable storage: {}\n", storage); }
+            Err(err)    => {
+                log_warn!("Variable storage unknown: {:?}\n", err);
+            }
+        }
 
-        // Initialize the serial device
-        Serial::init(spcr.interface_type, spcr.address, spcr.baud_rate)
-            .expect("Failed to initialize the serial device");
+        // Find out where we are and how we were started
+        let image = efi::loaded_image().expect("Failed to get our image");
+        log_info!("Loaded at {:#x} ({} bytes)\n", image.base, image.size);
+        match (image.device_path, image.file_path) {
+            (Some(device), Some(file)) => {
+                log_info!("Loaded from {}/{}\n", device, file);
+            }
+            (Some(device), None) => { log_info!("Loaded from {}\n", device); }
+            (None, _) => {}
+        }
+        let mut cmdline = CommandLine::from_load_options(image.load_options);
+        log_info!("Command line: \"{}\"\n", cmdline.as_str());
+
+        // Only print as much as asked for from here on
+        if let Some(val) = cmdline.get("loglevel") {
+            match print::Level::parse(val) {
+                Some(level) => print::set_level(level),
+                None => { log_warn!("Invalid loglevel=\"{}\"\n", val); }
+            }
+        }
+        match cmdline.get("logformat") {
+            Some("pretty") | None => {}
+            Some("kv")            => print::set_structured(true),
+            Some(val)             => {
+                log_warn!("Invalid logformat=\"{}\"\n", val);
+            }
+        }
+        match cmdline.text_mode() {
+            Some(Ok((columns, rows))) => {
+                if let Err(err) = efi::set_text_mode(columns, rows) {
+                    log_warn!("Failed to switch to text mode {}x{}: {:?}\n",
+                        columns, rows, err);
+                }
+            }
+            Some(Err(val)) => { log_warn!("Invalid textmode=\"{}\"\n", val); }
+            None => {}
+        }
+
+        // Pick what a panic from here on does
+        match cmdline.panic_action(PANIC_DELAY_SECS) {
+            Some(Ok(action)) => PANIC_ACTION = action,
+            Some(Err(val))   => { log_warn!("Invalid panic=\"{}\"\n", val); }
+            None             => {}
+        }
+
+        // Check the hand written mem* routines, if asked to
+        if cmdline.get("memselftest").is_some() {
+            match core_requirements::self_test() {
+                Ok(())   => { log_info!("mem* self-test passed\n"); }
+                Err(err) => panic!("mem* self-test failed: {:?}", err),
+            }
+        }
+
+        // Initialize ACPI. Some firmware ships tables which are otherwise
+        // fine but have a stale checksum or a wrong length, each table which
+        // fails on exactly that is warned about and used, while any other
+        // problem still skips it.
+        let acpi = acpi::init(ValidationPolicy::WarnAndContinue,
+                              ValidationPolicy::WarnAndContinue)
+            .expect("Failed to initialize ACPI");
+        timing::mark("acpi init");
+
+        // Take the frequency of the time base from the ACPI timers on x86_64,
+        // which are more trustworthy than the firmware, and from CNTFRQ_EL0
+        // on aarch64
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        match time::calibrate(&acpi) {
+            Ok((freq, reference)) => {
+                log_info!("Counter: {} Hz, from the {:?}\n", freq, reference);
+            }
+            Err(err) => {
+                log_warn!("Counter calibration failed: {:?}\n", err);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        if let Some(gtdt) = &acpi.gtdt {
+            log_debug!("EL1 physical timer interrupt {}\n",
+                gtdt.non_secure_el1.gsiv);
+        }
+
+        log_debug!("{:#x?}\n", acpi);
+        log_info!("ACPI revision {} by {} ({})\n", acpi.info.rsdp_revision,
+            acpi.info.oem_id_str(), acpi.info.oem_table_id_str());
+
+        // Report tables which have been skipped
+        for err in acpi.errors() {
+            log_warn!("ACPI: skipped table at {:#x}: {:?}\n",
+                err.addr.0, err.error);
+            if print::enabled(print::Level::Debug) {
+                hexdump!(err.addr.0, acpi::TABLE_HEADER_SIZE);
+            }
+        }
+        if acpi.dropped_errors() > 0 {
+            log_warn!("ACPI: {} more tables skipped\n", acpi.dropped_errors());
+        }
+
+        // Report the memory topology
+        if let Some(pmtt) = &acpi.pmtt {
+            log_debug!("Memory topology:\n");
+            for dev in pmtt.devices() {
+                log_debug!("{:width$}{:?} {:#x}\n", "", dev.typ, dev.id,
+                    width = 2 + dev.depth as usize * 2);
+            }
+        }
         
+        // Initialize the serial device, if there is one. A console given on
+        // the command line overrides the SPCR.
+        match cmdline.console() {
+            Some(Ok(Console::Firmware)) => {
+                log_info!("Serial disabled by the command line\n");
+            }
+            Some(Ok(Console::Serial { interface, address, baud_rate })) => {
+                Serial::init(interface, address, baud_rate, None)
+                    .expect("Failed to initialize the serial device");
+            }
+            Some(Err(val)) => {
+                log_warn!("Invalid console=\"{}\", serial disabled\n", val);
+            }
+            None => if let Some(spcr) = &acpi.spcr {
+                Serial::init(spcr.interface_type, spcr.address,
+                             spcr.baud_rate, spcr.clock)
+                    .expect("Failed to initialize the serial device");
+            } else {
+                // Without a UART to drive, riscv64 keeps printing through the
+                // SBI once the EFI serial port is gone
+                #[cfg(target_arch = "riscv64")]
+                match sbi::enable_console() {
+                    Ok(()) => log_info!("Using the SBI console once boot \
+                                         services are exited\n"),
+                    Err(err) => log_warn!("No SBI console: {:?}\n", err),
+                }
+
+                if let Err(err) = efi::init_serial_io() {
+                    log_warn!("ACPI did not report an SPCR, serial disabled: \
+                               {:?}\n", err);
+                } else {
+                    log_info!("ACPI did not report an SPCR, using the EFI \
+                               serial port until boot services are exited\n");
+                }
+            }
+        }
+
+        timing::mark("serial init");
+
+        // Color warnings and errors, unless the SPCR says the terminal
+        // can't show colors
+        let color = acpi.spcr.as_ref()
+            .map_or(true, |spcr| spcr.terminal.supports_color());
+        match cmdline.get("color") {
+            Some("on")  => print::set_color(true),
+            Some("off") => print::set_color(false),
+            Some(val)   => {
+                log_warn!("Invalid color=\"{}\"\n", val);
+                print::set_color(color);
+            }
+            None => print::set_color(color),
+        }
+
+        // Find the framebuffer while we can still ask EFI for it
+        let fb = efi::get_framebuffer();
+        if let Err(err) = &fb {
+            log_warn!("No framebuffer console: {:?}\n", err);
+        }
+        let framebuffer = fb.as_ref().map(|fb| boot_info::Framebuffer {
+            present: 1,
+            format:  match fb.format {
+                PixelFormat::Rgb => boot_info::PIXEL_FORMAT_RGB,
+                PixelFormat::Bgr => boot_info::PIXEL_FORMAT_BGR,
+            },
+            base:    fb.base as u64,
+            size:    fb.size as u64,
+            width:   fb.width,
+            height:  fb.height,
+            stride:  fb.stride,
+        }).unwrap_or_default();
+
+        // List the disks and partitions
+        let mut disks = [None; 32];
+        if let Ok(count) = efi::block_devices(&mut disks) {
+            for disk in disks[..count].iter().flatten() {
+                log_info!("Block device: {} blocks of {} bytes{}\n",
+                    disk.blocks(), disk.block_size(),
+                    if disk.is_partition() { " (partition)" } else { "" });
+            }
+        }
+
+        // List the processors, and check that the firmware agrees with the
+        // MADT
+        let cpus = match efi::processors() {
+            Ok(cpus) => {
+                log_info!("Processors: {} ({} enabled)\n", cpus.len(),
+                    cpus.iter().filter(|x| x.enabled).count());
+
+                #[cfg(target_arch = "x86_64")]
+                if let Some(madt) = &acpi.madt {
+                    for apic in madt.processors().filter(|x| x.enabled) {
+                        if !cpus.iter().any(|x| x.id == apic.apic_id as u64) {
+                            log_warn!("MADT processor {:#x} unknown to EFI\n",
+                                apic.apic_id);
+                        }
+                    }
+                }
+
+                cpus
+            }
+            Err(err) => {
+                log_warn!("No processor information: {:?}\n", err);
+                Vec::new()
+            }
+        };
+
+        // Enable our local APIC, which interprocessor interrupts are sent
+        // through
+        #[cfg(target_arch = "x86_64")]
+        let lapic = acpi.madt.as_ref().and_then(|madt| {
+            match apic::LocalApic::init(madt) {
+                Ok(lapic) => {
+                    log_info!("Local APIC ID {:#x}{}\n", lapic.id(),
+                        if lapic.is_x2apic() { " (x2APIC)" } else { "" });
+                    Some(lapic)
+                }
+                Err(err) => {
+                    log_warn!("Failed to enable the local APIC: {:?}\n", err);
+                    None
+                }
+            }
+        });
+
+        // Make sure the application processors are alive, if asked to
+        if cmdline.get("mpprobe").is_some() {
+            match efi::startup_all_aps(ap_probe, core::ptr::null_mut(),
+                                       AP_PROBE_TIMEOUT_US) {
+                Ok(()) => {
+                    log_info!("{} application processors responded\n",
+                        AP_PROBES.load(Ordering::SeqCst));
+                }
+                Err(err) => {
+                    log_warn!("Failed to probe the APs: {:?}\n", err);
+                }
+            }
+        }
+
+        // Give the user a chance to change how we boot
+        match menu::run(&mut cmdline) {
+            menu::Choice::Boot => {}
+            menu::Choice::BootNext => match efi::set_boot_next() {
+                Ok(next) => {
+                    log_info!("Booting Boot{:04X} next\n", next);
+                    efi::reset(efi::ResetType::Cold);
+                }
+                Err(err) => {
+                    log_error!("Failed to set the next boot entry: {:?}\n",
+                        err);
+                }
+            },
+            menu::Choice::Exit => {
+                let err = efi::exit(EfiStatusCode::SUCCESS);
+                log_error!("Failed to exit to the firmware: {:?}\n", err);
+            }
+            menu::Choice::Reset => { efi::reset(efi::ResetType::Cold); }
+        }
+
+        // Cover the console with a splash screen, if asked to
+        if let (Some(path), Ok(fb)) = (cmdline.get("splash"), &fb) {
+            let logo = if path.is_empty() {
+                acpi.bgrt.as_ref().map(|bgrt| {
+                    (bgrt.image(), bgrt.displayed.then_some((bgrt.x, bgrt.y)))
+                })
+            } else {
+                efi::read_file(path).map_err(|err| {
+                    log_warn!("Failed to read {}: {:?}\n", path, err);
+                }).ok().map(|image| (&*image, None))
+            };
+            if let Err(err) = splash::init(*fb, logo) {
+                log_warn!("Failed to show the splash screen: {:?}\n", err);
+            }
+        }
+
+        // Read the kernel while we still have file system and network access
+        let kernel_source = match cmdline.kernel() {
+            Some(Ok(source)) => source,
+            Some(Err(val)) => {
+                log_warn!("Invalid kernel=\"{}\", using {}\n", val,
+                    KERNEL_PATH);
+                Source::File(KERNEL_PATH)
+            }
+            None => Source::File(KERNEL_PATH),
+        };
+        let kernel = read_source(&kernel_source);
+        if let Err(err) = &kernel {
+            log_error!("Failed to read {}: {:?}\n", kernel_source, err);
+            if let Some(device) = image.device_path {
+                log_error!("Boot device was {}\n", device);
+            }
+        }
+
+        splash::progress(Milestone::KernelRead);
+
+        // Pick the random number placing the kernel while the firmware RNG
+        // is still available, unless KASLR is turned off
+        let kaslr = match cmdline.get("kaslr") {
+            Some("off") => None,
+            _ => {
+                let mut random = [0u8; 8];
+                match efi::get_random(&mut random) {
+                    Ok(()) => Some(u64::from_le_bytes(random)),
+                    Err(err) => {
+                        log_warn!("No randomness for KASLR: {:?}\n", err);
+                        None
+                    }
+                }
+            }
+        };
+
+        // Read the initial RAM disk, if there is one
+        let initrd = match cmdline.initrd() {
+            Some(Ok(source)) => read_source(&source).map_err(|err| {
+                log_warn!("Failed to read {}: {:?}\n", source, err);
+            }).ok(),
+            Some(Err(val)) => {
+                log_warn!("Invalid initrd=\"{}\", ignoring it\n", val);
+                None
+            }
+            None => None,
+        };
+
+        // Measure what we run before running it and keep the log of all the
+        // measurements for the kernel
+        if let Ok(kernel) = &kernel { measure(KERNEL_PCR, kernel, "kernel"); }
+        if let Some(initrd) = &initrd { measure(KERNEL_PCR, initrd, "initrd"); }
+        measure(CMDLINE_PCR, cmdline.as_str().as_bytes(), "command line");
+        let initrd = initrd.map(|initrd| boot_info::Initrd {
+            present: 1,
+            addr:    initrd.as_ptr() as u64,
+            size:    initrd.len() as u64,
+        }).unwrap_or_default();
+        let tpm_event_log = efi::tpm_event_log().unwrap_or_default();
+        splash::progress(Milestone::Measured);
+
+        // Set up what the application processors are started with, while
+        // low memory can still be allocated
+        #[cfg(target_arch = "x86_64")]
+        let mut trampoline = match (&acpi.madt, &lapic) {
+            (Some(madt), Some(lapic)) if cmdline.get("nosmp").is_none() => {
+                smp::prepare(madt, lapic).map_err(|err| {
+                    log_warn!("Not starting the APs: {:?}\n", err);
+                }).ok()
+            }
+            _ => None,
+        };
+        #[cfg(target_arch = "aarch64")]
+        let mut pen = match (&acpi.madt, &acpi.fadt) {
+            (Some(madt), Some(fadt)) if cmdline.get("nosmp").is_none() => {
+                psci::prepare(madt, fadt).map_err(|err| {
+                    log_warn!("Not starting the secondary cores: {:?}\n", err);
+                }).ok()
+            }
+            _ => None,
+        };
+        #[cfg(target_arch = "riscv64")]
+        let mut pen = match &acpi.madt {
+            Some(madt) if cmdline.get("nosmp").is_none() => {
+                hsm::prepare(madt).map_err(|err| {
+                    log_warn!("Not starting the secondary harts: {:?}\n", err);
+                }).ok()
+            }
+            _ => None,
+        };
+
         // Get the memory map and exit boot services
-        let mm = efi::get_memory_map_and_exit_boot_services(image_handle)
-            .expect("Failed to get EFI memory map");
-        print!("Exited boot services, bye EFI\n");
+        let (memory, mut memory_map) =
+            efi::get_memory_map_and_exit_boot_services(image_handle)
+                .expect("Failed to get EFI memory map");
+
+        // Boot services memory is left out, we are still running on the page
+        // tables, descriptor tables and stack in it
+        let mut mm = memory.filter(MemoryKind::is_usable)
+            .expect("Failed to get the usable memory");
+        timing::mark("memory map");
+
+        // The screen is ours now, bring up the framebuffer console unless
+        // the splash screen is on it
+        splash::progress(Milestone::ExitedBootServices);
+        if let (Ok(fb), false) = (fb, splash::active()) {
+            FbCon::init(fb).expect("Failed to initialize the framebuffer");
+        }
+        log_info!("Exited boot services, bye EFI\n");
+        if print::failed_writes() > 0 {
+            log_warn!("{} console writes failed\n", print::failed_writes());
+        }
+        if memory_map.dropped > 0 {
+            log_warn!("EFI memory map: {} descriptors did not fit\n",
+                memory_map.dropped);
+        }
+
+        // The firmware no longer takes interrupts, so its exception vectors
+        // can be replaced by ones which report faults
+        #[cfg(target_arch = "aarch64")]
+        if let Err(err) = vectors::init() {
+            log_warn!("No exception vectors: {:?}\n", err);
+        }
+
+        // Start the application processors. They start on the firmware's
+        // page table, which stays intact as boot services memory is never
+        // allocated from.
+        #[cfg(target_arch = "x86_64")]
+        if let (Some(madt), Some(lapic), Some(trampoline)) =
+                (&acpi.madt, &lapic, &mut trampoline) {
+            match smp::start_aps(trampoline, madt, lapic) {
+                Ok(count) => {
+                    log_info!("{} application processors started\n", count);
+                }
+                Err(err) => {
+                    log_warn!("Failed to start the APs: {:?}\n", err);
+                }
+            }
+            timing::mark("ap startup");
+        }
+        #[cfg(target_arch = "aarch64")]
+        if let Some(pen) = &mut pen {
+            match psci::start_secondaries(pen) {
+                Ok(count) => {
+                    log_info!("{} secondary cores started\n", count);
+                }
+                Err(err) => {
+                    log_warn!("Failed to start the secondary cores: {:?}\n",
+                        err);
+                }
+            }
+            timing::mark("ap startup");
+        }
+        #[cfg(target_arch = "riscv64")]
+        if let Some(pen) = &mut pen {
+            match hsm::start_secondaries(pen) {
+                Ok(count) => {
+                    log_info!("{} secondary harts started\n", count);
+                }
+                Err(err) => {
+                    log_warn!("Failed to start the secondary harts: {:?}\n",
+                        err);
+                }
+            }
+            timing::mark("ap startup");
+        }
+
+        // Processors which timed out may have come up after all, running
+        // code nobody is waiting on
+        if handshake::late() > 0 {
+            log_warn!("{} processors checked in after they were given up \
+                       on\n", handshake::late());
+        }
+
+        // Take the interrupt controller over from the firmware, and have the
+        // console UART interrupt routed to us for the kernel to unmask
+        #[cfg(target_arch = "x86_64")]
+        if let (Some(madt), Some(lapic), Some(spcr)) =
+                (&acpi.madt, &lapic, &acpi.spcr) {
+            if let Some(interrupt) = ioapic::Interrupt::for_spcr(madt, spcr) {
+                match ioapic::route_to_self(madt, lapic, interrupt) {
+                    Ok(()) => {
+                        log_info!("UART interrupt {} routed, masked ({:?}, \
+                                   {:?} triggered)\n", interrupt.gsi,
+                            interrupt.polarity, interrupt.trigger);
+                    }
+                    Err(err) => {
+                        log_warn!("Failed to route the UART interrupt: \
+                                   {:?}\n", err);
+                    }
+                }
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        let gic = acpi.madt.as_ref().and_then(|madt| {
+            match gic::Gic::init(madt) {
+                Ok(gic) => {
+                    log_info!("GICv{} enabled\n", gic.version());
+                    Some(gic)
+                }
+                Err(err) => {
+                    log_warn!("Failed to enable the GIC: {:?}\n", err);
+                    None
+                }
+            }
+        });
+        #[cfg(target_arch = "aarch64")]
+        if let (Some(gic), Some(intid)) =
+                (&gic, acpi.spcr.as_ref().and_then(|x| x.gic_interrupt())) {
+            match gic.route_to_self(intid, gic::Trigger::Level) {
+                Ok(()) => { log_info!("UART interrupt {} routed\n", intid); }
+                Err(err) => {
+                    log_warn!("Failed to route the UART interrupt: {:?}\n",
+                        err);
+                }
+            }
+        }
+
+        // Switch the runtime services over to the mapping the kernel uses
+        let runtime_services = match efi::set_virtual_address_map(
+                &mut memory_map, RUNTIME_SERVICES_OFFSET) {
+            Ok(addr) => boot_info::RuntimeServices { present: 1, addr },
+            Err(err) => {
+                log_warn!("Runtime services unavailable: {:?}\n", err);
+                Default::default()
+            }
+        };
+
+        // Collect the memory which must never be handed out, even if the
+        // firmware reported it as free
+        let mut reserved = RangeSet::new();
+
+        // Our own image
+        if image.size > 0 {
+            reserved.insert(Range {
+                start: image.base as u64,
+                end:   image.base as u64 + (image.size - 1),
+            }).expect("Failed to reserve our image");
+        }
 
-        print!("Physical free: {}\n", mm.sum().unwrap());
+        // The stack we are running on, which the firmware allocated as boot
+        // services data. The whole region containing it is kept.
+        let stack = &reserved as *const RangeSet as u64;
+        for desc in &memory_map.descriptors[..memory_map.num_descriptors
+                as usize] {
+            let end = desc.physical_start
+                .saturating_add(desc.number_of_pages.saturating_mul(4096));
+            if desc.number_of_pages > 0 &&
+                    (desc.physical_start..end).contains(&stack) {
+                reserved.insert(Range {
+                    start: desc.physical_start,
+                    end:   end - 1,
+                }).expect("Failed to reserve our stack");
+            }
+        }
 
-        print!("EFI MAIN {:#x}\n", efi_main as usize);
+        // Memory used by devices for DMA
+        if let Some(iommu) = &acpi.iommu {
+            for &range in iommu.reserved() {
+                reserved.insert(range)
+                    .expect("Failed to reserve IOMMU region");
+            }
+        }
+
+        // The ACPI tables, some firmware places them in memory which is
+        // otherwise free
+        reserved.union(acpi.regions()).expect("Failed to reserve ACPI tables");
+        mm.subtract(&reserved).expect("Failed to reserve memory");
+
+        // Test the free memory if asked to, so faulty memory is never used
+        if let Some(val) = cmdline.get("memtest") {
+            match PatternMode::parse(val) {
+                Some(mode) => {
+                    // Only conventional memory, persistent memory holds
+                    // data which has to survive
+                    let mut tested = RangeSet::new();
+                    for desc in &memory_map.descriptors[..memory_map
+                            .num_descriptors as usize] {
+                        if desc.typ != EFI_CONVENTIONAL_MEMORY ||
+                                desc.number_of_pages == 0 {
+                            continue;
+                        }
+                        tested.insert(Range {
+                            start: desc.physical_start,
+                            end:   desc.physical_start +
+                                (desc.number_of_pages * 4096 - 1),
+                        }).expect("Failed to collect memory to test");
+                    }
+                    tested.intersect(&mm)
+                        .expect("Failed to collect memory to test");
+
+                    let bad = match mm::memtest::memtest(&tested, mode) {
+                        Ok(bad) => bad,
+                        Err(err) => {
+                            log_warn!("Failed to record faulty memory \
+                                       ({:?}), dropping all tested memory\n",
+                                err);
+                            tested
+                        }
+                    };
+                    for range in &bad {
+                        log_warn!("Faulty memory at {:#x}-{:#x}\n",
+                            range.start, range.end);
+                    }
+                    mm.subtract(&bad).expect("Failed to remove faulty memory");
+                }
+                None => { log_warn!("Invalid memtest=\"{}\"\n", val); }
+            }
+            timing::mark("memtest");
+        }
+
+        // The kernel window depends on the levels of page tables the
+        // processor supports
+        let layout = Layout::detect();
+        log_info!("Kernel page tables: {} levels\n", layout.levels);
+
+        // Load the kernel before anything else is allocated, as the segments
+        // of an executable must go to fixed addresses without KASLR
+        let kernel = kernel.ok();
+        let loaded = kernel.as_deref().map(|kernel| {
+            elf::load(kernel, &mut mm, kaslr, layout)
+                .expect("Failed to load the kernel")
+        });
+        timing::mark("kernel load");
+        splash::progress(Milestone::KernelLoaded);
+
+        // Everything from here on is allocated in whole page frames
+        let mut frames = PageAlloc::new(mm, acpi.srat.as_ref())
+            .expect("Failed to create the page allocator");
+
+        // Build the address space of the kernel
+        let mut page_table = PageTable::new(&mut frames, layout)
+            .expect("Failed to create the kernel page table");
+
+        // Map all of RAM, in pages as large as it allows
+        let ram = memory.filter(MemoryKind::is_ram)
+            .expect("Failed to get the RAM from the memory map");
+        page_table.map_linear(&mut frames, &ram)
+            .expect("Failed to map RAM into the kernel page table");
+
+        // Map the kernel image with the permissions of its segments
+        if let (Some(kernel), Some(loaded)) = (&kernel, &loaded) {
+            elf::map(kernel, loaded, &mut page_table, &mut frames)
+                .expect("Failed to map the kernel image");
+        }
+
+        // Map the registers of the devices the kernel needs before it can map
+        // anything itself
+        let mut mmio = boot_info::Mmio::default();
+        if let Some(Gas::Memory { addr, .. }) =
+                acpi.spcr.as_ref().map(|x| x.address) {
+            mmio.uart = page_table.map_mmio(&mut frames,
+                    PhysAddr(addr as u64), UART_MMIO_SIZE)
+                .expect("Failed to map the serial port registers") as u64;
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(madt) = &acpi.madt {
+            mmio.lapic = page_table.map_mmio(&mut frames,
+                    madt.local_apic_addr(), apic::MMIO_SIZE)
+                .expect("Failed to map the local APIC registers") as u64;
+            for io_apic in madt.io_apics() {
+                let ent = match mmio.io_apics
+                        .get_mut(mmio.num_io_apics as usize) {
+                    Some(ent) => ent,
+                    None => {
+                        log_warn!("I/O APIC {:#x} does not fit the boot \
+                                   info\n", io_apic.id);
+                        continue;
+                    }
+                };
+                let virt = page_table.map_mmio(&mut frames, io_apic.base,
+                        ioapic::MMIO_SIZE)
+                    .expect("Failed to map the I/O APIC registers");
+                *ent = boot_info::IoApicMmio {
+                    id:       io_apic.id as u32,
+                    gsi_base: io_apic.gsi_base,
+                    phys:     io_apic.base.0,
+                    virt:     virt as u64,
+                };
+                mmio.num_io_apics += 1;
+            }
+        }
+
+        // Give every processor a stack from the memory closest to it
+        let mut stacks = boot_info::Stacks::default();
+        let mut entry_stack = None;
+        for cpu in cpus.iter().filter(|x| x.enabled)
+                .take(stacks.stacks.len()) {
+            let node = acpi.srat.as_ref().and_then(|srat| {
+                srat.apics().iter().find(|x| x.apic_id as u64 == cpu.id)
+            }).map(|x| x.domain).unwrap_or(0);
+
+            let (base, top) = mm::alloc_stack(&mut frames, &mut page_table,
+                    stacks.num_stacks as usize, KERNEL_STACK_SIZE, node)
+                .expect("Failed to allocate a kernel stack");
+
+            stacks.stacks[stacks.num_stacks as usize] = boot_info::CoreStack {
+                processor_id: cpu.id,
+                base:         base.0,
+                size:         KERNEL_STACK_SIZE,
+                top:          top.0,
+            };
+            stacks.num_stacks += 1;
+            if cpu.bsp { entry_stack = Some(top); }
+        }
+
+        // The kernel is entered on the stack of the boot processor, or on a
+        // stack of its own past the slots of the processors if the firmware
+        // didn't tell us which one we are
+        let entry_stack = match entry_stack {
+            Some(top) => top,
+            None => mm::alloc_stack(&mut frames, &mut page_table,
+                    stacks.stacks.len(), KERNEL_STACK_SIZE, 0)
+                .expect("Failed to allocate the kernel entry stack").1,
+        };
+
+        // The switch to the kernel page table runs from both page tables
+        page_table.map_handoff(&mut frames)
+            .expect("Failed to map the kernel handoff");
+
+        // Hand over the address space, nothing is added to it after this
+        let page_tables = boot_info::PageTables {
+            present:    1,
+            levels:     layout.levels as u32,
+            root:       page_table.root().0,
+            #[cfg(target_arch = "aarch64")]
+            root_high:  page_table.root_high().0,
+            #[cfg(not(target_arch = "aarch64"))]
+            root_high:  0,
+            linear_map: layout.linear_map_start,
+        };
+
+        // Place the boot information somewhere the kernel can find it
+        let boot_info = frames.alloc_zeroed_frames(
+                size_of::<BootInfo>().div_ceil(PAGE_SIZE as usize))
+            .expect("Failed to allocate boot info").0 as usize
+            as *mut BootInfo;
+
+        // Nothing the kernel needs is allocated after the boot info, so the
+        // free memory is final
+        frames.release_pool().expect("Failed to release the frame pool");
+        let mut free_memory = boot_info::FreeMemory {
+            num_ranges: 0,
+            dropped:    0,
+            ranges:     [Default::default(); boot_info::MAX_FREE_MEMORY_RANGES],
+        };
+        for range in frames.free_memory() {
+            match free_memory.ranges.get_mut(free_memory.num_ranges as usize) {
+                Some(ent) => {
+                    *ent = boot_info::MemoryRange {
+                        start: range.start,
+                        end:   range.end,
+                    };
+                    free_memory.num_ranges += 1;
+                }
+                None => free_memory.dropped += 1,
+            }
+        }
+        if free_memory.dropped > 0 {
+            log_warn!("Free memory: {} ranges did not fit\n",
+                free_memory.dropped);
+        }
+
+        core::ptr::write(boot_info, BootInfo {
+            magic: boot_info::BOOT_INFO_MAGIC,
+            version: boot_info::BOOT_INFO_VERSION,
+            size: size_of::<BootInfo>() as u32,
+            acpi: acpi.boot_info(),
+            tpm_event_log,
+            cmdline: cmdline.boot_info(),
+            memory_map,
+            runtime_services,
+            initrd,
+            firmware,
+            stacks,
+            free_memory,
+            framebuffer,
+            page_tables,
+            mmio,
+            log_buffer: print::log_buffer(),
+            cores: handshake::boot_info(),
+        });
+        log_info!("Boot info at {:#x}\n", boot_info as usize);
+
+        if cmdline.get("mmstats").is_some() { mm::dump_stats(&frames); }
+
+        log_info!("Physical free: {}\n", frames.free_memory().sum().unwrap());
+
+        log_debug!("EFI MAIN {:#x}\n", efi_main as usize);
+
+        // Jump to the kernel on its own page table, if we have one. The boot
+        // info is passed at its address in the linear map.
+        if let Some(loaded) = loaded {
+            timing::mark("handoff");
+            timing::print_summary();
+            log_info!("Entering kernel at {:#x}\n", loaded.entry);
+            splash::progress(Milestone::Handoff);
+            let err = page_table.switch_to(VirtAddr(loaded.entry),
+                entry_stack, layout.linear_map_start + boot_info as u64);
+            panic!("Failed to switch to the kernel page table: {:?}", err);
+        }
     }
 
-    panic!("exiting");
+    // Without a kernel there is nothing left to do. Boot services are gone
+    // so there is no returning to the firmware, and this is not a failure
+    // worth a reset, so just stay here.
+    log_info!("No kernel to enter, halting\n");
+    loop { core::hint::spin_loop(); }
 }
 
 /// The stack-probe implementation for Windows targets. This is currently
 /// needed for aarch64 because Rust doesn't disable/generate a stub for probes.
+/// The riscv64 target has probes disabled in its target spec, and there is no
+/// Windows convention for it to follow.
 #[no_mangle]
+#[cfg(not(target_arch = "riscv64"))]
 fn __chkstk() {}
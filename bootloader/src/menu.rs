@@ -0,0 +1,185 @@
+//! An interactive boot menu on the EFI console
+
+use crate::efi::{self, Key};
+use crate::cmdline::CommandLine;
+
+/// Number of seconds to wait for a key before booting
+const TIMEOUT_SECS: usize = 3;
+
+/// What the user chose to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Choice {
+    /// Continue booting the kernel
+    Boot,
+
+    /// Reset into the next entry of the firmware boot order
+    BootNext,
+
+    /// Exit back to the firmware
+    Exit,
+
+    /// Reset the system
+    Reset,
+}
+
+/// Entries of the boot menu
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    /// Continue booting the kernel
+    Boot,
+
+    /// Edit the kernel command line, then come back to the menu
+    EditCommandLine,
+
+    /// Reset into the next entry of the firmware boot order
+    BootNext,
+
+    /// Exit back to the firmware
+    Exit,
+
+    /// Reset the system
+    Reset,
+}
+
+/// All entries of the boot menu in the order they are shown
+const ENTRIES: &[(Entry, &str)] = &[
+    (Entry::Boot,            "Boot"),
+    (Entry::EditCommandLine, "Edit command line"),
+    (Entry::BootNext,        "Boot next entry"),
+    (Entry::Exit,            "Exit to firmware"),
+    (Entry::Reset,           "Reset"),
+];
+
+/// Count down while waiting for a key to be pressed
+///
+/// # Returns
+///
+/// `true` if a key was pressed before the timeout, `false` if the timeout
+/// expired or we cannot read keys
+///
+fn countdown() -> bool {
+    for remaining in (1..=TIMEOUT_SECS).rev() {
+        print!("\rPress any key for the boot menu, booting in {}s ",
+            remaining);
+
+        // Poll for a key every 10 milliseconds
+        for _ in 0..100 {
+            match efi::read_key() {
+                Ok(Some(_)) => {
+                    print!("\n");
+                    return true;
+                }
+                Ok(None) => {}
+                Err(_)   => break,
+            }
+            if efi::stall(10_000).is_err() { break; }
+        }
+    }
+
+    print!("\n");
+    false
+}
+
+/// Let the user edit the command line
+///
+/// # Parameters
+///
+/// * `cmdline` - The command line to edit, this is left unchanged if the user
+///               cancels with escape
+///
+fn edit(cmdline: &mut CommandLine) {
+    let mut new = *cmdline;
+    print!("Command line: {}", new.as_str());
+
+    loop {
+        match efi::wait_for_key() {
+            // Done editing
+            Ok(Key::Char('\r')) => {
+                *cmdline = new;
+                break;
+            }
+
+            // Erase the last character on screen as well
+            Ok(Key::Char('\x08')) => {
+                if new.pop().is_some() { print!("\x08 \x08"); }
+            }
+
+            // Append and echo printable characters
+            Ok(Key::Char(chr)) if !chr.is_control() => {
+                if new.push(chr) { print!("{}", chr); }
+            }
+
+            // Cancel
+            Ok(Key::Escape) | Err(_) => break,
+
+            Ok(_) => {}
+        }
+    }
+
+    print!("\n");
+}
+
+/// Run the boot menu
+///
+/// The menu is only shown if a key is pressed during a short countdown,
+/// otherwise we continue to boot.
+///
+/// # Parameters
+///
+/// * `cmdline` - The kernel command line, which may be edited from the menu
+///
+/// # Returns
+///
+/// What the user chose to do
+///
+pub fn run(cmdline: &mut CommandLine) -> Choice {
+    if !countdown() { return Choice::Boot; }
+
+    let mut selected = 0;
+    loop {
+        // Draw the menu on a clear screen, so moving the selection redraws
+        // it in place rather than scrolling. A failure just scrolls.
+        let _ = efi::clear();
+        print!("\nBoot menu\n");
+        for (ii, (_, label)) in ENTRIES.iter().enumerate() {
+            print!("{} {}) {}\n", if ii == selected { '>' } else { ' ' },
+                ii + 1, label);
+        }
+        print!("Command line: {}\n", cmdline.as_str());
+
+        // Wait for a selection, if we can no longer read keys just boot
+        let key = match efi::wait_for_key() {
+            Ok(key) => key,
+            Err(_)  => return Choice::Boot,
+        };
+        let entry = match key {
+            Key::Up => {
+                selected = selected.checked_sub(1)
+                    .unwrap_or(ENTRIES.len() - 1);
+                continue;
+            }
+            Key::Down => {
+                selected = (selected + 1) % ENTRIES.len();
+                continue;
+            }
+            Key::Char('\r') => ENTRIES[selected].0,
+            Key::Char(chr) => {
+                match chr.to_digit(10).and_then(|x| {
+                    ENTRIES.get((x as usize).checked_sub(1)?)
+                }) {
+                    Some(&(entry, _)) => entry,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        match entry {
+            Entry::Boot            => return Choice::Boot,
+            Entry::BootNext        => return Choice::BootNext,
+            Entry::Exit            => return Choice::Exit,
+            Entry::Reset           => return Choice::Reset,
+            Entry::EditCommandLine => edit(cmdline),
+        }
+    }
+}
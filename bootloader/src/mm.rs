@@ -1,3 +1,89 @@
 //! Memory management
 
 pub mod physmem;
+pub mod virtmem;
+pub mod page_alloc;
+pub mod heap;
+pub mod memtest;
+pub mod paging;
+
+use crate::mm::{
+    physmem::PhysAddr,
+    virtmem::VirtAddr,
+    page_alloc::{PageAlloc, PAGE_SIZE},
+    paging::{PageTable, Permissions},
+};
+
+/// Virtual address space (in bytes) set aside for the stack of each core,
+/// anything not taken by the stack stays unmapped
+const STACK_SLOT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Allocate a kernel stack for a core and map it, with an unmapped guard page
+/// below it so an overflow faults rather than corrupting memory
+///
+/// # Parameters
+///
+/// * `frames`  - The allocator to take the stack and page table frames from
+/// * `table`   - The page table to map the stack into
+/// * `core_id` - The index of the core, which selects the virtual address
+///               the stack is mapped at
+/// * `size`    - The size (in bytes) of the stack, a multiple of
+///               [`PAGE_SIZE`]
+/// * `node`    - The proximity domain to prefer memory from
+///
+/// # Returns
+///
+/// The physical address of the bottom of the stack and the virtual address
+/// of the top of the stack, on error [`paging::Error`]
+///
+/// # Safety
+///
+/// Physical memory must be identity mapped.
+///
+pub unsafe fn alloc_stack(frames: &mut PageAlloc, table: &mut PageTable,
+                          core_id: usize, size: u64, node: u32)
+        -> paging::Result<(PhysAddr, VirtAddr)> {
+    // The stack and its guard page must fit in the slot
+    if size.checked_add(PAGE_SIZE).map_or(true, |x| x > STACK_SLOT_SIZE) {
+        return Err(paging::Error::StackTooLarge(size));
+    }
+    let layout = table.layout();
+    let slot = (core_id as u64).checked_mul(STACK_SLOT_SIZE)
+        .and_then(|x| x.checked_add(layout.stack_window_start))
+        .ok_or(paging::Error::IntegerOverflow)?;
+    if layout.mmio_window_start - slot < STACK_SLOT_SIZE {
+        return Err(paging::Error::StackWindowFull);
+    }
+
+    // Map the stack right above the guard page at the bottom of the slot
+    let base = frames.alloc_on_node(size, PAGE_SIZE, node)
+        .map_err(paging::Error::PageAlloc)?;
+    let bottom = VirtAddr(slot + PAGE_SIZE);
+    if let Err(err) = table.map(frames, bottom, base, size,
+            Permissions { write: true, execute: false, user: false }) {
+        let _ = frames.free(base, size);
+        return Err(err);
+    }
+
+    Ok((base, VirtAddr(bottom.0 + size)))
+}
+
+/// Print the counters and free memory of the page allocator
+///
+/// # Parameters
+///
+/// * `frames` - The allocator to print the state of
+///
+pub fn dump_stats(frames: &PageAlloc) {
+    let stats = frames.stats();
+    let free: u64 = frames.free_memory().entries().iter()
+        .map(|ent| (ent.end - ent.start).saturating_add(1)).sum();
+
+    log_info!("Page allocator: {} allocations ({} bytes), {} frees ({} \
+               bytes), {} failures\n", stats.allocations, stats.allocated_bytes,
+        stats.frees, stats.freed_bytes, stats.failures);
+    log_info!("Page allocator: {} bytes free in {} ranges, largest {} bytes\n",
+        free, frames.free_memory().entries().len(), frames.largest_free());
+    log_info!("Page allocator: {} bytes free in the pool\n",
+        frames.pool_free());
+}
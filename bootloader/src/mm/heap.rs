@@ -0,0 +1,200 @@
+//! The heap backing `alloc`, carved out of memory which stays ours after boot
+//! services have been exited
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A free block of heap memory, stored at the start of the block itself
+struct FreeBlock {
+    /// Size of the block in bytes, including this header
+    size: usize,
+
+    /// The next free block at a higher address, or null
+    next: *mut FreeBlock,
+}
+
+/// Granularity (in bytes) of heap block addresses and sizes, such that every
+/// piece left over when splitting a block can hold a [`FreeBlock`]
+const BLOCK_ALIGN: usize = size_of::<FreeBlock>();
+
+/// A first-fit heap over a list of free blocks. The list is kept sorted by
+/// address, so neighbouring blocks can be merged again when they are freed.
+struct Heap {
+    /// The free block with the lowest address, or null
+    head: *mut FreeBlock,
+}
+
+impl Heap {
+    /// Allocate memory for `layout`
+    ///
+    /// # Parameters
+    ///
+    /// * `layout` - The size and alignment of the allocation
+    ///
+    /// # Returns
+    ///
+    /// A pointer to the allocation, or null if no free block fits it
+    ///
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size  = block_size(layout);
+        let align = core::cmp::max(layout.align(), BLOCK_ALIGN);
+
+        // Find the first block which can hold the allocation once aligned
+        let mut link: *mut *mut FreeBlock = &mut self.head;
+        while !(*link).is_null() {
+            let block = *link;
+            let base  = block as usize;
+            let end   = base + (*block).size;
+            let start = (base + (align - 1)) & !(align - 1);
+            if start.checked_add(size).map_or(true, |x| x > end) {
+                link = &mut (*block).next;
+                continue;
+            }
+
+            // Put the pieces before and after the allocation back in place
+            // of the block, they are multiples of `BLOCK_ALIGN` so either
+            // empty or large enough to be blocks themselves
+            let mut next = (*block).next;
+            if end > start + size {
+                let tail = (start + size) as *mut FreeBlock;
+                *tail = FreeBlock { size: end - (start + size), next };
+                next = tail;
+            }
+            if start > base {
+                (*block).size = start - base;
+                (*block).next = next;
+            } else {
+                *link = next;
+            }
+
+            return start as *mut u8;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    /// Return memory to the heap
+    ///
+    /// # Parameters
+    ///
+    /// * `ptr`  - The start of the memory, either an allocation or a fresh
+    ///            region aligned to [`BLOCK_ALIGN`]
+    /// * `size` - The size (in bytes) of the memory, a multiple of
+    ///            [`BLOCK_ALIGN`]
+    ///
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+        // Find the free blocks around the memory
+        let mut prev: *mut FreeBlock = core::ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < ptr as usize {
+            prev = next;
+            next = (*next).next;
+        }
+
+        // Link in the new block, merging it with the next one if they touch
+        let block = ptr as *mut FreeBlock;
+        *block = FreeBlock { size, next };
+        if !next.is_null() && ptr as usize + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next  = (*next).next;
+        }
+
+        // Merge it with the previous block as well
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == ptr as usize {
+            (*prev).size += (*block).size;
+            (*prev).next  = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+/// Round the size of an allocation up to whole heap blocks
+///
+/// # Parameters
+///
+/// * `layout` - The size and alignment of the allocation
+///
+/// # Returns
+///
+/// The number of bytes the allocation takes up in the heap
+///
+fn block_size(layout: Layout) -> usize {
+    (core::cmp::max(layout.size(), 1) + (BLOCK_ALIGN - 1)) & !(BLOCK_ALIGN - 1)
+}
+
+/// A [`Heap`] behind a spinlock, so it can be a global allocator
+struct LockedHeap {
+    /// Set while the heap is in use
+    locked: AtomicBool,
+
+    /// The heap
+    heap: UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for LockedHeap {}
+
+impl LockedHeap {
+    /// Run `func` with the heap locked
+    ///
+    /// # Parameters
+    ///
+    /// * `func` - The function to run on the heap
+    ///
+    /// # Returns
+    ///
+    /// The return value of `func`
+    ///
+    fn with<R>(&self, func: impl FnOnce(&mut Heap) -> R) -> R {
+        while self.locked.compare_exchange_weak(false, true,
+                Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        let ret = func(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        ret
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|heap| heap.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|heap| heap.free(ptr, block_size(layout)))
+    }
+}
+
+/// The heap used by `alloc`, empty until [`init`] hands it memory
+#[global_allocator]
+static HEAP: LockedHeap = LockedHeap {
+    locked: AtomicBool::new(false),
+    heap:   UnsafeCell::new(Heap { head: core::ptr::null_mut() }),
+};
+
+/// Give memory to the heap
+///
+/// # Parameters
+///
+/// * `memory` - The memory for the heap, this can be called multiple times to
+///              grow the heap
+///
+/// # Safety
+///
+/// The memory must not be used for anything else, for as long as anything
+/// allocated from the heap is in use.
+///
+pub unsafe fn init(memory: &'static mut [u8]) {
+    // Trim the memory to whole blocks
+    let base  = memory.as_mut_ptr() as usize;
+    let start = (base + (BLOCK_ALIGN - 1)) & !(BLOCK_ALIGN - 1);
+    let end   = (base + memory.len()) & !(BLOCK_ALIGN - 1);
+    if end <= start { return; }
+
+    HEAP.with(|heap| heap.free(start as *mut u8, end - start));
+}
@@ -0,0 +1,190 @@
+//! A boot-time test of free memory, so faulty memory is never handed out
+//!
+//! Every pass writes a pattern over the whole of the memory before reading it
+//! back, so a fault has to survive the rest of the pass to go unnoticed.
+
+use rangeset::{Range, RangeSet};
+
+use crate::mm::page_alloc::PAGE_SIZE;
+
+/// Which patterns to test memory with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatternMode {
+    /// Alternating bits and their inverse, which catches stuck and coupled
+    /// bits
+    Quick,
+
+    /// The quick patterns, then each word holding its own address, which
+    /// also catches faulty address lines
+    Full,
+}
+
+impl PatternMode {
+    /// Parse a pattern mode from the `memtest=` option
+    ///
+    /// # Parameters
+    ///
+    /// * `val` - The value of the option, empty for the default
+    ///
+    /// # Returns
+    ///
+    /// The [`PatternMode`], or `None` if `val` is not a mode
+    ///
+    pub fn parse(val: &str) -> Option<Self> {
+        match val {
+            "" | "quick" => Some(PatternMode::Quick),
+            "full"       => Some(PatternMode::Full),
+            _            => None,
+        }
+    }
+}
+
+/// A pattern written over memory in one pass
+#[derive(Clone, Copy)]
+enum Pattern {
+    /// Every word holds the same value
+    Fixed(u64),
+
+    /// Every word holds its own address
+    Address,
+}
+
+impl Pattern {
+    /// Get the value of the pattern for a word
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the word
+    ///
+    /// # Returns
+    ///
+    /// The value the word should hold
+    ///
+    fn value(self, addr: u64) -> u64 {
+        match self {
+            Pattern::Fixed(val) => val,
+            Pattern::Address    => addr,
+        }
+    }
+}
+
+/// Test one range of memory with every pattern
+///
+/// # Parameters
+///
+/// * `start`    - The page aligned address to start testing at
+/// * `end`      - The page aligned address to stop testing at, exclusive
+/// * `patterns` - The patterns to test with
+///
+/// # Returns
+///
+/// The pages which failed the test, with neighbouring pages coalesced into
+/// one range, on error [`rangeset::Error`] if they do not fit in a
+/// [`RangeSet`]
+///
+/// # Safety
+///
+/// See [`memtest`]
+///
+unsafe fn test_range(start: u64, end: u64, patterns: &[Pattern])
+        -> Result<RangeSet, rangeset::Error> {
+    let mut bad = RangeSet::new();
+    for &pattern in patterns.iter().chain(&[Pattern::Fixed(0)]) {
+        for addr in (start..end).step_by(8) {
+            (addr as usize as *mut u64).write_volatile(pattern.value(addr));
+        }
+
+        // Collect runs of faulty pages, and only record a run once it ends
+        let mut run: Option<Range> = None;
+        let mut addr = start;
+        while addr < end {
+            let val = (addr as usize as *const u64).read_volatile();
+            if val == pattern.value(addr) {
+                addr += 8;
+                continue;
+            }
+
+            // The rest of a faulty page doesn't need to be looked at
+            let page = addr & !(PAGE_SIZE - 1);
+            match &mut run {
+                Some(run) if run.end.wrapping_add(1) == page => {
+                    run.end = page + (PAGE_SIZE - 1);
+                }
+                _ => {
+                    if let Some(run) = run { bad.insert(run)?; }
+                    run = Some(Range {
+                        start: page,
+                        end:   page + (PAGE_SIZE - 1),
+                    });
+                }
+            }
+            addr = page + PAGE_SIZE;
+        }
+        if let Some(run) = run { bad.insert(run)?; }
+    }
+
+    Ok(bad)
+}
+
+/// Test memory, leaving the memory which passes zeroed
+///
+/// # Parameters
+///
+/// * `ranges` - The memory to test, partial pages at the edges of each range
+///              are not tested
+/// * `mode`   - The patterns to test with
+///
+/// # Returns
+///
+/// The pages which failed the test. A range of `ranges` with faults too
+/// scattered to record is given up on as a whole, with a warning. On error
+/// [`rangeset::Error`] if not even that fits in a [`RangeSet`].
+///
+/// # Safety
+///
+/// The memory is written through its physical address, so physical memory
+/// must be identity mapped and nothing may be using `ranges`.
+///
+pub unsafe fn memtest(ranges: &RangeSet, mode: PatternMode)
+        -> Result<RangeSet, rangeset::Error> {
+    /// Passes of the quick test
+    const QUICK: &[Pattern] = &[
+        Pattern::Fixed(0x5555_5555_5555_5555),
+        Pattern::Fixed(0xaaaa_aaaa_aaaa_aaaa),
+    ];
+
+    /// Passes of the full test
+    const FULL: &[Pattern] = &[
+        Pattern::Fixed(0x5555_5555_5555_5555),
+        Pattern::Fixed(0xaaaa_aaaa_aaaa_aaaa),
+        Pattern::Address,
+    ];
+
+    let patterns = match mode {
+        PatternMode::Quick => QUICK,
+        PatternMode::Full  => FULL,
+    };
+
+    let mut bad = RangeSet::new();
+    for ent in ranges.entries() {
+        // Only test whole pages, so whole pages can be thrown away
+        let start = match ent.start.checked_add(PAGE_SIZE - 1) {
+            Some(start) => start & !(PAGE_SIZE - 1),
+            None        => continue,
+        };
+        let end = ent.end.wrapping_add(1) & !(PAGE_SIZE - 1);
+        if end <= start { continue; }
+
+        // Memory too faulty to keep track of is not worth keeping any of
+        let whole = Range { start, end: end - 1 };
+        let found = test_range(start, end, patterns)
+            .and_then(|found| bad.union(&found));
+        if found.is_err() {
+            log_warn!("Too many memory faults at {:#x}-{:#x}, dropping all \
+                       of it\n", whole.start, whole.end);
+            bad.insert(whole)?;
+        }
+    }
+
+    Ok(bad)
+}
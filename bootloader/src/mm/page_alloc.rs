@@ -0,0 +1,539 @@
+//! A physical page-frame allocator for after boot services have been exited
+
+use core::mem::size_of;
+use rangeset::{Range, RangeSet};
+use buddy::BuddyAlloc;
+
+use crate::efi;
+use crate::mm::physmem::PhysAddr;
+use crate::core_requirements::fill64;
+use crate::acpi::{MemoryAffinity, Srat};
+
+/// The size (in bytes) of a page frame
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Size (in bytes) of the pool which allocations without any placement
+/// requirements are served from, such as the frames of page tables
+const POOL_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A `Result` type which wraps a page allocator error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the page allocator
+#[derive(Debug)]
+pub enum Error {
+    /// A request for zero frames was made
+    ZeroFrames,
+
+    /// An integer overflow occurred when computing the size of an allocation
+    IntegerOverflow,
+
+    /// An address to free is not aligned to a frame
+    UnalignedFree(PhysAddr),
+
+    /// Memory to free was not allocated from this allocator, or was already
+    /// freed
+    NotAllocated(Range),
+
+    /// There is no free memory for an allocation
+    OutOfMemory {
+        /// The size (in bytes) of the allocation
+        size: u64,
+
+        /// The alignment requirement of the allocation
+        align: u64,
+
+        /// The size (in bytes) of the largest free range at the time
+        largest_free: u64,
+    },
+
+    /// An operation on the free or allocated ranges failed
+    RangeSet(rangeset::Error),
+
+    /// An operation on the pool failed
+    Pool(buddy::Error),
+}
+
+/// Upper limits on the physical address of an allocation, for devices and
+/// code which cannot reach all of memory. Memory below 1 MiB is only needed
+/// while boot services are up, for the trampoline starting application
+/// processors, so it is allocated from EFI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// Below 4 GiB, for devices which can only do 32-bit DMA and for
+    /// anything 32-bit code has to reach
+    Below4GiB,
+
+    /// Below 1 MiB, for code which runs in real mode such as the trampoline
+    /// starting application processors
+    Below1MiB,
+}
+
+impl Limit {
+    /// Get the last physical address allocations under this limit may use
+    ///
+    /// # Returns
+    ///
+    /// The inclusive upper bound of the limit
+    ///
+    pub fn last(self) -> u64 {
+        match self {
+            Limit::Below4GiB => 0xffff_ffff,
+            Limit::Below1MiB => 0x000f_ffff,
+        }
+    }
+
+    /// Allocate whole frames below this limit from EFI, for memory which is
+    /// needed while boot services are still up
+    ///
+    /// # Parameters
+    ///
+    /// * `size` - The number of bytes to allocate, rounded up to whole
+    ///            frames
+    ///
+    /// # Returns
+    ///
+    /// The memory, which stays ours after boot services are exited, on error
+    /// [`efi::Error`]
+    ///
+    pub fn alloc_efi(self, size: usize)
+            -> core::result::Result<&'static mut [u8], efi::Error> {
+        efi::allocate_below(self.last(), size)
+    }
+}
+
+/// Counters of the calls made to a [`PageAlloc`]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Stats {
+    /// Number of successful allocations
+    pub allocations: u64,
+
+    /// Number of bytes handed out by successful allocations
+    pub allocated_bytes: u64,
+
+    /// Number of successful frees
+    pub frees: u64,
+
+    /// Number of bytes returned by successful frees
+    pub freed_bytes: u64,
+
+    /// Number of allocations which could not be satisfied
+    pub failures: u64,
+}
+
+/// Hands out 4 KiB page frames from the free physical memory
+///
+/// Every frame handed out is remembered, so the allocator always knows which
+/// memory is owned by whom.
+pub struct PageAlloc {
+    /// Physical memory which is free to be allocated
+    free: RangeSet,
+
+    /// Physical memory which has been handed out by this allocator
+    allocated: RangeSet,
+
+    /// Enabled memory ranges of the proximity domains from the SRAT
+    affinities: [MemoryAffinity; boot_info::MAX_MEMORY_AFFINITIES],
+
+    /// Number of valid entries in `affinities`
+    num_affinities: usize,
+
+    /// Frames carved out of the free memory for allocations which need no
+    /// placement control, until released with [`PageAlloc::release_pool`]
+    pool: Option<BuddyAlloc<'static>>,
+
+    /// Counters of the calls made so far
+    stats: Stats,
+}
+
+impl PageAlloc {
+    /// Create a new page allocator from free physical memory
+    ///
+    /// # Parameters
+    ///
+    /// * `free` - The free physical memory, partial frames at the edges of
+    ///            each range are never handed out
+    /// * `srat` - The SRAT, if any, whose proximity domains are used by
+    ///            [`PageAlloc::alloc_on_node`]
+    ///
+    /// # Returns
+    ///
+    /// A new [`PageAlloc`] with nothing allocated, on error [`Error`]
+    ///
+    pub fn new(free: RangeSet, srat: Option<&Srat>) -> Result<Self> {
+        // Shrink every range to whole frames, so an allocation never needs
+        // to waste memory on alignment
+        let mut frames = RangeSet::new();
+        for ent in free.entries() {
+            let start = match ent.start.checked_add(PAGE_SIZE - 1) {
+                Some(start) => start & !(PAGE_SIZE - 1),
+                None        => continue,
+            };
+            let end = ent.end.wrapping_add(1) & !(PAGE_SIZE - 1);
+            if end <= start { continue; }
+
+            frames.insert(Range { start, end: end - 1 })
+                .map_err(Error::RangeSet)?;
+        }
+
+        // Remember which memory belongs to which proximity domain
+        let mut affinities =
+            [MemoryAffinity::default(); boot_info::MAX_MEMORY_AFFINITIES];
+        let mut num_affinities = 0;
+        for &mem in srat.iter().flat_map(|x| x.memory()) {
+            if mem.flags & 1 == 0 || mem.length == 0 { continue; }
+
+            affinities[num_affinities] = mem;
+            num_affinities += 1;
+        }
+
+        // Carve out the pool, without one everything comes from the free
+        // ranges
+        let pool = match frames.allocate(POOL_SIZE, POOL_SIZE) {
+            Ok(addr) => {
+                let mut memory = RangeSet::new();
+                memory.insert(Range {
+                    start: addr as u64,
+                    end:   addr as u64 + (POOL_SIZE - 1),
+                }).map_err(Error::RangeSet)?;
+
+                // The bitmap is needed for as long as the pool is
+                let bitmap = alloc::vec![0; BuddyAlloc::bitmap_words(&memory)];
+                Some(BuddyAlloc::new(&memory, bitmap.leak())
+                    .map_err(Error::Pool)?)
+            }
+            Err(_) => None,
+        };
+
+        Ok(PageAlloc {
+            free:      frames,
+            allocated: RangeSet::new(),
+            affinities,
+            num_affinities,
+            pool,
+            stats:     Stats::default(),
+        })
+    }
+
+    /// Give the free memory of the pool back to the free ranges, so it is
+    /// reported by [`PageAlloc::free_memory`]. Frames allocated from the pool
+    /// can not be freed afterwards.
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    pub fn release_pool(&mut self) -> Result<()> {
+        if let Some(pool) = self.pool.take() {
+            for block in pool.free_blocks() {
+                self.free.insert(block).map_err(Error::RangeSet)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the number of free bytes in the pool
+    ///
+    /// # Returns
+    ///
+    /// The bytes which can still be allocated from the pool
+    ///
+    pub fn pool_free(&self) -> u64 {
+        self.pool.as_ref().map_or(0, |pool| pool.free_memory())
+    }
+
+    /// Get the counters of the calls made to the allocator
+    ///
+    /// # Returns
+    ///
+    /// A copy of the [`Stats`] so far
+    ///
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Get the size of the largest free range
+    ///
+    /// # Returns
+    ///
+    /// The size (in bytes) of the largest contiguous free memory
+    ///
+    pub fn largest_free(&self) -> u64 {
+        self.free.entries().iter()
+            .map(|ent| (ent.end - ent.start).saturating_add(1))
+            .max().unwrap_or(0)
+    }
+
+    /// Get the physical memory which is still free
+    ///
+    /// # Returns
+    ///
+    /// The [`RangeSet`] of free memory
+    ///
+    pub fn free_memory(&self) -> &RangeSet {
+        &self.free
+    }
+
+    /// Allocate `frames` contiguous page frames. A power of two number of
+    /// frames comes from the pool while it has room.
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The number of frames to allocate
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the first frame, on error [`Error`]
+    ///
+    pub fn alloc_frames(&mut self, frames: usize) -> Result<PhysAddr> {
+        if frames == 0 { return Err(Error::ZeroFrames); }
+
+        let size = (frames as u64).checked_mul(PAGE_SIZE)
+            .ok_or(Error::IntegerOverflow)?;
+
+        // The pool rounds up to a power of two, so only take exact fits
+        if let Some(pool) = self.pool.as_mut()
+                .filter(|_| frames.is_power_of_two()) {
+            if let Ok(addr) = pool.alloc(size, PAGE_SIZE) {
+                self.stats.allocations     += 1;
+                self.stats.allocated_bytes += size;
+                return Ok(PhysAddr(addr));
+            }
+        }
+
+        self.alloc_prefer(size, PAGE_SIZE, None)
+    }
+
+    /// Allocate `size` bytes, preferring memory of the proximity domain
+    /// `node`. If the domain has no memory left, or there is no SRAT, the
+    /// memory comes from anywhere.
+    ///
+    /// # Parameters
+    ///
+    /// * `size`  - The number of bytes to allocate, this is rounded up to
+    ///             whole frames
+    /// * `align` - The alignment requirement of the allocation, which is at
+    ///             least [`PAGE_SIZE`]
+    /// * `node`  - The proximity domain to prefer memory from
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the allocation, on error [`Error`]
+    ///
+    pub fn alloc_on_node(&mut self, size: u64, align: u64, node: u32)
+            -> Result<PhysAddr> {
+        if size == 0 { return Err(Error::ZeroFrames); }
+        let size = size.checked_add(PAGE_SIZE - 1)
+            .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+
+        // Build the memory of the domain
+        let mut regions = RangeSet::new();
+        for mem in self.affinities[..self.num_affinities].iter()
+                .filter(|x| x.domain == node) {
+            regions.insert(Range {
+                start: mem.base,
+                end:   mem.base.saturating_add(mem.length - 1),
+            }).map_err(Error::RangeSet)?;
+        }
+
+        let regions = (!regions.entries().is_empty()).then_some(&regions);
+        self.alloc_prefer(size, core::cmp::max(align, PAGE_SIZE), regions)
+    }
+
+    /// Allocate `size` bytes which lie entirely below `limit`. Unlike the
+    /// preference of [`PageAlloc::alloc_on_node`], the allocation fails if
+    /// no memory below the limit is free.
+    ///
+    /// # Parameters
+    ///
+    /// * `size`  - The number of bytes to allocate, this is rounded up to
+    ///             whole frames
+    /// * `align` - The alignment requirement of the allocation, which is at
+    ///             least [`PAGE_SIZE`]
+    /// * `limit` - The limit the whole allocation must lie below
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the allocation, on error [`Error`]
+    ///
+    pub fn alloc_below(&mut self, size: u64, align: u64, limit: Limit)
+            -> Result<PhysAddr> {
+        if size == 0 { return Err(Error::ZeroFrames); }
+        let size = size.checked_add(PAGE_SIZE - 1)
+            .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+        let align = core::cmp::max(align, PAGE_SIZE);
+
+        // Allocate out of a copy of the free memory below the limit, then
+        // take the same memory out of the real free memory
+        let mut window = RangeSet::new();
+        window.insert(Range { start: 0, end: limit.last() })
+            .map_err(Error::RangeSet)?;
+        let mut low = self.free;
+        low.intersect(&window).map_err(Error::RangeSet)?;
+        let found = low.allocate(size, align);
+        if let Ok(addr) = found {
+            self.free.remove(Range {
+                start: addr as u64,
+                end:   addr as u64 + (size - 1),
+            }).map_err(Error::RangeSet)?;
+        }
+
+        self.claim(found, size, align)
+    }
+
+    /// Allocate whole frames, preferring `regions`
+    ///
+    /// # Parameters
+    ///
+    /// * `size`    - The number of bytes to allocate, a multiple of
+    ///               [`PAGE_SIZE`]
+    /// * `align`   - The alignment requirement of the allocation
+    /// * `regions` - Memory to prefer for the allocation, `None` for no
+    ///               preference
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the allocation, on error [`Error`]
+    ///
+    fn alloc_prefer(&mut self, size: u64, align: u64,
+                    regions: Option<&RangeSet>) -> Result<PhysAddr> {
+        let found = self.free.allocate_prefer(size, align, regions);
+        self.claim(found, size, align)
+    }
+
+    /// Record the outcome of taking whole frames out of the free memory
+    ///
+    /// # Parameters
+    ///
+    /// * `found` - The address of the frames taken out of the free memory,
+    ///             or the error of looking for them
+    /// * `size`  - The number of bytes taken, a multiple of [`PAGE_SIZE`]
+    /// * `align` - The alignment requirement of the allocation
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the allocation, on error [`Error`]. Failures
+    /// are counted, and the counters are printed when out of memory.
+    ///
+    fn claim(&mut self, found: core::result::Result<usize, rangeset::Error>,
+             size: u64, align: u64) -> Result<PhysAddr> {
+        let addr = match found {
+            Ok(addr) => addr as u64,
+            Err(rangeset::Error::OutOfMemory) => {
+                self.stats.failures += 1;
+                crate::mm::dump_stats(self);
+                return Err(Error::OutOfMemory {
+                    size,
+                    align,
+                    largest_free: self.largest_free(),
+                });
+            }
+            Err(err) => {
+                self.stats.failures += 1;
+                return Err(Error::RangeSet(err));
+            }
+        };
+
+        // Remember the frames are handed out, if we can't there is no way
+        // to ever free them so give them back
+        if let Err(err) = self.allocated.insert(Range {
+            start: addr,
+            end:   addr + (size - 1),
+        }) {
+            self.free.insert(Range { start: addr, end: addr + (size - 1) })
+                .map_err(Error::RangeSet)?;
+            return Err(Error::RangeSet(err));
+        }
+
+        self.stats.allocations     += 1;
+        self.stats.allocated_bytes += size;
+        Ok(PhysAddr(addr))
+    }
+
+    /// Allocate a single page frame
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the frame, on error [`Error`]
+    ///
+    pub fn alloc_frame(&mut self) -> Result<PhysAddr> {
+        self.alloc_frames(1)
+    }
+
+    /// Allocate `frames` contiguous page frames filled with zeros
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The number of frames to allocate
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the first frame, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// The frames are zeroed through their physical address, so physical
+    /// memory must be identity mapped.
+    ///
+    pub unsafe fn alloc_zeroed_frames(&mut self, frames: usize)
+            -> Result<PhysAddr> {
+        let addr = self.alloc_frames(frames)?;
+        fill64(addr.0 as usize as *mut u64, 0,
+               frames * (PAGE_SIZE as usize / size_of::<u64>()));
+        Ok(addr)
+    }
+
+    /// Return page frames to the allocator, so they can be allocated again
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address of the first frame to free
+    /// * `size` - The size (in bytes) to free, this is rounded up to whole
+    ///            frames
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    pub fn free(&mut self, addr: PhysAddr, size: u64) -> Result<()> {
+        if size == 0 { return Err(Error::ZeroFrames); }
+        if addr.0 & (PAGE_SIZE - 1) != 0 {
+            return Err(Error::UnalignedFree(addr));
+        }
+
+        // Work out the frames to free
+        let size = size.checked_add(PAGE_SIZE - 1)
+            .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+        let range = Range {
+            start: addr.0,
+            end:   addr.0.checked_add(size - 1)
+                .ok_or(Error::IntegerOverflow)?,
+        };
+
+        // Memory of the pool goes back to the pool
+        if let Some(pool) = self.pool.as_mut()
+                .filter(|pool| pool.contains(addr.0)) {
+            pool.free(addr.0, size, PAGE_SIZE).map_err(Error::Pool)?;
+
+            self.stats.frees       += 1;
+            self.stats.freed_bytes += size;
+            return Ok(());
+        }
+
+        // Only memory we handed out may be freed, anything else is a double
+        // free or memory which was never ours
+        if !self.allocated.entries().iter()
+                .any(|ent| ent.start <= range.start && ent.end >= range.end) {
+            return Err(Error::NotAllocated(range));
+        }
+
+        // Hand the frames back, merging them with the free memory around them
+        self.allocated.remove(range).map_err(Error::RangeSet)?;
+        self.free.insert(range).map_err(Error::RangeSet)?;
+
+        self.stats.frees       += 1;
+        self.stats.freed_bytes += size;
+        Ok(())
+    }
+}
@@ -0,0 +1,1358 @@
+//! Page tables for the kernel, built out of page frames from a [`PageAlloc`]
+//!
+//! The tables use 4 levels of 512 entries with 4 KiB pages, and 2 MiB or 1 GiB
+//! pages where a mapping is aligned for them, covering a 48-bit virtual
+//! address space. On x86_64 this is a PML4 hierarchy, on aarch64 the
+//! lower half is translated by TTBR0 and the upper half by TTBR1, and on
+//! riscv64 this is Sv48. Harts which only implement Sv39 get 3 levels and a
+//! 39-bit virtual address space instead, with the same windows scaled down to
+//! fit, see [`Layout`]. The tables are written through their physical
+//! addresses, so they can only be built while physical memory is identity
+//! mapped.
+
+use rangeset::RangeSet;
+
+use crate::mm::physmem::PhysAddr;
+use crate::mm::virtmem::VirtAddr;
+use crate::mm::page_alloc::{self, PageAlloc, PAGE_SIZE};
+#[cfg(target_arch = "x86_64")]
+use crate::mm::page_alloc::Limit;
+
+/// A `Result` type which wraps a paging error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from building page tables
+#[derive(Debug)]
+pub enum Error {
+    /// A virtual or physical address, or a size, is not aligned to a page
+    Unaligned(u64),
+
+    /// A virtual address is not canonical, that is the bits above the
+    /// virtual address bits of the [`Layout`] are not all equal to the top
+    /// one of them
+    NonCanonical(u64),
+
+    /// A virtual address is already mapped
+    AlreadyMapped(u64),
+
+    /// An integer overflow occurred when computing the end of a mapping
+    IntegerOverflow,
+
+    /// We failed to allocate a frame for a page table
+    PageAlloc(page_alloc::Error),
+
+    /// We are running at an exception level whose translation regime we
+    /// cannot set up
+    UnsupportedExceptionLevel(u8),
+
+    /// The virtual address window for device mappings is exhausted
+    MmioWindowFull,
+
+    /// A kernel stack and its guard page do not fit in a slot of the stack
+    /// window
+    StackTooLarge(u64),
+
+    /// Physical memory up to this address does not fit in the linear map
+    LinearMapTooLarge(u64),
+
+    /// The processor does not support the translation mode the tables are
+    /// built for, Sv48 or Sv39 on riscv64
+    UnsupportedTranslationMode,
+
+    /// The windows of a [`Layout`] overlap, are out of order or are not
+    /// canonical for its number of levels
+    InvalidLayout,
+
+    /// The window for kernel stacks has no room for another stack
+    StackWindowFull,
+
+    /// The processor does not support no-execute pages, so memory mapped
+    /// without execute permission would still be executable
+    NoExecuteUnsupported,
+}
+
+/// Permissions of a mapping, every mapping can be read by the kernel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    /// The memory may be written
+    pub write: bool,
+
+    /// The memory may be executed
+    pub execute: bool,
+
+    /// The memory may be accessed by user mode
+    pub user: bool,
+}
+
+/// How accesses to mapped memory are cached and ordered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Regular write-back cached memory
+    Normal,
+
+    /// Uncached memory for device registers, accesses are neither merged,
+    /// reordered nor speculated
+    Device,
+}
+
+/// Entry bit: the entry is present (x86_64) or valid (aarch64, riscv64)
+const PTE_VALID: u64 = 1 << 0;
+
+/// Entry bit: the memory may be written
+#[cfg(target_arch = "x86_64")]
+const PTE_WRITE: u64 = 1 << 1;
+
+/// Entry bit: the memory may be accessed by user mode
+#[cfg(target_arch = "x86_64")]
+const PTE_USER: u64 = 1 << 2;
+
+/// Entry bit: writes go straight through to memory
+#[cfg(target_arch = "x86_64")]
+const PTE_WRITE_THROUGH: u64 = 1 << 3;
+
+/// Entry bit: the memory is not cached, with `PTE_WRITE_THROUGH` this selects
+/// the uncached entry of the default PAT
+#[cfg(target_arch = "x86_64")]
+const PTE_CACHE_DISABLE: u64 = 1 << 4;
+
+/// Entry bit: the entry maps a 2 MiB or 1 GiB page rather than pointing to
+/// a next level table
+#[cfg(target_arch = "x86_64")]
+const PTE_HUGE: u64 = 1 << 7;
+
+/// Entry bit: the memory may not be executed, needs `EFER.NXE`
+#[cfg(target_arch = "x86_64")]
+const PTE_NX: u64 = 1 << 63;
+
+/// Bits of an entry holding the physical address it points to
+#[cfg(target_arch = "x86_64")]
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Descriptor bit: a table descriptor at levels 0 to 2, or a page descriptor
+/// at level 3. Clear for block descriptors.
+#[cfg(target_arch = "aarch64")]
+const PTE_TABLE: u64 = 1 << 1;
+
+/// Descriptor bits: index into `MAIR_EL1` of the memory attributes
+#[cfg(target_arch = "aarch64")]
+const PTE_ATTR_INDEX_SHIFT: u32 = 2;
+
+/// Descriptor bit: `AP[1]`, the memory may be accessed by EL0
+#[cfg(target_arch = "aarch64")]
+const PTE_USER: u64 = 1 << 6;
+
+/// Descriptor bit: `AP[2]`, the memory may not be written
+#[cfg(target_arch = "aarch64")]
+const PTE_READ_ONLY: u64 = 1 << 7;
+
+/// Descriptor bits: inner shareable
+#[cfg(target_arch = "aarch64")]
+const PTE_INNER_SHAREABLE: u64 = 3 << 8;
+
+/// Descriptor bit: the access flag, without it the first access faults
+#[cfg(target_arch = "aarch64")]
+const PTE_ACCESSED: u64 = 1 << 10;
+
+/// Descriptor bit: the memory may not be executed at EL1
+#[cfg(target_arch = "aarch64")]
+const PTE_PXN: u64 = 1 << 53;
+
+/// Descriptor bit: the memory may not be executed at EL0
+#[cfg(target_arch = "aarch64")]
+const PTE_UXN: u64 = 1 << 54;
+
+/// Bits of a descriptor holding the physical address it points to
+#[cfg(target_arch = "aarch64")]
+const PTE_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// Index in `MAIR_EL1` of the attributes of [`MemoryType::Normal`]
+#[cfg(target_arch = "aarch64")]
+const MAIR_NORMAL: u64 = 0;
+
+/// Index in `MAIR_EL1` of the attributes of [`MemoryType::Device`]
+#[cfg(target_arch = "aarch64")]
+const MAIR_DEVICE: u64 = 1;
+
+/// Entry bit: the memory may be read, an entry with none of the read, write
+/// and execute bits set points to a next level table
+#[cfg(target_arch = "riscv64")]
+const PTE_READ: u64 = 1 << 1;
+
+/// Entry bit: the memory may be written
+#[cfg(target_arch = "riscv64")]
+const PTE_WRITE: u64 = 1 << 2;
+
+/// Entry bit: the memory may be executed
+#[cfg(target_arch = "riscv64")]
+const PTE_EXECUTE: u64 = 1 << 3;
+
+/// Entry bit: the memory is accessible to user mode
+#[cfg(target_arch = "riscv64")]
+const PTE_USER: u64 = 1 << 4;
+
+/// Entry bit: the memory has been accessed, set up front as the hardware
+/// may fault rather than set it
+#[cfg(target_arch = "riscv64")]
+const PTE_ACCESSED: u64 = 1 << 6;
+
+/// Entry bit: the memory has been written, set up front as the hardware may
+/// fault rather than set it
+#[cfg(target_arch = "riscv64")]
+const PTE_DIRTY: u64 = 1 << 7;
+
+/// Mask of the physical page number in an entry, which holds the physical
+/// address shifted right by 2
+#[cfg(target_arch = "riscv64")]
+const PTE_ADDR_MASK: u64 = 0x003f_ffff_ffff_fc00;
+
+/// `satp.MODE` of Sv39
+#[cfg(target_arch = "riscv64")]
+const SATP_MODE_SV39: u64 = 8;
+
+/// `satp.MODE` of Sv48
+#[cfg(target_arch = "riscv64")]
+const SATP_MODE_SV48: u64 = 9;
+
+/// Where the windows of the kernel address space are, which depends on how
+/// many levels the tables have. Every window is in the upper half.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    /// Number of levels of the tables, 4 for 48-bit and 3 for 39-bit virtual
+    /// addresses
+    pub levels: usize,
+
+    /// Virtual address physical memory is mapped at by
+    /// [`PageTable::map_linear`], the start of the upper half
+    pub linear_map_start: u64,
+
+    /// End (exclusive) of the virtual address window for the linear map
+    pub linear_map_end: u64,
+
+    /// Start of the virtual address window the kernel stacks are mapped into
+    pub stack_window_start: u64,
+
+    /// Start of the virtual address window device registers are mapped into
+    /// by [`PageTable::map_mmio`], which also ends the stack window
+    pub mmio_window_start: u64,
+
+    /// End (exclusive) of the virtual address window for device registers
+    pub mmio_window_end: u64,
+
+    /// Start of the virtual address window position independent kernels are
+    /// placed in, which runs to the end of the address space
+    pub kernel_window_start: u64,
+}
+
+/// The layout of 4-level tables
+const LAYOUT_48: Layout = Layout {
+    levels:              4,
+    linear_map_start:    0xffff_8000_0000_0000,
+    linear_map_end:      0xffff_fe00_0000_0000,
+    stack_window_start:  0xffff_fe00_0000_0000,
+    mmio_window_start:   0xffff_ff00_0000_0000,
+    mmio_window_end:     0xffff_ff80_0000_0000,
+    kernel_window_start: 0xffff_ff80_0000_0000,
+};
+
+/// The layout of 3-level tables, for riscv64 harts without Sv48. The linear
+/// map gives up 8 GiB of the 256 GiB upper half to the other windows.
+#[cfg(target_arch = "riscv64")]
+const LAYOUT_39: Layout = Layout {
+    levels:              3,
+    linear_map_start:    0xffff_ffc0_0000_0000,
+    linear_map_end:      0xffff_fffe_0000_0000,
+    stack_window_start:  0xffff_fffe_0000_0000,
+    mmio_window_start:   0xffff_ffff_8000_0000,
+    mmio_window_end:     0xffff_ffff_c000_0000,
+    kernel_window_start: 0xffff_ffff_c000_0000,
+};
+
+impl Layout {
+    /// Pick the layout for the tables the processor can use
+    ///
+    /// # Returns
+    ///
+    /// The 4-level [`Layout`], or on riscv64 the 3-level one if a write of
+    /// Sv48 to `satp` does not stick
+    ///
+    pub fn detect() -> &'static Layout {
+        #[cfg(target_arch = "riscv64")]
+        if !unsafe { sv48_supported() } { return &LAYOUT_39; }
+
+        &LAYOUT_48
+    }
+
+    /// Get the number of bits of a virtual address which are translated
+    ///
+    /// # Returns
+    ///
+    /// 48 for 4-level tables, 39 for 3-level tables
+    ///
+    fn va_bits(&self) -> u32 {
+        12 + 9 * self.levels as u32
+    }
+
+    /// Check if a virtual address is canonical
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The virtual address to check
+    ///
+    /// # Returns
+    ///
+    /// `true` if the bits of `addr` above the translated ones all equal the
+    /// top translated bit
+    ///
+    fn canonical(&self, addr: u64) -> bool {
+        let top = (addr as i64) >> (self.va_bits() - 1);
+        top == 0 || top == -1
+    }
+
+    /// Check that the windows match the number of levels
+    ///
+    /// # Returns
+    ///
+    /// `true` if the linear map starts the upper half, the windows follow
+    /// each other in order, and all of them are canonical
+    ///
+    fn valid(&self) -> bool {
+        let windows = [
+            self.linear_map_start, self.linear_map_end,
+            self.stack_window_start, self.mmio_window_start,
+            self.mmio_window_end, self.kernel_window_start,
+        ];
+
+        (1..=LEVEL_SHIFTS.len()).contains(&self.levels) &&
+            self.linear_map_start == !0 << (self.va_bits() - 1) &&
+            windows.windows(2).all(|x| x[0] <= x[1]) &&
+            self.linear_map_start < self.linear_map_end &&
+            self.mmio_window_start < self.mmio_window_end &&
+            windows.iter().all(|&x| self.canonical(x))
+    }
+}
+
+/// Shift of the virtual address bits indexing each level of the tables, from
+/// the top level table down to the page table
+const LEVEL_SHIFTS: [u32; 4] = [39, 30, 21, 12];
+
+/// Levels of the tables which may map memory directly, rather than point to
+/// a next level table. Levels 1 and 2 hold 1 GiB and 2 MiB blocks.
+const LEAF_LEVELS: &[usize] = &[1, 2, 3];
+
+/// Get the levels of the tables which may map memory directly on this
+/// processor
+///
+/// # Returns
+///
+/// A suffix of [`LEAF_LEVELS`], without 1 GiB pages on x86_64 processors
+/// which do not support them
+///
+fn leaf_levels() -> &'static [usize] {
+    // 1 GiB pages are reported in CPUID.80000001H:EDX[26]
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use core::arch::x86_64::__cpuid;
+        if __cpuid(0x8000_0000).eax < 0x8000_0001 ||
+                __cpuid(0x8000_0001).edx & (1 << 26) == 0 {
+            return &LEAF_LEVELS[1..];
+        }
+    }
+
+    LEAF_LEVELS
+}
+
+/// Check whether the processor supports no-execute pages
+///
+/// # Returns
+///
+/// Whether `EFER.NXE` and the no-execute entry bit are available
+///
+#[cfg(target_arch = "x86_64")]
+fn no_execute_supported() -> bool {
+    // No-execute pages are reported in CPUID.80000001H:EDX[20]
+    unsafe {
+        use core::arch::x86_64::__cpuid;
+        __cpuid(0x8000_0000).eax >= 0x8000_0001 &&
+            __cpuid(0x8000_0001).edx & (1 << 20) != 0
+    }
+}
+
+// The code switching to the kernel page table and entering the kernel. It
+// runs from both the old and the new page table, so it is identity mapped
+// in the new one by `PageTable::map_handoff`.
+#[cfg(target_arch = "x86_64")]
+global_asm!(r#"
+    .balign 16
+    .global paging_handoff
+paging_handoff:
+    // We get the PML4 in rdi, the stack in rsi, the entry point in rdx and
+    // its argument in rcx. Read-only pages are enforced from the moment the
+    // kernel page table is in use.
+    mov rax, cr0
+    or  rax, 0x10000
+    mov cr3, rdi
+    mov cr0, rax
+
+    // Enter the kernel with a zero return address, which leaves the stack
+    // aligned as on any function entry
+    mov rsp, rsi
+    mov rdi, rcx
+    xor ebp, ebp
+    push 0
+    jmp rdx
+
+    .global paging_handoff_end
+paging_handoff_end:
+"#);
+
+#[cfg(target_arch = "aarch64")]
+global_asm!(r#"
+    .balign 4
+    .global paging_handoff
+paging_handoff:
+    // We get TTBR0 in x0, TTBR1 in x1, MAIR in x2, TCR in x3, the stack in
+    // x4, the entry point in x5 and its argument in x6. Make the tables
+    // visible to the walker before using them.
+    dsb  ishst
+    msr  mair_el1, x2
+    msr  tcr_el1, x3
+    isb
+    msr  ttbr0_el1, x0
+    msr  ttbr1_el1, x1
+    isb
+    tlbi vmalle1
+    dsb  ish
+    isb
+
+    // Turn on the MMU (M), data caches (C) and instruction caches (I)
+    mrs  x7, sctlr_el1
+    mov  x8, #0x1005
+    orr  x7, x7, x8
+    msr  sctlr_el1, x7
+    isb
+
+    // Enter the kernel with no frame to return to
+    mov  sp, x4
+    mov  x0, x6
+    mov  x29, xzr
+    mov  x30, xzr
+    br   x5
+
+    .global paging_handoff_end
+paging_handoff_end:
+"#);
+
+#[cfg(target_arch = "riscv64")]
+global_asm!(r#"
+    .balign 4
+    .global paging_handoff
+paging_handoff:
+    // We get satp in a0, the stack in a1, the entry point in a2 and its
+    // argument in a3. Writes of an unsupported mode have no effect, so
+    // check it stuck before flushing the translations of the old mode, and
+    // go back if it didn't.
+    csrw  satp, a0
+    csrr  t0, satp
+    xor   t0, t0, a0
+    srli  t0, t0, 60
+    bnez  t0, 1f
+    sfence.vma
+
+    // Enter the kernel with no frame to return to
+    mv    sp, a1
+    mv    a0, a3
+    li    ra, 0
+    li    s0, 0
+    jr    a2
+1:
+    ret
+
+    .global paging_handoff_end
+paging_handoff_end:
+"#);
+
+extern {
+    /// Start of the handoff code
+    static paging_handoff: u8;
+
+    /// End of the handoff code
+    static paging_handoff_end: u8;
+}
+
+/// Check whether the hart supports Sv48, by writing it to `satp` with a
+/// table identity mapping the lower half and seeing if the write sticks
+///
+/// # Returns
+///
+/// `true` if Sv48 can be used, otherwise only Sv39 is left
+///
+/// # Safety
+///
+/// The code and stack must be identity mapped, below 128 TiB. The previous
+/// `satp` is restored before returning.
+///
+#[cfg(target_arch = "riscv64")]
+unsafe fn sv48_supported() -> bool {
+    /// A root table, it has to be aligned to a page
+    #[repr(C, align(4096))]
+    struct ProbeTable([u64; 512]);
+
+    /// The table to probe with, made of 512 GiB leaves
+    static mut PROBE_TABLE: ProbeTable = ProbeTable([0; 512]);
+
+    let table = &mut PROBE_TABLE.0;
+    for (ii, entry) in table[..256].iter_mut().enumerate() {
+        *entry = leaf_entry((ii as u64) << LEVEL_SHIFTS[0], 0,
+            Permissions { write: true, execute: true, user: false },
+            MemoryType::Normal);
+    }
+
+    let satp = (SATP_MODE_SV48 << 60) | (table.as_ptr() as u64 >> 12);
+    let mode: u64;
+    asm!(
+        "csrr {old}, satp",
+        "csrw satp, {new}",
+        "csrr {mode}, satp",
+        "csrw satp, {old}",
+        "sfence.vma",
+        old  = out(reg) _,
+        new  = in(reg) satp,
+        mode = out(reg) mode,
+        options(nostack),
+    );
+
+    mode >> 60 == SATP_MODE_SV48
+}
+
+/// A page table hierarchy of 4 levels, or 3 with a 3-level [`Layout`]
+pub struct PageTable {
+    /// Physical address of the top level table of the lower half, the PML4
+    /// on x86_64 and the Sv48 or Sv39 root on riscv64 which also cover the
+    /// upper half
+    root: PhysAddr,
+
+    /// Physical address of the top level table of the upper half
+    #[cfg(target_arch = "aarch64")]
+    root_high: PhysAddr,
+
+    /// Where the windows of the address space are
+    layout: &'static Layout,
+
+    /// Next free virtual address in the device register window
+    mmio_next: u64,
+}
+
+impl PageTable {
+    /// Create a new page table with nothing mapped
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take the page table frames from
+    /// * `layout` - The layout of the address space, from
+    ///              [`Layout::detect`]
+    ///
+    /// # Returns
+    ///
+    /// An empty [`PageTable`], on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn new(frames: &mut PageAlloc, layout: &'static Layout)
+            -> Result<Self> {
+        if !layout.valid() { return Err(Error::InvalidLayout); }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        let root = frames.alloc_zeroed_frames(1).map_err(Error::PageAlloc)?;
+
+        // Real mode trampolines, like the one starting application
+        // processors, load CR3 from 32-bit code, so the PML4 has to be below
+        // 4 GiB for the kernel to start processors on this table
+        #[cfg(target_arch = "x86_64")]
+        let root = {
+            let root = frames.alloc_below(PAGE_SIZE, PAGE_SIZE,
+                                          Limit::Below4GiB)
+                .map_err(Error::PageAlloc)?;
+            core::ptr::write_bytes(root.0 as usize as *mut u8, 0,
+                                   PAGE_SIZE as usize);
+            root
+        };
+
+        #[cfg(target_arch = "aarch64")]
+        let root_high =
+            frames.alloc_zeroed_frames(1).map_err(Error::PageAlloc)?;
+
+        Ok(PageTable {
+            root,
+            #[cfg(target_arch = "aarch64")]
+            root_high,
+            layout,
+            mmio_next: layout.mmio_window_start,
+        })
+    }
+
+    /// Get the layout of the address space
+    ///
+    /// # Returns
+    ///
+    /// The [`Layout`] the table was created with
+    ///
+    pub fn layout(&self) -> &'static Layout {
+        self.layout
+    }
+
+    /// Get the level of the top level table
+    ///
+    /// # Returns
+    ///
+    /// The index into [`LEVEL_SHIFTS`] of the root table, 0 with 4 levels
+    /// and 1 with 3
+    ///
+    fn top_level(&self) -> usize {
+        LEVEL_SHIFTS.len() - self.layout.levels
+    }
+
+    /// Get the physical address of the top level table
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the PML4 as loaded into CR3 on x86_64, the
+    /// table loaded into TTBR0 on aarch64, the root table in `satp` on
+    /// riscv64
+    ///
+    pub fn root(&self) -> PhysAddr {
+        self.root
+    }
+
+    /// Get the physical address of the top level table of the upper half
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the table loaded into TTBR1
+    ///
+    #[cfg(target_arch = "aarch64")]
+    pub fn root_high(&self) -> PhysAddr {
+        self.root_high
+    }
+
+    /// Map `size` bytes of physical memory at `phys` to `virt` as normal
+    /// memory
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `virt`   - The virtual address to map at
+    /// * `phys`   - The physical address to map
+    /// * `size`   - The size (in bytes) of the mapping
+    /// * `perms`  - The permissions of the mapping
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]. Pages mapped before an error are
+    /// left mapped.
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn map(&mut self, frames: &mut PageAlloc, virt: VirtAddr,
+                      phys: PhysAddr, size: u64, perms: Permissions)
+            -> Result<()> {
+        self.map_memory(frames, virt, phys, size, perms, MemoryType::Normal)
+    }
+
+    /// Map `size` bytes of physical memory at `phys` to `virt`
+    ///
+    /// Where the addresses and size allow it, and the architecture supports
+    /// it, memory is mapped with blocks larger than a page.
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `virt`   - The virtual address to map at
+    /// * `phys`   - The physical address to map
+    /// * `size`   - The size (in bytes) of the mapping
+    /// * `perms`  - The permissions of the mapping
+    /// * `typ`    - The kind of memory being mapped
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]. Pages mapped before an error are
+    /// left mapped.
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn map_memory(&mut self, frames: &mut PageAlloc,
+                             virt: VirtAddr, phys: PhysAddr, size: u64,
+                             perms: Permissions, typ: MemoryType)
+            -> Result<()> {
+        let virt = virt.0;
+
+        // Without no-execute pages the permissions could not be enforced
+        #[cfg(target_arch = "x86_64")]
+        if !perms.execute && !no_execute_supported() {
+            return Err(Error::NoExecuteUnsupported);
+        }
+
+        // Everything must be in whole pages
+        for &val in &[virt, phys.0, size] {
+            if val & (PAGE_SIZE - 1) != 0 { return Err(Error::Unaligned(val)); }
+        }
+        if size == 0 { return Ok(()); }
+
+        // The whole mapping must be canonical
+        let last = virt.checked_add(size - 1).ok_or(Error::IntegerOverflow)?;
+        phys.0.checked_add(size - 1).ok_or(Error::IntegerOverflow)?;
+        for &addr in &[virt, last] {
+            if !self.layout.canonical(addr) {
+                return Err(Error::NonCanonical(addr));
+            }
+        }
+        if (virt as i64) >= 0 && (last as i64) < 0 {
+            return Err(Error::NonCanonical(1 << (self.layout.va_bits() - 1)));
+        }
+
+        // Map with the largest blocks which fit
+        let mut offset = 0;
+        while offset < size {
+            let (virt, phys) = (virt + offset, phys.0 + offset);
+            let level = leaf_levels().iter().copied().find(|&level| {
+                let block = 1u64 << LEVEL_SHIFTS[level];
+                (virt | phys) & (block - 1) == 0 && size - offset >= block
+            }).unwrap_or(LEVEL_SHIFTS.len() - 1);
+
+            self.map_block(frames, virt, phys, level, perms, typ)?;
+            offset += 1 << LEVEL_SHIFTS[level];
+        }
+
+        Ok(())
+    }
+
+    /// Map `size` bytes of physical memory at `phys` to the same virtual
+    /// address
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `phys`   - The physical address to map
+    /// * `size`   - The size (in bytes) of the mapping
+    /// * `perms`  - The permissions of the mapping
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn identity_map(&mut self, frames: &mut PageAlloc,
+                               phys: PhysAddr, size: u64, perms: Permissions)
+            -> Result<()> {
+        self.map(frames, VirtAddr(phys.0), phys, size, perms)
+    }
+
+    /// Map physical memory at its address plus [`Layout::linear_map_start`],
+    /// so it stays reachable to the kernel once this page table is in use
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `memory` - The physical memory to map, only RAM should be in here as
+    ///              it is mapped cached. Partial pages are mapped whole.
+    ///
+    /// # Returns
+    ///
+    /// The last physical address which is mapped, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn map_linear(&mut self, frames: &mut PageAlloc,
+                             memory: &RangeSet) -> Result<u64> {
+        let mut last = 0;
+        for ent in memory {
+            let start = ent.start & !(PAGE_SIZE - 1);
+            let end = ent.end.checked_add(1)
+                .and_then(|x| x.checked_add(PAGE_SIZE - 1))
+                .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+            let layout = self.layout;
+            if end > layout.linear_map_end - layout.linear_map_start {
+                return Err(Error::LinearMapTooLarge(ent.end));
+            }
+
+            self.map(frames, VirtAddr(layout.linear_map_start + start),
+                PhysAddr(start), end - start,
+                Permissions { write: true, execute: false, user: false })?;
+            last = last.max(end - 1);
+        }
+
+        Ok(last)
+    }
+
+    /// Map device registers uncached into a window of virtual memory set
+    /// aside for them
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `phys`   - The physical address of the registers, this need not be
+    ///              page aligned
+    /// * `len`    - The size (in bytes) of the registers
+    ///
+    /// # Returns
+    ///
+    /// The virtual address of the register at `phys`, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn map_mmio(&mut self, frames: &mut PageAlloc,
+                           phys: PhysAddr, len: u64) -> Result<*mut u8> {
+        // Map the whole pages the registers are in
+        let offset = phys.0 & (PAGE_SIZE - 1);
+        let size = offset.checked_add(len)
+            .and_then(|x| x.checked_add(PAGE_SIZE - 1))
+            .ok_or(Error::IntegerOverflow)? & !(PAGE_SIZE - 1);
+
+        // Take the virtual addresses from the window
+        let virt = self.mmio_next;
+        if size > self.layout.mmio_window_end - virt {
+            return Err(Error::MmioWindowFull);
+        }
+
+        self.map_memory(frames, VirtAddr(virt), PhysAddr(phys.0 - offset),
+            size, Permissions { write: true, execute: false, user: false },
+            MemoryType::Device)?;
+        self.mmio_next += size;
+
+        Ok((virt + offset) as usize as *mut u8)
+    }
+
+    /// Unmap `size` bytes at `virt`, large pages which are only partly
+    /// unmapped are split into smaller pages first
+    ///
+    /// Nothing is flushed from the TLB, so this is only for page tables
+    /// which are not in use. Page tables which become empty are not freed.
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take the frames of split pages from
+    /// * `virt`   - The virtual address to unmap at
+    /// * `size`   - The size (in bytes) to unmap, memory which is not mapped
+    ///              is skipped
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]. Pages unmapped before an error
+    /// stay unmapped.
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn unmap(&mut self, frames: &mut PageAlloc, virt: VirtAddr,
+                        size: u64) -> Result<()> {
+        let virt = virt.0;
+        for &val in &[virt, size] {
+            if val & (PAGE_SIZE - 1) != 0 { return Err(Error::Unaligned(val)); }
+        }
+        if size == 0 { return Ok(()); }
+
+        let last = virt.checked_add(size - 1).ok_or(Error::IntegerOverflow)?;
+        for &addr in &[virt, last] {
+            if !self.layout.canonical(addr) {
+                return Err(Error::NonCanonical(addr));
+            }
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            let virt = virt + offset;
+
+            // Walk down until the entry mapping `virt`, if any
+            let mut table = self.top(virt).0;
+            for (depth, &shift) in LEVEL_SHIFTS.iter().enumerate()
+                    .skip(self.top_level()) {
+                let block = 1u64 << shift;
+                let entry = (table as usize as *mut u64)
+                    .add(((virt >> shift) & 0x1ff) as usize);
+
+                // Skip to the end of anything which isn't mapped
+                if *entry & PTE_VALID == 0 {
+                    offset += block - (virt & (block - 1));
+                    break;
+                }
+
+                // Go down to the next table, splitting a large page unless
+                // all of it is unmapped
+                if !is_table(*entry, depth) {
+                    if virt & (block - 1) == 0 && size - offset >= block {
+                        *entry = 0;
+                        offset += block;
+                        break;
+                    }
+                    split(frames, entry, depth)?;
+                }
+                table = entry_addr(*entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Map a single page or block, creating the tables on the way to it
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    /// * `virt`   - The virtual address to map at, aligned to the block
+    /// * `phys`   - The physical address to map, aligned to the block
+    /// * `level`  - The level of the table the block is mapped in, one of
+    ///              [`leaf_levels`]
+    /// * `perms`  - The permissions of the mapping
+    /// * `typ`    - The kind of memory being mapped
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    unsafe fn map_block(&mut self, frames: &mut PageAlloc, virt: u64,
+                        phys: u64, level: usize, perms: Permissions,
+                        typ: MemoryType) -> Result<()> {
+        // Walk down to the table holding the block, the upper levels allow
+        // everything so only the block decides the permissions
+        let mut table = self.top(virt).0;
+        for (depth, &shift) in LEVEL_SHIFTS[..level].iter().enumerate()
+                .skip(self.top_level()) {
+            let entry = (table as usize as *mut u64)
+                .add(((virt >> shift) & 0x1ff) as usize);
+            if *entry & PTE_VALID == 0 {
+                let next = frames.alloc_zeroed_frames(1)
+                    .map_err(Error::PageAlloc)?;
+                *entry = table_entry(next.0);
+            } else if !is_table(*entry, depth) {
+                return Err(Error::AlreadyMapped(virt));
+            }
+            table = entry_addr(*entry);
+        }
+
+        // Fill in the block
+        let entry = (table as usize as *mut u64)
+            .add(((virt >> LEVEL_SHIFTS[level]) & 0x1ff) as usize);
+        if *entry & PTE_VALID != 0 { return Err(Error::AlreadyMapped(virt)); }
+        *entry = leaf_entry(phys, level, perms, typ);
+
+        Ok(())
+    }
+
+    /// Get the top level table translating `virt`
+    ///
+    /// # Parameters
+    ///
+    /// * `virt` - The canonical virtual address to translate
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the top level table
+    ///
+    fn top(&self, virt: u64) -> PhysAddr {
+        #[cfg(target_arch = "aarch64")]
+        if (virt as i64) < 0 { return self.root_high; }
+
+        let _ = virt;
+        self.root
+    }
+
+    /// Map the handoff code at its physical address, so it keeps running
+    /// across the switch in [`PageTable::switch_to`]
+    ///
+    /// # Parameters
+    ///
+    /// * `frames` - The allocator to take new page table frames from
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// Physical memory must be identity mapped.
+    ///
+    pub unsafe fn map_handoff(&mut self, frames: &mut PageAlloc)
+            -> Result<()> {
+        let start = &paging_handoff as *const u8 as u64 & !(PAGE_SIZE - 1);
+        let end = (&paging_handoff_end as *const u8 as u64 + PAGE_SIZE - 1) &
+            !(PAGE_SIZE - 1);
+
+        self.identity_map(frames, PhysAddr(start), end - start,
+            Permissions { write: false, execute: true, user: false })
+    }
+
+    /// Switch to this page table and jump to `entry` on `stack`, with `arg`
+    /// in the first argument register
+    ///
+    /// This also turns on the enforcement of the permissions, no-execute
+    /// through `EFER.NXE` and read-only pages in the kernel through `CR0.WP`,
+    /// and resets the PAT to its power-on layout, which device mappings rely
+    /// on. The entry point is called with the System V calling convention.
+    ///
+    /// # Parameters
+    ///
+    /// * `entry` - The virtual address to jump to
+    /// * `stack` - The virtual address of the top of the stack to run on
+    /// * `arg`   - The value to pass in the first argument register
+    ///
+    /// # Returns
+    ///
+    /// This function does not return if the switch succeeded, otherwise the
+    /// [`Error`] which prevented it
+    ///
+    /// # Safety
+    ///
+    /// The handoff code must be mapped with [`PageTable::map_handoff`], and
+    /// `entry` and `stack` must be mapped in this page table. Nothing
+    /// running on the old page table is returned to.
+    ///
+    #[cfg(target_arch = "x86_64")]
+    pub unsafe fn switch_to(&self, entry: VirtAddr, stack: VirtAddr,
+                            arg: u64) -> Error {
+        /// The `IA32_EFER` MSR
+        const IA32_EFER: u32 = 0xc000_0080;
+
+        /// The `IA32_PAT` MSR
+        const IA32_PAT: u32 = 0x277;
+
+        // The power-on PAT is WB, WT, UC-, UC for the combinations of the
+        // cache disable and write-through bits, twice
+        let pat: u64 = 0x0007_0406_0007_0406;
+        asm!("wrmsr", in("ecx") IA32_PAT, in("eax") pat as u32,
+            in("edx") (pat >> 32) as u32, options(nostack, preserves_flags));
+
+        // Enable no-execute pages, mapping made sure the processor has them
+        let (lo, hi): (u32, u32);
+        asm!("rdmsr", in("ecx") IA32_EFER, out("eax") lo, out("edx") hi,
+            options(nomem, nostack, preserves_flags));
+        asm!("wrmsr", in("ecx") IA32_EFER, in("eax") lo | (1 << 11),
+            in("edx") hi, options(nostack, preserves_flags));
+
+        let handoff: extern "sysv64" fn(u64, u64, u64, u64) -> ! =
+            core::mem::transmute(&paging_handoff as *const u8);
+        handoff(self.root.0, stack.0, entry.0, arg)
+    }
+
+    /// Switch to this page table and jump to `entry` on `stack`, with `arg`
+    /// in the first argument register
+    ///
+    /// This programs the memory attributes into `MAIR_EL1`, configures 48-bit
+    /// halves with 4 KiB granules in `TCR_EL1`, loads both halves and turns
+    /// the MMU and caches on.
+    ///
+    /// # Parameters
+    ///
+    /// * `entry` - The virtual address to jump to
+    /// * `stack` - The virtual address of the top of the stack to run on
+    /// * `arg`   - The value to pass in the first argument register
+    ///
+    /// # Returns
+    ///
+    /// This function does not return if the switch succeeded, otherwise the
+    /// [`Error`] which prevented it
+    ///
+    /// # Safety
+    ///
+    /// The handoff code must be mapped with [`PageTable::map_handoff`], and
+    /// `entry` and `stack` must be mapped in this page table. Nothing
+    /// running on the old page table is returned to.
+    ///
+    #[cfg(target_arch = "aarch64")]
+    pub unsafe fn switch_to(&self, entry: VirtAddr, stack: VirtAddr,
+                            arg: u64) -> Error {
+        // Only the EL1 translation regime has both halves
+        let el: u64;
+        asm!("mrs {}, CurrentEL", out(reg) el,
+            options(nomem, nostack, preserves_flags));
+        let el = ((el >> 2) & 3) as u8;
+        if el != 1 { return Error::UnsupportedExceptionLevel(el); }
+
+        // Normal memory is write-back read/write-allocate, devices are
+        // Device-nGnRE
+        let mair: u64 = (0xff << (MAIR_NORMAL * 8)) |
+            (0x04 << (MAIR_DEVICE * 8));
+
+        // Use as many physical address bits as the processor supports
+        let mmfr0: u64;
+        asm!("mrs {}, id_aa64mmfr0_el1", out(reg) mmfr0,
+            options(nomem, nostack, preserves_flags));
+
+        // 48-bit halves (T0SZ/T1SZ of 16), inner shareable write-back
+        // table walks, 4 KiB granules (TG0 of 0, TG1 of 2)
+        let tcr: u64 = 16 | (1 << 8) | (1 << 10) | (3 << 12) |
+            (16 << 16) | (1 << 24) | (1 << 26) | (3 << 28) | (2 << 30) |
+            ((mmfr0 & 0x7) << 32);
+
+        let handoff: extern "C" fn(u64, u64, u64, u64, u64, u64, u64) -> ! =
+            core::mem::transmute(&paging_handoff as *const u8);
+        handoff(self.root.0, self.root_high.0, mair, tcr, stack.0, entry.0,
+                arg)
+    }
+
+    /// Switch to this page table and jump to `entry` on `stack`, with `arg`
+    /// in the first argument register
+    ///
+    /// The table is loaded into `satp` as Sv48, or Sv39 with a 3-level
+    /// [`Layout`], with ASID 0. A write of a mode the hart does not
+    /// implement is ignored, which is reported as an error.
+    ///
+    /// # Parameters
+    ///
+    /// * `entry` - The virtual address to jump to
+    /// * `stack` - The virtual address of the top of the stack to run on
+    /// * `arg`   - The value to pass in the first argument register
+    ///
+    /// # Returns
+    ///
+    /// This function does not return if the switch succeeded, otherwise the
+    /// [`Error`] which prevented it
+    ///
+    /// # Safety
+    ///
+    /// The handoff code must be mapped with [`PageTable::map_handoff`], and
+    /// `entry` and `stack` must be mapped in this page table. Nothing
+    /// running on the old page table is returned to.
+    ///
+    #[cfg(target_arch = "riscv64")]
+    pub unsafe fn switch_to(&self, entry: VirtAddr, stack: VirtAddr,
+                            arg: u64) -> Error {
+        let mode = match self.layout.levels {
+            3 => SATP_MODE_SV39,
+            _ => SATP_MODE_SV48,
+        };
+
+        // The handoff code only comes back if the mode did not stick
+        let handoff: extern "C" fn(u64, u64, u64, u64) =
+            core::mem::transmute(&paging_handoff as *const u8);
+        handoff((mode << 60) | (self.root.0 >> 12), stack.0, entry.0, arg);
+        Error::UnsupportedTranslationMode
+    }
+}
+
+/// Get the physical address an entry points to
+///
+/// # Parameters
+///
+/// * `entry` - The raw valid entry
+///
+/// # Returns
+///
+/// The physical address of the next level table or the mapped memory
+///
+fn entry_addr(entry: u64) -> u64 {
+    #[cfg(not(target_arch = "riscv64"))]
+    let addr = entry & PTE_ADDR_MASK;
+
+    // The physical page number is stored 2 bits lower than the address
+    #[cfg(target_arch = "riscv64")]
+    let addr = (entry & PTE_ADDR_MASK) << 2;
+
+    addr
+}
+
+/// Get the address bits of an entry pointing to a physical address
+///
+/// # Parameters
+///
+/// * `addr` - The page aligned physical address
+///
+/// # Returns
+///
+/// The bits of the entry holding `addr`
+///
+fn addr_bits(addr: u64) -> u64 {
+    #[cfg(not(target_arch = "riscv64"))]
+    let bits = addr;
+
+    #[cfg(target_arch = "riscv64")]
+    let bits = addr >> 2;
+
+    bits
+}
+
+/// Encode an entry pointing to a next level table
+///
+/// # Parameters
+///
+/// * `table` - The physical address of the next level table
+///
+/// # Returns
+///
+/// The raw entry
+///
+fn table_entry(table: u64) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    let entry = table | PTE_VALID | PTE_WRITE | PTE_USER;
+
+    #[cfg(target_arch = "aarch64")]
+    let entry = table | PTE_VALID | PTE_TABLE;
+
+    // Without any of the read, write and execute bits
+    #[cfg(target_arch = "riscv64")]
+    let entry = addr_bits(table) | PTE_VALID;
+
+    entry
+}
+
+/// Split a large page into a next level table of smaller pages mapping the
+/// same memory the same way
+///
+/// # Parameters
+///
+/// * `frames` - The allocator to take the new table from
+/// * `entry`  - The entry of the large page, which is replaced by the table
+/// * `level`  - The level of the table holding the entry
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+/// # Safety
+///
+/// Physical memory must be identity mapped, and `entry` must point to a
+/// valid large page entry.
+///
+unsafe fn split(frames: &mut PageAlloc, entry: *mut u64, level: usize)
+        -> Result<()> {
+    let next = frames.alloc_zeroed_frames(1).map_err(Error::PageAlloc)?;
+
+    // Smaller pages keep the attributes, only the page kind differs for 4
+    // KiB pages
+    let phys  = entry_addr(*entry);
+    let attrs = *entry & !PTE_ADDR_MASK;
+    let pages = level + 1 == LEVEL_SHIFTS.len() - 1;
+
+    // The huge bit is the PAT bit for 4 KiB pages
+    #[cfg(target_arch = "x86_64")]
+    let attrs = if pages { attrs & !PTE_HUGE } else { attrs };
+
+    // Pages have the table bit set
+    #[cfg(target_arch = "aarch64")]
+    let attrs = if pages { attrs | PTE_TABLE } else { attrs };
+
+    // Pages and larger leaves look the same
+    #[cfg(target_arch = "riscv64")]
+    let _ = pages;
+
+    let size = 1u64 << LEVEL_SHIFTS[level + 1];
+    for ii in 0..512 {
+        *(next.0 as usize as *mut u64).add(ii) =
+            addr_bits(phys + ii as u64 * size) | attrs;
+    }
+    *entry = table_entry(next.0);
+
+    Ok(())
+}
+
+/// Check if a valid entry points to a next level table
+///
+/// # Parameters
+///
+/// * `entry` - The raw valid entry
+/// * `level` - The level of the table holding the entry
+///
+/// # Returns
+///
+/// `true` if the entry points to a table, `false` if it maps memory
+///
+fn is_table(entry: u64, level: usize) -> bool {
+    // Entries in the last level always map pages
+    if level >= LEVEL_SHIFTS.len() - 1 { return false; }
+
+    // Large pages have the page size bit set
+    #[cfg(target_arch = "x86_64")]
+    let table = entry & PTE_HUGE == 0;
+
+    // Blocks have the table bit clear
+    #[cfg(target_arch = "aarch64")]
+    let table = entry & PTE_TABLE != 0;
+
+    // Leaves have at least one of the read, write and execute bits set
+    #[cfg(target_arch = "riscv64")]
+    let table = entry & (PTE_READ | PTE_WRITE | PTE_EXECUTE) == 0;
+
+    table
+}
+
+/// Encode an entry mapping memory
+///
+/// # Parameters
+///
+/// * `phys`  - The physical address mapped
+/// * `level` - The level of the table holding the entry
+/// * `perms` - The permissions of the mapping
+/// * `typ`   - The kind of memory being mapped
+///
+/// # Returns
+///
+/// The raw entry
+///
+fn leaf_entry(phys: u64, level: usize, perms: Permissions, typ: MemoryType)
+        -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    let entry = {
+        // Pages larger than 4 KiB have the huge bit set
+        let kind = if level < LEVEL_SHIFTS.len() - 1 { PTE_HUGE } else { 0 };
+
+        phys | PTE_VALID | kind |
+            if perms.write    { PTE_WRITE } else { 0 } |
+            if perms.user     { PTE_USER  } else { 0 } |
+            if !perms.execute { PTE_NX    } else { 0 } |
+            match typ {
+                MemoryType::Normal => 0,
+                MemoryType::Device => PTE_CACHE_DISABLE | PTE_WRITE_THROUGH,
+            }
+    };
+
+    #[cfg(target_arch = "aarch64")]
+    let entry = {
+        // Pages have the table bit set, blocks do not
+        let kind = if level == LEVEL_SHIFTS.len() - 1 { PTE_TABLE } else { 0 };
+
+        let attrs = match typ {
+            MemoryType::Normal =>
+                (MAIR_NORMAL << PTE_ATTR_INDEX_SHIFT) | PTE_INNER_SHAREABLE,
+            MemoryType::Device => MAIR_DEVICE << PTE_ATTR_INDEX_SHIFT,
+        };
+
+        // Each exception level has its own execute-never bit, and device
+        // memory is never executable
+        let exec = match (perms.execute && typ == MemoryType::Normal,
+                          perms.user) {
+            (true, false) => PTE_UXN,
+            (true, true)  => PTE_PXN,
+            (false, _)    => PTE_PXN | PTE_UXN,
+        };
+
+        phys | PTE_VALID | kind | attrs | exec | PTE_ACCESSED |
+            if !perms.write { PTE_READ_ONLY } else { 0 } |
+            if perms.user   { PTE_USER      } else { 0 }
+    };
+
+    // Leaves look the same at every level, and the cacheability of memory
+    // comes from the physical memory attributes of the platform
+    #[cfg(target_arch = "riscv64")]
+    let entry = {
+        let _ = (level, typ);
+        addr_bits(phys) | PTE_VALID | PTE_READ | PTE_ACCESSED | PTE_DIRTY |
+            if perms.write   { PTE_WRITE   } else { 0 } |
+            if perms.execute { PTE_EXECUTE } else { 0 } |
+            if perms.user    { PTE_USER    } else { 0 }
+    };
+
+    entry
+}
+
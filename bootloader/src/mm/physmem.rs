@@ -1,12 +1,124 @@
 //! Physical memory management for the OS
 
-use core::mem::size_of;
+use core::mem::{align_of, size_of};
+
+use crate::mm::virtmem::phys_to_virt;
+
+/// Errors from alignment-aware [`PhysSlice`] accesses and [`PhysAddr`]
+/// arithmetic
+#[derive(Debug)]
+pub enum Error {
+    /// An address computation overflowed the physical address space
+    IntegerOverflow,
+
+    /// The slice is too small for the access
+    TooSmall,
+
+    /// The alignment is not a power of two
+    InvalidAlignment(usize),
+
+    /// The start of the slice is not aligned as required
+    Unaligned {
+        /// The address of the start of the slice
+        addr: PhysAddr,
+
+        /// The required alignment
+        align: usize,
+    },
+}
 
 /// A strongly typed physical address
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysAddr(pub u64);
 
 impl PhysAddr {
+    /// Add `bytes` to the address
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` - The number of bytes to add
+    ///
+    /// # Returns
+    ///
+    /// The address `bytes` past `self`, or [`Error::IntegerOverflow`]
+    ///
+    #[inline]
+    pub fn checked_add(self, bytes: u64) -> Result<Self, Error> {
+        self.0.checked_add(bytes).map(PhysAddr).ok_or(Error::IntegerOverflow)
+    }
+
+    /// Move the address by a signed number of bytes
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` - The number of bytes to move by, negative to move down
+    ///
+    /// # Returns
+    ///
+    /// The moved address, or [`Error::IntegerOverflow`]
+    ///
+    #[inline]
+    pub fn offset_bytes(self, bytes: isize) -> Result<Self, Error> {
+        let addr = if bytes < 0 {
+            self.0.checked_sub((bytes as i64).wrapping_neg() as u64)
+        } else {
+            self.0.checked_add(bytes as u64)
+        };
+        addr.map(PhysAddr).ok_or(Error::IntegerOverflow)
+    }
+
+    /// Round the address up to a multiple of `align`
+    ///
+    /// # Parameters
+    ///
+    /// * `align` - The alignment, a power of two
+    ///
+    /// # Returns
+    ///
+    /// The aligned address, on error [`Error`]
+    ///
+    #[inline]
+    pub fn align_up(self, align: u64) -> Result<Self, Error> {
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment(align as usize));
+        }
+        Ok(PhysAddr(self.checked_add(align - 1)?.0 & !(align - 1)))
+    }
+
+    /// Round the address down to a multiple of `align`
+    ///
+    /// # Parameters
+    ///
+    /// * `align` - The alignment, a power of two
+    ///
+    /// # Returns
+    ///
+    /// The aligned address, on error [`Error`]
+    ///
+    #[inline]
+    pub fn align_down(self, align: u64) -> Result<Self, Error> {
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment(align as usize));
+        }
+        Ok(PhysAddr(self.0 & !(align - 1)))
+    }
+
+    /// Check if the address is a multiple of `align`
+    ///
+    /// # Parameters
+    ///
+    /// * `align` - The alignment, a power of two
+    ///
+    /// # Returns
+    ///
+    /// `true` if the address is aligned, `false` if it is not or `align` is
+    /// not a power of two
+    ///
+    #[inline]
+    pub fn is_aligned(self, align: u64) -> bool {
+        align.is_power_of_two() && self.0 & (align - 1) == 0
+    }
+
     /*
     /// Read a `T` from the aligned physical address at `self`
     ///
@@ -39,7 +151,30 @@ impl PhysAddr {
     ///
     #[inline]
     pub unsafe fn read_unaligned<T>(&self) -> T {
-        core::ptr::read_unaligned(self.0 as *const T)
+        core::ptr::read_unaligned(self.as_ptr::<T>())
+    }
+
+    /// Write an unaligned `val` to physical memory address `self`
+    ///
+    /// # Parameters
+    ///
+    /// * `val` - The value to write into physical memory
+    ///
+    #[inline]
+    pub unsafe fn write_unaligned<T>(&self, val: T) {
+        core::ptr::write_unaligned(self.as_ptr::<T>(), val);
+    }
+
+    /// Get a pointer to physical memory address `self` in the address space
+    /// in use
+    ///
+    /// # Returns
+    ///
+    /// A raw pointer to `T` at `self`, through [`phys_to_virt`]
+    ///
+    #[inline]
+    fn as_ptr<T>(&self) -> *mut T {
+        phys_to_virt(*self).as_mut_ptr()
     }
 }
 
@@ -77,6 +212,16 @@ impl PhysSlice {
         self.len
     }
 
+    /// Get the physical address of the start of the slice
+    ///
+    /// # Returns
+    ///
+    /// The [`PhysAddr`] the next consume will read from
+    ///
+    pub fn as_phys_addr(&self) -> PhysAddr {
+        self.addr
+    }
+
     /// Discard `bytes` from the slice by just updating the pointer and length
     ///
     /// # Parameters
@@ -122,4 +267,112 @@ impl PhysSlice {
         Ok(data)
         
     }
+
+    /// Read an aligned `T` from the slice, updating the pointer
+    ///
+    /// # Returns
+    ///
+    /// `T` read from the start of the slice, on error [`Error`] if the slice
+    /// is too small or not aligned for a `T`
+    ///
+    pub unsafe fn consume_aligned<T>(&mut self) -> Result<T, Error> {
+        // Make sure the data is aligned
+        let align = align_of::<T>();
+        if self.addr.0 & (align as u64 - 1) != 0 {
+            return Err(Error::Unaligned { addr: self.addr, align });
+        }
+
+        self.consume::<T>().map_err(|_| Error::TooSmall)
+    }
+
+    /// Discard bytes from the slice until its start is aligned to `align`
+    ///
+    /// # Parameters
+    ///
+    /// * `align` - The alignment (in bytes) to skip ahead to, a power of two
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the slice is now aligned, on error [`Error`] if `align` is
+    /// not a power of two or the slice ends before the aligned address
+    ///
+    pub fn align_to(&mut self, align: usize) -> Result<(), Error> {
+        if !align.is_power_of_two() {
+            return Err(Error::InvalidAlignment(align));
+        }
+
+        let mask = align as u64 - 1;
+        let pad  = ((align as u64 - (self.addr.0 & mask)) & mask) as usize;
+        self.discard(pad).map_err(|_| Error::TooSmall)
+    }
+
+    /// Read a potentially unaligned `T` from the slice, without updating the
+    /// pointer
+    ///
+    /// # Returns
+    ///
+    /// `T` read from the start of the slice, `Err(())` if the slice was too
+    /// small to hold a `T`
+    ///
+    pub unsafe fn peek<T>(&self) -> Result<T, ()> {
+        // Make sure we have enough data to read
+        if self.len() < size_of::<T>() {
+            return Err(());
+        }
+
+        Ok(self.addr.read_unaligned::<T>())
+    }
+
+    /// Write a potentially unaligned `T` to the slice, updating the pointer
+    ///
+    /// # Parameters
+    ///
+    /// * `val` - The value to write to the start of the slice
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if the value was written, `Err(())` if the slice was too small
+    /// to hold a `T`
+    ///
+    pub unsafe fn write<T>(&mut self, val: T) -> Result<(), ()> {
+        // Make sure we have enough room to write
+        if self.len() < size_of::<T>() {
+            return Err(());
+        }
+
+        // Write the actual data
+        self.addr.write_unaligned(val);
+
+        // Update the pointer and length
+        self.addr.0 += size_of::<T>() as u64;
+        self.len    -= size_of::<T>();
+        Ok(())
+    }
+
+    /// Copy bytes out of the slice into `out`, updating the pointer
+    ///
+    /// # Parameters
+    ///
+    /// * `out` - The buffer to fill, as many bytes as it holds are consumed
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` if `out` was filled, `Err(())` if the slice was too small to
+    /// fill `out`
+    ///
+    pub unsafe fn consume_bytes(&mut self, out: &mut [u8]) -> Result<(), ()> {
+        // Make sure we have enough data to copy
+        if self.len() < out.len() {
+            return Err(());
+        }
+
+        // Copy the actual data
+        core::ptr::copy_nonoverlapping(self.addr.as_ptr::<u8>(),
+                                       out.as_mut_ptr(), out.len());
+
+        // Update the pointer and length
+        self.addr.0 += out.len() as u64;
+        self.len    -= out.len();
+        Ok(())
+    }
 }
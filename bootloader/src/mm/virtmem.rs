@@ -0,0 +1,52 @@
+//! Virtual addresses, and their translation to and from physical addresses
+//!
+//! The bootloader only ever runs with physical memory identity mapped, so the
+//! translation is the identity. The kernel reaches physical memory through
+//! the linear map of its page table, which the bootloader never runs on.
+
+use crate::mm::physmem::PhysAddr;
+
+/// A strongly typed virtual address
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtAddr(pub u64);
+
+impl VirtAddr {
+    /// Get a pointer to the memory at this virtual address
+    ///
+    /// # Returns
+    ///
+    /// A raw pointer to `T` at `self`
+    ///
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as usize as *mut T
+    }
+}
+
+/// Get the virtual address physical memory is reachable at in the address
+/// space in use
+///
+/// # Parameters
+///
+/// * `addr` - The physical address to translate
+///
+/// # Returns
+///
+/// The virtual address `addr` is mapped at
+///
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr(addr.0)
+}
+
+/// Get the physical address behind a virtual address in the linear map
+///
+/// # Parameters
+///
+/// * `addr` - The virtual address to translate
+///
+/// # Returns
+///
+/// The physical address mapped at `addr`
+///
+pub fn virt_to_phys(addr: VirtAddr) -> PhysAddr {
+    PhysAddr(addr.0)
+}
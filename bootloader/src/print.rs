@@ -1,29 +1,821 @@
 //! This file handles the [`print!`] macro which allows displaying
-//! information to the UEFI standard out console via the UEFI API, or to the
-//! serial port specified by the ACPI SPCR table.
+//! information on every [`Sink`] which is available and enabled: the serial
+//! port specified by the ACPI SPCR table or the command line, and while boot
+//! services are up the UEFI standard out console and a firmware serial port.
+//! On riscv64 without a serial port, the SBI console takes over from the
+//! firmware serial port once boot services are exited. Once we own the
+//! screen the output is also drawn on the framebuffer console. While the
+//! splash screen is shown nothing is written to the screen. [`println!`]
+//! does the same with a newline at the end.
+//!
+//! [`eprint!`] and [`eprintln!`] print like [`print!`], but while boot
+//! services are up they write to the UEFI standard error console in place of
+//! the standard out console, so errors can be told apart from the rest of the
+//! output without going missing from the serial ports.
+//!
+//! Messages about the boot go through the [`log!`] family of macros instead,
+//! which drop anything less severe than the level set with [`set_level`].
+//! Every line they print starts with the time since boot, as `[ 0.123456]`.
+//! With colors enabled warnings and errors stand out, in ANSI colors on the
+//! serial ports and in text attributes on the EFI console. A message which
+//! repeats the last one is only counted, so a polling loop can't flood a slow
+//! console. With [`set_structured`] every message is printed as a single
+//! `ts=.. level=.. target=.. msg=".."` record instead, for harnesses which
+//! parse the log.
+//!
+//! [`bug_on!`] and [`ensure!`] check a condition, and log the condition and
+//! where it was checked before panicking or returning an error.
 
-use core::fmt::{Result, Write, Error};
+use core::fmt::{Arguments, Result, Write, Error};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use serial::serial_device;
+use fbcon::fbcon_device;
+use spinlock::{SpinLock, SpinLockGuard};
 
-/// A dummy screen writing structure we can implement [`Write`] on
-pub struct ScreenWriter;
+/// Size (in bytes) of the ring buffer all output is mirrored into
+const LOG_RING_SIZE: usize = 64 * 1024;
 
-impl Write for ScreenWriter {
+/// Everything printed, laid out as described by [`boot_info::LogBuffer`]
+#[repr(C)]
+struct LogRing {
+    /// Number of bytes ever written
+    written: u64,
+
+    /// The most recently printed bytes
+    bytes: [u8; LOG_RING_SIZE],
+}
+
+/// The ring buffer all output is mirrored into, only accessed with the lock
+/// on the sinks held. It lives in our image, which the kernel is never
+/// handed as free memory.
+static mut LOG_RING: LogRing = LogRing {
+    written: 0,
+    bytes:   [0; LOG_RING_SIZE],
+};
+
+/// The last log message, only accessed with the lock on the sinks held
+static mut LAST_MESSAGE: LastMessage = LastMessage {
+    hash:    None,
+    level:   Level::Info,
+    target:  "",
+    repeats: 0,
+};
+
+/// Set when log messages are printed as `key=value` records
+static STRUCTURED: AtomicBool = AtomicBool::new(false);
+
+/// The last log message, to count its repeats rather than printing them
+struct LastMessage {
+    /// Hash of the level and text of the message, `None` if it did not end
+    /// with a newline and so can't be repeated
+    hash: Option<u64>,
+
+    /// Level of the message
+    level: Level,
+
+    /// Module the message came from
+    target: &'static str,
+
+    /// Number of times the message was repeated since it was printed
+    repeats: usize,
+}
+
+/// A [`Write`] which escapes a log message into the quoted `msg` value of a
+/// record, dropping the newline at its end
+struct Escaper<'a> {
+    /// Where the escaped message goes
+    out: &'a mut ScreenWriter,
+
+    /// Set when a newline was held back, as it is only escaped if more of
+    /// the message follows
+    newline: bool,
+}
+
+impl Write for Escaper<'_> {
     fn write_str(&mut self, string: &str) -> Result {
-        if let Some(serial) = serial_device() { 
-            serial.write(string.as_bytes()).map_err(|_| Error)
+        let mut rest = string;
+        while !rest.is_empty() {
+            if self.newline {
+                self.out.write_str("\\n")?;
+                self.newline = false;
+            }
+
+            // Write everything up to the next character which needs escaping
+            let idx = rest.find(|x| matches!(x, '\n' | '"' | '\\'))
+                .unwrap_or(rest.len());
+            if idx > 0 { self.out.write_str(&rest[..idx])?; }
+            match rest[idx..].chars().next() {
+                Some('\n') => self.newline = true,
+                Some('"')  => self.out.write_str("\\\"")?,
+                Some(_)    => self.out.write_str("\\\\")?,
+                None       => break,
+            }
+            rest = &rest[idx + 1..];
+        }
+        Ok(())
+    }
+}
+
+/// A [`Write`] which hashes everything written to it with FNV-1a, to tell
+/// log messages apart without storing them
+struct MessageHash {
+    /// The hash of everything written so far
+    hash: u64,
+
+    /// The last byte written, zero if nothing was
+    last: u8,
+}
+
+impl MessageHash {
+    /// Create a hash of nothing
+    fn new() -> Self {
+        MessageHash { hash: 0xcbf2_9ce4_8422_2325, last: 0 }
+    }
+}
+
+impl Write for MessageHash {
+    fn write_str(&mut self, string: &str) -> Result {
+        for &byte in string.as_bytes() {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+            self.last = byte;
+        }
+        Ok(())
+    }
+}
+
+/// Set when log messages are colored by their [`Level`]
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// ANSI sequence which switches back to the default color
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// The most verbose [`Level`] which is printed, as a `usize`
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// How severe a log message is, from the most to the least severe
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Something we needed failed
+    Error,
+
+    /// Something failed but we carry on without it
+    Warn,
+
+    /// Progress of the boot
+    Info,
+
+    /// Details which are only of interest when debugging
+    Debug,
+}
+
+impl Level {
+    /// Parse a level from the `loglevel=` option
+    ///
+    /// # Parameters
+    ///
+    /// * `val` - The value of the option
+    ///
+    /// # Returns
+    ///
+    /// The [`Level`], or `None` if `val` is not a level
+    ///
+    pub fn parse(val: &str) -> Option<Self> {
+        match val {
+            "error" => Some(Level::Error),
+            "warn"  => Some(Level::Warn),
+            "info"  => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _       => None,
+        }
+    }
+
+    /// Get the name of the level, as used by the `loglevel=` option
+    ///
+    /// # Returns
+    ///
+    /// The name of the level
+    ///
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn  => "warn",
+            Level::Info  => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// Get the color messages of the level are printed in
+    ///
+    /// # Returns
+    ///
+    /// The ANSI sequence and EFI color switching to the color, `None` if the
+    /// level is printed in the default color
+    ///
+    fn color(self) -> Option<(&'static str, crate::efi::Color)> {
+        match self {
+            Level::Error => Some(("\x1b[31m", crate::efi::Color::LightRed)),
+            Level::Warn  => Some(("\x1b[33m", crate::efi::Color::Yellow)),
+            Level::Info | Level::Debug => None,
+        }
+    }
+}
+
+/// Set the most verbose level which is printed
+///
+/// # Parameters
+///
+/// * `level` - Messages less severe than this are dropped
+///
+pub fn set_level(level: Level) {
+    LOG_LEVEL.store(level as usize, Ordering::SeqCst);
+}
+
+/// Enable or disable coloring log messages by their level
+///
+/// # Parameters
+///
+/// * `enabled` - Whether warnings and errors are colored
+///
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::SeqCst);
+}
+
+/// Switch between pretty and `key=value` log messages
+///
+/// # Parameters
+///
+/// * `enabled` - Whether every log message is printed as a record
+///
+pub fn set_structured(enabled: bool) {
+    STRUCTURED.store(enabled, Ordering::SeqCst);
+}
+
+/// Check if messages of a level are printed
+///
+/// # Parameters
+///
+/// * `level` - The level of the message
+///
+/// # Returns
+///
+/// `true` if the message should be printed
+///
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LOG_LEVEL.load(Ordering::SeqCst)
+}
+
+/// Set while the last character printed was a newline
+static AT_LINE_START: AtomicBool = AtomicBool::new(true);
+
+/// Bit mask of the enabled sinks, indexed by [`Sink`]. The lock is held
+/// for whole messages, so messages from different processors don't
+/// interleave.
+static SINKS: SpinLock<usize> = SpinLock::new(!0);
+
+/// A place printed output goes to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sink {
+    /// The serial port we drive ourselves
+    Serial,
+
+    /// The firmware serial port, only used without a [`Sink::Serial`] as it
+    /// is likely the same port
+    EfiSerial,
+
+    /// The EFI console
+    EfiConsole,
+
+    /// The framebuffer console
+    Framebuffer,
+
+    /// The SBI console of riscv64, only used without a [`Sink::Serial`] once
+    /// boot services are exited, as the firmware serial port likely is the
+    /// same port until then
+    Sbi,
+}
+
+impl Sink {
+    /// Every sink, in the order output is written to them
+    const ALL: [Sink; 5] = [
+        Sink::Serial, Sink::EfiSerial, Sink::EfiConsole, Sink::Framebuffer,
+        Sink::Sbi,
+    ];
+
+    /// Write a string to the sink
+    ///
+    /// # Parameters
+    ///
+    /// * `string` - The string to write
+    ///
+    /// # Returns
+    ///
+    /// `None` if the sink is not available, otherwise whether the write
+    /// worked
+    ///
+    fn write(self, string: &str) -> Option<Result> {
+        match self {
+            Sink::Serial => serial_device().map(|serial| {
+                serial.write(string.as_bytes()).map_err(|_| Error)
+            }),
+            Sink::EfiSerial => {
+                if serial_device().is_some() { return None; }
+                crate::efi::serial_io().map(|serial| {
+                    serial.write(string.as_bytes()).map_err(|_| Error)
+                })
+            }
+            Sink::EfiConsole => crate::efi::boot_services_active().then(|| {
+                crate::efi::output_string(string).map_err(|_| Error)
+            }),
+            Sink::Framebuffer => fbcon_device().map(|fbcon| {
+                fbcon.write(string.as_bytes());
+                Ok(())
+            }),
+            #[cfg(target_arch = "riscv64")]
+            Sink::Sbi => {
+                if !crate::sbi::console_enabled() || serial_device().is_some()
+                        || crate::efi::boot_services_active() {
+                    return None;
+                }
+                Some(crate::sbi::write(string.as_bytes()).map_err(|_| Error))
+            }
+            #[cfg(not(target_arch = "riscv64"))]
+            Sink::Sbi => None,
+        }
+    }
+
+    /// Switch the color of the text written to the sink, the framebuffer
+    /// console has no colors so it is left alone
+    ///
+    /// # Parameters
+    ///
+    /// * `color` - The ANSI sequence and EFI color to switch to, `None` for
+    ///             the default color
+    ///
+    fn set_color(self, color: Option<(&str, crate::efi::Color)>) {
+        let (ansi, efi) = color.unwrap_or((ANSI_RESET,
+                                           crate::efi::Color::LightGray));
+        match self {
+            Sink::Serial | Sink::EfiSerial | Sink::Sbi => {
+                let _ = self.write(ansi);
+            }
+            Sink::EfiConsole => if crate::efi::boot_services_active() {
+                let _ = crate::efi::set_color(efi, crate::efi::Color::Black);
+            }
+            Sink::Framebuffer => {}
+        }
+    }
+}
+
+/// Enable or disable output to a sink, all sinks start out enabled
+///
+/// # Parameters
+///
+/// * `sink`    - The sink to change
+/// * `enabled` - Whether output should go to the sink when it is available
+///
+pub fn set_sink(sink: Sink, enabled: bool) {
+    let bit = 1 << sink as usize;
+    let mut sinks = SINKS.lock();
+    if enabled {
+        *sinks |= bit;
+    } else {
+        *sinks &= !bit;
+    }
+}
+
+/// Get the ring buffer all output is mirrored into
+///
+/// # Returns
+///
+/// The [`boot_info::LogBuffer`] describing the ring buffer
+///
+pub fn log_buffer() -> boot_info::LogBuffer {
+    boot_info::LogBuffer {
+        present: 1,
+        addr:    unsafe { &LOG_RING as *const LogRing as u64 },
+        size:    LOG_RING_SIZE as u64,
+    }
+}
+
+/// Release the lock on the sinks, even if a message is being printed
+///
+/// # Safety
+///
+/// Whoever is printing must never print again, this is only meant for a
+/// panic handler which may have interrupted them.
+///
+pub unsafe fn force_unlock() {
+    SINKS.force_unlock();
+}
+
+/// Number of writes which failed to reach a sink
+static FAILED_WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the number of writes which failed to reach a sink, as [`print!`] has
+/// no way to report them
+pub fn failed_writes() -> usize {
+    FAILED_WRITES.load(Ordering::SeqCst)
+}
+
+/// A screen writing structure we can implement [`Write`] on, which holds the
+/// lock on the sinks. When set up for errors, the EFI standard error console
+/// takes the place of the EFI console while boot services are up.
+pub struct ScreenWriter(SpinLockGuard<'static, usize>, bool);
+
+impl ScreenWriter {
+    /// Take the lock on the sinks, spinning while another processor prints
+    ///
+    /// # Returns
+    ///
+    /// A [`ScreenWriter`] which can print until it is dropped
+    ///
+    pub fn lock() -> Self {
+        ScreenWriter(SINKS.lock(), false)
+    }
+
+    /// Check whether writes go to the EFI standard error console rather than
+    /// the EFI console
+    fn to_stderr(&self) -> bool {
+        self.1 && crate::efi::boot_services_active()
+    }
+
+    /// Get the sinks to write to
+    ///
+    /// # Returns
+    ///
+    /// The bitmask of the enabled [`Sink`]s, without the EFI console if the
+    /// EFI standard error console is written to instead
+    ///
+    fn sinks(&self) -> usize {
+        if self.to_stderr() {
+            *self.0 & !(1 << Sink::EfiConsole as usize)
         } else {
-            crate::efi::output_string(string).map_err(|_| Error)
+            *self.0
+        }
+    }
+
+    /// Mirror a string into the ring buffer
+    ///
+    /// # Parameters
+    ///
+    /// * `string` - The string which was printed
+    ///
+    fn record(&mut self, string: &str) {
+        // Holding the lock on the sinks makes us the only user of the ring
+        let ring = unsafe { &mut LOG_RING };
+        for &byte in string.as_bytes() {
+            ring.bytes[(ring.written % LOG_RING_SIZE as u64) as usize] = byte;
+            ring.written += 1;
+        }
+    }
+
+    /// Switch the color of the text on every enabled sink
+    ///
+    /// # Parameters
+    ///
+    /// * `color` - The ANSI sequence and EFI color to switch to, `None` for
+    ///             the default color
+    ///
+    fn set_color(&mut self, color: Option<(&str, crate::efi::Color)>) {
+        let enabled = self.sinks();
+        for &sink in Sink::ALL.iter() {
+            if enabled & (1 << sink as usize) != 0 { sink.set_color(color); }
         }
     }
 }
 
+impl Write for ScreenWriter {
+    fn write_str(&mut self, string: &str) -> Result {
+        self.record(string);
+
+        // Write to every sink, a sink which is gone or broken never stops
+        // the output to the others. Errors also go to the EFI standard error
+        // console while there is one.
+        let enabled = self.sinks();
+        let mut ret = Ok(());
+        if self.to_stderr() {
+            ret = crate::efi::error_string(string).map_err(|_| Error);
+        }
+        for &sink in Sink::ALL.iter() {
+            if enabled & (1 << sink as usize) == 0 { continue; }
+            if let Some(Err(err)) = sink.write(string) { ret = Err(err); }
+        }
+
+        // Keep track of the breakage
+        if ret.is_err() { FAILED_WRITES.fetch_add(1, Ordering::SeqCst); }
+
+        if let Some(&last) = string.as_bytes().last() {
+            AT_LINE_START.store(last == b'\n', Ordering::SeqCst);
+        }
+        ret
+    }
+}
+
+/// A writer for errors, which also goes to the EFI standard error console
+/// while boot services are up
+pub struct ErrorWriter(ScreenWriter);
+
+impl ErrorWriter {
+    /// Take the lock on the sinks, spinning while another processor prints
+    ///
+    /// # Returns
+    ///
+    /// An [`ErrorWriter`] which can print until it is dropped
+    ///
+    pub fn lock() -> Self {
+        ErrorWriter(ScreenWriter(SINKS.lock(), true))
+    }
+}
+
+impl Write for ErrorWriter {
+    fn write_str(&mut self, string: &str) -> Result {
+        self.0.write_str(string)
+    }
+}
+
+/// A writer for log messages, which puts a timestamp in front of every line
+/// written through the [`ScreenWriter`]. Error messages go where an
+/// [`ErrorWriter`] would put them.
+pub struct LogWriter(ScreenWriter);
+
+impl LogWriter {
+    /// Take the lock on the sinks, spinning while another processor prints
+    ///
+    /// # Parameters
+    ///
+    /// * `level` - The level of the messages which will be printed
+    ///
+    /// # Returns
+    ///
+    /// A [`LogWriter`] which can print until it is dropped
+    ///
+    pub fn lock(level: Level) -> Self {
+        match level {
+            Level::Error => LogWriter(ErrorWriter::lock().0),
+            _            => LogWriter(ScreenWriter::lock()),
+        }
+    }
+
+    /// Print a log message in the format set with [`set_structured`]
+    ///
+    /// # Parameters
+    ///
+    /// * `level`  - The level of the message
+    /// * `target` - The module the message came from
+    /// * `args`   - The message
+    ///
+    fn message(&mut self, level: Level, target: &str, args: Arguments) {
+        if !STRUCTURED.load(Ordering::SeqCst) {
+            let color = level.color().filter(|_| COLOR.load(Ordering::SeqCst));
+            if color.is_some() { self.0.set_color(color); }
+            let _ = self.write_fmt(args);
+            if color.is_some() { self.0.set_color(None); }
+            return;
+        }
+
+        // A record carries its own timestamp, rather than one per line
+        let out = &mut self.0;
+        if let Some(us) = crate::time::uptime_us() {
+            let _ = write!(out, "ts={}.{:06} ", us / 1_000_000, us % 1_000_000);
+        }
+        let _ = write!(out, "level={} target={} msg=\"", level.name(), target);
+        let _ = Escaper { out: &mut *out, newline: false }.write_fmt(args);
+        let _ = out.write_str("\"\n");
+    }
+}
+
+impl Write for LogWriter {
+    fn write_str(&mut self, string: &str) -> Result {
+        for line in string.split_inclusive('\n') {
+            if AT_LINE_START.load(Ordering::SeqCst) {
+                if let Some(us) = crate::time::uptime_us() {
+                    write!(self.0, "[{:5}.{:06}] ",
+                        us / 1_000_000, us % 1_000_000)?;
+                }
+            }
+            self.0.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print a log message, if its level is enabled. Use the [`log!`] family of
+/// macros rather than calling this directly.
+///
+/// # Parameters
+///
+/// * `level`  - The level of the message
+/// * `target` - The module the message came from
+/// * `args`   - The message
+///
+pub fn log(level: Level, target: &'static str, args: Arguments) {
+    if !enabled(level) { return; }
+
+    // Hash the message before taking the lock, as formatting it may take a
+    // while
+    let mut hash = MessageHash::new();
+    let _ = write!(hash, "{:?} {}", level, args);
+    let hash = (hash.last == b'\n').then_some(hash.hash);
+
+    let mut out = LogWriter::lock(level);
+
+    // Only count repeats of the last message, and report them once a
+    // different message comes along. Holding the lock on the sinks makes us
+    // the only user of the last message.
+    let last = unsafe { &mut LAST_MESSAGE };
+    if hash.is_some() && hash == last.hash {
+        last.repeats += 1;
+        return;
+    }
+    if last.repeats > 0 {
+        out.message(last.level, last.target, format_args!(
+            "last message repeated {} times\n", last.repeats));
+    }
+    *last = LastMessage { hash, level, target, repeats: 0 };
+
+    out.message(level, target, args);
+}
+
+/// Print memory as lines of 16 bytes, each with its address, the bytes in
+/// hex and the bytes as ASCII, to the same sinks as log messages
+///
+/// # Parameters
+///
+/// * `bytes` - The memory to print
+/// * `addr`  - The address to print for the first byte
+///
+pub fn hexdump(bytes: &[u8], addr: u64) {
+    let mut out = LogWriter::lock(Level::Info);
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let start = addr.wrapping_add(line as u64 * 16);
+        let _ = write!(out, "{:016x} ", start);
+
+        // The bytes in hex, with a gap between the two halves of the line
+        for idx in 0..16 {
+            if idx == 8 { let _ = out.write_str(" "); }
+            let _ = match chunk.get(idx) {
+                Some(byte) => write!(out, " {:02x}", byte),
+                None       => out.write_str("   "),
+            };
+        }
+
+        // The bytes as ASCII, with a dot for anything unprintable
+        let _ = out.write_str("  |");
+        for &byte in chunk {
+            let chr = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            let _ = out.write_char(chr);
+        }
+        let _ = out.write_str("|\n");
+    }
+}
+
+/// Print the memory at a raw address with [`hexdump`]
+///
+/// # Parameters
+///
+/// * `addr` - The address of the memory to print
+/// * `len`  - The number of bytes to print
+///
+/// # Safety
+///
+/// `len` bytes at `addr` must be mapped and readable.
+///
+pub unsafe fn hexdump_raw(addr: u64, len: usize) {
+    hexdump(core::slice::from_raw_parts(addr as usize as *const u8, len),
+        addr);
+}
+
 /// The standard Rust [`print!`] macro!
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => {
         let _ = <$crate::print::ScreenWriter as core::fmt::Write>::write_fmt(
-            &mut $crate::print::ScreenWriter,
+            &mut $crate::print::ScreenWriter::lock(),
             format_args!($($arg)*));
     }
 }
+
+/// The standard Rust [`println!`] macro!
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::print!("{}\n", format_args!($($arg)*))
+    };
+}
+
+/// The standard Rust [`eprint!`] macro, also printing to the EFI standard
+/// error console while boot services are up
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        let _ = <$crate::print::ErrorWriter as core::fmt::Write>::write_fmt(
+            &mut $crate::print::ErrorWriter::lock(),
+            format_args!($($arg)*));
+    }
+}
+
+/// The standard Rust [`eprintln!`] macro, also printing to the EFI standard
+/// error console while boot services are up
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::eprint!("{}\n", format_args!($($arg)*))
+    };
+}
+
+/// Print a message if its [`Level`] is enabled
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::print::log($level, module_path!(), format_args!($($arg)*))
+    }
+}
+
+/// Print a message about something we needed which failed
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::print::Level::Error, $($arg)*)
+    }
+}
+
+/// Print a message about a failure we carry on without
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::print::Level::Warn, $($arg)*)
+    }
+}
+
+/// Print a message about the progress of the boot
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::print::Level::Info, $($arg)*)
+    }
+}
+
+/// Print a message which is only of interest when debugging
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::print::Level::Debug, $($arg)*)
+    }
+}
+
+/// Print memory in hex and ASCII, either a byte slice with `hexdump!(bytes)`
+/// or `len` bytes at a raw address with `hexdump!(addr, len)`. The raw
+/// address form calls the unsafe [`hexdump_raw`] and so must be used in an
+/// `unsafe` block.
+#[macro_export]
+macro_rules! hexdump {
+    ($bytes:expr) => {{
+        let bytes: &[u8] = &$bytes[..];
+        $crate::print::hexdump(bytes, bytes.as_ptr() as u64)
+    }};
+    ($addr:expr, $len:expr) => {
+        $crate::print::hexdump_raw($addr as u64, $len as usize)
+    };
+}
+
+/// Panic if a condition holds, after logging the condition and where it was
+/// checked. An optional message with format arguments can follow the
+/// condition.
+#[macro_export]
+macro_rules! bug_on {
+    ($cond:expr) => {
+        $crate::bug_on!($cond, "")
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if $cond {
+            $crate::log_error!("BUG: `{}` at {}:{}: {}\n", stringify!($cond),
+                file!(), line!(), format_args!($($arg)+));
+            panic!("BUG: `{}`", stringify!($cond));
+        }
+    };
+}
+
+/// Return an error from the enclosing function unless a condition holds.
+/// The condition, where it was checked and the error are logged at the debug
+/// level, as the caller may well handle the error.
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $err:expr) => {
+        if !$cond {
+            let err = $err;
+            $crate::log_debug!("{}:{}: `{}` does not hold, returning {:?}\n",
+                file!(), line!(), stringify!($cond), err);
+            return Err(err.into());
+        }
+    };
+}
@@ -0,0 +1,286 @@
+//! Starting the secondary cores of aarch64 machines with PSCI `CPU_ON`
+//!
+//! A started core enters the holding pen with its MMU and caches off. It
+//! checks in through its own slot of the pen and waits there until the
+//! kernel releases it, by writing the address to jump to into the slot.
+
+use core::mem::size_of;
+
+use crate::acpi::{Fadt, Madt};
+use crate::efi;
+use crate::handshake;
+
+/// A `Result` type which wraps a secondary core startup error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from starting the secondary cores
+#[derive(Debug)]
+pub enum Error {
+    /// The FADT does not report PSCI, so there is no way to start cores
+    NotSupported,
+
+    /// The holding pen could not be allocated
+    AllocatePen(efi::Error),
+
+    /// There is no calibrated counter to time the startup with
+    NoTimer,
+}
+
+/// PSCI function ID of `PSCI_VERSION`
+const PSCI_VERSION: u32 = 0x8400_0000;
+
+/// PSCI function ID of the 64-bit `CPU_ON`
+const PSCI_CPU_ON: u32 = 0xc400_0003;
+
+/// Number of microseconds to wait for a core to check in after `CPU_ON`,
+/// before giving up on it
+const STARTUP_TIMEOUT_US: u64 = 100_000;
+
+/// The affinity fields of the MPIDR, the rest are flags
+const MPIDR_AFFINITY: u64 = 0xff_00ff_ffff;
+
+global_asm!(r#"
+    .balign 4
+    .global psci_pen_entry
+psci_pen_entry:
+    // We get the physical address of our `PenSlot` in x0. The MMU and the
+    // caches are off, so our stores go straight to memory.
+    mov x1, #1
+    str x1, [x0, #8]
+    dsb sy
+    sev
+
+    // Wait for an entry point, then jump to it with the argument in x0
+1:
+    wfe
+    ldr x1, [x0, #16]
+    cbz x1, 1b
+    ldr x0, [x0, #24]
+    br  x1
+
+    .global psci_pen_entry_end
+psci_pen_entry_end:
+"#);
+
+extern {
+    /// Where started cores enter the holding pen
+    static psci_pen_entry: u8;
+
+    /// End of the holding pen code
+    static psci_pen_entry_end: u8;
+}
+
+/// The instruction used to call PSCI
+#[derive(Clone, Copy, Debug)]
+enum Conduit {
+    /// Secure monitor call, to firmware at EL3
+    Smc,
+
+    /// Hypervisor call, to a hypervisor at EL2
+    Hvc,
+}
+
+/// The slot of one secondary core in the holding pen. The layout is shared
+/// with the pen code and with the kernel, which releases the core.
+#[derive(Debug)]
+#[repr(C, align(64))]
+pub struct PenSlot {
+    /// The affinity fields of the MPIDR of the core
+    pub mpidr: u64,
+
+    /// Set to non-zero by the core once it is waiting in the pen
+    pub online: u64,
+
+    /// Physical address the core jumps to once released, zero to keep
+    /// waiting. The core runs with its MMU and caches off, so the kernel has
+    /// to clean this to the point of coherency before sending an event.
+    pub entry: u64,
+
+    /// The value the core is released with in x0
+    pub arg: u64,
+}
+
+/// The holding pen the secondary cores are started into
+pub struct Pen {
+    /// How PSCI is called
+    conduit: Conduit,
+
+    /// A [`PenSlot`] for each secondary core
+    slots: &'static mut [PenSlot],
+}
+
+/// Call a PSCI function
+///
+/// # Parameters
+///
+/// * `conduit` - How PSCI is called
+/// * `func`    - The PSCI function ID
+/// * `args`    - The arguments of the function
+///
+/// # Returns
+///
+/// The value the function returned in x0
+///
+unsafe fn call(conduit: Conduit, func: u32, args: [u64; 3]) -> i64 {
+    let ret: i64;
+    match conduit {
+        Conduit::Smc => {
+            asm!("smc #0", inlateout("x0") func as u64 => ret,
+                inlateout("x1") args[0] => _, inlateout("x2") args[1] => _,
+                inlateout("x3") args[2] => _, lateout("x4") _,
+                lateout("x5") _, lateout("x6") _, lateout("x7") _,
+                lateout("x8") _, lateout("x9") _, lateout("x10") _,
+                lateout("x11") _, lateout("x12") _, lateout("x13") _,
+                lateout("x14") _, lateout("x15") _, lateout("x16") _,
+                lateout("x17") _, options(nostack));
+        }
+        Conduit::Hvc => {
+            asm!("hvc #0", inlateout("x0") func as u64 => ret,
+                inlateout("x1") args[0] => _, inlateout("x2") args[1] => _,
+                inlateout("x3") args[2] => _, lateout("x4") _,
+                lateout("x5") _, lateout("x6") _, lateout("x7") _,
+                lateout("x8") _, lateout("x9") _, lateout("x10") _,
+                lateout("x11") _, lateout("x12") _, lateout("x13") _,
+                lateout("x14") _, lateout("x15") _, lateout("x16") _,
+                lateout("x17") _, options(nostack));
+        }
+    }
+    ret
+}
+
+/// Clean and invalidate memory from the data caches to the point of
+/// coherency, so it is seen the same by cores with their caches off
+///
+/// # Parameters
+///
+/// * `addr` - The start of the memory
+/// * `len`  - The size (in bytes) of the memory
+///
+unsafe fn clean_invalidate(addr: *const u8, len: usize) {
+    // The log2 of the smallest data cache line in words is in bits 16 to 19
+    let ctr: u64;
+    asm!("mrs {}, ctr_el0", out(reg) ctr,
+        options(nomem, nostack, preserves_flags));
+    let line = 4 << ((ctr >> 16) & 0xf);
+
+    let start = addr as usize & !(line - 1);
+    for line in (start..addr as usize + len).step_by(line) {
+        asm!("dc civac, {}", in(reg) line, options(nostack, preserves_flags));
+    }
+    asm!("dsb sy", options(nostack, preserves_flags));
+}
+
+/// Get the affinity fields of our own MPIDR
+pub fn own_mpidr() -> u64 {
+    let mpidr: u64;
+    unsafe {
+        asm!("mrs {}, mpidr_el1", out(reg) mpidr,
+            options(nomem, nostack, preserves_flags));
+    }
+    mpidr & MPIDR_AFFINITY
+}
+
+/// Set up the holding pen to start the secondary cores into, while boot
+/// services can still allocate memory
+///
+/// # Parameters
+///
+/// * `madt` - The MADT listing the cores
+/// * `fadt` - The FADT, which reports how PSCI is called
+///
+/// # Returns
+///
+/// The [`Pen`] to pass to [`start_secondaries`], on error [`Error`]
+///
+pub fn prepare(madt: &Madt, fadt: &Fadt) -> Result<Pen> {
+    let conduit = match fadt.psci_use_hvc().ok_or(Error::NotSupported)? {
+        true  => Conduit::Hvc,
+        false => Conduit::Smc,
+    };
+
+    let own   = own_mpidr();
+    let cores = || {
+        madt.gic_processors().filter(|x| x.enabled)
+            .map(|x| x.mpidr).filter(move |&x| x != own)
+    };
+
+    let slots = match cores().count() {
+        0     => &mut [][..],
+        count => unsafe {
+            let mem = efi::allocate_pages(count * size_of::<PenSlot>())
+                .map_err(Error::AllocatePen)?;
+            core::slice::from_raw_parts_mut(mem.as_mut_ptr() as *mut PenSlot,
+                                            count)
+        },
+    };
+    for (slot, mpidr) in slots.iter_mut().zip(cores()) {
+        *slot = PenSlot { mpidr, online: 0, entry: 0, arg: 0 };
+    }
+
+    Ok(Pen { conduit, slots })
+}
+
+/// Start every enabled secondary core in the MADT into the holding pen, one
+/// at a time
+///
+/// # Parameters
+///
+/// * `pen` - The holding pen from [`prepare`]
+///
+/// # Returns
+///
+/// The number of secondary cores which checked in, on error [`Error`].
+/// Cores which don't check in in time are skipped.
+///
+/// # Safety
+///
+/// The secondary cores must not be running anything, and the memory map must
+/// identity map the pen and the bootloader.
+///
+pub unsafe fn start_secondaries(pen: &mut Pen) -> Result<usize> {
+    let version = call(pen.conduit, PSCI_VERSION, [0; 3]);
+    log_info!("PSCI {}.{} via {:?}\n", version >> 16, version & 0xffff,
+        pen.conduit);
+
+    // The cores fetch the pen code and use their slot with the caches off
+    let entry = &psci_pen_entry as *const u8;
+    clean_invalidate(entry,
+        &psci_pen_entry_end as *const u8 as usize - entry as usize);
+    clean_invalidate(pen.slots.as_ptr() as *const u8,
+                     pen.slots.len() * size_of::<PenSlot>());
+
+    handshake::boot_processor(own_mpidr());
+
+    let mut online = 0;
+    for (num, slot) in pen.slots.iter().enumerate()
+            .map(|(num, slot)| (num + 1, slot)) {
+        handshake::announce(num);
+        let ret = call(pen.conduit, PSCI_CPU_ON,
+            [slot.mpidr, entry as u64, slot as *const PenSlot as u64]);
+        if ret != 0 {
+            log_warn!("Secondary core {:#x} did not start: PSCI error {}\n",
+                slot.mpidr, ret);
+            handshake::finish(num, slot.mpidr,
+                              boot_info::CORE_STATE_START_FAILED);
+            continue;
+        }
+
+        // The core checks in with its caches off, through its slot. Drop our
+        // stale copy of the slot before every look at it.
+        let started = handshake::wait(num, STARTUP_TIMEOUT_US, || {
+            clean_invalidate(slot as *const PenSlot as *const u8,
+                             size_of::<PenSlot>());
+            core::ptr::read_volatile(&slot.online) != 0
+        }).ok_or(Error::NoTimer)?;
+
+        if started {
+            online += 1;
+            handshake::finish(num, slot.mpidr, boot_info::CORE_STATE_ONLINE);
+        } else {
+            handshake::finish(num, slot.mpidr,
+                              boot_info::CORE_STATE_TIMED_OUT);
+        }
+    }
+
+    Ok(online)
+}
@@ -0,0 +1,344 @@
+//! CPU register state, captured in the panic handler so a fault which ended
+//! up as a panic can be diagnosed from more than a file and line
+
+use core::fmt;
+
+/// Names of the general purpose registers, in the order they are captured
+#[cfg(target_arch = "x86_64")]
+const GPR_NAMES: [&str; 16] = [
+        "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9",
+        "r10", "r11", "r12", "r13", "r14", "r15",
+];
+
+/// Index of the frame pointer in [`GPR_NAMES`]
+#[cfg(target_arch = "x86_64")]
+const FRAME_POINTER: usize = 6;
+
+/// Names of the control registers, in the order they are captured
+#[cfg(target_arch = "x86_64")]
+const CONTROL_NAMES: [&str; 5] = ["rflags", "cr0", "cr2", "cr3", "cr4"];
+
+/// Names of the general purpose registers, in the order they are captured
+#[cfg(target_arch = "aarch64")]
+const GPR_NAMES: [&str; 32] = [
+        "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10",
+        "x11", "x12", "x13", "x14", "x15", "x16", "x17", "x18", "x19", "x20",
+        "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29", "x30",
+        "sp",
+];
+
+/// Index of the frame pointer in [`GPR_NAMES`]
+#[cfg(target_arch = "aarch64")]
+const FRAME_POINTER: usize = 29;
+
+/// Names of the control registers, in the order they are captured. They are
+/// the ones of the exception level we run at.
+#[cfg(target_arch = "aarch64")]
+const CONTROL_NAMES: [&str; 5] = ["el", "elr", "esr", "far", "ttbr0"];
+
+/// Names of the general purpose registers, in the order they are captured
+#[cfg(target_arch = "riscv64")]
+const GPR_NAMES: [&str; 31] = [
+        "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2",
+        "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8",
+        "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+/// Index of the frame pointer in [`GPR_NAMES`]
+#[cfg(target_arch = "riscv64")]
+const FRAME_POINTER: usize = 7;
+
+/// Names of the control registers, in the order they are captured
+#[cfg(target_arch = "riscv64")]
+const CONTROL_NAMES: [&str; 5] = ["sstatus", "sepc", "scause", "stval", "satp"];
+
+/// A snapshot of the registers of the current processor
+pub struct Registers {
+    /// General purpose registers, in the order of [`GPR_NAMES`]
+    gpr: [u64; GPR_NAMES.len()],
+
+    /// Control registers, in the order of [`CONTROL_NAMES`]
+    control: [u64; CONTROL_NAMES.len()],
+}
+
+impl Registers {
+    /// Capture the registers of the current processor. This is always
+    /// inlined, so the registers are the ones of the caller.
+    ///
+    /// # Returns
+    ///
+    /// The [`Registers`], one of the general purpose registers holds the
+    /// address they are stored at rather than its value in the caller
+    ///
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut regs = Registers {
+            gpr:     [0; GPR_NAMES.len()],
+            control: [0; CONTROL_NAMES.len()],
+        };
+
+        unsafe {
+            capture_gprs(&mut regs.gpr);
+            capture_control(&mut regs.control);
+        }
+
+        regs
+    }
+
+    /// Build a snapshot out of registers saved on exception entry. The
+    /// control registers other than the flags are captured now, which in an
+    /// exception handler still describe the exception.
+    ///
+    /// # Parameters
+    ///
+    /// * `gpr`    - The saved registers, in the order of [`GPR_NAMES`]
+    /// * `rflags` - The saved flags
+    ///
+    /// # Returns
+    ///
+    /// The [`Registers`]
+    ///
+    #[cfg(target_arch = "x86_64")]
+    pub fn from_exception(gpr: [u64; GPR_NAMES.len()], rflags: u64) -> Self {
+        let mut regs = Registers { gpr, control: [0; CONTROL_NAMES.len()] };
+        unsafe { capture_control(&mut regs.control); }
+        regs.control[0] = rflags;
+        regs
+    }
+
+    /// Build a snapshot out of registers saved on exception entry. The
+    /// control registers are captured now, which in an exception handler
+    /// still describe the exception.
+    ///
+    /// # Parameters
+    ///
+    /// * `gpr` - The saved registers, in the order of [`GPR_NAMES`]
+    ///
+    /// # Returns
+    ///
+    /// The [`Registers`]
+    ///
+    #[cfg(target_arch = "aarch64")]
+    pub fn from_exception(gpr: [u64; GPR_NAMES.len()]) -> Self {
+        let mut regs = Registers { gpr, control: [0; CONTROL_NAMES.len()] };
+        unsafe { capture_control(&mut regs.control); }
+        regs
+    }
+
+    /// Get the frame pointer
+    ///
+    /// # Returns
+    ///
+    /// The frame pointer of the function the registers were captured in
+    ///
+    pub fn frame_pointer(&self) -> u64 {
+        self.gpr[FRAME_POINTER]
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /// Number of registers printed on one line
+        const PER_LINE: usize = 3;
+
+        let regs = GPR_NAMES.iter().zip(&self.gpr)
+            .chain(CONTROL_NAMES.iter().zip(&self.control));
+        for (idx, (name, val)) in regs.enumerate() {
+            write!(f, "{:>7} {:016x}", name, val)?;
+            let end_of_line = idx % PER_LINE == PER_LINE - 1;
+            f.write_str(if end_of_line { "\n" } else { " " })?;
+        }
+
+        // Finish the last line if it is not full
+        if (GPR_NAMES.len() + CONTROL_NAMES.len()) % PER_LINE != 0 {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Store the general purpose registers
+///
+/// # Parameters
+///
+/// * `gpr` - Where to store the registers, in the order of [`GPR_NAMES`]
+///
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn capture_gprs(gpr: &mut [u64; GPR_NAMES.len()]) {
+    asm!(
+            "mov [{0} + 0x00], rax",
+            "mov [{0} + 0x08], rbx",
+            "mov [{0} + 0x10], rcx",
+            "mov [{0} + 0x18], rdx",
+            "mov [{0} + 0x20], rsi",
+            "mov [{0} + 0x28], rdi",
+            "mov [{0} + 0x30], rbp",
+            "mov [{0} + 0x38], rsp",
+            "mov [{0} + 0x40], r8",
+            "mov [{0} + 0x48], r9",
+            "mov [{0} + 0x50], r10",
+            "mov [{0} + 0x58], r11",
+            "mov [{0} + 0x60], r12",
+            "mov [{0} + 0x68], r13",
+            "mov [{0} + 0x70], r14",
+            "mov [{0} + 0x78], r15",
+        in(reg) gpr.as_mut_ptr(),
+        options(nostack, preserves_flags));
+}
+
+/// Store the control registers
+///
+/// # Parameters
+///
+/// * `control` - Where to store the registers, in the order of
+///               [`CONTROL_NAMES`]
+///
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn capture_control(control: &mut [u64; CONTROL_NAMES.len()]) {
+    asm!("pushfq", "pop {}", out(reg) control[0], options(preserves_flags));
+    asm!("mov {}, cr0", out(reg) control[1],
+        options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr2", out(reg) control[2],
+        options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr3", out(reg) control[3],
+        options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr4", out(reg) control[4],
+        options(nomem, nostack, preserves_flags));
+}
+
+/// Store the general purpose registers
+///
+/// # Parameters
+///
+/// * `gpr` - Where to store the registers, in the order of [`GPR_NAMES`]
+///
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+unsafe fn capture_gprs(gpr: &mut [u64; GPR_NAMES.len()]) {
+    asm!(
+            "stp x0, x1, [{0}, #0x00]",
+            "stp x2, x3, [{0}, #0x10]",
+            "stp x4, x5, [{0}, #0x20]",
+            "stp x6, x7, [{0}, #0x30]",
+            "stp x8, x9, [{0}, #0x40]",
+            "stp x10, x11, [{0}, #0x50]",
+            "stp x12, x13, [{0}, #0x60]",
+            "stp x14, x15, [{0}, #0x70]",
+            "stp x16, x17, [{0}, #0x80]",
+            "stp x18, x19, [{0}, #0x90]",
+            "stp x20, x21, [{0}, #0xa0]",
+            "stp x22, x23, [{0}, #0xb0]",
+            "stp x24, x25, [{0}, #0xc0]",
+            "stp x26, x27, [{0}, #0xd0]",
+            "stp x28, x29, [{0}, #0xe0]",
+            "str x30, [{0}, #0xf0]",
+            "mov {1}, sp",
+            "str {1}, [{0}, #0xf8]",
+        in(reg) gpr.as_mut_ptr(), out(reg) _,
+        options(nostack, preserves_flags));
+}
+
+/// Store the control registers
+///
+/// # Parameters
+///
+/// * `control` - Where to store the registers, in the order of
+///               [`CONTROL_NAMES`]
+///
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+unsafe fn capture_control(control: &mut [u64; CONTROL_NAMES.len()]) {
+    asm!("mrs {}, CurrentEL", out(reg) control[0],
+        options(nomem, nostack, preserves_flags));
+    control[0] = (control[0] >> 2) & 3;
+
+    // The registers of other exception levels are not accessible
+    if control[0] == 2 {
+        asm!("mrs {}, elr_el2", out(reg) control[1],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, esr_el2", out(reg) control[2],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, far_el2", out(reg) control[3],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, ttbr0_el2", out(reg) control[4],
+            options(nomem, nostack, preserves_flags));
+    } else {
+        asm!("mrs {}, elr_el1", out(reg) control[1],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, esr_el1", out(reg) control[2],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, far_el1", out(reg) control[3],
+            options(nomem, nostack, preserves_flags));
+        asm!("mrs {}, ttbr0_el1", out(reg) control[4],
+            options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Store the general purpose registers
+///
+/// # Parameters
+///
+/// * `gpr` - Where to store the registers, in the order of [`GPR_NAMES`]
+///
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn capture_gprs(gpr: &mut [u64; GPR_NAMES.len()]) {
+    asm!(
+            "sd ra, 0x00({0})",
+            "sd sp, 0x08({0})",
+            "sd gp, 0x10({0})",
+            "sd tp, 0x18({0})",
+            "sd t0, 0x20({0})",
+            "sd t1, 0x28({0})",
+            "sd t2, 0x30({0})",
+            "sd s0, 0x38({0})",
+            "sd s1, 0x40({0})",
+            "sd a0, 0x48({0})",
+            "sd a1, 0x50({0})",
+            "sd a2, 0x58({0})",
+            "sd a3, 0x60({0})",
+            "sd a4, 0x68({0})",
+            "sd a5, 0x70({0})",
+            "sd a6, 0x78({0})",
+            "sd a7, 0x80({0})",
+            "sd s2, 0x88({0})",
+            "sd s3, 0x90({0})",
+            "sd s4, 0x98({0})",
+            "sd s5, 0xa0({0})",
+            "sd s6, 0xa8({0})",
+            "sd s7, 0xb0({0})",
+            "sd s8, 0xb8({0})",
+            "sd s9, 0xc0({0})",
+            "sd s10, 0xc8({0})",
+            "sd s11, 0xd0({0})",
+            "sd t3, 0xd8({0})",
+            "sd t4, 0xe0({0})",
+            "sd t5, 0xe8({0})",
+            "sd t6, 0xf0({0})",
+        in(reg) gpr.as_mut_ptr(),
+        options(nostack, preserves_flags));
+}
+
+/// Store the control registers
+///
+/// # Parameters
+///
+/// * `control` - Where to store the registers, in the order of
+///               [`CONTROL_NAMES`]
+///
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+unsafe fn capture_control(control: &mut [u64; CONTROL_NAMES.len()]) {
+    asm!("csrr {}, sstatus", out(reg) control[0],
+        options(nomem, nostack, preserves_flags));
+    asm!("csrr {}, sepc", out(reg) control[1],
+        options(nomem, nostack, preserves_flags));
+    asm!("csrr {}, scause", out(reg) control[2],
+        options(nomem, nostack, preserves_flags));
+    asm!("csrr {}, stval", out(reg) control[3],
+        options(nomem, nostack, preserves_flags));
+    asm!("csrr {}, satp", out(reg) control[4],
+        options(nomem, nostack, preserves_flags));
+}
@@ -0,0 +1,356 @@
+//! The RISC-V Supervisor Binary Interface, through which the firmware below
+//! us provides what supervisor mode can't do itself
+//!
+//! It gives riscv64 a console when ACPI reports no UART we can drive, using
+//! the debug console extension or the legacy console calls, a timer through
+//! the TIME extension, and the hart state management extension to start the
+//! secondary harts with.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// A `Result` type which wraps an SBI error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from SBI calls
+#[derive(Debug)]
+pub enum Error {
+    /// The SBI does not implement an extension we need, with this ID
+    NotSupported(u64),
+
+    /// An SBI call returned this error code
+    Call(i64),
+}
+
+/// Extension ID of the base extension
+const EXT_BASE: u64 = 0x10;
+
+/// Extension ID of the TIME extension
+const EXT_TIME: u64 = 0x5449_4d45;
+
+/// Extension ID of the debug console extension
+const EXT_DBCN: u64 = 0x4442_434e;
+
+/// Extension ID of the hart state management extension
+const EXT_HSM: u64 = 0x48_534d;
+
+/// Extension ID of the legacy `sbi_set_timer`
+const EXT_LEGACY_SET_TIMER: u64 = 0x00;
+
+/// Extension ID of the legacy `sbi_console_putchar`
+const EXT_LEGACY_PUTCHAR: u64 = 0x01;
+
+/// Extension ID of the legacy `sbi_console_getchar`
+const EXT_LEGACY_GETCHAR: u64 = 0x02;
+
+/// Function ID of `sbi_get_spec_version` in the base extension
+const BASE_GET_SPEC_VERSION: u64 = 0;
+
+/// Function ID of `sbi_probe_extension` in the base extension
+const BASE_PROBE_EXTENSION: u64 = 3;
+
+/// Function ID of `sbi_set_timer` in the TIME extension
+const TIME_SET_TIMER: u64 = 0;
+
+/// Function ID of `sbi_debug_console_read` in the debug console extension
+const DBCN_READ: u64 = 1;
+
+/// Function ID of `sbi_debug_console_write_byte` in the debug console
+/// extension
+const DBCN_WRITE_BYTE: u64 = 2;
+
+/// Function ID of `sbi_hart_start` in the hart state management extension
+const HSM_HART_START: u64 = 0;
+
+/// Function ID of `sbi_hart_get_status` in the hart state management
+/// extension
+const HSM_HART_GET_STATUS: u64 = 2;
+
+/// No console, the [`CONSOLE`] before [`enable_console`]
+const CONSOLE_NONE: u8 = 0;
+
+/// The console is driven through the debug console extension
+const CONSOLE_DBCN: u8 = 1;
+
+/// The console is driven through the legacy console calls
+const CONSOLE_LEGACY: u8 = 2;
+
+/// How the console is driven, one of the `CONSOLE_` constants
+static CONSOLE: AtomicU8 = AtomicU8::new(CONSOLE_NONE);
+
+/// Set if the TIME extension is implemented, probed by [`set_timer`]
+static HAS_TIME: AtomicBool = AtomicBool::new(false);
+
+/// Set once the TIME extension has been probed for
+static TIME_PROBED: AtomicBool = AtomicBool::new(false);
+
+/// Call an SBI function
+///
+/// # Parameters
+///
+/// * `ext`  - The extension ID
+/// * `func` - The function ID within the extension
+/// * `args` - The arguments of the function
+///
+/// # Returns
+///
+/// The value the function returned, on error [`Error`]
+///
+unsafe fn call(ext: u64, func: u64, args: [u64; 3]) -> Result<u64> {
+    let (error, value): (i64, u64);
+    asm!("ecall", inlateout("a0") args[0] => error,
+        inlateout("a1") args[1] => value, in("a2") args[2], in("a6") func,
+        in("a7") ext, options(nostack));
+    if error != 0 { return Err(Error::Call(error)); }
+    Ok(value)
+}
+
+/// Call a legacy SBI function, which only returns a value in `a0`
+///
+/// # Parameters
+///
+/// * `ext` - The extension ID, which names the function
+/// * `arg` - The argument of the function
+///
+/// # Returns
+///
+/// The value the function returned
+///
+unsafe fn call_legacy(ext: u64, arg: u64) -> i64 {
+    let ret: i64;
+    asm!("ecall", inlateout("a0") arg => ret, lateout("a1") _,
+        in("a7") ext, options(nostack));
+    ret
+}
+
+/// Check whether the SBI implements an extension
+///
+/// # Parameters
+///
+/// * `ext` - The extension ID
+///
+/// # Returns
+///
+/// `true` if the extension is implemented
+///
+fn probe(ext: u64) -> bool {
+    unsafe { call(EXT_BASE, BASE_PROBE_EXTENSION, [ext, 0, 0]) }
+        .map_or(false, |x| x != 0)
+}
+
+/// Get the version of the SBI specification the SBI implements
+///
+/// # Returns
+///
+/// The major and minor version, on error [`Error`]
+///
+pub fn spec_version() -> Result<(u64, u64)> {
+    let version = unsafe { call(EXT_BASE, BASE_GET_SPEC_VERSION, [0; 3])? };
+    Ok(((version >> 24) & 0x7f, version & 0xff_ffff))
+}
+
+/// Start printing to the SBI console, preferring the debug console
+/// extension over the legacy calls the SBI may have dropped
+///
+/// # Returns
+///
+/// `()` once the console can be written to, on error [`Error`] if the SBI
+/// has no console
+///
+pub fn enable_console() -> Result<()> {
+    let console = if probe(EXT_DBCN) {
+        CONSOLE_DBCN
+    } else if probe(EXT_LEGACY_PUTCHAR) {
+        CONSOLE_LEGACY
+    } else {
+        return Err(Error::NotSupported(EXT_DBCN));
+    };
+    CONSOLE.store(console, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Check whether the SBI console is in use
+///
+/// # Returns
+///
+/// `true` once [`enable_console`] succeeded
+///
+pub fn console_enabled() -> bool {
+    CONSOLE.load(Ordering::SeqCst) != CONSOLE_NONE
+}
+
+/// Write a byte to the SBI console
+///
+/// # Parameters
+///
+/// * `byte` - The byte to write
+///
+/// # Returns
+///
+/// `()` once the byte is written, on error [`Error`]
+///
+fn write_byte(byte: u8) -> Result<()> {
+    // Write a CR prior to all LFs
+    if byte == b'\n' { write_byte(b'\r')?; }
+
+    unsafe {
+        match CONSOLE.load(Ordering::SeqCst) {
+            CONSOLE_DBCN => {
+                call(EXT_DBCN, DBCN_WRITE_BYTE, [byte as u64, 0, 0])?;
+            }
+            CONSOLE_LEGACY => {
+                let ret = call_legacy(EXT_LEGACY_PUTCHAR, byte as u64);
+                if ret != 0 { return Err(Error::Call(ret)); }
+            }
+            _ => return Err(Error::NotSupported(EXT_DBCN)),
+        }
+    }
+    Ok(())
+}
+
+/// Write a slice of bytes to the SBI console
+///
+/// # Parameters
+///
+/// * `bytes` - The slice of bytes to write
+///
+/// # Returns
+///
+/// `()` on success, on error [`Error`]
+///
+pub fn write(bytes: &[u8]) -> Result<()> {
+    for &byte in bytes {
+        write_byte(byte)?;
+    }
+    Ok(())
+}
+
+/// Read a byte from the SBI console
+///
+/// # Returns
+///
+/// On success, returns the byte which was read or `None` if no byte was
+/// available. On error [`Error`]
+///
+pub fn read_byte() -> Result<Option<u8>> {
+    unsafe {
+        match CONSOLE.load(Ordering::SeqCst) {
+            CONSOLE_DBCN => {
+                // The buffer is passed by physical address, which is the
+                // address while we run identity mapped
+                let mut byte = 0u8;
+                let addr = &mut byte as *mut u8 as u64;
+                let read = call(EXT_DBCN, DBCN_READ, [1, addr, 0])?;
+                Ok((read == 1).then_some(byte))
+            }
+            CONSOLE_LEGACY => {
+                let ret = call_legacy(EXT_LEGACY_GETCHAR, 0);
+                Ok((ret >= 0).then_some(ret as u8))
+            }
+            _ => Err(Error::NotSupported(EXT_DBCN)),
+        }
+    }
+}
+
+/// Program the supervisor timer to fire at a point in time. Firing makes the
+/// timer interrupt pending, until the timer is programmed again.
+///
+/// # Parameters
+///
+/// * `deadline` - The value of the `time` counter to fire at, `!0` to never
+///                fire
+///
+/// # Returns
+///
+/// `()` once the timer is programmed, on error [`Error`]
+///
+/// # Safety
+///
+/// The firmware may be using the timer while boot services run
+///
+pub unsafe fn set_timer(deadline: u64) -> Result<()> {
+    if !TIME_PROBED.load(Ordering::SeqCst) {
+        HAS_TIME.store(probe(EXT_TIME), Ordering::SeqCst);
+        TIME_PROBED.store(true, Ordering::SeqCst);
+    }
+
+    if HAS_TIME.load(Ordering::SeqCst) {
+        call(EXT_TIME, TIME_SET_TIMER, [deadline, 0, 0]).map(|_| ())
+    } else {
+        match call_legacy(EXT_LEGACY_SET_TIMER, deadline) {
+            0   => Ok(()),
+            ret => Err(Error::Call(ret)),
+        }
+    }
+}
+
+/// The state of a hart, as reported by [`hart_status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HartState {
+    /// The hart is running
+    Started,
+
+    /// The hart is not running, and can be started with [`hart_start`]
+    Stopped,
+
+    /// The hart is on its way to [`HartState::Started`]
+    StartPending,
+
+    /// The hart is on its way to [`HartState::Stopped`]
+    StopPending,
+
+    /// The hart is suspended, or on its way in or out of suspension
+    Suspended,
+}
+
+/// Check whether the SBI can start harts
+///
+/// # Returns
+///
+/// `true` if the hart state management extension is implemented
+///
+pub fn hsm_supported() -> bool {
+    probe(EXT_HSM)
+}
+
+/// Get the state of a hart
+///
+/// # Parameters
+///
+/// * `hart_id` - The hart ID of the hart
+///
+/// # Returns
+///
+/// The [`HartState`] of the hart, on error [`Error`]
+///
+pub fn hart_status(hart_id: u64) -> Result<HartState> {
+    match unsafe { call(EXT_HSM, HSM_HART_GET_STATUS, [hart_id, 0, 0])? } {
+        0 => Ok(HartState::Started),
+        1 => Ok(HartState::Stopped),
+        2 => Ok(HartState::StartPending),
+        3 => Ok(HartState::StopPending),
+        _ => Ok(HartState::Suspended),
+    }
+}
+
+/// Start a stopped hart. It enters supervisor mode at `start_addr` with
+/// translation off, its hart ID in `a0` and `opaque` in `a1`.
+///
+/// # Parameters
+///
+/// * `hart_id`    - The hart ID of the hart to start
+/// * `start_addr` - The physical address the hart starts at
+/// * `opaque`     - The value the hart is started with in `a1`
+///
+/// # Returns
+///
+/// `()` once the hart is on its way, on error [`Error`]
+///
+/// # Safety
+///
+/// The code at `start_addr` must run with translation off, and the hart must
+/// not be used by anything else.
+///
+pub unsafe fn hart_start(hart_id: u64, start_addr: u64, opaque: u64)
+        -> Result<()> {
+    call(EXT_HSM, HSM_HART_START, [hart_id, start_addr, opaque]).map(|_| ())
+}
@@ -0,0 +1,391 @@
+//! Starting the application processors of x86_64 machines with the
+//! INIT-SIPI-SIPI sequence
+//!
+//! A started processor runs a real mode trampoline copied to low memory,
+//! which takes it straight to long mode on the page table the firmware left
+//! us. It then checks in on its own stack and halts, until the kernel starts
+//! it again.
+
+use crate::acpi::Madt;
+use crate::apic::{self, Destination, Ipi, LocalApic};
+use crate::efi;
+use crate::handshake;
+use crate::mm::page_alloc::Limit;
+use crate::time;
+
+/// A `Result` type which wraps an application processor startup error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from starting the application processors
+#[derive(Debug)]
+pub enum Error {
+    /// The page below 1 MiB for the trampoline could not be allocated
+    AllocateTrampoline(efi::Error),
+
+    /// The stacks could not be allocated
+    AllocateStacks(efi::Error),
+
+    /// The page table in use is above 4 GiB, so it can't be loaded from real
+    /// mode
+    PageTableTooHigh(u64),
+
+    /// Sending an interprocessor interrupt failed
+    Apic(apic::Error),
+
+    /// There is no calibrated counter to time the startup sequence with
+    NoTimer,
+}
+
+/// Size (in bytes) of the stack of each application processor
+const AP_STACK_SIZE: usize = 16 * 1024;
+
+/// Number of microseconds to wait after the INIT
+const INIT_DELAY_US: u64 = 10_000;
+
+/// Number of microseconds to wait for a processor to check in after each
+/// SIPI
+const SIPI_TIMEOUT_US: u64 = 200;
+
+/// Number of microseconds to wait for a processor to check in after the
+/// second SIPI, before giving up on it
+const STARTUP_TIMEOUT_US: u64 = 100_000;
+
+global_asm!(r#"
+    .balign 16
+    .global ap_trampoline_start
+ap_trampoline_start:
+    .code16
+    jmp 3f
+
+    // The data comes first, so real mode code can use fixed offsets for it.
+    // Null, 64-bit code and data segments at offset 8.
+    .balign 8
+    .global ap_trampoline_gdt
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff
+    .quad 0x00cf92000000ffff
+ap_trampoline_gdt_end:
+
+    // Pointer to the GDT at offset 32, the base is patched to its physical
+    // address
+    .global ap_trampoline_gdtr
+ap_trampoline_gdtr:
+    .word ap_trampoline_gdt_end - ap_trampoline_gdt - 1
+    .long 0
+
+    // Far pointer to the long mode code at offset 40, the offset is patched
+    // to the physical address of `ap_trampoline_long`
+    .balign 8
+    .global ap_trampoline_far
+ap_trampoline_far:
+    .long 0
+    .word 0x08
+
+    // Control registers at offsets 48 to 63
+    .balign 8
+    .global ap_trampoline_cr0
+ap_trampoline_cr0:
+    .long 0
+    .global ap_trampoline_cr3
+ap_trampoline_cr3:
+    .long 0
+    .global ap_trampoline_cr4
+ap_trampoline_cr4:
+    .long 0
+    .global ap_trampoline_efer
+ap_trampoline_efer:
+    .long 0
+
+    // Only used from long mode, so the offsets don't matter
+    .global ap_trampoline_stack
+ap_trampoline_stack:
+    .quad 0
+    .global ap_trampoline_entry
+ap_trampoline_entry:
+    .quad 0
+
+3:
+    cli
+    cld
+
+    // The trampoline starts at offset zero of our code segment, use the same
+    // segment for the data
+    mov ax, cs
+    mov ds, ax
+
+    lgdt [32]
+
+    // Paging is set up like on the BSP, but without the PCID bit of CR4
+    // which can only be set in long mode
+    mov eax, dword ptr [56]
+    mov cr4, eax
+    mov eax, dword ptr [52]
+    mov cr3, eax
+    mov ecx, 0xc0000080
+    mov eax, dword ptr [60]
+    xor edx, edx
+    wrmsr
+
+    // Enabling protection and paging together takes us straight to long
+    // mode, from which a far jump loads the 64-bit code segment
+    mov eax, dword ptr [48]
+    mov cr0, eax
+    jmp fword ptr [40]
+
+    .code64
+    .global ap_trampoline_long
+ap_trampoline_long:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    xor eax, eax
+    mov fs, ax
+    mov gs, ax
+
+    // Call the entry point on the stack we have been given, leaving it
+    // space to spill its register arguments into
+    mov rsp, qword ptr [rip + ap_trampoline_stack]
+    mov rax, qword ptr [rip + ap_trampoline_entry]
+    sub rsp, 32
+    call rax
+2:
+    cli
+    hlt
+    jmp 2b
+
+    .global ap_trampoline_end
+ap_trampoline_end:
+"#);
+
+extern {
+    /// Start of the trampoline
+    static ap_trampoline_start: u8;
+
+    /// End of the trampoline
+    static ap_trampoline_end: u8;
+
+    /// The long mode code
+    static ap_trampoline_long: u8;
+
+    /// The GDT
+    static ap_trampoline_gdt: u8;
+
+    /// Pointer to the GDT, with the base to patch
+    static ap_trampoline_gdtr: u8;
+
+    /// Far pointer to the long mode code, with the offset to patch
+    static ap_trampoline_far: u8;
+
+    /// Value to load into CR0
+    static ap_trampoline_cr0: u8;
+
+    /// Value to load into CR3
+    static ap_trampoline_cr3: u8;
+
+    /// Value to load into CR4
+    static ap_trampoline_cr4: u8;
+
+    /// Value to load into the low half of `IA32_EFER`
+    static ap_trampoline_efer: u8;
+
+    /// Top of the stack of the processor being started
+    static ap_trampoline_stack: u8;
+
+    /// Address of the function the processor calls
+    static ap_trampoline_entry: u8;
+}
+
+/// Low memory holding the trampoline, and stacks for the application
+/// processors to be started with
+pub struct Trampoline {
+    /// The page below 1 MiB the trampoline has been copied to
+    page: &'static mut [u8],
+
+    /// A stack of [`AP_STACK_SIZE`] bytes for each application processor
+    stacks: &'static mut [u8],
+}
+
+/// Where an application processor goes once it is in long mode, it checks in
+/// and halts
+extern fn ap_entry() -> ! {
+    handshake::check_in();
+
+    loop {
+        unsafe { asm!("cli", "hlt", options(nomem, nostack)); }
+    }
+}
+
+/// Get the offset of a label from the start of the trampoline
+///
+/// # Parameters
+///
+/// * `label` - The label in the trampoline
+///
+/// # Returns
+///
+/// The offset (in bytes) of `label`
+///
+fn offset(label: &u8) -> usize {
+    label as *const u8 as usize -
+        unsafe { &ap_trampoline_start as *const u8 as usize }
+}
+
+/// Get the application processors which can be started
+///
+/// # Parameters
+///
+/// * `madt` - The MADT listing the processors
+/// * `lapic` - Our own local APIC
+///
+/// # Returns
+///
+/// An iterator over the APIC IDs of the enabled application processors
+///
+fn aps<'a>(madt: &'a Madt, lapic: &'a LocalApic)
+        -> impl Iterator<Item = u32> + 'a {
+    let own = lapic.id();
+    madt.processors().filter(|x| x.enabled).map(|x| x.apic_id)
+        // Only x2APIC mode can send interrupts to IDs above 255
+        .filter(move |&id| id != own && (lapic.is_x2apic() || id <= 0xff))
+}
+
+/// Set up the trampoline and stacks to start the application processors
+/// with, while boot services can still allocate memory
+///
+/// # Parameters
+///
+/// * `madt`  - The MADT listing the processors
+/// * `lapic` - Our own local APIC
+///
+/// # Returns
+///
+/// The [`Trampoline`] to pass to [`start_aps`], on error [`Error`]
+///
+pub fn prepare(madt: &Madt, lapic: &LocalApic) -> Result<Trampoline> {
+    // The SIPI vector is the page number of the trampoline, so it has to be
+    // in the first 1 MiB
+    let page = Limit::Below1MiB.alloc_efi(4096)
+        .map_err(Error::AllocateTrampoline)?;
+
+    // Only x2APIC mode can send interrupts to IDs above 255
+    if !lapic.is_x2apic() {
+        for x in madt.processors().filter(|x| x.enabled && x.apic_id > 0xff) {
+            log_warn!("Processor {:#x} is out of reach without x2APIC mode, \
+                       not starting it\n", x.apic_id);
+        }
+    }
+
+    let stacks = match aps(madt, lapic).count() * AP_STACK_SIZE {
+        0    => &mut [][..],
+        size => efi::allocate_pages(size).map_err(Error::AllocateStacks)?,
+    };
+
+    // Copy the trampoline to its page, and point it at itself
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len   = offset(&ap_trampoline_end);
+        page[..len].copy_from_slice(core::slice::from_raw_parts(start, len));
+
+        let base = page.as_ptr() as usize;
+        core::ptr::write_unaligned(page.as_mut_ptr()
+            .add(offset(&ap_trampoline_gdtr) + 2) as *mut u32,
+            (base + offset(&ap_trampoline_gdt)) as u32);
+        core::ptr::write_unaligned(page.as_mut_ptr()
+            .add(offset(&ap_trampoline_far)) as *mut u32,
+            (base + offset(&ap_trampoline_long)) as u32);
+    }
+
+    Ok(Trampoline { page, stacks })
+}
+
+/// Start every enabled application processor in the MADT, one at a time
+///
+/// # Parameters
+///
+/// * `trampoline` - The trampoline from [`prepare`]
+/// * `madt`       - The MADT listing the processors
+/// * `lapic`      - Our own local APIC
+///
+/// # Returns
+///
+/// The number of application processors which checked in, on error
+/// [`Error`]. Processors which don't check in in time are skipped.
+///
+/// # Safety
+///
+/// The application processors must not be running anything, the page table
+/// in use must identity map the trampoline and the bootloader and stay in
+/// place for as long as they are starting.
+///
+pub unsafe fn start_aps(trampoline: &mut Trampoline, madt: &Madt,
+                        lapic: &LocalApic) -> Result<usize> {
+    /// `IA32_EFER` bits which are copied from our own, the NX bit keeps our
+    /// page table entries valid
+    const EFER_NXE: u32 = 1 << 11;
+
+    /// `IA32_EFER` bit which enables long mode
+    const EFER_LME: u32 = 1 << 8;
+
+    /// CR4 bit which enables PCIDs, which can't be set outside long mode
+    const CR4_PCIDE: u64 = 1 << 17;
+
+    // Use the same paging setup as we run with
+    let (cr0, cr3, cr4): (u64, u64, u64);
+    asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    let efer: u32;
+    asm!("rdmsr", in("ecx") 0xc000_0080u32, out("eax") efer, out("edx") _,
+        options(nomem, nostack, preserves_flags));
+    if cr3 > u32::MAX as u64 { return Err(Error::PageTableTooHigh(cr3)); }
+
+    let page  = trampoline.page.as_mut_ptr();
+    let patch = |label: &u8, val: u64| {
+        core::ptr::write_unaligned(page.add(offset(label)) as *mut u64, val);
+    };
+    patch(&ap_trampoline_entry, ap_entry as usize as u64);
+    for (label, val) in [
+        (&ap_trampoline_cr0, cr0 as u32),
+        (&ap_trampoline_cr3, cr3 as u32),
+        (&ap_trampoline_cr4, (cr4 & !CR4_PCIDE) as u32),
+        (&ap_trampoline_efer, (efer & EFER_NXE) | EFER_LME),
+    ].iter() {
+        core::ptr::write_unaligned(page.add(offset(label)) as *mut u32, *val);
+    }
+
+    handshake::boot_processor(lapic.id() as u64);
+
+    let vector = (page as usize >> 12) as u8;
+    let mut online = 0;
+    for (num, (id, stack)) in aps(madt, lapic)
+            .zip(trampoline.stacks.chunks_exact_mut(AP_STACK_SIZE))
+            .enumerate().map(|(num, ap)| (num + 1, ap)) {
+        patch(&ap_trampoline_stack,
+              stack.as_mut_ptr().add(stack.len()) as u64);
+
+        // The second SIPI is only sent if the processor missed the first
+        handshake::announce(num);
+        lapic.send_ipi(Destination::Apic(id), Ipi::Init).map_err(Error::Apic)?;
+        time::busy_wait_us(INIT_DELAY_US).ok_or(Error::NoTimer)?;
+        let mut started = false;
+        for timeout in &[SIPI_TIMEOUT_US, STARTUP_TIMEOUT_US] {
+            lapic.send_ipi(Destination::Apic(id), Ipi::Startup(vector))
+                .map_err(Error::Apic)?;
+            started = handshake::wait(num, *timeout, || false)
+                .ok_or(Error::NoTimer)?;
+            if started { break; }
+        }
+
+        if started {
+            online += 1;
+            handshake::finish(num, id as u64, boot_info::CORE_STATE_ONLINE);
+        } else {
+            handshake::finish(num, id as u64,
+                              boot_info::CORE_STATE_TIMED_OUT);
+        }
+    }
+
+    Ok(online)
+}
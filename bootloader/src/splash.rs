@@ -0,0 +1,122 @@
+//! A boot splash screen, which shows a logo and a progress bar driven by the
+//! milestones of the boot. While it is shown the EFI console and the
+//! framebuffer console are not drawn, output only goes to a serial port.
+
+use fbcon::Framebuffer;
+use fbcon::bmp::Bmp;
+
+use crate::print::Sink;
+
+/// Color of the progress bar outline, as `0xRRGGBB`
+const BAR_OUTLINE: u32 = 0x555555;
+
+/// Color of the filled part of the progress bar, as `0xRRGGBB`
+const BAR_FILL: u32 = 0xaaaaaa;
+
+/// Height of the progress bar in pixels
+const BAR_HEIGHT: u32 = 8;
+
+/// Milestones of the boot, in the order they are reached
+#[derive(Clone, Copy, Debug)]
+pub enum Milestone {
+    /// The kernel has been read
+    KernelRead,
+
+    /// Everything has been measured into the TPM
+    Measured,
+
+    /// Boot services have been exited
+    ExitedBootServices,
+
+    /// The kernel segments have been loaded
+    KernelLoaded,
+
+    /// We are about to jump to the kernel
+    Handoff,
+}
+
+/// Number of milestones, the bar is full after the last one
+const MILESTONES: u32 = Milestone::Handoff as u32 + 1;
+
+/// The framebuffer the splash screen is shown on, if it is shown
+static mut SPLASH: Option<Framebuffer> = None;
+
+/// Check whether the splash screen is shown
+pub fn active() -> bool {
+    unsafe { SPLASH.is_some() }
+}
+
+/// Get the position and size of the progress bar, including its outline
+fn bar(fb: &Framebuffer) -> (u32, u32, u32, u32) {
+    let width = fb.width / 3;
+    ((fb.width - width) / 2, fb.height / 4 * 3, width, BAR_HEIGHT)
+}
+
+/// Clear the screen and show the splash screen
+///
+/// # Parameters
+///
+/// * `fb`   - The framebuffer to draw on
+/// * `logo` - The BMP image to show, with the position of its top left
+///            corner. Without a position the image is centered.
+///
+/// # Returns
+///
+/// `()` on success, on error [`fbcon::Error`]
+///
+/// # Safety
+///
+/// `fb` must describe memory which is mapped and writable and which is not
+/// used for anything else. This function must be called in a single threaded
+/// environment as it initializes a mutable static without locks.
+///
+pub unsafe fn init(fb: Framebuffer, logo: Option<(&[u8], Option<(u32, u32)>)>)
+        -> fbcon::Result<()> {
+    fb.validate()?;
+
+    // Decode the logo before touching the screen
+    let logo = logo.map(|(image, pos)| {
+        Bmp::parse(image).map(|bmp| (bmp, pos))
+    }).transpose()?;
+
+    // Draw the logo onto a blank screen
+    fb.fill_rect(0, 0, fb.width, fb.height, 0);
+    if let Some((bmp, pos)) = logo {
+        let (x, y) = pos.unwrap_or((
+            fb.width.saturating_sub(bmp.width()) / 2,
+            fb.height.saturating_sub(bmp.height()) / 2));
+        fb.draw_bmp(&bmp, x, y);
+    }
+
+    // Draw the outline of the progress bar
+    let (x, y, width, height) = bar(&fb);
+    fb.fill_rect(x, y, width, height, BAR_OUTLINE);
+    fb.fill_rect(x + 1, y + 1, width.saturating_sub(2),
+                 height.saturating_sub(2), 0);
+
+    // Keep the consoles from drawing over it
+    crate::print::set_sink(Sink::EfiConsole, false);
+    crate::print::set_sink(Sink::Framebuffer, false);
+
+    SPLASH = Some(fb);
+    Ok(())
+}
+
+/// Move the progress bar to a milestone, does nothing if the splash screen is
+/// not shown
+///
+/// # Parameters
+///
+/// * `milestone` - The milestone which has been reached
+///
+pub fn progress(milestone: Milestone) {
+    if let Some(fb) = unsafe { SPLASH } {
+        let (x, y, width, height) = bar(&fb);
+        let filled = width.saturating_sub(2) * (milestone as u32 + 1) /
+            MILESTONES;
+        unsafe {
+            fb.fill_rect(x + 1, y + 1, filled, height.saturating_sub(2),
+                         BAR_FILL);
+        }
+    }
+}
@@ -0,0 +1,599 @@
+//! A calibrated time base, for timeouts and delays which have to work after
+//! boot services are exited
+//!
+//! Time is kept by a CPU counter which keeps running without the firmware.
+//! Its frequency is first measured against the EFI timestamp protocol, and
+//! once ACPI is up taken from sources which don't depend on the firmware
+//! being accurate: on x86_64 the PM timer or the HPET, on aarch64 CNTFRQ_EL0
+//! which the architecture has the firmware program. Once boot services are
+//! exited, timeouts are kept by the EL1 physical timer on aarch64 and by the
+//! SBI timer on riscv64.
+
+use crate::efi;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use crate::acpi;
+#[cfg(target_arch = "riscv64")] use crate::sbi;
+
+/// A `Result` type which wraps a time base error
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from calibrating the time base
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[derive(Debug)]
+pub enum Error {
+    /// There is nothing to take the counter frequency from
+    NoReference,
+
+    /// Waiting on the PM timer failed
+    #[cfg(target_arch = "x86_64")]
+    PmTimer(acpi::Error),
+
+    /// The HPET reports a counter period (in femtoseconds) the spec does not
+    /// allow
+    #[cfg(target_arch = "x86_64")]
+    InvalidHpetPeriod(u64),
+
+    /// The counter did not advance while measuring it
+    #[cfg(target_arch = "x86_64")]
+    Stopped,
+}
+
+/// What the counter frequency was measured against
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[derive(Debug, Clone, Copy)]
+pub enum Reference {
+    /// The ACPI PM timer
+    #[cfg(target_arch = "x86_64")]
+    PmTimer,
+
+    /// The High Precision Event Timer
+    #[cfg(target_arch = "x86_64")]
+    Hpet,
+
+    /// The frequency the firmware programmed into CNTFRQ
+    #[cfg(target_arch = "aarch64")]
+    Cntfrq,
+}
+
+/// Number of microseconds to measure the counter frequency over with the
+/// EFI timestamp
+const EFI_CALIBRATION_US: usize = 10_000;
+
+/// Number of microseconds to measure the counter frequency over with the PM
+/// timer or the HPET
+#[cfg(target_arch = "x86_64")]
+const CALIBRATION_US: u64 = 50_000;
+
+/// Offset of the HPET general capabilities and ID register
+#[cfg(target_arch = "x86_64")]
+const HPET_CAPABILITIES: usize = 0x00;
+
+/// Offset of the HPET general configuration register
+#[cfg(target_arch = "x86_64")]
+const HPET_CONFIG: usize = 0x10;
+
+/// Offset of the HPET main counter value register
+#[cfg(target_arch = "x86_64")]
+const HPET_COUNTER: usize = 0xf0;
+
+/// Set in the HPET capabilities if the main counter is 64 bits wide
+#[cfg(target_arch = "x86_64")]
+const HPET_COUNT_SIZE_CAP: u64 = 1 << 13;
+
+/// Set in the HPET configuration to make the main counter run
+#[cfg(target_arch = "x86_64")]
+const HPET_ENABLE_CNF: u64 = 1 << 0;
+
+/// Largest HPET counter period (in femtoseconds) the spec allows
+#[cfg(target_arch = "x86_64")]
+const HPET_MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Set in CNTP_CTL_EL0 to enable the timer
+#[cfg(target_arch = "aarch64")]
+const CNTP_CTL_ENABLE: u64 = 1 << 0;
+
+/// Set in CNTP_CTL_EL0 to mask the timer interrupt
+#[cfg(target_arch = "aarch64")]
+const CNTP_CTL_IMASK: u64 = 1 << 1;
+
+/// Set in CNTP_CTL_EL0 once the timer condition is met
+#[cfg(target_arch = "aarch64")]
+const CNTP_CTL_ISTATUS: u64 = 1 << 2;
+
+/// Set in `sip` while the supervisor timer interrupt is pending
+#[cfg(target_arch = "riscv64")]
+const SIP_STIP: u64 = 1 << 5;
+
+/// Frequency of the counter in Hz, zero if unknown
+static mut FREQUENCY: u64 = 0;
+
+/// Counter value when the time base was set up
+static mut START: u64 = 0;
+
+/// Read the CPU counter
+///
+/// # Returns
+///
+/// The current counter value, which only ever goes up
+///
+pub fn now() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe { core::arch::x86_64::_rdtsc() }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let val: u64;
+        asm!("mrs {}, cntvct_el0", out(reg) val,
+            options(nomem, nostack, preserves_flags));
+        val
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        let val: u64;
+        asm!("rdtime {}", out(reg) val,
+            options(nomem, nostack, preserves_flags));
+        val
+    }
+}
+
+/// Get the frequency of the counter
+///
+/// # Returns
+///
+/// The frequency in Hz, `None` if it is unknown
+///
+pub fn frequency() -> Option<u64> {
+    let freq = unsafe { FREQUENCY };
+    (freq != 0).then_some(freq)
+}
+
+/// Convert a number of counter ticks into time
+///
+/// # Parameters
+///
+/// * `ticks` - The number of counter ticks, e.g. the difference of two
+///             [`now`] values
+///
+/// # Returns
+///
+/// The number of microseconds `ticks` take, `None` if the counter frequency
+/// is unknown
+///
+pub fn ticks_to_us(ticks: u64) -> Option<u64> {
+    let freq = frequency()?;
+    Some((ticks as u128 * 1_000_000 / freq as u128) as u64)
+}
+
+/// Convert time into a number of counter ticks
+///
+/// # Parameters
+///
+/// * `us` - The number of microseconds
+///
+/// # Returns
+///
+/// The number of counter ticks in `us` microseconds, `None` if the counter
+/// frequency is unknown
+///
+pub fn us_to_ticks(us: u64) -> Option<u64> {
+    let freq = frequency()?;
+    Some((us as u128 * freq as u128 / 1_000_000) as u64)
+}
+
+/// Measure the frequency of the counter against the firmware
+///
+/// # Returns
+///
+/// The frequency in Hz, `None` if it could not be measured
+///
+fn calibrate_efi() -> Option<u64> {
+    // The architectural counter reports its own frequency
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let freq: u64;
+        asm!("mrs {}, cntfrq_el0", out(reg) freq,
+            options(nomem, nostack, preserves_flags));
+        if freq != 0 { return Some(freq); }
+    }
+
+    // Measure against the EFI timestamp, or rely on the stall being exact
+    // when there is no timestamp protocol
+    let start    = efi::timestamp().ok();
+    let start_ct = now();
+    efi::stall(EFI_CALIBRATION_US).ok()?;
+    let end_ct   = now();
+    let end      = efi::timestamp().ok();
+    let ticks    = end_ct.wrapping_sub(start_ct) as u128;
+
+    let freq = match (start, end) {
+        (Some((start, freq)), Some((end, _))) if end > start => {
+            ticks * freq as u128 / (end - start) as u128
+        }
+        _ => ticks * 1_000_000 / EFI_CALIBRATION_US as u128,
+    };
+    (freq != 0).then_some(freq as u64)
+}
+
+/// Set up the time base, while boot services are still running
+pub fn init() {
+    unsafe {
+        FREQUENCY = calibrate_efi().unwrap_or(0);
+        START     = now();
+    }
+}
+
+/// Busy-wait using the HPET main counter
+///
+/// # Parameters
+///
+/// * `hpet` - The HPET to wait on
+/// * `us`   - The number of microseconds to wait
+///
+/// # Returns
+///
+/// `()` after at least `us` microseconds have passed, on error [`Error`]
+///
+#[cfg(target_arch = "x86_64")]
+unsafe fn hpet_delay_us(hpet: &acpi::Hpet, us: u64) -> Result<()> {
+    let base = hpet.base.0 as usize as *mut u8;
+    let read = |reg: usize| {
+        core::ptr::read_volatile(base.add(reg) as *const u64)
+    };
+
+    let caps   = read(HPET_CAPABILITIES);
+    let period = caps >> 32;
+    if period == 0 || period > HPET_MAX_PERIOD_FS {
+        return Err(Error::InvalidHpetPeriod(period));
+    }
+    let mask = if caps & HPET_COUNT_SIZE_CAP != 0 {
+        !0
+    } else {
+        0xffff_ffff
+    };
+
+    // Start the main counter if the firmware left it stopped, and leave it
+    // as we found it
+    let config = read(HPET_CONFIG);
+    if config & HPET_ENABLE_CNF == 0 {
+        core::ptr::write_volatile(base.add(HPET_CONFIG) as *mut u64,
+            config | HPET_ENABLE_CNF);
+    }
+
+    // Accumulate elapsed ticks, taking into account that a 32-bit counter
+    // wraps around
+    let ticks = (us as u128 * 1_000_000_000 / period as u128) as u64;
+    let mut prev    = read(HPET_COUNTER) & mask;
+    let mut elapsed = 0u64;
+    while elapsed < ticks {
+        let now = read(HPET_COUNTER) & mask;
+        elapsed = elapsed.saturating_add(now.wrapping_sub(prev) & mask);
+        prev    = now;
+        core::hint::spin_loop();
+    }
+
+    if config & HPET_ENABLE_CNF == 0 {
+        core::ptr::write_volatile(base.add(HPET_CONFIG) as *mut u64, config);
+    }
+    Ok(())
+}
+
+/// Measure the frequency of the time stamp counter against the PM timer, or
+/// the HPET if there is no PM timer, and use it from here on
+///
+/// # Parameters
+///
+/// * `acpi` - The parsed ACPI tables, locating the PM timer and the HPET
+///
+/// # Returns
+///
+/// The frequency in Hz and what it was measured against, on error [`Error`]
+/// with the previous frequency kept
+///
+/// # Safety
+///
+/// Nothing else may be using the HPET
+///
+#[cfg(target_arch = "x86_64")]
+pub unsafe fn calibrate(acpi: &acpi::Acpi) -> Result<(u64, Reference)> {
+    let has_pm_timer = acpi.fadt.map_or(false, |x| x.pm_timer.is_some());
+
+    let start = now();
+    let reference = if has_pm_timer {
+        acpi::pm_delay_us(CALIBRATION_US).map_err(Error::PmTimer)?;
+        Reference::PmTimer
+    } else if let Some(hpet) = &acpi.hpet {
+        hpet_delay_us(hpet, CALIBRATION_US)?;
+        Reference::Hpet
+    } else {
+        return Err(Error::NoReference);
+    };
+    let ticks = now().wrapping_sub(start);
+
+    let freq = (ticks as u128 * 1_000_000 / CALIBRATION_US as u128) as u64;
+    if freq == 0 { return Err(Error::Stopped); }
+    FREQUENCY = freq;
+    Ok((freq, reference))
+}
+
+/// Take the frequency of the generic timer counter from CNTFRQ_EL0, and use
+/// it from here on
+///
+/// The CNTControlBase frame the GTDT may report is not read, as it can be
+/// only accessible to the Secure world, where a read from us aborts.
+///
+/// # Parameters
+///
+/// * `acpi` - The parsed ACPI tables, unused as the frequency is
+///            architectural
+///
+/// # Returns
+///
+/// The frequency in Hz and where it was taken from, on error [`Error`] with
+/// the previous frequency kept
+///
+/// # Safety
+///
+/// Nothing else may be using the time base
+///
+#[cfg(target_arch = "aarch64")]
+pub unsafe fn calibrate(acpi: &acpi::Acpi) -> Result<(u64, Reference)> {
+    let _ = acpi;
+
+    let cntfrq: u64;
+    asm!("mrs {}, cntfrq_el0", out(reg) cntfrq,
+        options(nomem, nostack, preserves_flags));
+    if cntfrq == 0 { return Err(Error::NoReference); }
+
+    FREQUENCY = cntfrq;
+    Ok((cntfrq, Reference::Cntfrq))
+}
+
+/// The per-processor timer, with its interrupt masked so expiry is polled:
+/// the EL1 physical timer on aarch64, the supervisor timer of the SBI on
+/// riscv64. It is stopped when dropped.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+pub struct Timer {
+    /// Value of the timer counter the timer expires at next
+    deadline: u64,
+
+    /// Number of counter ticks between expiries of a periodic timer, zero
+    /// for a one-shot timer
+    interval: u64,
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+impl Timer {
+    /// Program the timer
+    ///
+    /// # Parameters
+    ///
+    /// * `us`       - The number of microseconds until the timer expires
+    /// * `periodic` - Whether the timer expires again every `us`
+    ///                microseconds, rather than once
+    ///
+    /// # Returns
+    ///
+    /// The running [`Timer`], `None` if the counter frequency is unknown or
+    /// the timer could not be programmed
+    ///
+    /// # Safety
+    ///
+    /// The timer is per processor, only one [`Timer`] may exist on it at a
+    /// time. Boot services must have been exited, as the firmware may be
+    /// using the timer.
+    ///
+    pub unsafe fn start(us: u64, periodic: bool) -> Option<Self> {
+        let ticks = us_to_ticks(us)?;
+        let timer = Timer {
+            deadline: Self::counter().wrapping_add(ticks),
+            interval: if periodic { ticks.max(1) } else { 0 },
+        };
+        timer.arm()?;
+        Some(timer)
+    }
+
+    /// Check whether the timer expired. A periodic timer is set to expire
+    /// again, an interval after it last expired so it doesn't drift.
+    ///
+    /// # Returns
+    ///
+    /// Whether the timer expired, since it was started for a one-shot timer
+    /// and since it was last checked for a periodic one
+    ///
+    pub fn expired(&mut self) -> bool {
+        if !self.fired() { return false; }
+
+        if self.interval != 0 {
+            self.deadline = self.deadline.wrapping_add(self.interval);
+            unsafe { let _ = self.arm(); }
+        }
+        true
+    }
+
+    /// Read the counter the timer compares against
+    #[cfg(target_arch = "aarch64")]
+    fn counter() -> u64 {
+        let val: u64;
+        unsafe {
+            asm!("isb", "mrs {}, cntpct_el0", out(reg) val,
+                options(nomem, nostack, preserves_flags));
+        }
+        val
+    }
+
+    /// Read the counter the timer compares against
+    #[cfg(target_arch = "riscv64")]
+    fn counter() -> u64 {
+        now()
+    }
+
+    /// Program the timer to expire at the deadline
+    ///
+    /// # Returns
+    ///
+    /// `()` once the timer is programmed, `None` if it could not be
+    ///
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn arm(&self) -> Option<()> {
+        asm!("msr cntp_cval_el0, {}", in(reg) self.deadline,
+            options(nomem, nostack, preserves_flags));
+        asm!("msr cntp_ctl_el0, {}", "isb",
+            in(reg) CNTP_CTL_ENABLE | CNTP_CTL_IMASK,
+            options(nomem, nostack, preserves_flags));
+        Some(())
+    }
+
+    /// Program the timer to expire at the deadline
+    ///
+    /// # Returns
+    ///
+    /// `()` once the timer is programmed, `None` if it could not be
+    ///
+    #[cfg(target_arch = "riscv64")]
+    unsafe fn arm(&self) -> Option<()> {
+        sbi::set_timer(self.deadline).ok()
+    }
+
+    /// Check whether the timer condition is met
+    #[cfg(target_arch = "aarch64")]
+    fn fired(&self) -> bool {
+        let ctl: u64;
+        unsafe {
+            asm!("isb", "mrs {}, cntp_ctl_el0", out(reg) ctl,
+                options(nomem, nostack, preserves_flags));
+        }
+        ctl & CNTP_CTL_ISTATUS != 0
+    }
+
+    /// Check whether the timer condition is met, which makes the supervisor
+    /// timer interrupt pending
+    #[cfg(target_arch = "riscv64")]
+    fn fired(&self) -> bool {
+        let sip: u64;
+        unsafe {
+            asm!("csrr {}, sip", out(reg) sip,
+                options(nomem, nostack, preserves_flags));
+        }
+        sip & SIP_STIP != 0
+    }
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+impl Drop for Timer {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            asm!("msr cntp_ctl_el0, {}", "isb", in(reg) 0u64,
+                options(nomem, nostack, preserves_flags));
+        }
+
+        // Push the deadline out of reach, which also clears the pending
+        // interrupt
+        #[cfg(target_arch = "riscv64")]
+        unsafe { let _ = sbi::set_timer(!0); }
+    }
+}
+
+/// When a wait times out
+enum Deadline {
+    /// Once the counter has advanced by a number of ticks since a start
+    /// value
+    Counter {
+        /// Counter value when the wait started
+        start: u64,
+
+        /// Number of ticks to wait for
+        ticks: u64,
+    },
+
+    /// Once a one-shot timer expires
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    Timer(Timer),
+}
+
+impl Deadline {
+    /// Start timing a wait. The firmware may be ticking with the timer, so
+    /// it is only used once boot services are exited.
+    ///
+    /// # Parameters
+    ///
+    /// * `us` - The number of microseconds until the deadline
+    ///
+    /// # Returns
+    ///
+    /// The [`Deadline`], `None` if the counter frequency is unknown
+    ///
+    fn new(us: u64) -> Option<Self> {
+        #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+        if !efi::boot_services_active() {
+            return unsafe { Timer::start(us, false) }.map(Deadline::Timer);
+        }
+
+        Some(Deadline::Counter { start: now(), ticks: us_to_ticks(us)? })
+    }
+
+    /// Check whether the deadline has passed
+    ///
+    /// # Returns
+    ///
+    /// Whether the deadline has passed
+    ///
+    fn expired(&mut self) -> bool {
+        match self {
+            Deadline::Counter { start, ticks } => {
+                now().wrapping_sub(*start) >= *ticks
+            }
+            #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+            Deadline::Timer(timer) => timer.expired(),
+        }
+    }
+}
+
+/// Get the time since the time base was set up
+///
+/// # Returns
+///
+/// The number of microseconds since [`init`], `None` if the counter frequency
+/// is unknown
+///
+pub fn uptime_us() -> Option<u64> {
+    ticks_to_us(now().wrapping_sub(unsafe { START }))
+}
+
+/// Spin until a condition holds, or until a timeout
+///
+/// # Parameters
+///
+/// * `us`   - The number of microseconds to wait at most
+/// * `done` - The condition to wait for
+///
+/// # Returns
+///
+/// Whether `done` held before the timeout, `None` if the counter frequency is
+/// unknown
+///
+pub fn wait_us(us: u64, mut done: impl FnMut() -> bool) -> Option<bool> {
+    let mut deadline = Deadline::new(us)?;
+    loop {
+        if done() { return Some(true); }
+        if deadline.expired() { return Some(false); }
+        core::hint::spin_loop();
+    }
+}
+
+/// Spin for a number of microseconds
+///
+/// # Parameters
+///
+/// * `us` - The number of microseconds to wait
+///
+/// # Returns
+///
+/// `()` after at least `us` microseconds have passed, `None` if the counter
+/// frequency is unknown
+///
+pub fn busy_wait_us(us: u64) -> Option<()> {
+    wait_us(us, || false).map(|_| ())
+}
@@ -0,0 +1,59 @@
+//! Boot phase timing, to keep track of boot time regressions. Timestamps are
+//! taken from the counter of the [`time`] base, and converted once they are
+//! printed so a later calibration applies to all of them.
+
+use spinlock::SpinLock;
+
+use crate::time;
+
+/// Maximum number of timestamps which are recorded
+const MAX_MARKS: usize = 16;
+
+/// Recorded timestamps, as a name and a counter value
+static MARKS: SpinLock<[Option<(&str, u64)>; MAX_MARKS]> =
+    SpinLock::new([None; MAX_MARKS]);
+
+/// Start timing the boot, once the time base is set up
+pub fn init() {
+    mark("start");
+}
+
+/// Record a timestamp, once the table is full timestamps are dropped
+///
+/// # Parameters
+///
+/// * `name` - Name of the boot phase which has been reached
+///
+pub fn mark(name: &'static str) {
+    let now = time::now();
+    if let Some(ent) = MARKS.lock().iter_mut().find(|x| x.is_none()) {
+        *ent = Some((name, now));
+    }
+}
+
+/// Print a table of the time each recorded phase was reached, relative to
+/// the start and to the previous phase
+pub fn print_summary() {
+    let freq = match time::frequency() {
+        Some(freq) => freq,
+        None => {
+            log_warn!("Boot timing: counter frequency unknown\n");
+            return;
+        }
+    };
+
+    // Convert counter ticks into microseconds
+    let us = |ticks: u64| time::ticks_to_us(ticks).unwrap_or(0);
+
+    log_info!("Boot timing ({} Hz counter):\n", freq);
+    let marks = *MARKS.lock();
+    let marks = marks.iter().flatten();
+    if let Some(&(_, start)) = marks.clone().next() {
+        let mut prev = start;
+        for &(name, stamp) in marks {
+            log_info!("  {:<20} {:>10} us {:>+10} us\n", name,
+                us(stamp.wrapping_sub(start)), us(stamp.wrapping_sub(prev)));
+            prev = stamp;
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Reporting processor exceptions on aarch64, rather than hanging silently
+//! in whatever is left of the firmware's handlers
+//!
+//! The vectors are installed once boot services are exited. Until then the
+//! firmware takes interrupts through its own vectors, and reports its own
+//! exceptions. Every exception which reaches us is reported with its
+//! syndrome decoded, and the core is parked.
+
+use crate::print;
+use crate::regs::Registers;
+
+/// A `Result` type which wraps a vector installation error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from installing the exception vectors
+#[derive(Debug)]
+pub enum Error {
+    /// We run at an exception level whose vectors we don't take over
+    UnsupportedEl(u64),
+}
+
+/// Exception class of data aborts from a lower exception level
+const EC_DATA_ABORT_LOWER: u64 = 0x24;
+
+/// Exception class of data aborts from the same exception level
+const EC_DATA_ABORT: u64 = 0x25;
+
+/// Exception class of instruction aborts from a lower exception level
+const EC_INSTRUCTION_ABORT_LOWER: u64 = 0x20;
+
+/// Exception class of instruction aborts from the same exception level
+const EC_INSTRUCTION_ABORT: u64 = 0x21;
+
+/// Data abort ISS bit set if the abort was caused by a write
+const ISS_WNR: u64 = 1 << 6;
+
+/// Abort ISS bit set if the FAR does not hold the faulting address
+const ISS_FNV: u64 = 1 << 10;
+
+global_asm!(r#"
+    // Every entry saves x0 and x1, and passes the index of the vector in x0
+    // to the common code. The frame is laid out as `Frame`.
+    .macro vectors_entry index
+    .balign 0x80
+    sub sp, sp, #0x110
+    stp x0, x1, [sp, #0x00]
+    mov x0, #\index
+    b   vectors_common
+    .endm
+
+    .balign 0x800
+    .global vectors_table
+vectors_table:
+    vectors_entry 0
+    vectors_entry 1
+    vectors_entry 2
+    vectors_entry 3
+    vectors_entry 4
+    vectors_entry 5
+    vectors_entry 6
+    vectors_entry 7
+    vectors_entry 8
+    vectors_entry 9
+    vectors_entry 10
+    vectors_entry 11
+    vectors_entry 12
+    vectors_entry 13
+    vectors_entry 14
+    vectors_entry 15
+
+vectors_common:
+    str x0, [sp, #0x100]
+    stp x2, x3, [sp, #0x10]
+    stp x4, x5, [sp, #0x20]
+    stp x6, x7, [sp, #0x30]
+    stp x8, x9, [sp, #0x40]
+    stp x10, x11, [sp, #0x50]
+    stp x12, x13, [sp, #0x60]
+    stp x14, x15, [sp, #0x70]
+    stp x16, x17, [sp, #0x80]
+    stp x18, x19, [sp, #0x90]
+    stp x20, x21, [sp, #0xa0]
+    stp x22, x23, [sp, #0xb0]
+    stp x24, x25, [sp, #0xc0]
+    stp x26, x27, [sp, #0xd0]
+    stp x28, x29, [sp, #0xe0]
+    add x1, sp, #0x110
+    stp x30, x1, [sp, #0xf0]
+
+    mov x0, sp
+    bl  vectors_exception_handler
+1:
+    wfe
+    b   1b
+"#);
+
+extern {
+    /// The vector table, 16 entries of 128 bytes
+    static vectors_table: u8;
+}
+
+/// The state saved on the stack when an exception is taken
+#[repr(C)]
+struct Frame {
+    /// The general purpose registers, in the order of the register dump
+    gpr: [u64; 32],
+
+    /// The index of the vector the exception was taken through
+    index: u64,
+}
+
+/// Get the exception level we run at
+///
+/// # Returns
+///
+/// The current exception level, 0 to 3
+///
+fn current_el() -> u64 {
+    let el: u64;
+    unsafe {
+        asm!("mrs {}, CurrentEL", out(reg) el,
+            options(nomem, nostack, preserves_flags));
+    }
+    (el >> 2) & 3
+}
+
+/// Install our exception vectors for the exception level we run at
+///
+/// # Returns
+///
+/// `()` once the vectors are in place, on error [`Error`]
+///
+/// # Safety
+///
+/// The firmware must no longer be taking interrupts, they would be reported
+/// as unexpected and park the core.
+///
+pub unsafe fn init() -> Result<()> {
+    let table = &vectors_table as *const u8 as u64;
+    match current_el() {
+        1 => asm!("msr vbar_el1, {}", "isb", in(reg) table,
+            options(nomem, nostack, preserves_flags)),
+        2 => asm!("msr vbar_el2, {}", "isb", in(reg) table,
+            options(nomem, nostack, preserves_flags)),
+        el => return Err(Error::UnsupportedEl(el)),
+    }
+    Ok(())
+}
+
+/// Read the registers describing the exception being handled
+///
+/// # Returns
+///
+/// The exception syndrome, the fault address and the exception link
+/// register, of the exception level we run at
+///
+fn syndrome() -> (u64, u64, u64) {
+    let (esr, far, elr): (u64, u64, u64);
+    unsafe {
+        if current_el() == 2 {
+            asm!("mrs {}, esr_el2", "mrs {}, far_el2", "mrs {}, elr_el2",
+                out(reg) esr, out(reg) far, out(reg) elr,
+                options(nomem, nostack, preserves_flags));
+        } else {
+            asm!("mrs {}, esr_el1", "mrs {}, far_el1", "mrs {}, elr_el1",
+                out(reg) esr, out(reg) far, out(reg) elr,
+                options(nomem, nostack, preserves_flags));
+        }
+    }
+    (esr, far, elr)
+}
+
+/// Describe an exception class
+///
+/// # Parameters
+///
+/// * `class` - The EC field of the ESR
+///
+/// # Returns
+///
+/// What caused the exception
+///
+fn class_name(class: u64) -> &'static str {
+    match class {
+        0x00 => "unknown reason",
+        0x01 => "trapped WFI/WFE",
+        0x07 => "trapped SIMD/FP access",
+        0x0e => "illegal execution state",
+        0x15 => "SVC",
+        0x16 => "HVC",
+        0x17 => "SMC",
+        0x18 => "trapped MSR/MRS",
+        EC_INSTRUCTION_ABORT_LOWER | EC_INSTRUCTION_ABORT => {
+            "instruction abort"
+        }
+        0x22 => "PC alignment fault",
+        EC_DATA_ABORT_LOWER | EC_DATA_ABORT => "data abort",
+        0x26 => "SP alignment fault",
+        0x2c => "floating point exception",
+        0x2f => "SError",
+        0x30 | 0x31 => "breakpoint",
+        0x32 | 0x33 => "software step",
+        0x34 | 0x35 => "watchpoint",
+        0x3c => "BRK instruction",
+        _ => "reserved exception class",
+    }
+}
+
+/// Describe the fault status of an abort
+///
+/// # Parameters
+///
+/// * `status` - The DFSC or IFSC field of the ISS
+///
+/// # Returns
+///
+/// What faulted, and the translation table level it faulted at if it is a
+/// translation table fault
+///
+fn fault_name(status: u64) -> (&'static str, Option<u64>) {
+    let level = Some(status & 3);
+    match status {
+        0x00..=0x03 => ("address size fault", level),
+        0x04..=0x07 => ("translation fault", level),
+        0x08..=0x0b => ("access flag fault", level),
+        0x0c..=0x0f => ("permission fault", level),
+        0x10        => ("synchronous external abort", None),
+        0x21        => ("alignment fault", None),
+        0x30        => ("TLB conflict abort", None),
+        _           => ("other fault", None),
+    }
+}
+
+/// Report an exception, then park the core
+///
+/// # Parameters
+///
+/// * `frame` - The state saved when the exception was taken
+///
+#[no_mangle]
+extern fn vectors_exception_handler(frame: &Frame) {
+    // We may have faulted while printing
+    unsafe { print::force_unlock(); }
+
+    let kind = match frame.index & 3 {
+        0 => "synchronous exception",
+        1 => "IRQ",
+        2 => "FIQ",
+        _ => "SError",
+    };
+    let from = match frame.index >> 2 {
+        0 => "current EL with SP0",
+        1 => "current EL with SPx",
+        2 => "lower EL (AArch64)",
+        _ => "lower EL (AArch32)",
+    };
+    let (esr, far, elr) = syndrome();
+    let class = (esr >> 26) & 0x3f;
+    log_error!("{} from {} at {:#x}, ESR {:#x} ({})\n", kind, from, elr,
+        esr, class_name(class));
+
+    // The ISS of an abort tells what kind of access faulted
+    if frame.index & 3 == 0 && matches!(class, EC_INSTRUCTION_ABORT_LOWER |
+            EC_INSTRUCTION_ABORT | EC_DATA_ABORT_LOWER | EC_DATA_ABORT) {
+        let access = match class {
+            EC_INSTRUCTION_ABORT_LOWER | EC_INSTRUCTION_ABORT => {
+                "instruction fetch"
+            }
+            _ if esr & ISS_WNR != 0 => "write",
+            _                       => "read",
+        };
+        let (fault, level) = fault_name(esr & 0x3f);
+        log_error!("Abort on {} of {:#x}{}, {}\n", access, far,
+            if esr & ISS_FNV != 0 { " (address not valid)" } else { "" },
+            fault);
+        if let Some(level) = level {
+            log_error!("Faulted at translation table level {}\n", level);
+        }
+    }
+
+    log_error!("Registers at the exception:\n{}",
+        Registers::from_exception(frame.gpr));
+    log_error!("Core parked\n");
+}
@@ -0,0 +1,592 @@
+//! Structures which are handed from the bootloader to the kernel. Everything
+//! in here is `#[repr(C)]` such that the layout is stable between the two
+//! binaries, regardless of which compiler versions were used to build them.
+//!
+//! Optional information is not represented with `Option` as it has no stable
+//! layout, instead a `present` field is set to non-zero if the information
+//! was available.
+
+#![no_std]
+
+/// Maximum number of cores which can be handed over, the first ones are kept
+/// on systems with more
+pub const MAX_CORES: usize = 256;
+
+/// Maximum number of SRAT memory affinity ranges which can be handed over
+pub const MAX_MEMORY_AFFINITIES: usize = 32;
+
+/// Maximum length of the kernel command line in bytes
+pub const MAX_CMDLINE: usize = 256;
+
+/// Maximum number of EFI memory descriptors which can be handed over
+pub const MAX_MEMORY_DESCRIPTORS: usize = 384;
+
+/// Maximum length of the firmware vendor string in bytes
+pub const MAX_FIRMWARE_VENDOR: usize = 64;
+
+/// Maximum number of free physical memory ranges which can be handed over
+pub const MAX_FREE_MEMORY_RANGES: usize = 256;
+
+/// Maximum number of I/O APICs whose registers can be handed over mapped
+pub const MAX_IO_APICS: usize = 8;
+
+/// Value of [`BootInfo::magic`], `FOOBBOOT` in little endian
+pub const BOOT_INFO_MAGIC: u64 = 0x544f_4f42_424f_4f46;
+
+/// Value of [`BootInfo::version`] for the layout in this crate. It is bumped
+/// whenever the layout of anything in [`BootInfo`] changes.
+pub const BOOT_INFO_VERSION: u32 = 9;
+
+/// [`Core::state`] of a processor which was never started
+pub const CORE_STATE_NOT_STARTED: u32 = 0;
+
+/// [`Core::state`] of a processor which checked in with the bootloader
+pub const CORE_STATE_ONLINE: u32 = 1;
+
+/// [`Core::state`] of a processor which was started but did not check in in
+/// time, it may still be running
+pub const CORE_STATE_TIMED_OUT: u32 = 2;
+
+/// [`Core::state`] of a processor the firmware refused to start
+pub const CORE_STATE_START_FAILED: u32 = 3;
+
+/// [`Framebuffer::format`] of pixels with red in byte 0 and blue in byte 2
+pub const PIXEL_FORMAT_RGB: u32 = 0;
+
+/// [`Framebuffer::format`] of pixels with blue in byte 0 and red in byte 2
+pub const PIXEL_FORMAT_BGR: u32 = 1;
+
+/// Information handed over from the bootloader to the kernel
+///
+/// The kernel is entered on [`BootInfo::page_tables`], on the stack of the
+/// boot processor, and receives a pointer to this through the linear map in
+/// its first argument register. It must check `magic` and `version` before
+/// trusting anything else in here.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct BootInfo {
+    /// Always [`BOOT_INFO_MAGIC`]
+    pub magic: u64,
+
+    /// The [`BOOT_INFO_VERSION`] of the bootloader which filled this in
+    pub version: u32,
+
+    /// Size of this structure in bytes
+    pub size: u32,
+
+    /// Information parsed out of the ACPI tables
+    pub acpi: Acpi,
+
+    /// The TPM event log of the measurements done during boot
+    pub tpm_event_log: TpmEventLog,
+
+    /// The kernel command line
+    pub cmdline: CommandLine,
+
+    /// The EFI memory map as it was when boot services were exited
+    pub memory_map: MemoryMap,
+
+    /// The EFI runtime services
+    pub runtime_services: RuntimeServices,
+
+    /// The initial RAM disk
+    pub initrd: Initrd,
+
+    /// Information about the firmware
+    pub firmware: Firmware,
+
+    /// The kernel stacks of the processors
+    pub stacks: Stacks,
+
+    /// Physical memory which is free for the kernel to use
+    pub free_memory: FreeMemory,
+
+    /// The linear framebuffer
+    pub framebuffer: Framebuffer,
+
+    /// The page tables built for the kernel
+    pub page_tables: PageTables,
+
+    /// Device registers mapped into the page tables
+    pub mmio: Mmio,
+
+    /// Everything the bootloader printed
+    pub log_buffer: LogBuffer,
+
+    /// The processors the bootloader started
+    pub cores: Cores,
+}
+
+/// Page tables the bootloader built for the kernel, they are in use when the
+/// kernel is entered
+///
+/// RAM is linearly mapped at its physical address plus `linear_map`. The
+/// only other lower half mapping besides the kernel image is the bootloader
+/// code which switched to the tables.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct PageTables {
+    /// Non-zero if page tables were built
+    pub present: u32,
+
+    /// Number of levels of the tables, 4 for 48-bit virtual addresses, or 3
+    /// for 39-bit ones on riscv64 harts without Sv48
+    pub levels: u32,
+
+    /// Physical address of the top level table, the PML4 on x86_64 and the
+    /// TTBR0 table on aarch64. On x86_64 it is below 4 GiB, so real mode
+    /// trampolines can load it into CR3.
+    pub root: u64,
+
+    /// Physical address of the TTBR1 table on aarch64, zero elsewhere
+    pub root_high: u64,
+
+    /// Virtual address physical address zero is mapped at, the start of the
+    /// upper half: `0xffff_8000_0000_0000` with 4 levels and
+    /// `0xffff_ffc0_0000_0000` with 3
+    pub linear_map: u64,
+}
+
+/// The registers of an I/O APIC, mapped into [`BootInfo::page_tables`]
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct IoApicMmio {
+    /// The I/O APIC ID
+    pub id: u32,
+
+    /// The global system interrupt of the first interrupt input
+    pub gsi_base: u32,
+
+    /// Physical address of the registers
+    pub phys: u64,
+
+    /// Virtual address of the registers
+    pub virt: u64,
+}
+
+/// Device registers the kernel needs before it can map anything itself,
+/// mapped uncached into [`BootInfo::page_tables`]. Zero addresses are not
+/// mapped.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Mmio {
+    /// Virtual address of the registers of the SPCR serial port, zero if it
+    /// is not memory mapped
+    pub uart: u64,
+
+    /// Virtual address of the local APIC registers on x86_64, zero elsewhere
+    pub lapic: u64,
+
+    /// Number of valid entries in `io_apics`
+    pub num_io_apics: u32,
+
+    /// The I/O APICs of x86_64 machines
+    pub io_apics: [IoApicMmio; MAX_IO_APICS],
+}
+
+/// A ring buffer holding everything the bootloader printed, including what
+/// was printed before any console was up
+///
+/// The buffer starts with a `u64` holding the number of bytes ever written,
+/// followed by `size` bytes of ring. Once more than `size` bytes were written
+/// the oldest bytes are overwritten, the next byte goes to offset
+/// `written % size` of the ring. The bootloader keeps writing to it until
+/// the kernel is entered.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct LogBuffer {
+    /// Non-zero if there is a log buffer
+    pub present: u32,
+
+    /// Physical address of the start of the buffer
+    pub addr: u64,
+
+    /// Size of the ring in bytes, not including the count in front of it
+    pub size: u64,
+}
+
+/// An inclusive range of physical memory
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct MemoryRange {
+    /// Physical address of the first byte of the range
+    pub start: u64,
+
+    /// Physical address of the last byte of the range
+    pub end: u64,
+}
+
+/// Physical memory nothing was placed in, everything used by the bootloader,
+/// the kernel image and the rest of [`BootInfo`] is already taken out
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct FreeMemory {
+    /// Number of valid entries in `ranges`
+    pub num_ranges: u32,
+
+    /// Number of free ranges which did not fit in `ranges`, the memory they
+    /// cover is free but the kernel is not told about it
+    pub dropped: u32,
+
+    /// The free ranges, which never overlap or touch
+    pub ranges: [MemoryRange; MAX_FREE_MEMORY_RANGES],
+}
+
+/// A linear framebuffer
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Framebuffer {
+    /// Non-zero if a framebuffer was found
+    pub present: u32,
+
+    /// Layout of each 32-bit pixel, [`PIXEL_FORMAT_RGB`] or
+    /// [`PIXEL_FORMAT_BGR`]
+    pub format: u32,
+
+    /// Physical address of the first pixel
+    pub base: u64,
+
+    /// Size of the framebuffer in bytes
+    pub size: u64,
+
+    /// Number of visible pixels in a row
+    pub width: u32,
+
+    /// Number of rows
+    pub height: u32,
+
+    /// Number of pixels in a row in memory
+    pub stride: u32,
+}
+
+/// A kernel stack allocated for a processor
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct CoreStack {
+    /// The firmware ID of the processor, the APIC ID on x86_64 and the MPIDR
+    /// on aarch64
+    pub processor_id: u64,
+
+    /// Physical address of the lowest byte of the stack
+    pub base: u64,
+
+    /// Size of the stack in bytes
+    pub size: u64,
+
+    /// Virtual address of the top of the stack in [`BootInfo::page_tables`].
+    /// The page below the stack is left unmapped.
+    pub top: u64,
+}
+
+/// Kernel stacks allocated from memory close to their processors
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Stacks {
+    /// Number of valid entries in `stacks`
+    pub num_stacks: u32,
+
+    /// The stacks
+    pub stacks: [CoreStack; MAX_CORES],
+}
+
+impl Default for Stacks {
+    fn default() -> Self {
+        Self {
+            num_stacks: 0,
+            stacks:     [Default::default(); MAX_CORES],
+        }
+    }
+}
+
+/// A processor and how starting it went
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Core {
+    /// The firmware ID of the processor, the APIC ID on x86_64, the MPIDR on
+    /// aarch64 and the hart ID on riscv64
+    pub processor_id: u64,
+
+    /// One of the `CORE_STATE_` constants
+    pub state: u32,
+}
+
+/// The processors the bootloader started and waited on to check in, the
+/// boot processor first
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Cores {
+    /// Number of valid entries in `cores`, zero if no processors were
+    /// started
+    pub num_cores: u32,
+
+    /// Bit `n % 64` of word `n / 64` is set if `cores[n]` is online
+    pub online: [u64; MAX_CORES.div_ceil(64)],
+
+    /// The processors
+    pub cores: [Core; MAX_CORES],
+}
+
+impl Default for Cores {
+    fn default() -> Self {
+        Self {
+            num_cores: 0,
+            online:    [0; MAX_CORES.div_ceil(64)],
+            cores:     [Default::default(); MAX_CORES],
+        }
+    }
+}
+
+/// Information about the firmware, to key quirks on
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Firmware {
+    /// Non-zero if the firmware information was available
+    pub present: u32,
+
+    /// Number of valid bytes in `vendor`
+    pub vendor_len: u32,
+
+    /// UTF-8 name of the firmware vendor, not null terminated
+    pub vendor: [u8; MAX_FIRMWARE_VENDOR],
+
+    /// Vendor specific revision of the firmware
+    pub revision: u32,
+
+    /// The UEFI specification revision the firmware implements
+    pub uefi_revision: u32,
+
+    /// Non-zero if the firmware accepts firmware management capsules
+    pub capsule_present: u32,
+
+    /// The `EFI_RESET_TYPE` a capsule update requires
+    pub capsule_reset_type: u32,
+
+    /// Maximum size of a capsule in bytes
+    pub max_capsule_size: u64,
+}
+
+impl Default for Firmware {
+    fn default() -> Self {
+        Self {
+            present:            0,
+            vendor_len:         0,
+            vendor:             [0; MAX_FIRMWARE_VENDOR],
+            revision:           0,
+            uefi_revision:      0,
+            capsule_present:    0,
+            capsule_reset_type: 0,
+            max_capsule_size:   0,
+        }
+    }
+}
+
+/// The initial RAM disk loaded for the kernel
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Initrd {
+    /// Non-zero if an initial RAM disk was loaded
+    pub present: u32,
+
+    /// Physical address of the initial RAM disk
+    pub addr: u64,
+
+    /// Size of the initial RAM disk in bytes
+    pub size: u64,
+}
+
+/// The EFI runtime services, after they were switched to virtual addressing
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct RuntimeServices {
+    /// Non-zero if the runtime services can be used
+    pub present: u32,
+
+    /// Virtual address of the `EFI_RUNTIME_SERVICES` table. It is only
+    /// valid once every runtime region in the memory map is mapped at its
+    /// `virtual_start`.
+    pub addr: u64,
+}
+
+/// A raw EFI memory descriptor, laid out like an `EFI_MEMORY_DESCRIPTOR`
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct MemoryDescriptor {
+    /// The `EFI_MEMORY_TYPE` of the region
+    pub typ: u32,
+
+    /// Physical address of the first byte of the region
+    pub physical_start: u64,
+
+    /// Virtual address of the first byte of the region, only meaningful for
+    /// runtime regions once a virtual address map has been set
+    pub virtual_start: u64,
+
+    /// Number of 4 KiB pages in the region
+    pub number_of_pages: u64,
+
+    /// The `EFI_MEMORY_*` attribute bits of the region
+    pub attribute: u64,
+}
+
+/// The EFI memory map with every descriptor, not just the usable memory
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct MemoryMap {
+    /// Number of valid entries in `descriptors`
+    pub num_descriptors: u32,
+
+    /// Number of descriptors which did not fit in `descriptors`
+    pub dropped: u32,
+
+    /// The memory descriptors, in the order EFI reported them
+    pub descriptors: [MemoryDescriptor; MAX_MEMORY_DESCRIPTORS],
+}
+
+/// The kernel command line
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct CommandLine {
+    /// Number of valid bytes in `bytes`
+    pub len: u32,
+
+    /// UTF-8 contents of the command line, not null terminated
+    pub bytes: [u8; MAX_CMDLINE],
+}
+
+/// A copy of the TCG2 event log handed over by the firmware
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct TpmEventLog {
+    /// Non-zero if a TPM event log was found
+    pub present: u32,
+
+    /// Non-zero if the firmware ran out of room for events, in which case
+    /// the log does not match the PCRs
+    pub truncated: u32,
+
+    /// Physical address of the event log, in the TCG crypto agile format
+    pub addr: u64,
+
+    /// Size of the event log in bytes
+    pub size: u64,
+}
+
+/// Information parsed out of the ACPI tables
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Acpi {
+    /// Physical address of the RSDP
+    pub rsdp: u64,
+
+    /// Processor topology from the MADT
+    pub madt: Madt,
+
+    /// Serial port information from the SPCR
+    pub spcr: Spcr,
+
+    /// NUMA information from the SRAT
+    pub srat: Srat,
+}
+
+/// A processor local APIC or x2APIC entry from the MADT
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Apic {
+    /// ACPI processor UID the APIC is associated with
+    pub acpi_processor_uid: u32,
+
+    /// The APIC ID (or x2APIC ID) of the processor
+    pub apic_id: u32,
+
+    /// Local APIC flags as specified by the MADT
+    pub flags: u32,
+}
+
+/// Processor topology from the MADT
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Madt {
+    /// Non-zero if an MADT was found
+    pub present: u32,
+
+    /// Number of valid entries in `apics`
+    pub num_apics: u32,
+
+    /// Local APICs
+    pub apics: [Apic; MAX_CORES],
+
+    /// Number of valid entries in `x2apics`
+    pub num_x2apics: u32,
+
+    /// Local x2APICs
+    pub x2apics: [Apic; MAX_CORES],
+}
+
+/// Serial port information from the SPCR
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Spcr {
+    /// Non-zero if an SPCR was found
+    pub present: u32,
+
+    /// The SPCR interface type of the serial port
+    pub interface_type: u32,
+
+    /// The raw ACPI Generic Address Structure of the serial port
+    pub address: [u8; 12],
+
+    /// The baud rate of the serial port in bits per second, zero if the
+    /// firmware configured rate should be used as-is
+    pub baud_rate: u32,
+}
+
+/// A memory range associated with a proximity domain
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct MemoryAffinity {
+    /// Proximity domain of the memory range
+    pub domain: u32,
+
+    /// Memory affinity flags as specified by the SRAT
+    pub flags: u32,
+
+    /// Physical base address of the memory range
+    pub base: u64,
+
+    /// Length of the memory range in bytes
+    pub length: u64,
+}
+
+/// A processor associated with a proximity domain
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct ApicAffinity {
+    /// Proximity domain of the processor
+    pub domain: u32,
+
+    /// APIC ID (or x2APIC ID) of the processor
+    pub apic_id: u32,
+
+    /// Affinity flags as specified by the SRAT
+    pub flags: u32,
+}
+
+/// NUMA information from the SRAT
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Srat {
+    /// Non-zero if an SRAT was found
+    pub present: u32,
+
+    /// Number of valid entries in `memory`
+    pub num_memory: u32,
+
+    /// Memory affinity ranges
+    pub memory: [MemoryAffinity; MAX_MEMORY_AFFINITIES],
+
+    /// Number of valid entries in `apics`
+    pub num_apics: u32,
+
+    /// Processor affinities
+    pub apics: [ApicAffinity; MAX_CORES],
+}
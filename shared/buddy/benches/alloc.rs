@@ -0,0 +1,115 @@
+//! Compares a [`RangeSet`] and a [`BuddyAlloc`] under the same mix of
+//! allocation sizes and frees, as the post-boot allocator sees them
+//!
+//! Run with `cargo bench -p buddy --target <host triple>`, the bootloader
+//! itself only builds for UEFI.
+
+use std::time::{Duration, Instant};
+
+use buddy::{BuddyAlloc, PAGE_SIZE};
+use rangeset::{Range, RangeSet};
+
+/// Number of allocations made by each run
+const ALLOCATIONS: usize = 200_000;
+
+/// Most allocations alive at once, a random one is freed to make room
+const LIVE: usize = 1024;
+
+/// Sizes (in frames) the allocations are picked from, mostly small
+const SIZES: [u64; 8] = [1, 1, 1, 2, 3, 4, 8, 16];
+
+/// The free memory both allocators start out with, as inclusive ranges
+const FREE: [(u64, u64); 3] = [
+    (0x0010_0000, 0x009f_ffff),
+    (0x0100_0000, 0x3fff_ffff),
+    (0x1_0000_0000, 0x1_3fff_ffff),
+];
+
+/// An allocator being compared
+trait Alloc {
+    /// Allocate `size` bytes aligned to a frame, `None` on failure
+    fn alloc(&mut self, size: u64) -> Option<u64>;
+
+    /// Free `size` bytes at `addr`, `false` if the memory could not be
+    /// taken back
+    fn free(&mut self, addr: u64, size: u64) -> bool;
+}
+
+impl Alloc for RangeSet {
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        self.allocate(size, PAGE_SIZE).ok().map(|addr| addr as u64)
+    }
+
+    fn free(&mut self, addr: u64, size: u64) -> bool {
+        self.insert(Range { start: addr, end: addr + (size - 1) }).is_ok()
+    }
+}
+
+impl Alloc for BuddyAlloc<'_> {
+    fn alloc(&mut self, size: u64) -> Option<u64> {
+        BuddyAlloc::alloc(self, size, PAGE_SIZE).ok()
+    }
+
+    fn free(&mut self, addr: u64, size: u64) -> bool {
+        BuddyAlloc::free(self, addr, size, PAGE_SIZE).is_ok()
+    }
+}
+
+/// A xorshift generator, so both allocators see the same requests
+struct XorShift(u64);
+
+impl XorShift {
+    /// Get the next number below `bound`, the slight bias doesn't matter here
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
+/// Run the allocations against an allocator
+///
+/// # Returns
+///
+/// The time taken, the number of failed allocations and the number of
+/// failed frees
+///
+fn run(alloc: &mut impl Alloc) -> (Duration, usize, usize) {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut live: Vec<(u64, u64)> = Vec::with_capacity(LIVE);
+    let (mut alloc_failures, mut free_failures) = (0, 0);
+
+    let start = Instant::now();
+    for _ in 0..ALLOCATIONS {
+        if live.len() == LIVE {
+            let (addr, size) = live.swap_remove(rng.below(LIVE));
+            if !alloc.free(addr, size) { free_failures += 1; }
+        }
+
+        let size = SIZES[rng.below(SIZES.len())] * PAGE_SIZE;
+        match alloc.alloc(size) {
+            Some(addr) => live.push((addr, size)),
+            None       => alloc_failures += 1,
+        }
+    }
+
+    (start.elapsed(), alloc_failures, free_failures)
+}
+
+fn main() {
+    let mut free = RangeSet::new();
+    for &(start, end) in &FREE {
+        free.insert(Range { start, end }).unwrap();
+    }
+
+    let mut bitmap = vec![0; BuddyAlloc::bitmap_words(&free)];
+    let mut buddy = BuddyAlloc::new(&free, &mut bitmap).unwrap();
+    for &(name, (time, alloc_failures, free_failures)) in
+            &[("RangeSet", run(&mut free)), ("BuddyAlloc", run(&mut buddy))] {
+        println!("{:<10} {:>8.1} ns per allocation, {} failed allocations, \
+                  {} failed frees", name,
+            time.as_nanos() as f64 / ALLOCATIONS as f64,
+            alloc_failures, free_failures);
+    }
+}
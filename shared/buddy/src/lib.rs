@@ -0,0 +1,559 @@
+//! A buddy allocator for physical memory, for once the early allocations
+//! which need the placement control of a [`RangeSet`] are done
+//!
+//! The bootloader places everything with a [`RangeSet`], then hands out the
+//! page frames of its page tables from a pool managed by a [`BuddyAlloc`].
+//! The allocator needs no heap, the free blocks are tracked in a bitmap the
+//! caller provides. `benches/alloc.rs` compares the two under mixed-size
+//! allocations.
+
+#![no_std]
+
+use rangeset::{Range, RangeSet};
+
+/// Size (in bytes) of the smallest block, a page frame
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The largest block order, blocks are `PAGE_SIZE << order` bytes so this
+/// makes the largest block 1 GiB
+pub const MAX_ORDER: usize = 18;
+
+/// Number of bits to shift a frame number by to get its address
+const PAGE_SHIFT: usize = 12;
+
+/// Size (in bytes) of a block of [`MAX_ORDER`]
+const MAX_BLOCK: u64 = PAGE_SIZE << MAX_ORDER;
+
+/// A `Result` type which wraps a buddy allocator error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Errors from the buddy allocator
+#[derive(Debug)]
+pub enum Error {
+    /// A request for zero bytes was made
+    ZeroSize,
+
+    /// The alignment requested was not a power of two
+    InvalidAlignment,
+
+    /// The allocation is larger than a block of [`MAX_ORDER`]
+    TooLarge,
+
+    /// There is no free block large enough for the allocation
+    OutOfMemory,
+
+    /// The bitmap provided is smaller than the memory needs
+    BitmapTooSmall {
+        /// The number of words the bitmap needs
+        needed: usize,
+    },
+
+    /// An address to free lies outside of the memory the allocator covers
+    NotManaged(u64),
+
+    /// An address to free is not aligned to the size of its block
+    UnalignedFree(u64),
+
+    /// Memory to free is already free
+    DoubleFree(u64),
+}
+
+/// Hands out naturally aligned blocks of `PAGE_SIZE << order` bytes, and
+/// merges freed blocks with their buddies so memory doesn't fragment
+///
+/// Unlike a [`RangeSet`], allocating and freeing only ever looks at the
+/// bitmap of a single order per step, rather than every free range.
+pub struct BuddyAlloc<'a> {
+    /// Address of the first block, aligned to a block of [`MAX_ORDER`]
+    base: u64,
+
+    /// Number of blocks of [`MAX_ORDER`] covered by the allocator
+    top_blocks: u64,
+
+    /// One bit for every block of every order, set if the block is free
+    bitmap: &'a mut [u64],
+
+    /// Index of the first word in `bitmap` of every order
+    offsets: [usize; MAX_ORDER + 1],
+
+    /// Number of free blocks of every order
+    free: [u64; MAX_ORDER + 1],
+
+    /// Lowest word in `bitmap` of every order which may have a bit set, so
+    /// searches skip the part of the bitmap which is known to be empty
+    hint: [usize; MAX_ORDER + 1],
+}
+
+impl<'a> BuddyAlloc<'a> {
+    /// Get the size of the bitmap needed to manage free physical memory
+    ///
+    /// # Parameters
+    ///
+    /// * `free` - The free physical memory which will be passed to
+    ///            [`BuddyAlloc::new`]
+    ///
+    /// # Returns
+    ///
+    /// The number of `u64` words the bitmap must have
+    ///
+    pub fn bitmap_words(free: &RangeSet) -> usize {
+        let (_, top_blocks) = coverage(free);
+        (0..=MAX_ORDER).map(|order| order_words(top_blocks, order)).sum()
+    }
+
+    /// Create a new buddy allocator from free physical memory
+    ///
+    /// # Parameters
+    ///
+    /// * `free`   - The free physical memory, partial frames at the edges of
+    ///              each range are never handed out
+    /// * `bitmap` - Memory to track the free blocks in, at least
+    ///              [`BuddyAlloc::bitmap_words`] long
+    ///
+    /// # Returns
+    ///
+    /// A new [`BuddyAlloc`] holding all of `free`, on error [`Error`]
+    ///
+    pub fn new(free: &RangeSet, bitmap: &'a mut [u64]) -> Result<Self> {
+        let (base, top_blocks) = coverage(free);
+
+        // Lay the bitmaps of the orders out after each other
+        let mut offsets = [0; MAX_ORDER + 1];
+        let mut words = 0;
+        for (order, offset) in offsets.iter_mut().enumerate() {
+            *offset = words;
+            words += order_words(top_blocks, order);
+        }
+        let bitmap = bitmap.get_mut(..words)
+            .ok_or(Error::BitmapTooSmall { needed: words })?;
+        bitmap.iter_mut().for_each(|x| *x = 0);
+
+        let mut buddy = BuddyAlloc {
+            base,
+            top_blocks,
+            bitmap,
+            offsets,
+            free: [0; MAX_ORDER + 1],
+            hint: offsets,
+        };
+
+        // Split every range into the largest naturally aligned blocks which
+        // fit in it
+        for ent in free.entries() {
+            let mut addr = match ent.start.checked_add(PAGE_SIZE - 1) {
+                Some(start) => start & !(PAGE_SIZE - 1),
+                None        => continue,
+            };
+            let last = match ent.end.checked_add(1) {
+                Some(end) => match (end & !(PAGE_SIZE - 1)).checked_sub(1) {
+                    Some(last) => last,
+                    None       => continue,
+                },
+                None      => u64::MAX,
+            };
+
+            while addr < last {
+                let left  = (last - addr).saturating_add(1);
+                let order = (addr.trailing_zeros() as usize)
+                    .min(63 - left.leading_zeros() as usize)
+                    .saturating_sub(PAGE_SHIFT)
+                    .min(MAX_ORDER);
+                buddy.push(addr, order);
+
+                addr = match addr.checked_add(PAGE_SIZE << order) {
+                    Some(next) => next,
+                    None       => break,
+                };
+            }
+        }
+
+        Ok(buddy)
+    }
+
+    /// Get the number of free bytes
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes which can still be allocated
+    ///
+    pub fn free_memory(&self) -> u64 {
+        self.free.iter().enumerate().map(|(order, &blocks)| {
+            blocks * (PAGE_SIZE << order)
+        }).sum()
+    }
+
+    /// Get the free blocks
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the [`Range`] of every free block
+    ///
+    pub fn free_blocks(&self) -> impl Iterator<Item = Range> + '_ {
+        (0..=MAX_ORDER).flat_map(move |order| {
+            let start = self.offsets[order];
+            let words = order_words(self.top_blocks, order);
+
+            self.bitmap[start..start + words].iter().enumerate()
+                    .flat_map(move |(ii, &word)| {
+                (0..64).filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| {
+                        let idx  = (ii as u64 * 64) + bit;
+                        let addr = self.base + (idx << (PAGE_SHIFT + order));
+                        Range {
+                            start: addr,
+                            end:   addr + ((PAGE_SIZE << order) - 1),
+                        }
+                    })
+            })
+        })
+    }
+
+    /// Check if an address lies in the memory the allocator covers
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The physical address to check
+    ///
+    /// # Returns
+    ///
+    /// `true` if `addr` may have been allocated from this allocator
+    ///
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && (addr - self.base) / MAX_BLOCK < self.top_blocks
+    }
+
+    /// Allocate `size` bytes
+    ///
+    /// # Parameters
+    ///
+    /// * `size`  - The number of bytes to allocate, this is rounded up to a
+    ///             power of two number of frames
+    /// * `align` - The alignment requirement of the allocation
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the allocation, on error [`Error`]
+    ///
+    pub fn alloc(&mut self, size: u64, align: u64) -> Result<u64> {
+        if align.count_ones() != 1 { return Err(Error::InvalidAlignment); }
+
+        // Blocks are aligned to their size, so a large enough block is also
+        // aligned enough
+        let order = order(size)?.max(order(align)?);
+
+        // Take the smallest free block which fits
+        let (from, addr) = (order..=MAX_ORDER)
+            .find_map(|x| self.pop(x).map(|addr| (x, addr)))
+            .ok_or(Error::OutOfMemory)?;
+
+        // Hand the upper halves back while splitting it down to size
+        for split in (order..from).rev() {
+            self.push(addr + (PAGE_SIZE << split), split);
+        }
+
+        Ok(addr)
+    }
+
+    /// Return memory to the allocator, so it can be allocated again
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the allocation
+    /// * `size`  - The size (in bytes) the memory was allocated with
+    /// * `align` - The alignment the memory was allocated with, as it may
+    ///             have made the block larger
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    pub fn free(&mut self, addr: u64, size: u64, align: u64) -> Result<()> {
+        if align.count_ones() != 1 { return Err(Error::InvalidAlignment); }
+
+        let mut order = order(size)?.max(order(align)?);
+        let mut block = addr;
+        if !self.contains(addr) { return Err(Error::NotManaged(addr)); }
+        if block & ((PAGE_SIZE << order) - 1) != 0 {
+            return Err(Error::UnalignedFree(addr));
+        }
+        if self.is_free(block, order) { return Err(Error::DoubleFree(addr)); }
+
+        // Merge the block with its buddy for as long as the buddy is free
+        while order < MAX_ORDER {
+            let buddy = block ^ (PAGE_SIZE << order);
+            if !self.take(buddy, order) { break; }
+
+            block  = block.min(buddy);
+            order += 1;
+        }
+        self.push(block, order);
+
+        Ok(())
+    }
+
+    /// Get the bit of a block in the bitmap
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the block
+    /// * `order` - The order of the block
+    ///
+    /// # Returns
+    ///
+    /// The index of the word in `bitmap` and the mask of the bit in it
+    ///
+    fn bit(&self, addr: u64, order: usize) -> (usize, u64) {
+        let idx = (addr - self.base) >> (PAGE_SHIFT + order);
+        (self.offsets[order] + (idx / 64) as usize, 1 << (idx % 64))
+    }
+
+    /// Mark a block as free
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the block
+    /// * `order` - The order of the block
+    ///
+    fn push(&mut self, addr: u64, order: usize) {
+        let (word, mask) = self.bit(addr, order);
+        self.bitmap[word] |= mask;
+        self.free[order] += 1;
+        self.hint[order] = self.hint[order].min(word);
+    }
+
+    /// Take a specific block out of the free blocks
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the block
+    /// * `order` - The order of the block
+    ///
+    /// # Returns
+    ///
+    /// `true` if the block was free and has been taken
+    ///
+    fn take(&mut self, addr: u64, order: usize) -> bool {
+        let (word, mask) = self.bit(addr, order);
+        if self.bitmap[word] & mask == 0 { return false; }
+
+        self.bitmap[word] &= !mask;
+        self.free[order] -= 1;
+        true
+    }
+
+    /// Take the lowest free block of an order
+    ///
+    /// # Parameters
+    ///
+    /// * `order` - The order of the block
+    ///
+    /// # Returns
+    ///
+    /// The physical address of the block, `None` if there is no free block
+    /// of `order`
+    ///
+    fn pop(&mut self, order: usize) -> Option<u64> {
+        if self.free[order] == 0 { return None; }
+
+        let end = self.offsets[order] + order_words(self.top_blocks, order);
+        let word = (self.hint[order]..end).find(|&x| self.bitmap[x] != 0)?;
+        self.hint[order] = word;
+
+        let bit = self.bitmap[word].trailing_zeros() as u64;
+        self.bitmap[word] &= !(1 << bit);
+        self.free[order] -= 1;
+
+        let idx = (word - self.offsets[order]) as u64 * 64 + bit;
+        Some(self.base + (idx << (PAGE_SHIFT + order)))
+    }
+
+    /// Check if any part of a block is free
+    ///
+    /// # Parameters
+    ///
+    /// * `addr`  - The physical address of the block
+    /// * `order` - The order of the block
+    ///
+    /// # Returns
+    ///
+    /// `true` if the block overlaps with a free block
+    ///
+    fn is_free(&self, addr: u64, order: usize) -> bool {
+        // A free block of the same or a larger order contains the block
+        let containing = (order..=MAX_ORDER).any(|x| {
+            let (word, mask) = self.bit(addr & !((PAGE_SIZE << x) - 1), x);
+            self.bitmap[word] & mask != 0
+        });
+
+        // A free block of a smaller order lies within it, the bits of those
+        // are next to each other and aligned to their count
+        containing || (0..order).any(|x| {
+            let (word, mask) = self.bit(addr, x);
+            let count = 1u64 << (order - x);
+            if count >= 64 {
+                self.bitmap[word..word + (count / 64) as usize].iter()
+                    .any(|&x| x != 0)
+            } else {
+                self.bitmap[word] & (((1 << count) - 1) * mask) != 0
+            }
+        })
+    }
+}
+
+/// Get the memory a buddy allocator has to cover for free memory
+///
+/// # Parameters
+///
+/// * `free` - The free physical memory
+///
+/// # Returns
+///
+/// The address of the first block of [`MAX_ORDER`] and the number of them
+///
+fn coverage(free: &RangeSet) -> (u64, u64) {
+    let start = free.entries().iter().map(|x| x.start).min();
+    let end   = free.entries().iter().map(|x| x.end).max();
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            let base = start & !(MAX_BLOCK - 1);
+            (base, (end - base) / MAX_BLOCK + 1)
+        }
+        _ => (0, 0),
+    }
+}
+
+/// Get the number of bitmap words holding the blocks of an order
+///
+/// # Parameters
+///
+/// * `top_blocks` - Number of blocks of [`MAX_ORDER`] covered
+/// * `order`      - The order of the blocks
+///
+/// # Returns
+///
+/// The number of `u64` words with a bit for every block of `order`
+///
+fn order_words(top_blocks: u64, order: usize) -> usize {
+    (top_blocks << (MAX_ORDER - order)).div_ceil(64) as usize
+}
+
+/// Get the order of the smallest block which holds `size` bytes
+///
+/// # Parameters
+///
+/// * `size` - The number of bytes the block must hold
+///
+/// # Returns
+///
+/// The order of the block, on error [`Error`]
+///
+fn order(size: u64) -> Result<usize> {
+    if size == 0 { return Err(Error::ZeroSize); }
+
+    let frames = ((size - 1) / PAGE_SIZE) + 1;
+    let order  = (64 - (frames - 1).leading_zeros()) as usize;
+    if order > MAX_ORDER { return Err(Error::TooLarge); }
+
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+
+    /// Build a [`BuddyAlloc`] out of inclusive `(start, end)` pairs of free
+    /// memory
+    fn buddy(ranges: &[(u64, u64)]) -> BuddyAlloc<'static> {
+        let set = RangeSet::from_pairs(ranges).unwrap();
+        let bitmap = vec![0; BuddyAlloc::bitmap_words(&set)];
+        BuddyAlloc::new(&set, bitmap.leak()).unwrap()
+    }
+
+    #[test]
+    fn new_drops_partial_frames() {
+        let buddy = buddy(&[(0x1800, 0x3fff), (0x5000, 0x67ff)]);
+        assert_eq!(buddy.free_memory(), 0x3000);
+    }
+
+    #[test]
+    fn new_splits_ranges_into_aligned_blocks() {
+        // 0x1000 and 0x4000 only fit single frames, 0x2000 a pair
+        let mut buddy = buddy(&[(0x1000, 0x4fff)]);
+        assert_eq!(buddy.free_memory(), 0x4000);
+        assert_eq!(buddy.alloc(0x2000, 0x1000).unwrap(), 0x2000);
+        assert!(matches!(buddy.alloc(0x2000, 0x1000),
+                         Err(Error::OutOfMemory)));
+    }
+
+    #[test]
+    fn alloc_splits_and_free_merges() {
+        let mut buddy = buddy(&[(0, 0x3fff)]);
+        let addr = buddy.alloc(0x1000, 0x1000).unwrap();
+        assert_eq!(buddy.free_memory(), 0x3000);
+
+        // Once the frame is back the whole block can be handed out again
+        buddy.free(addr, 0x1000, 0x1000).unwrap();
+        assert_eq!(buddy.alloc(0x4000, 0x1000).unwrap(), 0);
+        assert_eq!(buddy.free_memory(), 0);
+    }
+
+    #[test]
+    fn alloc_rounds_up_and_aligns() {
+        let mut buddy = buddy(&[(0x1000, 0x10_0fff)]);
+        let addr = buddy.alloc(0x3000, 0x1000).unwrap();
+        assert_eq!(addr & 0x3fff, 0);
+        let addr = buddy.alloc(0x1000, 0x10000).unwrap();
+        assert_eq!(addr & 0xffff, 0);
+        assert_eq!(buddy.free_memory(), 0x10_0000 - 0x4000 - 0x10000);
+    }
+
+    #[test]
+    fn free_rejects_bad_blocks() {
+        let mut buddy = buddy(&[(0, 0x3fff)]);
+        let addr = buddy.alloc(0x2000, 0x1000).unwrap();
+        assert!(matches!(buddy.free(addr + 0x1000, 0x2000, 0x1000),
+                         Err(Error::UnalignedFree(_))));
+        buddy.free(addr, 0x2000, 0x1000).unwrap();
+        assert!(matches!(buddy.free(addr, 0x1000, 0x1000),
+                         Err(Error::DoubleFree(_))));
+        assert_eq!(buddy.free_memory(), 0x4000);
+    }
+
+    #[test]
+    fn alloc_rejects_bad_requests() {
+        let mut buddy = buddy(&[(0, 0x3fff)]);
+        assert!(matches!(buddy.alloc(0, 0x1000), Err(Error::ZeroSize)));
+        assert!(matches!(buddy.alloc(0x1000, 0x3000),
+                         Err(Error::InvalidAlignment)));
+        assert!(matches!(buddy.alloc(PAGE_SIZE << (MAX_ORDER + 1), 0x1000),
+                         Err(Error::TooLarge)));
+    }
+
+    #[test]
+    fn new_rejects_a_small_bitmap() {
+        let set = RangeSet::from_pairs(&[(0, 0x3fff)]).unwrap();
+        let mut bitmap = [0; 16];
+        assert!(matches!(BuddyAlloc::new(&set, &mut bitmap),
+                         Err(Error::BitmapTooSmall { .. })));
+    }
+
+    #[test]
+    fn free_rejects_unmanaged_memory() {
+        let mut buddy = buddy(&[(0, 0x3fff)]);
+        assert!(matches!(buddy.free(MAX_BLOCK, 0x1000, 0x1000),
+                         Err(Error::NotManaged(_))));
+    }
+
+    #[test]
+    fn free_blocks_reports_every_free_block() {
+        let mut buddy = buddy(&[(0x1000, 0x4fff)]);
+        buddy.alloc(0x2000, 0x1000).unwrap();
+        let mut blocks = buddy.free_blocks()
+            .map(|x| (x.start, x.end)).collect::<std::vec::Vec<_>>();
+        blocks.sort_unstable();
+        assert_eq!(blocks, [(0x1000, 0x1fff), (0x4000, 0x4fff)]);
+    }
+}
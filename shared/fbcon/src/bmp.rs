@@ -0,0 +1,110 @@
+//! A minimal decoder for uncompressed 24-bit and 32-bit BMP images, as used
+//! for firmware boot logos
+
+use crate::{Error, Result};
+
+/// A BMP image borrowed from its raw file contents
+#[derive(Clone, Copy)]
+pub struct Bmp<'a> {
+    /// The pixel array of the image
+    pixels: &'a [u8],
+
+    /// Width of the image in pixels
+    width: u32,
+
+    /// Height of the image in pixels
+    height: u32,
+
+    /// Number of bytes per pixel
+    bytes_per_pixel: usize,
+
+    /// Number of bytes per row, including the padding to 4 bytes
+    stride: usize,
+
+    /// Set if the first row in `pixels` is the top row of the image
+    top_down: bool,
+}
+
+/// Read a little-endian `u32` from `bytes` at `offset`
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let bytes = bytes.get(offset..offset + 4).ok_or(Error::BitmapTruncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl<'a> Bmp<'a> {
+    /// Parse a BMP image
+    ///
+    /// # Parameters
+    ///
+    /// * `image` - The raw BMP file contents
+    ///
+    /// # Returns
+    ///
+    /// The parsed [`Bmp`], on error [`Error`]
+    ///
+    pub fn parse(image: &'a [u8]) -> Result<Self> {
+        /// Compression type of uncompressed images
+        const BI_RGB: u32 = 0;
+
+        if image.get(..2) != Some(b"BM") {
+            return Err(Error::UnsupportedBitmap);
+        }
+
+        // Get the information header, which is at least 40 bytes
+        let offset      = read_u32(image, 10)? as usize;
+        let header_size = read_u32(image, 14)?;
+        let width       = read_u32(image, 18)? as i32;
+        let height      = read_u32(image, 22)? as i32;
+        let bpp         = read_u32(image, 28)? & 0xffff;
+        let compression = read_u32(image, 30)?;
+        if header_size < 40 || compression != BI_RGB ||
+                (bpp != 24 && bpp != 32) || width <= 0 || height == 0 {
+            return Err(Error::UnsupportedBitmap);
+        }
+
+        // A negative height means the rows are stored top to bottom
+        let top_down = height < 0;
+        let width    = width as u32;
+        let height   = height.wrapping_abs() as u32;
+
+        // Rows are padded to 4 bytes
+        let bytes_per_pixel = bpp as usize / 8;
+        let stride = (width as usize * bytes_per_pixel + 3) & !3;
+        let pixels = stride.checked_mul(height as usize)
+            .and_then(|size| image.get(offset..)?.get(..size))
+            .ok_or(Error::BitmapTruncated)?;
+
+        Ok(Self { pixels, width, height, bytes_per_pixel, stride, top_down })
+    }
+
+    /// Get the width of the image in pixels
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Get the height of the image in pixels
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Get the color of a pixel
+    ///
+    /// # Parameters
+    ///
+    /// * `x` - The column of the pixel, must be less than the width
+    /// * `y` - The row of the pixel from the top, must be less than the height
+    ///
+    /// # Returns
+    ///
+    /// The color of the pixel as `0xRRGGBB`
+    ///
+    pub fn pixel(&self, x: u32, y: u32) -> u32 {
+        let row = if self.top_down { y } else { self.height - 1 - y };
+        let offset = row as usize * self.stride +
+            x as usize * self.bytes_per_pixel;
+
+        // Pixels are stored as blue, green, red
+        let bgr = &self.pixels[offset..offset + 3];
+        (bgr[2] as u32) << 16 | (bgr[1] as u32) << 8 | bgr[0] as u32
+    }
+}
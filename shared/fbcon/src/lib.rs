@@ -0,0 +1,343 @@
+//! A text console drawn onto a linear framebuffer, and helpers to draw
+//! images onto the framebuffer
+
+#![no_std]
+
+mod font;
+pub mod bmp;
+
+use core::cell::Cell;
+use crate::font::{FONT, FONT_WIDTH, FONT_HEIGHT, FONT_FIRST, FONT_LAST};
+use crate::bmp::Bmp;
+
+/// A `Result` type which wraps a framebuffer console error
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Framebuffer console errors
+#[derive(Debug)]
+pub enum Error {
+    /// The framebuffer is too small to hold a single character
+    FramebufferTooSmall,
+
+    /// The framebuffer extends outside of its reported size
+    FramebufferOutOfBounds,
+
+    /// The image is not a BMP we know how to decode
+    UnsupportedBitmap,
+
+    /// The image is smaller than its headers claim
+    BitmapTruncated,
+}
+
+/// Fill memory with a 32-bit pattern, which `memset` can't do unless every
+/// byte of it is the same
+///
+/// # Parameters
+///
+/// * `ptr`   - Pointer to the memory to fill
+/// * `val`   - Value to fill the memory with
+/// * `count` - Number of `u32`s to fill
+///
+/// # Safety
+///
+/// `count` `u32`s at `ptr` must be writable
+///
+unsafe fn fill32(ptr: *mut u32, val: u32, count: usize) {
+    for ii in 0..count {
+        core::ptr::write(ptr.add(ii), val);
+    }
+}
+
+/// Global framebuffer console
+static mut FBCON_DEVICE: Option<FbCon> = None;
+
+/// Get a reference to the framebuffer console
+pub fn fbcon_device() -> Option<&'static FbCon> {
+    unsafe { (*core::ptr::addr_of!(FBCON_DEVICE)).as_ref() }
+}
+
+/// The layout of a 32-bit pixel in the framebuffer
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub enum PixelFormat {
+    /// Byte 0 is red, byte 1 is green, byte 2 is blue
+    Rgb,
+
+    /// Byte 0 is blue, byte 1 is green, byte 2 is red
+    Bgr,
+}
+
+/// A linear framebuffer as handed to us by the firmware
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Framebuffer {
+    /// Physical address of the first pixel
+    pub base: usize,
+
+    /// Size of the framebuffer (in bytes)
+    pub size: usize,
+
+    /// Number of visible pixels in a row
+    pub width: u32,
+
+    /// Number of rows
+    pub height: u32,
+
+    /// Number of pixels in a row in memory, this may be larger than `width`
+    pub stride: u32,
+
+    /// Layout of each 32-bit pixel
+    pub format: PixelFormat,
+}
+
+impl Framebuffer {
+    /// Make sure every pixel is within the reported size of the framebuffer
+    ///
+    /// # Returns
+    ///
+    /// `()` if the framebuffer is sane, on error [`Error`]
+    ///
+    pub fn validate(&self) -> Result<()> {
+        if self.stride < self.width {
+            return Err(Error::FramebufferTooSmall);
+        }
+
+        let needed = (self.stride as usize)
+            .checked_mul(self.height as usize)
+            .and_then(|x| x.checked_mul(4))
+            .ok_or(Error::FramebufferOutOfBounds)?;
+        if needed > self.size {
+            return Err(Error::FramebufferOutOfBounds);
+        }
+
+        Ok(())
+    }
+
+    /// Convert a `0xRRGGBB` color into the pixel format of the framebuffer
+    fn color(&self, rgb: u32) -> u32 {
+        match self.format {
+            PixelFormat::Bgr => rgb,
+            PixelFormat::Rgb => {
+                ((rgb & 0xff) << 16) | (rgb & 0xff00) | ((rgb >> 16) & 0xff)
+            }
+        }
+    }
+
+    /// Get a pointer to the pixel at `x`, `y`
+    fn pixel(&self, x: u32, y: u32) -> *mut u32 {
+        let offset = y as usize * self.stride as usize + x as usize;
+        (self.base as *mut u32).wrapping_add(offset)
+    }
+
+    /// Fill a rectangle with a color, clipped to the screen
+    ///
+    /// # Parameters
+    ///
+    /// * `x`, `y`          - The top left corner of the rectangle
+    /// * `width`, `height` - The size of the rectangle
+    /// * `rgb`             - The color as `0xRRGGBB`
+    ///
+    /// # Safety
+    ///
+    /// The framebuffer must have passed [`Framebuffer::validate`] and be
+    /// mapped and writable.
+    ///
+    pub unsafe fn fill_rect(&self, x: u32, y: u32, width: u32, height: u32,
+                            rgb: u32) {
+        let color = self.color(rgb);
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        if x >= x_end { return; }
+
+        for py in y..y_end {
+            fill32(self.pixel(x, py), color, (x_end - x) as usize);
+        }
+    }
+
+    /// Draw a BMP image, clipped to the screen
+    ///
+    /// # Parameters
+    ///
+    /// * `bmp`    - The image to draw
+    /// * `x`, `y` - Where to put the top left corner of the image
+    ///
+    /// # Safety
+    ///
+    /// The framebuffer must have passed [`Framebuffer::validate`] and be
+    /// mapped and writable.
+    ///
+    pub unsafe fn draw_bmp(&self, bmp: &Bmp, x: u32, y: u32) {
+        let width  = bmp.width().min(self.width.saturating_sub(x));
+        let height = bmp.height().min(self.height.saturating_sub(y));
+        for by in 0..height {
+            for bx in 0..width {
+                core::ptr::write_volatile(self.pixel(x + bx, y + by),
+                                          self.color(bmp.pixel(bx, by)));
+            }
+        }
+    }
+}
+
+/// Foreground color of the console text, as `0xRRGGBB`
+const FOREGROUND: u32 = 0xaaaaaa;
+
+/// Background color of the console, as `0xRRGGBB`
+const BACKGROUND: u32 = 0x000000;
+
+/// A text console which renders characters onto a [`Framebuffer`]
+pub struct FbCon {
+    /// The framebuffer we draw into
+    fb: Framebuffer,
+
+    /// Number of text columns which fit on the screen
+    columns: u32,
+
+    /// Number of text rows which fit on the screen
+    rows: u32,
+
+    /// Current cursor column
+    x: Cell<u32>,
+
+    /// Current cursor row
+    y: Cell<u32>,
+
+    /// Foreground color in the framebuffer pixel format
+    foreground: u32,
+
+    /// Background color in the framebuffer pixel format
+    background: u32,
+}
+
+impl FbCon {
+    /// Initialize the framebuffer console and clear the screen
+    ///
+    /// # Parameters
+    ///
+    /// * `fb` - The framebuffer to draw the console onto
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    /// # Safety
+    ///
+    /// `fb` must describe memory which is mapped and writable for as long as
+    /// the console is in use, and which is not used for anything else.
+    ///
+    /// This function must be called in a single threaded environment as it
+    /// initializes a mutable static without locks.
+    ///
+    pub unsafe fn init(fb: Framebuffer) -> Result<()> {
+        // Compute the size of the console in characters
+        let columns = fb.width  / FONT_WIDTH  as u32;
+        let rows    = fb.height / FONT_HEIGHT as u32;
+        if columns == 0 || rows == 0 {
+            return Err(Error::FramebufferTooSmall);
+        }
+
+        // Make sure every pixel we may touch is within the framebuffer
+        fb.validate()?;
+
+        // Create the console
+        let ret = Self {
+            fb,
+            columns,
+            rows,
+            x:          Cell::new(0),
+            y:          Cell::new(0),
+            foreground: fb.color(FOREGROUND),
+            background: fb.color(BACKGROUND),
+        };
+
+        // Start with a blank screen
+        for row in 0..fb.height {
+            ret.fill_row(row, ret.background);
+        }
+
+        // Set up the console global
+        FBCON_DEVICE = Some(ret);
+        Ok(())
+    }
+
+    /// Get a pointer to the pixel at `x`, `y`
+    fn pixel(&self, x: u32, y: u32) -> *mut u32 {
+        self.fb.pixel(x, y)
+    }
+
+    /// Fill the pixel row `y` with `color`
+    fn fill_row(&self, y: u32, color: u32) {
+        unsafe { fill32(self.pixel(0, y), color, self.fb.width as usize); }
+    }
+
+    /// Draw `chr` into the character cell at `column`, `row`
+    fn draw(&self, column: u32, row: u32, chr: u8) {
+        // Characters we have no glyph for are shown as a question mark
+        let chr = if (FONT_FIRST..=FONT_LAST).contains(&chr) {
+            chr
+        } else {
+            b'?'
+        };
+        let glyph = &FONT[(chr - FONT_FIRST) as usize];
+
+        // Draw the glyph one pixel at a time
+        for (dy, &bits) in glyph.iter().enumerate() {
+            let y = row * FONT_HEIGHT as u32 + dy as u32;
+            for dx in 0..FONT_WIDTH {
+                let x = column * FONT_WIDTH as u32 + dx as u32;
+                let color = if bits & (0x80 >> dx) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                unsafe { core::ptr::write_volatile(self.pixel(x, y), color); }
+            }
+        }
+    }
+
+    /// Move the cursor to the start of the next line, scrolling the screen up
+    /// by one line if we are on the last line
+    fn newline(&self) {
+        self.x.set(0);
+        if self.y.get() + 1 < self.rows {
+            self.y.set(self.y.get() + 1);
+            return;
+        }
+
+        // Move every text row but the first one up by one text row
+        let line   = FONT_HEIGHT as u32;
+        let height = (self.rows - 1) * line;
+        for y in 0..height {
+            unsafe {
+                core::ptr::copy(self.pixel(0, y + line), self.pixel(0, y),
+                                self.fb.width as usize);
+            }
+        }
+
+        // Clear the last text row
+        for y in height..height + line {
+            self.fill_row(y, self.background);
+        }
+    }
+
+    /// Write a slice of bytes to the console
+    ///
+    /// # Parameters
+    ///
+    /// * `bytes` - The slice of bytes to write to the console
+    ///
+    pub fn write(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            match byte {
+                b'\n' => self.newline(),
+                b'\r' => self.x.set(0),
+                _ => {
+                    // Wrap long lines
+                    if self.x.get() >= self.columns { self.newline(); }
+
+                    self.draw(self.x.get(), self.y.get(), byte);
+                    self.x.set(self.x.get() + 1);
+                }
+            }
+        }
+    }
+}
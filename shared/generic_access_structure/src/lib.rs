@@ -77,6 +77,19 @@ impl From<u8> for AccessSize {
     }
 }
 
+impl From<AccessSize> for u8 {
+    fn from(val: AccessSize) -> Self {
+        match val {
+            AccessSize::Undefined   => 0,
+            AccessSize::Byte        => 1,
+            AccessSize::Word        => 2,
+            AccessSize::Dword       => 3,
+            AccessSize::Qword       => 4,
+            AccessSize::Unspecified => 0,
+        }
+    }
+}
+
 /// An I/O port address
 #[derive(Clone, Copy, Debug)]
 pub struct IoAddr(pub u64);
@@ -470,3 +483,32 @@ impl From<[u8; 12]> for Gas {
         }
     }
 }
+
+impl From<Gas> for [u8; 12] {
+    /// Convert a [`Gas`] back into its raw ACPI representation. An
+    /// [`Gas::Unimplemented`] is converted to all zeroes, which is a system
+    /// memory [`Gas`] with a zero register width and thus cannot be accessed.
+    fn from(val: Gas) -> Self {
+        let mut ret = [0u8; 12];
+
+        let (space, addr, register_width, register_offset, access_size) =
+                match val {
+            Gas::Memory { addr, register_width, register_offset,
+                          access_size } => {
+                (0, addr as u64, register_width, register_offset, access_size)
+            }
+            Gas::Io { addr, register_width, register_offset,
+                      access_size } => {
+                (1, addr.0, register_width, register_offset, access_size)
+            }
+            Gas::Unimplemented => return ret,
+        };
+
+        ret[0] = space;
+        ret[1] = register_width;
+        ret[2] = register_offset;
+        ret[3] = access_size.into();
+        ret[4..12].copy_from_slice(&addr.to_le_bytes());
+        ret
+    }
+}
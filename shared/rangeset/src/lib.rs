@@ -1,6 +1,9 @@
 //! Library which provides a `RangeSet` which contains non-overlapping sets of
 //! `u64` inclusive ranges. The `RangeSet` can be used to insert or remove
 //! ranges of `u64`s and thus is very useful for physical memory management.
+//!
+//! A `TaggedRangeSet` additionally remembers a tag for every range, such as
+//! the type of memory in a memory map.
 
 #![no_std]
 
@@ -70,6 +73,25 @@ impl RangeSet {
         }
     }
 
+    /// Create a RangeSet out of inclusive `(start, end)` pairs
+    ///
+    /// # Parameters
+    ///
+    /// * `ranges` - The inclusive `(start, end)` pairs to insert, which may
+    ///              overlap or touch
+    ///
+    /// # Returns
+    ///
+    /// The [`RangeSet`] covering every pair, on error [`Error`]
+    ///
+    pub fn from_pairs(ranges: &[(u64, u64)]) -> Result<RangeSet> {
+        let mut set = RangeSet::new();
+        for &(start, end) in ranges {
+            set.insert(Range { start, end })?;
+        }
+        Ok(set)
+    }
+
     /// Get all the entries in the RangeSet as a slice
     ///
     /// # Returns
@@ -80,6 +102,37 @@ impl RangeSet {
         &self.ranges[..self.in_use]
     }
 
+    /// Check if an address is in the RangeSet
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The address to look for
+    ///
+    /// # Returns
+    ///
+    /// `true` if a [`Range`] in the [`RangeSet`] contains `addr`
+    ///
+    pub fn contains_addr(&self, addr: u64) -> bool {
+        self.entries().iter().any(|ent| ent.start <= addr && ent.end >= addr)
+    }
+
+    /// Find the ranges in the RangeSet which overlap with `range`
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - The [`Range`] to look for overlap with
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the whole [`Range`]s in the [`RangeSet`] which share
+    /// at least one address with `range`
+    ///
+    pub fn overlapping(&self, range: Range)
+            -> impl Iterator<Item = Range> + '_ {
+        self.entries().iter().copied()
+            .filter(move |&ent| overlaps(ent, range).is_some())
+    }
+
     /// Delete the Range contained in the RangeSet at `idx`
     ///
     /// # Parameters
@@ -231,11 +284,11 @@ impl RangeSet {
                 } else {
                     // If the range to remove fits inside of the range then
                     // we need to split it into two ranges.
-                    self.ranges[ii].start = range.end.saturating_add(1);
 
-                    // Insert new range for the tail
-                    if let Some(ent) = self.ranges.get_mut(self.in_use) {
-                        *ent = Range {
+                    // Insert new range for the head, before touching the
+                    // existing entry so a failure leaves the set unchanged
+                    if let Some(head) = self.ranges.get_mut(self.in_use) {
+                        *head = Range {
                             start: ent.start,
                             end:   range.start.saturating_sub(1),
                         };
@@ -244,6 +297,9 @@ impl RangeSet {
                         return Err(Error::OutOfEntries);
                     }
 
+                    // The existing entry becomes the tail
+                    self.ranges[ii].start = range.end.saturating_add(1);
+
                     continue 'try_subtractions;
                 }
             }
@@ -255,6 +311,77 @@ impl RangeSet {
         Ok(())
     }
 
+    /// Insert every range of `other` into this RangeSet
+    ///
+    /// # Parameters
+    ///
+    /// * `other` - The [`RangeSet`] to add to this [`RangeSet`]
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`] and the [`RangeSet`] is left
+    /// unchanged
+    ///
+    pub fn union(&mut self, other: &RangeSet) -> Result<()> {
+        // Work on a copy, so a failure part way doesn't leave half a union
+        let mut set = *self;
+        for &range in other.entries() {
+            set.insert(range)?;
+        }
+
+        *self = set;
+        Ok(())
+    }
+
+    /// Remove every range of `other` from this RangeSet
+    ///
+    /// # Parameters
+    ///
+    /// * `other` - The [`RangeSet`] to remove from this [`RangeSet`]
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`] and the [`RangeSet`] is left
+    /// unchanged
+    ///
+    pub fn subtract(&mut self, other: &RangeSet) -> Result<()> {
+        // Work on a copy, so a failure part way doesn't leave half a
+        // subtraction
+        let mut set = *self;
+        for &range in other.entries() {
+            set.remove(range)?;
+        }
+
+        *self = set;
+        Ok(())
+    }
+
+    /// Trim this RangeSet down to only the parts which are also in `other`
+    ///
+    /// # Parameters
+    ///
+    /// * `other` - The [`RangeSet`] to intersect this [`RangeSet`] with
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`] and the [`RangeSet`] is left
+    /// unchanged
+    ///
+    pub fn intersect(&mut self, other: &RangeSet) -> Result<()> {
+        // Collect the overlap of every pair of ranges
+        let mut set = RangeSet::new();
+        for &ent in self.entries() {
+            for &range in other.entries() {
+                if let Some(overlap) = overlaps(ent, range) {
+                    set.insert(overlap)?;
+                }
+            }
+        }
+
+        *self = set;
+        Ok(())
+    }
+
     /// Compute the size of the range covered by this [`RangeSet`]
     ///
     /// # Returns
@@ -392,9 +519,10 @@ impl RangeSet {
             }
         }
 
-        if let Some((base, end, ptr)) = allocation {
-            // Remove this range from the available set
-            self.remove(Range { start: base, end: end })?;
+        if let Some((_, end, ptr)) = allocation {
+            // Remove this range from the available set, leaving any padding
+            // for the alignment free
+            self.remove(Range { start: ptr as u64, end: end })?;
             
             // Return out the pointer!
             Ok(ptr)
@@ -405,6 +533,245 @@ impl RangeSet {
     }
 }
 
+impl core::fmt::Debug for RangeSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Only the ranges which are in use
+        f.debug_list().entries(self.entries()).finish()
+    }
+}
+
+/// An inclusive range with a tag describing it
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TaggedRange<T> {
+    /// The range
+    pub range: Range,
+
+    /// The tag of every address in the range
+    pub tag: T,
+}
+
+/// A set of non-overlapping inclusive `u64` ranges, which each carry a tag.
+/// Touching ranges are only merged if their tags are equal.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct TaggedRangeSet<T: Copy> {
+    /// Fixed array of ranges in the set
+    ranges: [TaggedRange<T>; 256],
+
+    /// Number of in use entries in `ranges`
+    in_use: usize,
+}
+
+impl<T: Copy + PartialEq + Default> TaggedRangeSet<T> {
+    /// Create a new empty TaggedRangeSet
+    ///
+    /// # Returns
+    ///
+    /// An empty [`TaggedRangeSet`]
+    ///
+    pub fn new() -> Self {
+        TaggedRangeSet {
+            ranges: [TaggedRange {
+                range: Range { start: 0, end: 0 },
+                tag:   T::default(),
+            }; 256],
+            in_use: 0,
+        }
+    }
+
+    /// Get all the entries in the TaggedRangeSet as a slice
+    ///
+    /// # Returns
+    ///
+    /// A slice to the [`TaggedRange`]s in the [`TaggedRangeSet`]
+    ///
+    pub fn entries(&self) -> &[TaggedRange<T>] {
+        &self.ranges[..self.in_use]
+    }
+
+    /// Get the tag of an address
+    ///
+    /// # Parameters
+    ///
+    /// * `addr` - The address to look for
+    ///
+    /// # Returns
+    ///
+    /// The tag of the range containing `addr`, or `None` if no range does
+    ///
+    pub fn tag_of(&self, addr: u64) -> Option<T> {
+        self.entries().iter()
+            .find(|ent| ent.range.start <= addr && ent.range.end >= addr)
+            .map(|ent| ent.tag)
+    }
+
+    /// Add a range to the end of the TaggedRangeSet, without merging it
+    ///
+    /// # Parameters
+    ///
+    /// * `ent` - The [`TaggedRange`] to add, it must not overlap with any
+    ///           range in the set
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`]
+    ///
+    fn push(&mut self, ent: TaggedRange<T>) -> Result<()> {
+        let slot = self.ranges.get_mut(self.in_use)
+            .ok_or(Error::OutOfEntries)?;
+        *slot = ent;
+        self.in_use += 1;
+        Ok(())
+    }
+
+    /// Insert a new range with `tag` into this TaggedRangeSet
+    ///
+    /// Any part of the set which overlaps with the range takes on `tag`, and
+    /// touching ranges with the same tag are merged with it.
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - The [`Range`] to insert into the [`TaggedRangeSet`]
+    /// * `tag`   - The tag of the range
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`] and the [`TaggedRangeSet`] is left
+    /// unchanged
+    ///
+    pub fn insert(&mut self, mut range: Range, tag: T) -> Result<()> {
+        // Work on a copy, so a failure part way leaves the set unchanged
+        let mut set = *self;
+        set.remove(range)?;
+
+        // Merge with the touching ranges of the same tag
+        'try_merges: loop {
+            for ii in 0..set.in_use {
+                let ent = set.ranges[ii];
+                if ent.tag != tag || overlaps(
+                        Range {
+                            start: range.start,
+                            end:   range.end.saturating_add(1),
+                        },
+                        Range {
+                            start: ent.range.start,
+                            end:   ent.range.end.saturating_add(1),
+                        }).is_none() {
+                    continue;
+                }
+
+                range.start = cmp::min(range.start, ent.range.start);
+                range.end   = cmp::max(range.end,   ent.range.end);
+
+                set.ranges.swap(ii, set.in_use - 1);
+                set.in_use -= 1;
+                continue 'try_merges;
+            }
+
+            break;
+        }
+
+        set.push(TaggedRange { range, tag })?;
+        *self = set;
+        Ok(())
+    }
+
+    /// Remove `range` from the TaggedRangeSet
+    ///
+    /// Any range in the set which overlaps with `range` is trimmed, or split
+    /// in two, such that there is no more overlap. The pieces keep their
+    /// tags.
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - The [`Range`] to remove from the [`TaggedRangeSet`]
+    ///
+    /// # Returns
+    ///
+    /// `()` on success, on error [`Error`] and the [`TaggedRangeSet`] is left
+    /// unchanged
+    ///
+    pub fn remove(&mut self, range: Range) -> Result<()> {
+        // Check the range
+        if range.end < range.start {
+            return Err(Error::InvalidRange);
+        }
+
+        // Build the set again out of what is left of every range
+        let mut set = TaggedRangeSet::new();
+        for &ent in self.entries() {
+            if overlaps(ent.range, range).is_none() {
+                set.push(ent)?;
+                continue;
+            }
+
+            if ent.range.start < range.start {
+                set.push(TaggedRange {
+                    range: Range {
+                        start: ent.range.start,
+                        end:   range.start - 1,
+                    },
+                    tag: ent.tag,
+                })?;
+            }
+            if ent.range.end > range.end {
+                set.push(TaggedRange {
+                    range: Range {
+                        start: range.end + 1,
+                        end:   ent.range.end,
+                    },
+                    tag: ent.tag,
+                })?;
+            }
+        }
+
+        *self = set;
+        Ok(())
+    }
+
+    /// Get the ranges whose tags match
+    ///
+    /// # Parameters
+    ///
+    /// * `matches` - Returns `true` for the tags of ranges to get
+    ///
+    /// # Returns
+    ///
+    /// A [`RangeSet`] of the ranges whose tag `matches`, on error [`Error`]
+    ///
+    pub fn filter(&self, matches: impl Fn(T) -> bool) -> Result<RangeSet> {
+        let mut set = RangeSet::new();
+        for ent in self.entries().iter().filter(|ent| matches(ent.tag)) {
+            set.insert(ent.range)?;
+        }
+
+        Ok(set)
+    }
+}
+
+impl<T: Copy + PartialEq + Default> Default for TaggedRangeSet<T> {
+    fn default() -> Self {
+        TaggedRangeSet::new()
+    }
+}
+
+impl<T: Copy + core::fmt::Debug> core::fmt::Debug for TaggedRangeSet<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Only the ranges which are in use
+        f.debug_list().entries(&self.ranges[..self.in_use]).finish()
+    }
+}
+
+impl<'a> IntoIterator for &'a RangeSet {
+    type Item     = &'a Range;
+    type IntoIter = core::slice::Iter<'a, Range>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries().iter()
+    }
+}
+
 /// Determines overlap of `a` and `b`. If there is overlap, returns the range
 /// of the overlap
 ///
@@ -475,3 +842,256 @@ fn contains(mut a: Range, mut b: Range) -> bool {
     a.start >= b.start && a.end <= b.end
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a [`RangeSet`] out of inclusive `(start, end)` pairs
+    fn set(ranges: &[(u64, u64)]) -> RangeSet {
+        RangeSet::from_pairs(ranges).unwrap()
+    }
+
+    /// Check that `set` holds exactly the inclusive `(start, end)` pairs in
+    /// `expected`, which are sorted by their start
+    fn assert_ranges(set: &RangeSet, expected: &[(u64, u64)]) {
+        let mut got = [(0, 0); 256];
+        for (ent, range) in got.iter_mut().zip(set.entries()) {
+            *ent = (range.start, range.end);
+        }
+        let got = &mut got[..set.entries().len()];
+        got.sort_unstable();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn remove_splits_a_range() {
+        let mut set = set(&[(0x1000, 0x4fff)]);
+        set.remove(Range { start: 0x2000, end: 0x2fff }).unwrap();
+        assert_ranges(&set, &[(0x1000, 0x1fff), (0x3000, 0x4fff)]);
+    }
+
+    #[test]
+    fn remove_split_without_room_leaves_the_set_unchanged() {
+        // Every entry is in use and apart, so a split has nowhere to go
+        let mut set = RangeSet::new();
+        for ii in 0..256 {
+            set.insert(Range { start: ii * 0x10, end: ii * 0x10 + 7 })
+                .unwrap();
+        }
+        let before = set;
+
+        assert!(matches!(set.remove(Range { start: 2, end: 3 }),
+                         Err(Error::OutOfEntries)));
+        assert_eq!(set.entries().len(), before.entries().len());
+        for (a, b) in set.entries().iter().zip(before.entries()) {
+            assert_eq!((a.start, a.end), (b.start, b.end));
+        }
+    }
+
+    #[test]
+    fn remove_trims_the_edges() {
+        let mut set = set(&[(0x1000, 0x4fff)]);
+        set.remove(Range { start: 0, end: 0x1fff }).unwrap();
+        set.remove(Range { start: 0x4000, end: 0x8fff }).unwrap();
+        assert_ranges(&set, &[(0x2000, 0x3fff)]);
+    }
+
+    #[test]
+    fn allocate_leaves_the_alignment_padding_free() {
+        let mut set = set(&[(0x1000, 0x4fff)]);
+        assert_eq!(set.allocate(0x1000, 0x2000).unwrap(), 0x2000);
+        assert_ranges(&set, &[(0x1000, 0x1fff), (0x3000, 0x4fff)]);
+    }
+
+    #[test]
+    fn allocate_prefer_leaves_the_alignment_padding_free() {
+        let mut set = set(&[(0x1000, 0x4fff), (0x10000, 0x1ffff)]);
+        let regions = self::set(&[(0x11000, 0x1ffff)]);
+        assert_eq!(set.allocate_prefer(0x1000, 0x4000, Some(&regions))
+            .unwrap(), 0x14000);
+        assert_ranges(&set, &[(0x1000, 0x4fff), (0x10000, 0x13fff),
+                              (0x15000, 0x1ffff)]);
+    }
+
+    #[test]
+    fn allocate_prefer_falls_back_outside_the_regions() {
+        let mut set = set(&[(0x1000, 0x1fff), (0x10000, 0x11fff)]);
+        let regions = self::set(&[(0x1000, 0x1fff)]);
+        assert_eq!(set.allocate_prefer(0x2000, 0x1000, Some(&regions))
+            .unwrap(), 0x10000);
+        assert_ranges(&set, &[(0x1000, 0x1fff)]);
+        assert!(matches!(set.allocate_prefer(0x2000, 0x1000, Some(&regions)),
+                         Err(Error::OutOfMemory)));
+    }
+
+    #[test]
+    fn subtract_partial_overlaps() {
+        let mut set = set(&[(0x1000, 0x2fff), (0x5000, 0x6fff),
+                            (0x9000, 0x9fff)]);
+        set.subtract(&self::set(&[(0x2000, 0x5fff), (0x9800, 0xafff)]))
+            .unwrap();
+        assert_ranges(&set, &[(0x1000, 0x1fff), (0x6000, 0x6fff),
+                              (0x9000, 0x97ff)]);
+    }
+
+    #[test]
+    fn subtract_splits_and_drops_ranges() {
+        let mut set = set(&[(0x1000, 0x4fff), (0x8000, 0x8fff)]);
+        set.subtract(&self::set(&[(0x2000, 0x2fff), (0x7000, 0x9fff)]))
+            .unwrap();
+        assert_ranges(&set, &[(0x1000, 0x1fff), (0x3000, 0x4fff)]);
+    }
+
+    #[test]
+    fn intersect_keeps_only_the_overlap() {
+        let mut set = set(&[(0x1000, 0x3fff), (0x6000, 0x7fff),
+                            (0xa000, 0xafff)]);
+        set.intersect(&self::set(&[(0x0, 0x1fff), (0x3000, 0x6fff)]))
+            .unwrap();
+        assert_ranges(&set, &[(0x1000, 0x1fff), (0x3000, 0x3fff),
+                              (0x6000, 0x6fff)]);
+    }
+
+    #[test]
+    fn intersect_with_nothing_is_empty() {
+        let mut set = set(&[(0x1000, 0x3fff)]);
+        set.intersect(&RangeSet::new()).unwrap();
+        assert_ranges(&set, &[]);
+    }
+
+    #[test]
+    fn contains_addr_is_inclusive() {
+        let set = set(&[(0x1000, 0x1fff)]);
+        assert!(!set.contains_addr(0xfff));
+        assert!(set.contains_addr(0x1000));
+        assert!(set.contains_addr(0x1fff));
+        assert!(!set.contains_addr(0x2000));
+    }
+
+    #[test]
+    fn overlapping_returns_whole_ranges() {
+        let set = set(&[(0x1000, 0x1fff), (0x3000, 0x3fff),
+                        (0x5000, 0x5fff)]);
+        let mut found = [(0, 0); 2];
+        let query = Range { start: 0x1fff, end: 0x3000 };
+        for (ii, range) in set.overlapping(query).enumerate() {
+            found[ii] = (range.start, range.end);
+        }
+        found.sort_unstable();
+        assert_eq!(found, [(0x1000, 0x1fff), (0x3000, 0x3fff)]);
+    }
+
+    /// Tags for the [`TaggedRangeSet`] tests
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+    enum Kind {
+        /// Usable memory
+        #[default]
+        Ram,
+
+        /// Memory holding ACPI tables
+        Acpi,
+
+        /// Device registers
+        Mmio,
+    }
+
+    /// Build a [`TaggedRangeSet`] out of inclusive `(start, end, tag)`
+    /// triples, inserted in order
+    fn tagged(ranges: &[(u64, u64, Kind)]) -> TaggedRangeSet<Kind> {
+        let mut set = TaggedRangeSet::new();
+        for &(start, end, tag) in ranges {
+            set.insert(Range { start, end }, tag).unwrap();
+        }
+        set
+    }
+
+    /// Check that `set` holds exactly the inclusive `(start, end, tag)`
+    /// triples in `expected`, which are sorted by their start
+    fn assert_tagged(set: &TaggedRangeSet<Kind>,
+                     expected: &[(u64, u64, Kind)]) {
+        let mut got = [(0, 0, Kind::Ram); 256];
+        for (ent, tagged) in got.iter_mut().zip(set.entries()) {
+            *ent = (tagged.range.start, tagged.range.end, tagged.tag);
+        }
+        let got = &mut got[..set.entries().len()];
+        got.sort_unstable();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn tagged_insert_merges_only_equal_tags() {
+        let set = tagged(&[(0x1000, 0x1fff, Kind::Ram),
+                           (0x2000, 0x2fff, Kind::Ram),
+                           (0x3000, 0x3fff, Kind::Acpi),
+                           (0x4000, 0x4fff, Kind::Ram)]);
+        assert_tagged(&set, &[(0x1000, 0x2fff, Kind::Ram),
+                              (0x3000, 0x3fff, Kind::Acpi),
+                              (0x4000, 0x4fff, Kind::Ram)]);
+
+        // Retagging the middle joins all three
+        let mut set = set;
+        set.insert(Range { start: 0x3000, end: 0x3fff }, Kind::Ram).unwrap();
+        assert_tagged(&set, &[(0x1000, 0x4fff, Kind::Ram)]);
+    }
+
+    #[test]
+    fn tagged_insert_across_tag_boundaries_takes_over() {
+        let mut set = tagged(&[(0x1000, 0x2fff, Kind::Ram),
+                               (0x3000, 0x4fff, Kind::Acpi)]);
+        set.insert(Range { start: 0x2800, end: 0x37ff }, Kind::Mmio)
+            .unwrap();
+        assert_tagged(&set, &[(0x1000, 0x27ff, Kind::Ram),
+                              (0x2800, 0x37ff, Kind::Mmio),
+                              (0x3800, 0x4fff, Kind::Acpi)]);
+        assert_eq!(set.tag_of(0x27ff), Some(Kind::Ram));
+        assert_eq!(set.tag_of(0x2800), Some(Kind::Mmio));
+        assert_eq!(set.tag_of(0x5000), None);
+    }
+
+    #[test]
+    fn tagged_remove_across_tag_boundaries_keeps_tags() {
+        let mut set = tagged(&[(0x1000, 0x2fff, Kind::Ram),
+                               (0x3000, 0x4fff, Kind::Acpi),
+                               (0x5000, 0x5fff, Kind::Mmio)]);
+        set.remove(Range { start: 0x2000, end: 0x3fff }).unwrap();
+        set.remove(Range { start: 0x5800, end: 0x58ff }).unwrap();
+        assert_tagged(&set, &[(0x1000, 0x1fff, Kind::Ram),
+                              (0x4000, 0x4fff, Kind::Acpi),
+                              (0x5000, 0x57ff, Kind::Mmio),
+                              (0x5900, 0x5fff, Kind::Mmio)]);
+        assert!(matches!(set.remove(Range { start: 2, end: 1 }),
+                         Err(Error::InvalidRange)));
+    }
+
+    #[test]
+    fn tagged_insert_without_room_leaves_the_set_unchanged() {
+        // Every entry is in use and apart, so a split has nowhere to go
+        let mut set = TaggedRangeSet::new();
+        for ii in 0..256 {
+            set.insert(Range { start: ii * 0x10, end: ii * 0x10 + 7 },
+                       Kind::Ram).unwrap();
+        }
+
+        assert!(matches!(set.insert(Range { start: 2, end: 3 }, Kind::Mmio),
+                         Err(Error::OutOfEntries)));
+        assert_eq!(set.entries().len(), 256);
+        assert_eq!(set.tag_of(2), Some(Kind::Ram));
+    }
+
+    #[test]
+    fn tagged_filter_allocates_by_tag() {
+        let set = tagged(&[(0x1000, 0x1fff, Kind::Ram),
+                           (0x2000, 0x3fff, Kind::Acpi),
+                           (0x8000, 0x9fff, Kind::Ram)]);
+        let mut ram = set.filter(|tag| tag == Kind::Ram).unwrap();
+        assert_ranges(&ram, &[(0x1000, 0x1fff), (0x8000, 0x9fff)]);
+
+        // Only memory with the tag is handed out, even though the ACPI
+        // memory would fit
+        assert_eq!(ram.allocate(0x2000, 0x1000).unwrap(), 0x8000);
+        assert!(matches!(ram.allocate(0x2000, 0x1000),
+                         Err(Error::OutOfMemory)));
+        assert_eq!(set.tag_of(0x8000), Some(Kind::Ram));
+    }
+}
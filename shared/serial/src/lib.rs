@@ -2,7 +2,7 @@
 
 #![no_std]
 
-use generic_access_structure::{Gas, AccessSize};
+use generic_access_structure::Gas;
 
 /// A `Result` type which wraps a serial error
 pub type Result<T> = core::result::Result<T, Error>;
@@ -15,6 +15,9 @@ pub enum Error {
 
     /// Accessing the device via the [`Gas`] returned an error
     GasError(generic_access_structure::Error),
+
+    /// The baud rate can not be programmed with the clock of the device
+    UnsupportedBaudRate(BaudRate),
 }
 
 impl From<generic_access_structure::Error> for Error {
@@ -48,6 +51,55 @@ pub enum BaudRate {
 
     /// 115200
     Baud115200,
+
+    /// 230400
+    Baud230400,
+
+    /// 460800
+    Baud460800,
+
+    /// 921600
+    Baud921600,
+
+    /// Any other baud rate, in bits per second
+    Other(u32),
+}
+
+impl From<u32> for BaudRate {
+    fn from(val: u32) -> Self {
+        match val {
+                 9600 => Self::Baud9600,
+                19200 => Self::Baud19200,
+                57600 => Self::Baud57600,
+               115200 => Self::Baud115200,
+               230400 => Self::Baud230400,
+               460800 => Self::Baud460800,
+               921600 => Self::Baud921600,
+                    _ => Self::Other(val),
+        }
+    }
+}
+
+impl BaudRate {
+    /// Get the baud rate in bits per second
+    ///
+    /// # Returns
+    ///
+    /// The baud rate in bits per second, or `None` for [`BaudRate::AsIs`]
+    ///
+    pub fn bps(&self) -> Option<u32> {
+        match self {
+            Self::AsIs       => None,
+            Self::Baud9600   => Some(9600),
+            Self::Baud19200  => Some(19200),
+            Self::Baud57600  => Some(57600),
+            Self::Baud115200 => Some(115200),
+            Self::Baud230400 => Some(230400),
+            Self::Baud460800 => Some(460800),
+            Self::Baud921600 => Some(921600),
+            Self::Other(bps) => Some(*bps),
+        }
+    }
 }
 
 /// Different types of serial devices
@@ -147,6 +199,34 @@ impl From<u8> for Interface {
     }
 }
 
+impl From<Interface> for u8 {
+    fn from(val: Interface) -> Self {
+        match val {
+            Interface::Serial16550    =>  0,
+            Interface::Serial16450    =>  1,
+            Interface::Max311         =>  2,
+            Interface::ArmPL011       =>  3,
+            Interface::Msm8x60        =>  4,
+            Interface::Nvidia16550    =>  5,
+            Interface::TiOmap         =>  6,
+            Interface::Apm88xxxx      =>  8,
+            Interface::Msm8974        =>  9,
+            Interface::Sam5250        => 10,
+            Interface::IntelUsif      => 11,
+            Interface::IMX6           => 12,
+            Interface::ArmSbsa32      => 13,
+            Interface::ArmSbsa        => 14,
+            Interface::ArmDcc         => 15,
+            Interface::Bcm2835        => 16,
+            Interface::Sdm845_18432   => 17,
+            Interface::Serial16550Gas => 18,
+            Interface::Sdm845_7362    => 19,
+            Interface::IntelLpss      => 20,
+            Interface::Unknown(val)   => val,
+        }
+    }
+}
+
 /// A serial port driver
 pub struct Serial {
     /// Generic Address Structure parsed out of the ACPI tables
@@ -162,6 +242,8 @@ impl Serial {
     /// * `device`    - Generic Address Structure which was parsed from the
     ///                 SPCR ACPI table
     /// * `baud_rate` - Baud rate to configure the device at
+    /// * `clock`     - Input clock frequency of the device in Hz, `None` for
+    ///                 the standard 1.8432 MHz clock
     ///
     /// # Returns
     ///
@@ -178,35 +260,27 @@ impl Serial {
     /// restriction as this function should be called very early in boot before
     /// we are multi-core.
     ///
-    pub unsafe fn init(interface: Interface,
-                       mut device: Gas, baud_rate: BaudRate) -> Result<()> {
-                
-        // WORKAROUND: Sometimes the I/O port on 16550 serial
-        // interfaces is set to `Undefined` in the SPCR. We know that
-        // for x86_64 16550's, the access size should always be byte.
+    pub unsafe fn init(interface: Interface, device: Gas,
+                       baud_rate: BaudRate, clock: Option<u32>) -> Result<()> {
+        // We do not know how to support any other serial device (yet)
         #[cfg(target_arch = "x86_64")]
-        if let Interface::Serial16550 = interface {
-            if let Gas::Io { access_size, .. } = &mut device {
-                if let AccessSize::Undefined = access_size {
-                    *access_size = AccessSize::Byte;
-                }
-            }
-        } else {
-            // We do not know how to support this serial device (yet)
+        if !matches!(interface, Interface::Serial16550) {
             return Err(Error::UnsupportedDevice(interface));
-
         }
 
         // Disable all interrupts
         device.write(1, 0x00)?;
 
-        // Convert the baud rate into the divisor
-        let divisor = match baud_rate {
-            BaudRate::AsIs       => None,
-            BaudRate::Baud115200 => Some((0, 1)),
-            BaudRate::Baud57600  => Some((0, 2)),
-            BaudRate::Baud19200  => Some((0, 6)),
-            BaudRate::Baud9600   => Some((0, 12))
+        // Convert the baud rate into the divisor, the UART samples each bit
+        // 16 times
+        let divisor = if let Some(bps) = baud_rate.bps() {
+            let divisor = clock.unwrap_or(1_843_200) / bps.saturating_mul(16);
+            if divisor == 0 || divisor > 0xffff {
+                return Err(Error::UnsupportedBaudRate(baud_rate));
+            }
+            Some(((divisor >> 8) as u64, (divisor & 0xff) as u64))
+        } else {
+            None
         };
 
         // Program the baud rate for the device
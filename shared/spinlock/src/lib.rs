@@ -0,0 +1,157 @@
+//! A spinlock which masks interrupts while it is held, so the processor
+//! holding it can't be interrupted into taking it again. We implement this in
+//! its own library so anything which runs on more than one processor can
+//! share it.
+
+#![feature(asm)]
+#![no_std]
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A value which only one processor at a time can access
+pub struct SpinLock<T> {
+    /// Set while the lock is held
+    locked: AtomicBool,
+
+    /// The value behind the lock
+    val: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    /// Create a new unlocked spinlock
+    ///
+    /// # Parameters
+    ///
+    /// * `val` - The value behind the lock
+    ///
+    /// # Returns
+    ///
+    /// A new [`SpinLock`]
+    ///
+    pub const fn new(val: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            val:    UnsafeCell::new(val),
+        }
+    }
+
+    /// Take the lock, spinning until it is free. Interrupts are masked until
+    /// the lock is released again.
+    ///
+    /// # Returns
+    ///
+    /// A [`SpinLockGuard`] which releases the lock when dropped
+    ///
+    pub fn lock(&self) -> SpinLockGuard<T> {
+        let irqs = unsafe { disable_interrupts() };
+        while self.locked.compare_exchange_weak(false, true,
+                Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+
+        SpinLockGuard { lock: self, irqs }
+    }
+
+    /// Release the lock, whoever holds it
+    ///
+    /// # Safety
+    ///
+    /// The holder of the lock must never touch the value again, this is only
+    /// meant for a panic handler which may have interrupted the holder.
+    ///
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// Access to the value behind a [`SpinLock`], for as long as it is held
+pub struct SpinLockGuard<'a, T> {
+    /// The lock which is held
+    lock: &'a SpinLock<T>,
+
+    /// Whether interrupts were enabled before the lock was taken
+    irqs: bool,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.val.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.val.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        if self.irqs { unsafe { enable_interrupts(); } }
+    }
+}
+
+/// Mask interrupts on the current processor
+///
+/// # Returns
+///
+/// `true` if interrupts were enabled before
+///
+#[cfg(target_arch = "x86_64")]
+unsafe fn disable_interrupts() -> bool {
+    let flags: u64;
+    asm!("pushfq", "pop {}", "cli", out(reg) flags);
+    flags & (1 << 9) != 0
+}
+
+/// Unmask interrupts on the current processor
+#[cfg(target_arch = "x86_64")]
+unsafe fn enable_interrupts() {
+    asm!("sti", options(nostack));
+}
+
+/// Mask interrupts on the current processor
+///
+/// # Returns
+///
+/// `true` if interrupts were enabled before
+///
+#[cfg(target_arch = "aarch64")]
+unsafe fn disable_interrupts() -> bool {
+    let daif: u64;
+    asm!("mrs {}, daif", "msr daifset, #2", out(reg) daif,
+        options(nostack));
+    daif & (1 << 7) == 0
+}
+
+/// Unmask interrupts on the current processor
+#[cfg(target_arch = "aarch64")]
+unsafe fn enable_interrupts() {
+    asm!("msr daifclr, #2", options(nostack));
+}
+
+/// Mask interrupts on the current processor
+///
+/// # Returns
+///
+/// `true` if interrupts were enabled before
+///
+#[cfg(target_arch = "riscv64")]
+unsafe fn disable_interrupts() -> bool {
+    let sstatus: u64;
+    asm!("csrrci {}, sstatus, 2", out(reg) sstatus, options(nostack));
+    sstatus & 2 != 0
+}
+
+/// Unmask interrupts on the current processor
+#[cfg(target_arch = "riscv64")]
+unsafe fn enable_interrupts() {
+    asm!("csrsi sstatus, 2", options(nostack));
+}